@@ -640,6 +640,11 @@ impl<const NUM_REGIONS: usize> kernel::mpu::MPU for MPU<NUM_REGIONS> {
             return Err(());
         }
 
+        // The breaks must lie within the process memory block.
+        if app_memory_break < region_start || kernel_memory_break > region_start + region_size {
+            return Err(());
+        }
+
         // Number of bytes the process wants access to.
         let app_memory_size = app_memory_break - region_start;
         // Number of bytes the kernel has reserved.
@@ -658,8 +663,9 @@ impl<const NUM_REGIONS: usize> kernel::mpu::MPU for MPU<NUM_REGIONS> {
                 // Calculate the minimum number of subregions needed to cover
                 // the `app_memory_size`.
                 //
-                // Want `round_up(app_memory_size / subregion_size)`.
-                (app_memory_size + subregion_size - 1) / subregion_size
+                // Want `round_up(app_memory_size / subregion_size)`, and at
+                // least one subregion as the region cannot be empty.
+                cmp::max(1, (app_memory_size + subregion_size - 1) / subregion_size)
             }
         };
 
@@ -701,3 +707,61 @@ impl<const NUM_REGIONS: usize> kernel::mpu::MPU for MPU<NUM_REGIONS> {
         }
     }
 }
+
+impl<const NUM_REGIONS: usize> mpu::ProtectionModel for MPU<NUM_REGIONS> {
+    fn user_access(&self, config: &Self::MpuConfig, address: usize) -> mpu::Access {
+        let address = address as u64;
+        // Higher numbered regions take priority over lower numbered ones.
+        for region in config.regions.iter().rev() {
+            let attributes = region.attributes();
+            if attributes.read(RegionAttributes::ENABLE) == 0 {
+                continue;
+            }
+
+            // The hardware ignores the address bits below the region size.
+            let size = 1u64 << (attributes.read(RegionAttributes::SIZE) + 1);
+            let start = (region.base_address().read(RegionBaseAddress::ADDR) as u64) << 5;
+            let start = start & !(size - 1);
+            if address < start || address >= start + size {
+                continue;
+            }
+
+            // Regions smaller than 256 bytes have no subregions.
+            if size >= 256 {
+                let subregion = (address - start) / (size / 8);
+                if (attributes.read(RegionAttributes::SRD) >> subregion) & 1 == 1 {
+                    continue;
+                }
+            }
+
+            let access = attributes.read(RegionAttributes::AP);
+            let read = match access {
+                0b010 | 0b011 | 0b110 | 0b111 => true,
+                _ => false,
+            };
+            return mpu::Access {
+                read,
+                write: access == 0b011,
+                execute: read && attributes.read(RegionAttributes::XN) == 0,
+            };
+        }
+        mpu::Access::NONE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MPU;
+    use kernel::mpu_check;
+
+    #[test]
+    fn follows_the_mpu_contract() {
+        let mpu = unsafe { MPU::<8>::new() };
+        for seed in 0..4 {
+            let coverage = mpu_check::check_mpu(&mpu, seed, 500).unwrap();
+            assert!(coverage.regions > 0);
+            assert!(coverage.app_memory_regions > 0);
+            assert!(coverage.updates > 0);
+        }
+    }
+}
@@ -259,6 +259,51 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
         (switch_reason, Some(new_stack_pointer as *const u8))
     }
 
+    /// Stores R0-R12, SP, LR, PC and xPSR, in that order.
+    unsafe fn store_registers(
+        &self,
+        accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &CortexMStoredState,
+        registers: &mut [u32],
+    ) -> usize {
+        // R0-R3, R12, LR, PC and xPSR are in the frame on the process stack.
+        // Leave them zero if the stored stack pointer is not valid.
+        let mut frame = [0; 8];
+        if state.psp >= accessible_memory_start as usize
+            && (state.psp + SVC_FRAME_SIZE) <= app_brk as usize
+        {
+            let stack_pointer = state.psp as *const usize;
+            for (i, value) in frame.iter_mut().enumerate() {
+                *value = read_volatile(stack_pointer.add(i));
+            }
+        }
+        let values = [
+            frame[0],
+            frame[1],
+            frame[2],
+            frame[3],
+            state.regs[0],
+            state.regs[1],
+            state.regs[2],
+            state.regs[3],
+            state.regs[4],
+            state.regs[5],
+            state.regs[6],
+            state.regs[7],
+            frame[4],
+            state.psp,
+            frame[5],
+            frame[6],
+            frame[7],
+        ];
+        let count = values.len().min(registers.len());
+        for (register, value) in registers.iter_mut().zip(values.iter()) {
+            *register = *value as u32;
+        }
+        count
+    }
+
     unsafe fn print_context(
         &self,
         accessible_memory_start: *const u8,
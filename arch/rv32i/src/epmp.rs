@@ -479,6 +479,11 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::mpu::MPU
             size = 8;
         }
 
+        // Check that our logical region fits in memory.
+        if start + size > (unallocated_memory_start as usize) + unallocated_memory_size {
+            return None;
+        }
+
         let region = PMPRegion::new_app(start as *const u8, size, permissions);
 
         if region.is_none() {
@@ -590,8 +595,21 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::mpu::MPU
             return Err(());
         }
 
-        // Get size of updated region
-        let region_size = app_memory_break - region_start as usize;
+        // The app break must lie within the process memory block.
+        if app_memory_break < region_start as usize {
+            return Err(());
+        }
+
+        // Get size of updated region, which has to align to 4 bytes
+        let mut region_size = app_memory_break - region_start as usize;
+        if region_size % 4 != 0 {
+            region_size += 4 - (region_size % 4);
+        }
+
+        // The rounded up region must not cover kernel memory.
+        if region_start as usize + region_size > kernel_memory_break {
+            return Err(());
+        }
 
         let region = PMPRegion::new_app(region_start as *const u8, region_size, permissions);
 
@@ -808,3 +826,60 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::mpu::KernelMPU
         csr::CSR.mseccfg.modify(csr::mseccfg::mseccfg::mml::SET);
     }
 }
+
+impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> mpu::ProtectionModel
+    for PMP<MAX_AVAILABLE_REGIONS_OVER_TWO>
+{
+    fn user_access(&self, config: &Self::MpuConfig, address: usize) -> mpu::Access {
+        // The lowest numbered matching region applies.
+        for region in config.regions.iter().flatten() {
+            let cfg = u8::from(region.cfg);
+            if !pmpcfg::a::TOR.matches_all(cfg) {
+                continue;
+            }
+
+            // `configure_mpu()` writes the addresses shifted right by two, so
+            // regions start and end on word boundaries.
+            let (start, size) = region.location();
+            let start = start as usize;
+            if address < start & !3 || address >= (start + size) & !3 {
+                continue;
+            }
+            // Locked regions only apply to machine mode.
+            if pmpcfg::l::SET.matches_all(cfg) {
+                return mpu::Access::NONE;
+            }
+
+            return mpu::Access {
+                read: pmpcfg::r::SET.matches_all(cfg),
+                write: pmpcfg::w::SET.matches_all(cfg),
+                execute: pmpcfg::x::SET.matches_all(cfg),
+            };
+        }
+        mpu::Access::NONE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PMP;
+    use core::cell::Cell;
+    use kernel::common::cells::MapCell;
+    use kernel::mpu_check;
+
+    #[test]
+    fn follows_the_mpu_contract() {
+        // `PMP::new()` probes the CSRs, so build one with all 16 entries.
+        let pmp = PMP::<8> {
+            last_configured_for: MapCell::empty(),
+            locked_region_mask: Cell::new(0),
+            num_regions: 16,
+        };
+        for seed in 0..4 {
+            let coverage = mpu_check::check_mpu(&pmp, seed, 500).unwrap();
+            assert!(coverage.regions > 0);
+            assert!(coverage.app_memory_regions > 0);
+            assert!(coverage.updates > 0);
+        }
+    }
+}
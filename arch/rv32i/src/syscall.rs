@@ -426,6 +426,24 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
         (ret, Some(new_stack_pointer as *const u8))
     }
 
+    /// Stores x1-x31, the PC, mcause and mtval, in that order.
+    unsafe fn store_registers(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &Riscv32iStoredState,
+        registers: &mut [u32],
+    ) -> usize {
+        let csrs = [state.pc, state.mcause, state.mtval];
+        let values = state.regs.iter().chain(csrs.iter());
+        let mut count = 0;
+        for (register, value) in registers.iter_mut().zip(values) {
+            *register = *value;
+            count += 1;
+        }
+        count
+    }
+
     unsafe fn print_context(
         &self,
         _accessible_memory_start: *const u8,
@@ -486,7 +486,11 @@ pub unsafe fn main() {
 
     let chip = static_init!(
         nrf52832::chip::NRF52<Nrf52832DefaultPeripherals>,
-        nrf52832::chip::NRF52::new(nrf52832_peripherals)
+        nrf52832::chip::NRF52::new(
+            nrf52832_peripherals,
+            &base_peripherals.pwr_clk,
+            &base_peripherals.clock,
+        )
     );
 
     nrf52832_peripherals.gpio_port[Pin::P0_31].make_output();
@@ -4,6 +4,9 @@
 //! command console for controlling processes over a UART bus. On imix this is
 //! typically USART3 (the DEBUG USB connector).
 //!
+//! The component tells the console where the kernel's memory regions are,
+//! from the symbols `kernel_layout.ld` defines.
+//!
 //! Usage
 //! -----
 //! ```rust
@@ -40,6 +43,20 @@ impl ProcessConsoleComponent {
 pub struct Capability;
 unsafe impl capabilities::ProcessManagementCapability for Capability {}
 
+// Defined by the linker script `kernel_layout.ld`.
+extern "C" {
+    static _stext: u8;
+    static _etext: u8;
+    static _sstack: u8;
+    static _estack: u8;
+    static _srelocate: u8;
+    static _erelocate: u8;
+    static _szero: u8;
+    static _ezero: u8;
+    static _sappmem: u8;
+    static _eappmem: u8;
+}
+
 impl Component for ProcessConsoleComponent {
     type StaticInput = ();
     type Output = &'static process_console::ProcessConsole<'static, Capability>;
@@ -60,6 +77,18 @@ impl Component for ProcessConsoleComponent {
                 Capability,
             )
         );
+        console.set_kernel_addresses(process_console::KernelAddresses {
+            text_start: &_stext as *const u8,
+            text_end: &_etext as *const u8,
+            stack_start: &_sstack as *const u8,
+            stack_end: &_estack as *const u8,
+            relocate_start: &_srelocate as *const u8,
+            relocate_end: &_erelocate as *const u8,
+            bss_start: &_szero as *const u8,
+            bss_end: &_ezero as *const u8,
+            app_memory_start: &_sappmem as *const u8,
+            app_memory_end: &_eappmem as *const u8,
+        });
         hil::uart::Transmit::set_transmit_client(console_uart, console);
         hil::uart::Receive::set_receive_client(console_uart, console);
 
@@ -258,6 +258,15 @@ pub unsafe fn main() {
         .finalize(components::alarm_mux_component_helper!(sam4l::ast::Ast));
     peripherals.ast.configure(mux_alarm);
 
+    // Sleep as deeply as the peripherals in use and the next alarm allow.
+    let power_manager = static_init!(
+        kernel::power::PowerManager,
+        kernel::power::PowerManager::new()
+    );
+    power_manager.set_wakeup_alarm(&peripherals.ast);
+    peripherals.set_power_manager(power_manager);
+    board_kernel.set_power_manager(power_manager, &process_management_capability);
+
     let sensors_i2c = static_init!(
         MuxI2C<'static>,
         MuxI2C::new(&peripherals.i2c1, None, dynamic_deferred_caller)
@@ -513,7 +513,11 @@ pub unsafe fn main() {
 
     let chip = static_init!(
         nrf52833::chip::NRF52<Nrf52833DefaultPeripherals>,
-        nrf52833::chip::NRF52::new(nrf52833_peripherals)
+        nrf52833::chip::NRF52::new(
+            nrf52833_peripherals,
+            &base_peripherals.pwr_clk,
+            &base_peripherals.clock,
+        )
     );
     CHIP = Some(chip);
 
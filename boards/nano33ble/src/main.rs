@@ -466,7 +466,11 @@ pub unsafe fn main() {
 
     let chip = static_init!(
         nrf52840::chip::NRF52<Nrf52840DefaultPeripherals>,
-        nrf52840::chip::NRF52::new(nrf52840_peripherals)
+        nrf52840::chip::NRF52::new(
+            nrf52840_peripherals,
+            &base_peripherals.pwr_clk,
+            &base_peripherals.clock,
+        )
     );
     CHIP = Some(chip);
 
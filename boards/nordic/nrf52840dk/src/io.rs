@@ -9,6 +9,7 @@ use kernel::hil::uart::Configure;
 use nrf52840::gpio::Pin;
 
 use crate::CHIP;
+use crate::CRASH_DUMP;
 use crate::PROCESSES;
 
 enum Writer {
@@ -95,6 +96,7 @@ pub unsafe extern "C" fn panic_fmt(pi: &PanicInfo) -> ! {
     let led_kernel_pin = &nrf52840::gpio::GPIOPin::new(Pin::P0_13);
     let led = &mut led::LedLow::new(led_kernel_pin);
     let writer = &mut WRITER;
+    debug::panic_crash_dump(&CRASH_DUMP, pi);
     debug::panic(
         &mut [led],
         writer,
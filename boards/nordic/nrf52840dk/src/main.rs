@@ -135,6 +135,13 @@ static mut PROCESSES: [Option<&'static dyn kernel::procs::Process>; NUM_PROCS] =
 
 static mut CHIP: Option<&'static nrf52840::chip::NRF52<Nrf52840DefaultPeripherals>> = None;
 
+static mut CRASH_DUMP: Option<&'static kernel::crash_dump::CrashDump> = None;
+
+/// Region for the record of the last crash. It is not initialized at boot so
+/// that the record survives a reset.
+#[link_section = ".noinit"]
+static mut CRASH_DUMP_REGION: [u8; 256] = [0; 256];
+
 /// Dummy buffer that causes the linker to reserve enough space for the stack.
 #[no_mangle]
 #[link_section = ".stack_buffer"]
@@ -305,7 +312,11 @@ pub unsafe fn main() {
 
     let chip = static_init!(
         nrf52840::chip::NRF52<Nrf52840DefaultPeripherals>,
-        nrf52840::chip::NRF52::new(nrf52840_peripherals)
+        nrf52840::chip::NRF52::new(
+            nrf52840_peripherals,
+            &base_peripherals.pwr_clk,
+            &base_peripherals.clock,
+        )
     );
     CHIP = Some(chip);
 
@@ -323,6 +334,15 @@ pub unsafe fn main() {
         create_capability!(capabilities::ProcessManagementCapability);
     let main_loop_capability = create_capability!(capabilities::MainLoopCapability);
     let memory_allocation_capability = create_capability!(capabilities::MemoryAllocationCapability);
+
+    // Keep a record of the last crash across resets.
+    let crash_dump = static_init!(
+        kernel::crash_dump::CrashDump,
+        kernel::crash_dump::CrashDump::new(&mut CRASH_DUMP_REGION)
+    );
+    board_kernel.set_crash_dump(crash_dump, &process_management_capability);
+    CRASH_DUMP = Some(crash_dump);
+
     let gpio_port = &nrf52840_peripherals.gpio_port;
     // Configure kernel debug gpios as early as possible
     kernel::debug::assign_gpios(
@@ -333,6 +353,15 @@ pub unsafe fn main() {
 
     let rtc = &base_peripherals.rtc;
     let _ = rtc.start();
+
+    // Sleep as deeply as the peripherals in use and the next alarm allow.
+    let power_manager = static_init!(
+        kernel::power::PowerManager,
+        kernel::power::PowerManager::new()
+    );
+    power_manager.set_wakeup_alarm(rtc);
+    nrf52840_peripherals.set_power_manager(power_manager);
+    board_kernel.set_power_manager(power_manager, &process_management_capability);
     let mux_alarm = components::alarm::AlarmMuxComponent::new(rtc)
         .finalize(components::alarm_mux_component_helper!(nrf52840::rtc::Rtc));
     let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
@@ -270,7 +270,11 @@ pub unsafe fn main() {
 
     let chip = static_init!(
         nrf52832::chip::NRF52<Nrf52832DefaultPeripherals>,
-        nrf52832::chip::NRF52::new(nrf52832_peripherals)
+        nrf52832::chip::NRF52::new(
+            nrf52832_peripherals,
+            &base_peripherals.pwr_clk,
+            &base_peripherals.clock,
+        )
     );
     CHIP = Some(chip);
 
@@ -19,6 +19,9 @@ pub enum NUM {
 
     // Kernel
     Ipc                   = 0x10000,
+    IpcMailbox            = 0x10001,
+    Heartbeat             = 0x10002,
+    UpcallOverflow        = 0x10003,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -42,6 +45,8 @@ pub enum NUM {
     AppFlash              = 0x50000,
     NvmStorage            = 0x50001,
     SdCard                = 0x50002,
+    ProcessCheckpoint     = 0x50003,
+    KVStore               = 0x50004,
 
     // Sensors
     Temperature           = 0x60000,
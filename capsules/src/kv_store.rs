@@ -0,0 +1,748 @@
+//! Key-value store with per-process permissions, and its system call driver.
+//!
+//! `KVStore` implements `hil::kv_store` on top of a `hil::kv_system`
+//! implementation such as `capsules::tickv::TicKVStore`. It hashes the keys
+//! it is given, and stores every value behind a header recording who stored
+//! it:
+//!
+//! ```text
+//! +-------------+----------------+----------------+-------------
+//! | version (u8)| write_id (u32) | length (u32)   | value ...
+//! +-------------+----------------+----------------+-------------
+//! ```
+//!
+//! Before a value is read, overwritten or deleted, the `StoragePermissions`
+//! of the caller are checked against the `write_id` in its header. Processes
+//! get their permissions from the storage permissions element of their TBF
+//! header.
+//!
+//! Values are overwritten with `KVSystem::update_key()`, so a power loss
+//! while a value is overwritten keeps either the old or the new value.
+//!
+//! `KVStoreDriver` lets processes use the store. Each process may have one
+//! operation outstanding; operations of different processes run one at a
+//! time, in turn.
+//!
+//! +-----------------------+
+//! |                       |
+//! |  KVStoreDriver        |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::kv_store
+//!
+//! +-----------------------+
+//! |                       |
+//! |  KVStore              |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::kv_system
+//!
+//! +-----------------------+
+//! |                       |
+//! |  TicKV                |
+//! |                       |
+//! +-----------------------+
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let kv_store = static_init!(
+//!     capsules::kv_store::KVStore<'static, TicKVStore<...>, TicKVKeyType>,
+//!     capsules::kv_store::KVStore::new(
+//!         tickv,
+//!         static_init!(TicKVKeyType, [0; 8]),
+//!         // Must hold the largest stored value, including its header.
+//!         static_init!([u8; 512], [0; 512]),
+//!     )
+//! );
+//! tickv.set_client(kv_store);
+//!
+//! let kv_store_driver = static_init!(
+//!     capsules::kv_store::KVStoreDriver<'static, capsules::kv_store::KVStore<...>>,
+//!     capsules::kv_store::KVStoreDriver::new(
+//!         kv_store,
+//!         board_kernel.create_grant(&grant_cap),
+//!         static_init!([u8; 64], [0; 64]),  // Longest key.
+//!         static_init!([u8; 512], [0; 512]), // Largest value and its header.
+//!     )
+//! );
+//! kv_store.set_client(kv_store_driver);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::kv_store;
+use kernel::hil::kv_system::{self, KVSystem, KeyType};
+use kernel::storage_permissions::StoragePermissions;
+use kernel::ErrorCode;
+use kernel::{
+    CommandReturn, Driver, Grant, ProcessId, Read, ReadOnlyAppSlice, ReadWrite, ReadWriteAppSlice,
+    Upcall,
+};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::KVStore as usize;
+
+/// Length of the header stored in front of every value.
+pub const HEADER_LENGTH: usize = 9;
+/// Version of the header layout.
+const HEADER_VERSION: u8 = 0;
+
+/// Parse the header at the start of `buf`, returning the write ID and the
+/// length of the value that follows.
+fn parse_header(buf: &[u8]) -> Option<(u32, usize)> {
+    if buf.len() < HEADER_LENGTH || buf[0] != HEADER_VERSION {
+        return None;
+    }
+    let write_id = u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]);
+    let length = u32::from_le_bytes([buf[5], buf[6], buf[7], buf[8]]) as usize;
+    if length > buf.len() - HEADER_LENGTH {
+        return None;
+    }
+    Some((write_id, length))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    Get,
+    Set,
+    Delete,
+}
+
+pub struct KVStore<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType> {
+    kv: &'a K,
+    client: OptionalCell<&'a dyn kv_store::StoreClient>,
+    operation: Cell<Operation>,
+    permissions: OptionalCell<StoragePermissions>,
+    unhashed_key: TakeCell<'static, [u8]>,
+    hashed_key: TakeCell<'static, T>,
+    // The caller's value buffer, and for `set` the length to store.
+    value: TakeCell<'static, [u8]>,
+    value_length: Cell<usize>,
+    // Holds the value already stored under a key while its header is checked.
+    header_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType> KVStore<'a, K, T> {
+    pub fn new(
+        kv: &'a K,
+        hashed_key: &'static mut T,
+        header_buffer: &'static mut [u8],
+    ) -> KVStore<'a, K, T> {
+        KVStore {
+            kv,
+            client: OptionalCell::empty(),
+            operation: Cell::new(Operation::None),
+            permissions: OptionalCell::empty(),
+            unhashed_key: TakeCell::empty(),
+            hashed_key: TakeCell::new(hashed_key),
+            value: TakeCell::empty(),
+            value_length: Cell::new(0),
+            header_buffer: TakeCell::new(header_buffer),
+        }
+    }
+
+    /// Start `operation` by hashing the key. The operation continues in
+    /// `generate_key_complete()`.
+    fn start(
+        &self,
+        operation: Operation,
+        key: &'static mut [u8],
+        key_len: usize,
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], ErrorCode)> {
+        let hashed_key = match self.hashed_key.take() {
+            Some(hashed_key) => hashed_key,
+            None => return Err((key, ErrorCode::BUSY)),
+        };
+        self.operation.set(operation);
+        self.permissions.set(permissions);
+        self.kv
+            .generate_key(key, key_len, hashed_key)
+            .map_err(|(key, hashed_key, e)| {
+                self.hashed_key.replace(hashed_key);
+                self.operation.set(Operation::None);
+                (key, e.err().unwrap_or(ErrorCode::FAIL))
+            })
+    }
+
+    /// Whether the caller may access the value whose header is at the start
+    /// of `buf`, according to `allowed`.
+    fn check_header<F: Fn(&StoragePermissions, u32) -> bool>(
+        &self,
+        buf: &[u8],
+        allowed: F,
+    ) -> Result<usize, ErrorCode> {
+        match parse_header(buf) {
+            Some((write_id, length))
+                if self
+                    .permissions
+                    .map_or(false, |permissions| allowed(permissions, write_id)) =>
+            {
+                Ok(length)
+            }
+            _ => Err(ErrorCode::FAIL),
+        }
+    }
+
+    fn append(&self, hashed_key: &'static mut T) {
+        let value = self.value.take().unwrap();
+        if let Err((hashed_key, value, e)) =
+            self.kv
+                .append_key(hashed_key, value, self.value_length.get())
+        {
+            self.hashed_key.replace(hashed_key);
+            self.value.replace(value);
+            self.complete(Err(e.err().unwrap_or(ErrorCode::FAIL)));
+        }
+    }
+
+    fn update(&self, hashed_key: &'static mut T) {
+        let value = self.value.take().unwrap();
+        if let Err((hashed_key, value, e)) =
+            self.kv
+                .update_key(hashed_key, value, self.value_length.get())
+        {
+            self.hashed_key.replace(hashed_key);
+            self.value.replace(value);
+            self.complete(Err(e.err().unwrap_or(ErrorCode::FAIL)));
+        }
+    }
+
+    fn invalidate(&self, hashed_key: &'static mut T) {
+        if let Err((hashed_key, e)) = self.kv.invalidate_key(hashed_key) {
+            self.hashed_key.replace(hashed_key);
+            self.complete(Err(e.err().unwrap_or(ErrorCode::FAIL)));
+        }
+    }
+
+    /// Finish the current operation and return the buffers to the client.
+    fn complete(&self, result: Result<usize, ErrorCode>) {
+        let operation = self.operation.replace(Operation::None);
+        self.permissions.clear();
+        let key = self.unhashed_key.take().unwrap();
+        self.client.map(move |cb| match operation {
+            Operation::Get => cb.get_complete(result, key, self.value.take().unwrap()),
+            Operation::Set => cb.set_complete(result.map(|_| ()), key, self.value.take().unwrap()),
+            Operation::Delete => cb.delete_complete(result.map(|_| ()), key),
+            Operation::None => {}
+        });
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType> kv_store::KVStore<'a> for KVStore<'a, K, T> {
+    fn set_client(&self, client: &'a dyn kv_store::StoreClient) {
+        self.client.set(client);
+    }
+
+    fn header_size(&self) -> usize {
+        HEADER_LENGTH
+    }
+
+    fn get(
+        &self,
+        key: &'static mut [u8],
+        key_len: usize,
+        value: &'static mut [u8],
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], &'static mut [u8], ErrorCode)> {
+        if self.operation.get() != Operation::None {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+        if value.len() < HEADER_LENGTH {
+            return Err((key, value, ErrorCode::INVAL));
+        }
+
+        self.value.replace(value);
+        self.start(Operation::Get, key, key_len, permissions)
+            .map_err(|(key, e)| (key, self.value.take().unwrap(), e))
+    }
+
+    fn set(
+        &self,
+        key: &'static mut [u8],
+        key_len: usize,
+        value: &'static mut [u8],
+        value_len: usize,
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], &'static mut [u8], ErrorCode)> {
+        if self.operation.get() != Operation::None {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+        let write_id = match permissions.write_id() {
+            Some(write_id) => write_id,
+            None => return Err((key, value, ErrorCode::FAIL)),
+        };
+        if HEADER_LENGTH + value_len > value.len() {
+            return Err((key, value, ErrorCode::INVAL));
+        }
+
+        value[0] = HEADER_VERSION;
+        value[1..5].copy_from_slice(&write_id.to_le_bytes());
+        value[5..9].copy_from_slice(&(value_len as u32).to_le_bytes());
+        self.value.replace(value);
+        self.value_length.set(HEADER_LENGTH + value_len);
+        self.start(Operation::Set, key, key_len, permissions)
+            .map_err(|(key, e)| (key, self.value.take().unwrap(), e))
+    }
+
+    fn delete(
+        &self,
+        key: &'static mut [u8],
+        key_len: usize,
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], ErrorCode)> {
+        if self.operation.get() != Operation::None {
+            return Err((key, ErrorCode::BUSY));
+        }
+
+        self.start(Operation::Delete, key, key_len, permissions)
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType> kv_system::Client<T> for KVStore<'a, K, T> {
+    fn generate_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        unhashed_key: &'static mut [u8],
+        key_buf: &'static mut T,
+    ) {
+        self.unhashed_key.replace(unhashed_key);
+        if let Err(e) = result {
+            self.hashed_key.replace(key_buf);
+            self.complete(Err(e));
+            return;
+        }
+
+        // Get is done once the value is read. Set and delete first read the
+        // value already stored, to check that the caller may modify it.
+        let ret = match self.operation.get() {
+            Operation::Get => self.kv.get_value(key_buf, self.value.take().unwrap()),
+            _ => self
+                .kv
+                .get_value(key_buf, self.header_buffer.take().unwrap()),
+        };
+        if let Err((key_buf, buf, e)) = ret {
+            self.hashed_key.replace(key_buf);
+            match self.operation.get() {
+                Operation::Get => self.value.replace(buf),
+                _ => self.header_buffer.replace(buf),
+            };
+            self.complete(Err(e.err().unwrap_or(ErrorCode::FAIL)));
+        }
+    }
+
+    fn append_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        value: &'static mut [u8],
+    ) {
+        self.hashed_key.replace(key);
+        self.value.replace(value);
+        self.complete(result.map(|()| self.value_length.get() - HEADER_LENGTH));
+    }
+
+    fn update_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        value: &'static mut [u8],
+    ) {
+        self.hashed_key.replace(key);
+        self.value.replace(value);
+        self.complete(result.map(|()| self.value_length.get() - HEADER_LENGTH));
+    }
+
+    fn get_value_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        ret_buf: &'static mut [u8],
+    ) {
+        match self.operation.get() {
+            Operation::Get => {
+                let result = match result {
+                    Ok(()) => self.check_header(ret_buf, StoragePermissions::can_read),
+                    Err(ErrorCode::NOSUPPORT) => Err(ErrorCode::FAIL),
+                    Err(e) => Err(e),
+                };
+                self.hashed_key.replace(key);
+                self.value.replace(ret_buf);
+                self.complete(result);
+            }
+            Operation::Set => {
+                let result =
+                    result.map(|()| self.check_header(ret_buf, StoragePermissions::can_modify));
+                self.header_buffer.replace(ret_buf);
+                match result {
+                    // There is no value yet.
+                    Err(ErrorCode::NOSUPPORT) => self.append(key),
+                    Ok(Ok(_)) => self.update(key),
+                    Ok(Err(e)) | Err(e) => {
+                        self.hashed_key.replace(key);
+                        self.complete(Err(e));
+                    }
+                }
+            }
+            Operation::Delete => {
+                let result = match result {
+                    Ok(()) => self.check_header(ret_buf, StoragePermissions::can_modify),
+                    Err(ErrorCode::NOSUPPORT) => Err(ErrorCode::FAIL),
+                    Err(e) => Err(e),
+                };
+                self.header_buffer.replace(ret_buf);
+                match result {
+                    Ok(_) => self.invalidate(key),
+                    Err(e) => {
+                        self.hashed_key.replace(key);
+                        self.complete(Err(e));
+                    }
+                }
+            }
+            Operation::None => {
+                self.hashed_key.replace(key);
+                self.header_buffer.replace(ret_buf);
+            }
+        }
+    }
+
+    fn invalidate_key_complete(&self, result: Result<(), ErrorCode>, key: &'static mut T) {
+        self.hashed_key.replace(key);
+        self.complete(result.map(|()| 0));
+    }
+
+    fn garbage_collect_complete(&self, _result: Result<(), ErrorCode>) {}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum UserCommand {
+    Get,
+    Set,
+    Delete,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Upcall,
+    key: ReadOnlyAppSlice,
+    value: ReadOnlyAppSlice,
+    data: ReadWriteAppSlice,
+    pending: Option<UserCommand>,
+}
+
+pub struct KVStoreDriver<'a, V: kv_store::KVStore<'a>> {
+    kv: &'a V,
+    apps: Grant<App>,
+    current_app: OptionalCell<ProcessId>,
+    key_buffer: TakeCell<'static, [u8]>,
+    value_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, V: kv_store::KVStore<'a>> KVStoreDriver<'a, V> {
+    pub fn new(
+        kv: &'a V,
+        grant: Grant<App>,
+        key_buffer: &'static mut [u8],
+        value_buffer: &'static mut [u8],
+    ) -> KVStoreDriver<'a, V> {
+        KVStoreDriver {
+            kv,
+            apps: grant,
+            current_app: OptionalCell::empty(),
+            key_buffer: TakeCell::new(key_buffer),
+            value_buffer: TakeCell::new(value_buffer),
+        }
+    }
+
+    /// Start `command` for `appid` now if the store is idle, otherwise queue
+    /// it until the operations of other processes complete.
+    fn enqueue(&self, appid: ProcessId, command: UserCommand) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(appid, |app| {
+                if app.pending.is_some() {
+                    Err(ErrorCode::BUSY)
+                } else {
+                    Ok(())
+                }
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        if self.current_app.is_none() {
+            self.start(appid, command)
+        } else {
+            self.apps
+                .enter(appid, |app| app.pending = Some(command))
+                .map_err(ErrorCode::from)
+        }
+    }
+
+    /// Copy the key, and the value to set, of `appid` into the kernel
+    /// buffers and start `command`.
+    fn start(&self, appid: ProcessId, command: UserCommand) -> Result<(), ErrorCode> {
+        let header_size = self.kv.header_size();
+        let (key, value) = match (self.key_buffer.take(), self.value_buffer.take()) {
+            (Some(key), Some(value)) => (key, value),
+            (key, value) => {
+                key.map(|key| self.key_buffer.replace(key));
+                value.map(|value| self.value_buffer.replace(value));
+                return Err(ErrorCode::BUSY);
+            }
+        };
+
+        let lengths = self
+            .apps
+            .enter(appid, |app| {
+                let key_len = app.key.len();
+                if key_len == 0 {
+                    return Err(ErrorCode::INVAL);
+                } else if key_len > key.len() {
+                    return Err(ErrorCode::SIZE);
+                }
+                app.key
+                    .map_or((), |app_key| key[..key_len].copy_from_slice(app_key));
+
+                let value_len = match command {
+                    UserCommand::Set => app.value.len(),
+                    UserCommand::Get | UserCommand::Delete => 0,
+                };
+                if header_size + value_len > value.len() {
+                    return Err(ErrorCode::SIZE);
+                }
+                app.value.map_or((), |app_value| {
+                    value[header_size..header_size + value_len]
+                        .copy_from_slice(&app_value[..value_len])
+                });
+                Ok((key_len, value_len))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        let (key_len, value_len) = match lengths {
+            Ok(lengths) => lengths,
+            Err(e) => {
+                self.key_buffer.replace(key);
+                self.value_buffer.replace(value);
+                return Err(e);
+            }
+        };
+
+        // The store may complete the operation before it returns, so the
+        // process must be current already.
+        self.current_app.set(appid);
+        let permissions = appid.get_storage_permissions();
+        let result = match command {
+            UserCommand::Get => {
+                self.kv
+                    .get(key, key_len, value, permissions)
+                    .map_err(|(key, value, e)| {
+                        self.value_buffer.replace(value);
+                        (key, e)
+                    })
+            }
+            UserCommand::Set => self
+                .kv
+                .set(key, key_len, value, value_len, permissions)
+                .map_err(|(key, value, e)| {
+                    self.value_buffer.replace(value);
+                    (key, e)
+                }),
+            UserCommand::Delete => {
+                self.value_buffer.replace(value);
+                self.kv.delete(key, key_len, permissions)
+            }
+        };
+        result.map_err(|(key, e)| {
+            self.current_app.clear();
+            self.key_buffer.replace(key);
+            e
+        })
+    }
+
+    /// Report the end of the current operation to its process, and start
+    /// the next queued one.
+    fn done(&self, result: Result<(), ErrorCode>, length: usize) {
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app| {
+                app.callback
+                    .schedule(kernel::into_statuscode(result), length, 0);
+            });
+        });
+
+        for cntr in self.apps.iter() {
+            let appid = cntr.processid();
+            let command = cntr.enter(|app| app.pending.take());
+            if let Some(command) = command {
+                match self.start(appid, command) {
+                    Ok(()) => break,
+                    Err(e) => {
+                        let _ = self.apps.enter(appid, |app| {
+                            app.callback.schedule(kernel::into_statuscode(Err(e)), 0, 0);
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, V: kv_store::KVStore<'a>> kv_store::StoreClient for KVStoreDriver<'a, V> {
+    fn get_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    ) {
+        let header_size = self.kv.header_size();
+        let length = result.unwrap_or(0);
+        if result.is_ok() {
+            self.current_app.map(|appid| {
+                let _ = self.apps.enter(*appid, |app| {
+                    app.data.mut_map_or((), |data| {
+                        let copy_len = cmp::min(length, data.len());
+                        data[..copy_len]
+                            .copy_from_slice(&value[header_size..header_size + copy_len]);
+                    });
+                });
+            });
+        }
+        self.key_buffer.replace(key);
+        self.value_buffer.replace(value);
+        self.done(result.map(|_| ()), length);
+    }
+
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    ) {
+        self.key_buffer.replace(key);
+        self.value_buffer.replace(value);
+        self.done(result, 0);
+    }
+
+    fn delete_complete(&self, result: Result<(), ErrorCode>, key: &'static mut [u8]) {
+        self.key_buffer.replace(key);
+        self.done(result, 0);
+    }
+}
+
+/// Provide an interface for userland.
+impl<'a, V: kv_store::KVStore<'a>> Driver for KVStoreDriver<'a, V> {
+    /// Setup the key and the value to set.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The key.
+    /// - `1`: The value to store with command `2`.
+    fn allow_readonly(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app| match allow_num {
+                0 => {
+                    mem::swap(&mut app.key, &mut slice);
+                    Ok(())
+                }
+                1 => {
+                    mem::swap(&mut app.value, &mut slice);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Setup the buffer values are read into.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The buffer command `1` copies the value into.
+    fn allow_readwrite(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app| match allow_num {
+                0 => {
+                    mem::swap(&mut app.data, &mut slice);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Setup a callback for when an operation completes. The callback
+    ///   gets the status of the operation and, for command `1`, the length
+    ///   of the value.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        app_id: ProcessId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(app_id, |app| match subscribe_num {
+                0 => {
+                    mem::swap(&mut app.callback, &mut callback);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return Ok(()) if this driver is included on the platform.
+    /// - `1`: Get the value stored under the key.
+    /// - `2`: Store the value under the key.
+    /// - `3`: Delete the value stored under the key.
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: ProcessId) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.enqueue(appid, UserCommand::Get).into(),
+            2 => self.enqueue(appid, UserCommand::Set).into(),
+            3 => self.enqueue(appid, UserCommand::Delete).into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
@@ -37,6 +37,7 @@ pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod kv_store;
 pub mod l3gd20;
 pub mod led;
 pub mod led_matrix;
@@ -57,6 +58,7 @@ pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod panic_button;
 pub mod pca9544a;
+pub mod process_checkpoint;
 pub mod process_console;
 pub mod proximity;
 pub mod rf233;
@@ -65,6 +67,7 @@ pub mod rng;
 pub mod screen;
 pub mod sdcard;
 pub mod segger_rtt;
+pub mod sha256;
 pub mod sht3x;
 pub mod si7021;
 pub mod sound_pressure;
@@ -76,6 +79,7 @@ pub mod temperature_stm;
 pub mod text_screen;
 pub mod tickv;
 pub mod touch;
+pub mod trace_drain;
 pub mod tsl2561;
 pub mod usb;
 pub mod virtual_adc;
@@ -0,0 +1,262 @@
+//! Saves the persistent state of processes to nonvolatile storage, so that it
+//! survives a reboot.
+//!
+//! A process that declares a `Persistent RAM` element in its TBF header keeps
+//! a region of its RAM across restarts: the kernel holds a checkpoint of the
+//! region and copies it back when the restarted process registers the region
+//! with the `memop` system call. The checkpoint is lost when the board
+//! reboots. This capsule lets processes that also declare a flash slot in
+//! that element save their checkpoint to that slot of a nonvolatile storage
+//! region, and load it back after a reboot.
+//!
+//! The storage region is split into `num_slots` slots of `slot_size` bytes.
+//! Each slot holds a header followed by the saved state:
+//!
+//! ```text
+//! +-------------+-------------+----------------
+//! | magic (u32) | length (u32)| state ...
+//! +-------------+-------------+----------------
+//! ```
+//!
+//! A process that boots without a checkpoint (`memop` 13 returns 0) asks this
+//! capsule to load its saved state, waits for the upcall, and then registers
+//! its persistent region again to get the state.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! struct Capability;
+//! unsafe impl capabilities::ProcessManagementCapability for Capability {}
+//!
+//! let process_checkpoint = static_init!(
+//!     capsules::process_checkpoint::ProcessCheckpoint<'static, Capability>,
+//!     capsules::process_checkpoint::ProcessCheckpoint::new(
+//!         nonvolatile_storage,                   // The underlying storage.
+//!         board_kernel.create_grant(&grant_cap), // Per-process upcalls.
+//!         0x1000,                                // Start of the slots.
+//!         256,                                   // Size of each slot.
+//!         8,                                     // Number of slots.
+//!         &mut capsules::process_checkpoint::BUFFER,
+//!         Capability
+//!     )
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(
+//!     nonvolatile_storage,
+//!     process_checkpoint
+//! );
+//! ```
+
+use core::cmp;
+use core::mem;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::ErrorCode;
+use kernel::{CommandReturn, Driver, Grant, ProcessId, Upcall};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessCheckpoint as usize;
+
+pub static mut BUFFER: [u8; 256] = [0; 256];
+
+/// Marks a slot that holds saved state.
+const SLOT_MAGIC: u32 = 0x5354_4b50;
+/// Length of the header at the start of each slot.
+const SLOT_HEADER_LEN: usize = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Save = 1,
+    Load = 2,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Upcall,
+}
+
+pub struct ProcessCheckpoint<'a, C: ProcessManagementCapability> {
+    // The underlying physical storage device.
+    storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'a>,
+    // Per-app state.
+    apps: Grant<App>,
+    // Buffer holding one slot while it is read or written.
+    buffer: TakeCell<'a, [u8]>,
+    // The process the storage is busy for, and what it is doing.
+    current: OptionalCell<(ProcessId, Operation)>,
+    // The address of the first slot in the storage.
+    start_address: usize,
+    // The size of each slot in bytes, including the header.
+    slot_size: usize,
+    // How many slots there are.
+    num_slots: usize,
+    capability: C,
+}
+
+impl<'a, C: ProcessManagementCapability> ProcessCheckpoint<'a, C> {
+    pub fn new(
+        storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'a>,
+        grant: Grant<App>,
+        start_address: usize,
+        slot_size: usize,
+        num_slots: usize,
+        buffer: &'a mut [u8],
+        capability: C,
+    ) -> ProcessCheckpoint<'a, C> {
+        ProcessCheckpoint {
+            storage: storage,
+            apps: grant,
+            buffer: TakeCell::new(buffer),
+            current: OptionalCell::empty(),
+            start_address: start_address,
+            slot_size: slot_size,
+            num_slots: num_slots,
+            capability: capability,
+        }
+    }
+
+    /// The address of the slot of `appid` and the number of bytes of state
+    /// the app keeps, if the app can save its state. The state and the slot
+    /// header always fit in a slot.
+    fn slot(&self, appid: ProcessId) -> Result<(usize, usize), ErrorCode> {
+        let persistent_ram = appid.get_persistent_ram().ok_or(ErrorCode::NOSUPPORT)?;
+        let slot = persistent_ram.flash_slot().ok_or(ErrorCode::NOSUPPORT)?;
+        if slot >= self.num_slots {
+            return Err(ErrorCode::INVAL);
+        }
+        let len = persistent_ram.size() as usize;
+        match SLOT_HEADER_LEN.checked_add(len) {
+            Some(slot_len) if slot_len <= self.slot_size => {}
+            _ => return Err(ErrorCode::SIZE),
+        }
+        Ok((self.start_address + slot * self.slot_size, len))
+    }
+
+    fn start(&self, appid: ProcessId, operation: Operation) -> Result<(), ErrorCode> {
+        if self.current.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let (address, len) = self.slot(appid)?;
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        if buffer.len() < SLOT_HEADER_LEN + len {
+            self.buffer.replace(buffer);
+            return Err(ErrorCode::SIZE);
+        }
+
+        let result = match operation {
+            Operation::Save => {
+                match appid.read_persistent_state(
+                    &mut buffer[SLOT_HEADER_LEN..SLOT_HEADER_LEN + len],
+                    &self.capability,
+                ) {
+                    Ok(saved_len) => {
+                        buffer[0..4].copy_from_slice(&SLOT_MAGIC.to_le_bytes());
+                        buffer[4..8].copy_from_slice(&(saved_len as u32).to_le_bytes());
+                        self.storage
+                            .write(buffer, address, SLOT_HEADER_LEN + saved_len)
+                    }
+                    Err(e) => {
+                        self.buffer.replace(buffer);
+                        Err(e)
+                    }
+                }
+            }
+            Operation::Load => self.storage.read(buffer, address, SLOT_HEADER_LEN + len),
+        };
+        if result.is_ok() {
+            self.current.set((appid, operation));
+        }
+        result
+    }
+
+    fn done(&self, appid: ProcessId, operation: Operation, result: Result<(), ErrorCode>) {
+        let _ = self.apps.enter(appid, |app| {
+            app.callback
+                .schedule(kernel::into_statuscode(result), operation as usize, 0);
+        });
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> hil::nonvolatile_storage::NonvolatileStorageClient<'a>
+    for ProcessCheckpoint<'a, C>
+{
+    fn read_done(&self, buffer: &'a mut [u8], length: usize) {
+        self.current.take().map(move |(appid, operation)| {
+            let magic = u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
+            let saved_len = u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
+            let result = if length < SLOT_HEADER_LEN || magic != SLOT_MAGIC {
+                // Nothing has been saved to this slot yet.
+                Err(ErrorCode::RESERVE)
+            } else {
+                let saved_len = cmp::min(saved_len as usize, length - SLOT_HEADER_LEN);
+                appid.write_persistent_state(
+                    &buffer[SLOT_HEADER_LEN..SLOT_HEADER_LEN + saved_len],
+                    &self.capability,
+                )
+            };
+            self.buffer.replace(buffer);
+            self.done(appid, operation, result);
+        });
+    }
+
+    fn write_done(&self, buffer: &'a mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+        self.current.take().map(|(appid, operation)| {
+            self.done(appid, operation, Ok(()));
+        });
+    }
+}
+
+/// Provide an interface for userland.
+impl<'a, C: ProcessManagementCapability> Driver for ProcessCheckpoint<'a, C> {
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Setup a callback for when a save or a load is done. The
+    ///   callback gets the status of the operation and which command
+    ///   started it.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        app_id: ProcessId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(app_id, |app| match subscribe_num {
+                0 => {
+                    mem::swap(&mut app.callback, &mut callback);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return Ok(()) if this driver is included on the platform.
+    /// - `1`: Save the process's checkpoint to its slot.
+    /// - `2`: Load the process's checkpoint from its slot. The process gets
+    ///   the loaded state when it next registers its persistent region.
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: ProcessId) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.start(appid, Operation::Save).into(),
+            2 => self.start(appid, Operation::Load).into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
@@ -5,13 +5,28 @@
 //! --------
 //!
 //! This module provides a simple text-based console to inspect and control
-//! which processes are running. The console has five commands:
+//! which processes are running. The console has these commands:
 //!  - 'help' prints the available commands and arguments
 //!  - 'status' prints the current system status
 //!  - 'list' lists the current processes with their IDs and running state
 //!  - 'stop n' stops the process with name n
 //!  - 'start n' starts the stopped process with name n
 //!  - 'fault n' forces the process with name n into a fault state
+//!  - 'terminate n' terminates the process with name n
+//!  - 'boot n' starts the terminated or faulted process with name n again
+//!  - 'restart n' terminates the process with name n and starts it again
+//!  - 'process n' prints the memory map of the process with name n
+//!  - 'kernel' prints the kernel's memory use and the grant use of processes
+//!  - 'trace' prints the oldest records of the kernel trace, if the board
+//!    set one, and removes them from the trace
+//!  - 'crash' prints the record of the last process fault or kernel panic,
+//!    which survives a reset if the board keeps one. 'crash clear' forgets it
+//!  - 'profile' prints where the CPU time went, if the board set a kernel
+//!    profile. 'profile clear' starts measuring the kernel over
+//!
+//! 'boot' and 'restart' start the process right away, whatever the restart
+//! policy of the board. If the board uses `kernel::procs::DependentRestart`,
+//! the processes that depend on the process restart with it.
 //!
 //! ### `list` Command Fields:
 //!
@@ -29,6 +44,41 @@
 //! - `Grants`: The number of grants that have been initialized for the process
 //!   out of the total number of grants defined by the kernel.
 //!
+//! Line editing
+//! ------------
+//!
+//! Backspace (or delete) removes the last character and Ctrl-U clears the
+//! line. The up and down arrow keys step through the last `HISTORY_LEN`
+//! commands. Tab completes the command name, or the name of the process a
+//! command takes, as far as it is unambiguous.
+//!
+//! Custom commands
+//! ---------------
+//!
+//! Capsules can add their own commands, which `help` lists and tab completes
+//! with the built-in ones. A command gets the rest of the line as its
+//! arguments and prints its output with `debug!()`. Built-in commands take
+//! precedence over custom commands with the same name.
+//!
+//! ```rust
+//! # use capsules::process_console::{ConsoleCommand, ConsoleCommandClient};
+//! # use kernel::{debug, static_init};
+//!
+//! struct Radio;
+//! impl ConsoleCommandClient for Radio {
+//!     fn execute(&self, args: &str) {
+//!         debug!("Radio channel {}", args);
+//!     }
+//! }
+//!
+//! let radio = static_init!(Radio, Radio);
+//! let command = static_init!(
+//!     ConsoleCommand<'static>,
+//!     ConsoleCommand::new("radio", "radio c: switch to channel c", radio)
+//! );
+//! pconsole.add_command(command);
+//! ```
+//!
 //! Setup
 //! -----
 //!
@@ -59,10 +109,15 @@
 //!
 //! Buffer use and output
 //! ---------------------
-//! `ProcessConsole` does not use its own write buffer for output:
+//! `ProcessConsole` mostly does not use its own write buffer for output:
 //! it uses the debug!() buffer, so as not to repeat all of its buffering and
 //! to maintain a correct ordering with debug!() calls. The write buffer of
-//! `ProcessConsole` is used solely for echoing what someone types.
+//! `ProcessConsole` is used for echoing what someone types, and for the
+//! memory map the `process` command prints, which is too long for the
+//! debug!() buffer. It is sent in pieces of the size of the write buffer.
+//!
+//! Boards set where the kernel's memory regions are with
+//! `set_kernel_addresses()`, for the `kernel` command to print them.
 //!
 //! Using ProcessConsole
 //! --------------------
@@ -110,27 +165,401 @@
 //! stop blink
 //! Process blink stopped
 //! ```
+//!
+//! `terminate`, `boot` and `restart` control the lifecycle of a process.
+//! A terminated process stays in memory, and `boot` starts it from the
+//! beginning:
+//!
+//! ```text
+//! terminate blink
+//! Process blink terminated
+//! boot blink
+//! Process blink booted
+//! ```
+//!
+//! The console reports when a process fails to restart. A process whose
+//! buffers are pinned for DMA restarts once they are unpinned instead.
+//!
+//! `process` prints the memory map of a process, with its grant, heap, data
+//! and stack regions and its flash. `kernel` prints how much memory the kernel
+//! uses, and how much of the grant region each process uses:
+//!
+//! ```text
+//! kernel
+//! Kernel memory:
+//!  Code   0x00010000-0x0002d3c8  119752 bytes
+//!  Stack  0x20000000-0x20002000    8192 bytes
+//!  Data   0x20002000-0x20002090     144 bytes
+//!  BSS    0x20002090-0x20007a60   23504 bytes
+//!  Apps   0x20008000-0x20040000   20480 of 229376 bytes used
+//!  PID    Name                Grants  Grant bytes
+//!   0x0   blink                 1/12          108
+//!   0x1   c_hello               3/12          336
+//! ```
+//!
+//! The `trace` command prints up to `TRACE_RECORDS_PER_COMMAND` records of
+//! the kernel trace as hex, one per line, and how many records are left.
+//! Repeat it to print the rest. `tools/trace_decoder` turns the output back
+//! into text:
+//!
+//! ```text
+//! trace
+//! trace 1a2b00000102000001000000000000000000000000000000
+//! Trace records remaining: 0, overwritten: 0
+//! ```
+//!
+//! After a crash, the `crash` command shows what happened even if nobody was
+//! watching the console at the time:
+//!
+//! ```text
+//! crash
+//! Process blink faulted (kernel panicked), 0 restarts
+//!  Flash 0x00030000-0x00032000 RAM 0x20004000-0x20006000
+//!  App break 0x20005000 Grants 0x20005c00 SP 0x20004f80
+//!  Registers:
+//!   0x00000000 0x20004fa0 0x00000001 0x00000000
+//!   ...
+//!  Stack:
+//!   00000000a04f00200100000000000000
+//!   ...
+//! ```
+//!
+//! The `profile` command prints, in ticks of the board's profile counter, how
+//! long each process has run since it started, how long the kernel spent in
+//! interrupt bottom halves and deferred calls, and how long the system calls
+//! to each driver took. Each driver's histogram lists how many calls took at
+//! least the given number of ticks, up to the next bucket:
+//!
+//! ```text
+//! profile
+//! Profile counter: 16000000 Hz
+//!  blink                       182443 ticks
+//!  c_hello                      10322 ticks
+//! Interrupts: 90211 ticks, deferred calls: 4410 ticks
+//! Driver      Calls     Mean      Max
+//!  0x00000      113      912     2301
+//!   >=512:97 >=1024:14 >=2048:2
+//! ```
 
 use core::cell::Cell;
 use core::cmp;
+use core::fmt;
 use core::str;
 use kernel::capabilities::ProcessManagementCapability;
-use kernel::common::cells::TakeCell;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::{List, ListLink, ListNode};
+use kernel::crash_dump::{self, CrashKind};
 use kernel::debug;
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
+use kernel::procs::{Process, State};
+use kernel::profile::{self, LatencyHistogram};
+use kernel::trace;
 use kernel::ErrorCode;
 use kernel::Kernel;
+use kernel::ProcessId;
+
+/// Length of the command buffer, and of the commands kept in the history.
+const COMMAND_LEN: usize = 32;
 
-// Since writes are character echoes, we do not need more than 4 bytes:
-// the longest write is 3 bytes for a backspace (backspace, space, backspace).
-pub static mut WRITE_BUF: [u8; 4] = [0; 4];
+/// How many commands the history keeps.
+pub const HISTORY_LEN: usize = 4;
+
+// Writes are mostly character echoes, but recalling a command from the
+// history rewrites the whole line: erasing it takes 4 bytes, followed by the
+// command. The memory map of the `process` command is sent in pieces of
+// this size.
+pub static mut WRITE_BUF: [u8; 64] = [0; 64];
 // Since reads are byte-by-byte, to properly echo what's typed,
 // we can use a very small read buffer.
 pub static mut READ_BUF: [u8; 4] = [0; 4];
-// Commands can be up to 32 bytes long: since commands themselves are 4-5
-// characters, limiting arguments to 25 bytes or so seems fine for now.
-pub static mut COMMAND_BUF: [u8; 32] = [0; 32];
+// Commands can be up to 32 bytes long: since commands themselves are 4-9
+// characters, limiting arguments to 22 bytes or so seems fine for now.
+pub static mut COMMAND_BUF: [u8; COMMAND_LEN] = [0; COMMAND_LEN];
+
+/// The built-in commands.
+const COMMANDS: [&str; 14] = [
+    "help",
+    "status",
+    "list",
+    "stop",
+    "start",
+    "fault",
+    "terminate",
+    "boot",
+    "restart",
+    "process",
+    "kernel",
+    "trace",
+    "crash",
+    "profile",
+];
+
+/// The built-in commands that take a process name.
+const PROCESS_COMMANDS: [&str; 7] = [
+    "stop",
+    "start",
+    "fault",
+    "terminate",
+    "boot",
+    "restart",
+    "process",
+];
+
+/// Moves the cursor to the start of the line and erases the line.
+const ERASE_LINE: &[u8] = b"\r\x1b[K";
+
+/// How many trace records the `trace` command prints at most, so that it does
+/// not overflow the debug buffer.
+const TRACE_RECORDS_PER_COMMAND: usize = 16;
+
+/// Formats bytes, such as a trace record, as hex.
+struct HexRecord<'a>(&'a [u8]);
+
+impl fmt::Display for HexRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats words as hex, separated by spaces.
+struct HexWords<'a>(&'a [u32]);
+
+impl fmt::Display for HexWords<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, word) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:#010x}", word)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the buckets of a latency histogram that counted calls.
+struct Buckets<'a>(&'a LatencyHistogram);
+
+impl fmt::Display for Buckets<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for (bucket, count) in self.0.buckets.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            if !first {
+                write!(f, " ")?;
+            }
+            first = false;
+            write!(f, ">={}:{}", LatencyHistogram::bucket_start(bucket), count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the part of some output that starts `skip` bytes in into a
+/// buffer, so that output longer than the buffer can be sent in pieces.
+struct WindowWriter<'a> {
+    skip: usize,
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for WindowWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let bytes = s.as_bytes();
+        let skipped = cmp::min(self.skip, bytes.len());
+        self.skip -= skipped;
+        let bytes = &bytes[skipped..];
+        let len = cmp::min(bytes.len(), self.buffer.len() - self.len);
+        self.buffer[self.len..self.len + len].copy_from_slice(&bytes[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// Where the kernel's memory regions are, as the linker placed them. Each
+/// region is from its start address up to, but not including, its end
+/// address.
+#[derive(Clone, Copy)]
+pub struct KernelAddresses {
+    /// Kernel code and read-only data in flash.
+    pub text_start: *const u8,
+    pub text_end: *const u8,
+    /// The kernel stack.
+    pub stack_start: *const u8,
+    pub stack_end: *const u8,
+    /// Initialized kernel data, relocated from flash to RAM.
+    pub relocate_start: *const u8,
+    pub relocate_end: *const u8,
+    /// Zero-initialized kernel data.
+    pub bss_start: *const u8,
+    pub bss_end: *const u8,
+    /// RAM set aside for processes.
+    pub app_memory_start: *const u8,
+    pub app_memory_end: *const u8,
+}
+
+/// Runs a custom command of the process console.
+pub trait ConsoleCommandClient {
+    /// Run the command. `args` is the rest of the line after the name of the
+    /// command, without leading or trailing whitespace.
+    fn execute(&self, args: &str);
+}
+
+/// A custom command, added with `ProcessConsole::add_command()`.
+pub struct ConsoleCommand<'a> {
+    name: &'static str,
+    help: &'static str,
+    client: &'a dyn ConsoleCommandClient,
+    next: ListLink<'a, ConsoleCommand<'a>>,
+}
+
+impl<'a> ConsoleCommand<'a> {
+    /// `name` is what someone types to run the command. `help` is the line
+    /// `help` prints for it.
+    pub fn new(
+        name: &'static str,
+        help: &'static str,
+        client: &'a dyn ConsoleCommandClient,
+    ) -> ConsoleCommand<'a> {
+        ConsoleCommand {
+            name: name,
+            help: help,
+            client: client,
+            next: ListLink::empty(),
+        }
+    }
+}
+
+impl<'a> ListNode<'a, ConsoleCommand<'a>> for ConsoleCommand<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, ConsoleCommand<'a>> {
+        &self.next
+    }
+}
+
+/// Formats the names of the built-in and custom commands.
+struct CommandNames<'a, 'b>(&'b List<'a, ConsoleCommand<'a>>);
+
+impl fmt::Display for CommandNames<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", COMMANDS[0])?;
+        for name in COMMANDS[1..].iter() {
+            write!(f, " {}", name)?;
+        }
+        for command in self.0.iter() {
+            write!(f, " {}", command.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The last `HISTORY_LEN` commands that were run.
+struct CommandHistory {
+    commands: [[u8; COMMAND_LEN]; HISTORY_LEN],
+    lens: [usize; HISTORY_LEN],
+    /// Where the next command goes.
+    next: usize,
+    len: usize,
+}
+
+impl CommandHistory {
+    const fn new() -> CommandHistory {
+        CommandHistory {
+            commands: [[0; COMMAND_LEN]; HISTORY_LEN],
+            lens: [0; HISTORY_LEN],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// Keep `command`, unless it is empty, too long, or the same as the last
+    /// command.
+    fn push(&mut self, command: &[u8]) {
+        if command.is_empty() || command.len() > COMMAND_LEN || self.get(0) == Some(command) {
+            return;
+        }
+        self.commands[self.next][..command.len()].copy_from_slice(command);
+        self.lens[self.next] = command.len();
+        self.next = (self.next + 1) % HISTORY_LEN;
+        self.len = cmp::min(self.len + 1, HISTORY_LEN);
+    }
+
+    /// The command run `age` commands before the last one.
+    fn get(&self, age: usize) -> Option<&[u8]> {
+        if age >= self.len {
+            return None;
+        }
+        let index = (self.next + HISTORY_LEN - 1 - age) % HISTORY_LEN;
+        Some(&self.commands[index][..self.lens[index]])
+    }
+}
+
+/// Finds the longest completion of a word that all candidates agree on.
+struct Completion<'w> {
+    word: &'w str,
+    first: Cell<Option<&'static str>>,
+    /// How much of `first` all matching candidates share.
+    common: Cell<usize>,
+    matches: Cell<usize>,
+}
+
+impl<'w> Completion<'w> {
+    fn new(word: &'w str) -> Completion<'w> {
+        Completion {
+            word: word,
+            first: Cell::new(None),
+            common: Cell::new(0),
+            matches: Cell::new(0),
+        }
+    }
+
+    fn add(&self, candidate: &'static str) {
+        if !candidate.starts_with(self.word) {
+            return;
+        }
+        self.matches.set(self.matches.get() + 1);
+        match self.first.get() {
+            None => {
+                self.first.set(Some(candidate));
+                self.common.set(candidate.len());
+            }
+            Some(first) => {
+                let mut common = first
+                    .bytes()
+                    .zip(candidate.bytes())
+                    .take(self.common.get())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                while !first.is_char_boundary(common) {
+                    common -= 1;
+                }
+                self.common.set(common);
+            }
+        }
+    }
+
+    /// What to add to the word, and whether the completion is unambiguous.
+    fn suffix(&self) -> Option<(&'static str, bool)> {
+        self.first.get().map(|first| {
+            (
+                &first[self.word.len()..self.common.get()],
+                self.matches.get() == 1,
+            )
+        })
+    }
+}
+
+/// How far into an escape sequence, such as an arrow key, the input is.
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Started,
+    ControlSequence,
+}
 
 pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     uart: &'a dyn uart::UartData<'a>,
@@ -148,6 +577,20 @@ pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     /// Internal flag that the process console should parse the command it just
     /// received after finishing echoing the last newline character.
     execute: Cell<bool>,
+
+    /// Commands that were run, and which one the command buffer holds if
+    /// someone is stepping through them.
+    history: MapCell<CommandHistory>,
+    history_position: Cell<Option<usize>>,
+    escape: Cell<Escape>,
+
+    /// Commands that capsules added.
+    commands: List<'a, ConsoleCommand<'a>>,
+
+    /// The process whose memory map is being sent, and how much of it was
+    /// sent.
+    memory_map: OptionalCell<(ProcessId, usize)>,
+    kernel_addresses: OptionalCell<KernelAddresses>,
     kernel: &'static Kernel,
     capability: C,
 }
@@ -171,11 +614,28 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             command_index: Cell::new(0),
             running: Cell::new(false),
             execute: Cell::new(false),
+            history: MapCell::new(CommandHistory::new()),
+            history_position: Cell::new(None),
+            escape: Cell::new(Escape::None),
+            commands: List::new(),
+            memory_map: OptionalCell::empty(),
+            kernel_addresses: OptionalCell::empty(),
             kernel: kernel,
             capability: capability,
         }
     }
 
+    /// Add a custom command.
+    pub fn add_command(&self, command: &'a ConsoleCommand<'a>) {
+        self.commands.push_tail(command);
+    }
+
+    /// Tell the console where the kernel's memory regions are, for the
+    /// `kernel` command.
+    pub fn set_kernel_addresses(&self, addresses: KernelAddresses) {
+        self.kernel_addresses.set(addresses);
+    }
+
     pub fn start(&self) -> Result<(), ErrorCode> {
         if self.running.get() == false {
             self.rx_buffer.take().map(|buffer| {
@@ -191,117 +651,401 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
     // Process the command in the command buffer and clear the buffer.
     fn read_command(&self) {
         self.command_buffer.map(|command| {
-            let mut terminator = 0;
-            let len = command.len();
-            for i in 0..len {
-                if command[i] == 0 {
-                    terminator = i;
-                    break;
+            let len = self.command_index.get();
+            self.history.map(|history| history.push(&command[..len]));
+            match str::from_utf8(&command[..len]) {
+                Ok(s) => self.run_command(s.trim()),
+                Err(_e) => debug!("Invalid command: {:?}", &command[..len]),
+            }
+        });
+        self.command_buffer.map(|command| {
+            command[0] = 0;
+        });
+        self.command_index.set(0);
+        self.history_position.set(None);
+    }
+
+    fn run_command(&self, clean_str: &str) {
+        let mut words = clean_str.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return,
+        };
+        let argument = words.next();
+        match name {
+            "help" => {
+                debug!("Welcome to the process console.");
+                debug!("Valid commands are: {}", CommandNames(&self.commands));
+                for command in self.commands.iter() {
+                    debug!(" {}", command.help);
                 }
             }
-            //debug!("Command: {}-{} {:?}", start, terminator, command);
-            // A command is valid only if it starts inside the buffer,
-            // ends before the beginning of the buffer, and ends after
-            // it starts.
-            if terminator > 0 {
-                let cmd_str = str::from_utf8(&command[0..terminator]);
-                match cmd_str {
-                    Ok(s) => {
-                        let clean_str = s.trim();
-                        if clean_str.starts_with("help") {
-                            debug!("Welcome to the process console.");
-                            debug!("Valid commands are: help status list stop start fault");
-                        } else if clean_str.starts_with("start") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.resume();
-                                            debug!("Process {} resumed.", name);
-                                        }
-                                    },
-                                );
-                            });
-                        } else if clean_str.starts_with("stop") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.stop();
-                                            debug!("Process {} stopped", proc_name);
-                                        }
-                                    },
-                                );
-                            });
-                        } else if clean_str.starts_with("fault") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.set_fault_state();
-                                            debug!("Process {} now faulted", proc_name);
-                                        }
-                                    },
-                                );
-                            });
-                        } else if clean_str.starts_with("list") {
-                            debug!(" PID    Name                Quanta  Syscalls  Dropped Upcalls  Restarts    State  Grants");
-                            self.kernel
-                                .process_each_capability(&self.capability, |proc| {
-                                    let info: KernelInfo = KernelInfo::new(self.kernel);
-
-                                    let pname = proc.get_process_name();
-                                    let appid = proc.processid();
-                                    let (grants_used, grants_total) = info.number_app_grant_uses(appid, &self.capability);
-
-                                    debug!(
-                                        "  {:?}\t{:<20}{:6}{:10}{:17}{:10}  {:?}{:5}/{}",
-                                        appid,
-                                        pname,
-                                        proc.debug_timeslice_expiration_count(),
-                                        proc.debug_syscall_count(),
-                                        proc.debug_dropped_upcall_count(),
-                                        proc.get_restart_count(),
-                                        proc.get_state(),
-                                        grants_used,
-                                        grants_total
-                                    );
-                                });
-                        } else if clean_str.starts_with("status") {
-                            let info: KernelInfo = KernelInfo::new(self.kernel);
-                            debug!(
-                                "Total processes: {}",
-                                info.number_loaded_processes(&self.capability)
-                            );
-                            debug!(
-                                "Active processes: {}",
-                                info.number_active_processes(&self.capability)
-                            );
-                            debug!(
-                                "Timeslice expirations: {}",
-                                info.timeslice_expirations(&self.capability)
-                            );
-                        } else {
-                            debug!("Valid commands are: help status list stop start fault");
-                        }
+            "start" => self.with_process(argument, |proc| {
+                proc.resume();
+                debug!("Process {} resumed.", proc.get_process_name());
+            }),
+            "stop" => self.with_process(argument, |proc| {
+                proc.stop();
+                debug!("Process {} stopped", proc.get_process_name());
+            }),
+            "fault" => self.with_process(argument, |proc| {
+                proc.set_fault_state();
+                debug!("Process {} now faulted", proc.get_process_name());
+            }),
+            "terminate" => self.with_process(argument, |proc| match proc.get_state() {
+                State::Terminated => {
+                    debug!("Process {} is already terminated", proc.get_process_name())
+                }
+                _ => {
+                    proc.terminate(0);
+                    debug!("Process {} terminated", proc.get_process_name());
+                }
+            }),
+            "boot" => self.with_process(argument, |proc| match proc.get_state() {
+                State::Terminated | State::Faulted => self.restart_process(proc, "booted"),
+                State::Unstarted => debug!(
+                    "Process {} has not started yet, it starts when it is scheduled",
+                    proc.get_process_name()
+                ),
+                _ => debug!(
+                    "Process {} is running, use restart instead",
+                    proc.get_process_name()
+                ),
+            }),
+            "restart" => {
+                self.with_process(argument, |proc| self.restart_process(proc, "restarted"))
+            }
+            "process" => self.with_process(argument, |proc| {
+                self.memory_map.set((proc.processid(), 0));
+            }),
+            "list" => {
+                debug!(" PID    Name                Quanta  Syscalls  Dropped Upcalls  Restarts    State  Grants");
+                self.kernel
+                    .process_each_capability(&self.capability, |proc| {
+                        let info: KernelInfo = KernelInfo::new(self.kernel);
+
+                        let pname = proc.get_process_name();
+                        let appid = proc.processid();
+                        let (grants_used, grants_total) =
+                            info.number_app_grant_uses(appid, &self.capability);
+
+                        debug!(
+                            "  {:?}\t{:<20}{:6}{:10}{:17}{:10}  {:?}{:5}/{}",
+                            appid,
+                            pname,
+                            proc.debug_timeslice_expiration_count(),
+                            proc.debug_syscall_count(),
+                            proc.debug_dropped_upcall_count(),
+                            proc.get_restart_count(),
+                            proc.get_state(),
+                            grants_used,
+                            grants_total
+                        );
+                    });
+            }
+            "status" => {
+                let info: KernelInfo = KernelInfo::new(self.kernel);
+                debug!(
+                    "Total processes: {}",
+                    info.number_loaded_processes(&self.capability)
+                );
+                debug!(
+                    "Active processes: {}",
+                    info.number_active_processes(&self.capability)
+                );
+                debug!(
+                    "Timeslice expirations: {}",
+                    info.timeslice_expirations(&self.capability)
+                );
+            }
+            "kernel" => self.print_kernel(),
+            "trace" => self.print_trace(),
+            "crash" => self.print_crash(argument == Some("clear")),
+            "profile" => self.print_profile(argument == Some("clear")),
+            _ => match self.commands.iter().find(|command| command.name == name) {
+                Some(command) => command.client.execute(clean_str[name.len()..].trim()),
+                None => debug!("Valid commands are: {}", CommandNames(&self.commands)),
+            },
+        }
+
+        // The memory map is longer than the debug buffer, so it is sent
+        // directly.
+        self.send_memory_map();
+    }
+
+    /// Restart `proc` and report whether it was `done`. A process that is
+    /// still terminated afterwards either did not restart, or waits for
+    /// hardware to release its pinned buffers first.
+    fn restart_process(&self, proc: &dyn Process, done: &str) {
+        proc.try_restart(0);
+        match proc.get_state() {
+            State::Terminated if proc.pinned_buffer_count() > 0 => debug!(
+                "Process {} restarts once its DMA buffers are unpinned",
+                proc.get_process_name()
+            ),
+            State::Terminated => debug!("Process {} failed to restart", proc.get_process_name()),
+            _ => debug!("Process {} {}", proc.get_process_name(), done),
+        }
+    }
+
+    /// Run `f` on the process named `name`.
+    fn with_process<F: Fn(&dyn Process)>(&self, name: Option<&str>, f: F) {
+        let name = match name {
+            Some(name) => name,
+            None => {
+                debug!("Which process?");
+                return;
+            }
+        };
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                if proc.get_process_name() == name {
+                    found.set(true);
+                    f(proc);
+                }
+            });
+        if !found.get() {
+            debug!("No process named {}", name);
+        }
+    }
+
+    /// Send the next piece of the memory map of the process the `process`
+    /// command is printing, unless the last piece is still being sent.
+    ///
+    /// The memory map is formatted again for every piece, so if the process
+    /// changes in between the pieces may not match up.
+    fn send_memory_map(&self) {
+        if self.tx_in_progress.get() {
+            return;
+        }
+        let (processid, sent) = match self.memory_map.take() {
+            Some(progress) => progress,
+            None => return,
+        };
+        let len = Cell::new(0);
+        let buffer_len = Cell::new(0);
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                if proc.processid() == processid {
+                    self.tx_buffer.map(|buffer| {
+                        let mut writer = WindowWriter {
+                            skip: sent,
+                            buffer: buffer,
+                            len: 0,
+                        };
+                        proc.print_memory_map(&mut writer);
+                        len.set(writer.len);
+                        buffer_len.set(writer.buffer.len());
+                    });
+                }
+            });
+        // The process is gone, or all of its memory map was sent.
+        if len.get() == 0 {
+            return;
+        }
+        // There may be more to send if this piece fills the buffer.
+        if len.get() == buffer_len.get() {
+            self.memory_map.set((processid, sent + len.get()));
+        }
+        self.tx_buffer.take().map(|buffer| {
+            self.tx_in_progress.set(true);
+            if let Err((_e, buffer)) = self.uart.transmit_buffer(buffer, len.get()) {
+                self.tx_buffer.replace(buffer);
+                self.tx_in_progress.set(false);
+                self.memory_map.clear();
+            }
+        });
+    }
+
+    /// Print where the kernel's memory is, and how much of the grant region
+    /// of each process is in use.
+    fn print_kernel(&self) {
+        let region = |name: &str, start: *const u8, end: *const u8| {
+            debug!(
+                " {:<6} {:#010x}-{:#010x} {:7} bytes",
+                name,
+                start as usize,
+                end as usize,
+                end as usize - start as usize
+            );
+        };
+        match self.kernel_addresses.extract() {
+            Some(addresses) => {
+                let app_memory_used = Cell::new(0);
+                self.kernel
+                    .process_each_capability(&self.capability, |proc| {
+                        app_memory_used.set(
+                            app_memory_used.get() + proc.mem_end() as usize
+                                - proc.mem_start() as usize,
+                        );
+                    });
+                debug!("Kernel memory:");
+                region("Code", addresses.text_start, addresses.text_end);
+                region("Stack", addresses.stack_start, addresses.stack_end);
+                region("Data", addresses.relocate_start, addresses.relocate_end);
+                region("BSS", addresses.bss_start, addresses.bss_end);
+                debug!(
+                    " Apps   {:#010x}-{:#010x} {:7} of {} bytes used",
+                    addresses.app_memory_start as usize,
+                    addresses.app_memory_end as usize,
+                    app_memory_used.get(),
+                    addresses.app_memory_end as usize - addresses.app_memory_start as usize
+                );
+            }
+            None => debug!("Kernel memory: unknown"),
+        }
+
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        debug!(" PID    Name                Grants  Grant bytes");
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                let appid = proc.processid();
+                let (grants_used, total) = info.number_app_grant_uses(appid, &self.capability);
+                let grant_bytes: usize = (0..total)
+                    .map(|grant_num| info.app_grant_bytes(appid, grant_num, &self.capability))
+                    .sum();
+                debug!(
+                    "  {:?}\t{:<20}{:3}/{:<3}{:12}",
+                    appid,
+                    proc.get_process_name(),
+                    grants_used,
+                    total,
+                    grant_bytes
+                );
+            });
+    }
+
+    /// Print and remove the oldest records of the kernel trace.
+    fn print_trace(&self) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        match info.kernel_trace(&self.capability) {
+            Some(trace) => {
+                let mut record = [0; trace::RECORD_LEN];
+                for _ in 0..TRACE_RECORDS_PER_COMMAND {
+                    if trace.drain(&mut record) == 0 {
+                        break;
+                    }
+                    debug!("trace {}", HexRecord(&record));
+                }
+                debug!(
+                    "Trace records remaining: {}, overwritten: {}",
+                    trace.len(),
+                    trace.overwritten()
+                );
+            }
+            None => debug!("No kernel trace"),
+        }
+    }
+
+    /// Print the record of the last crash, and forget it if `clear`.
+    fn print_crash(&self, clear: bool) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        let crash_dump = match info.crash_dump(&self.capability) {
+            Some(crash_dump) => crash_dump,
+            None => {
+                debug!("No crash dump");
+                return;
+            }
+        };
+        if clear {
+            crash_dump.clear();
+            debug!("Crash record cleared");
+            return;
+        }
+        let found = crash_dump.map_record(|record| match record.kind() {
+            CrashKind::KernelPanic => debug!("Kernel panic: {}", record.message()),
+            CrashKind::ProcessFault => {
+                let map = record.memory_map();
+                debug!(
+                    "Process {} faulted{}, {} restarts",
+                    record.process_name(),
+                    if record.kernel_panicked() {
+                        " (kernel panicked)"
+                    } else {
+                        ""
+                    },
+                    record.restart_count()
+                );
+                debug!(
+                    " Flash {:#010x}-{:#010x} RAM {:#010x}-{:#010x}",
+                    map.flash_start, map.flash_end, map.mem_start, map.mem_end
+                );
+                debug!(
+                    " App break {:#010x} Grants {:#010x} SP {:#010x}",
+                    map.app_break, map.kernel_memory_break, map.stack_pointer
+                );
+                let mut registers = [0; crash_dump::MAX_REGISTERS];
+                let mut count = 0;
+                for (register, value) in registers.iter_mut().zip(record.registers()) {
+                    *register = value;
+                    count += 1;
+                }
+                debug!(" Registers:");
+                for line in registers[..count].chunks(4) {
+                    debug!("  {}", HexWords(line));
+                }
+                if !record.stack().is_empty() {
+                    debug!(" Stack:");
+                    for line in record.stack().chunks(16) {
+                        debug!("  {}", HexRecord(line));
                     }
-                    Err(_e) => debug!("Invalid command: {:?}", command),
                 }
             }
         });
-        self.command_buffer.map(|command| {
-            command[0] = 0;
+        if found.is_none() {
+            debug!("No crash recorded");
+        }
+    }
+
+    /// Print where the CPU time went, or forget the kernel's measurements if
+    /// `clear`.
+    fn print_profile(&self, clear: bool) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        let kernel_profile = match info.kernel_profile(&self.capability) {
+            Some(kernel_profile) => kernel_profile,
+            None => {
+                debug!("No kernel profile");
+                return;
+            }
+        };
+        if clear {
+            kernel_profile.clear();
+            debug!("Kernel profile cleared");
+            return;
+        }
+        debug!("Profile counter: {} Hz", kernel_profile.frequency());
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                debug!(
+                    " {:<20}{:14} ticks",
+                    proc.get_process_name(),
+                    info.app_cpu_ticks(proc.processid(), &self.capability)
+                );
+            });
+        debug!(
+            "Interrupts: {} ticks, deferred calls: {} ticks",
+            kernel_profile.interrupt_ticks(),
+            kernel_profile.deferred_call_ticks()
+        );
+        debug!("Driver      Calls     Mean      Max");
+        kernel_profile.each_syscall_latency(|driver_num, latency| {
+            debug!(
+                " {:#07x}{:9}{:9}{:9}",
+                driver_num,
+                latency.count,
+                latency.mean_ticks(),
+                latency.max_ticks
+            );
+            debug!("  {}", Buckets(latency));
         });
-        self.command_index.set(0);
+        if kernel_profile.untracked_syscalls() > 0 {
+            debug!(
+                "Syscalls to drivers beyond the first {}: {}",
+                profile::MAX_PROFILED_DRIVERS,
+                kernel_profile.untracked_syscalls()
+            );
+        }
     }
 
     fn write_byte(&self, byte: u8) -> Result<(), ErrorCode> {
@@ -331,6 +1075,147 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             Ok(())
         }
     }
+
+    /// Erase the line and write `line` instead.
+    fn rewrite_line(&self, line: &[u8]) -> Result<(), ErrorCode> {
+        if self.tx_in_progress.get() {
+            Err(ErrorCode::BUSY)
+        } else {
+            self.tx_in_progress.set(true);
+            self.tx_buffer.take().map(|buffer| {
+                let len = cmp::min(ERASE_LINE.len() + line.len(), buffer.len());
+                buffer[..ERASE_LINE.len()].copy_from_slice(ERASE_LINE);
+                buffer[ERASE_LINE.len()..len].copy_from_slice(&line[..len - ERASE_LINE.len()]);
+                let _ = self.uart.transmit_buffer(buffer, len);
+            });
+            Ok(())
+        }
+    }
+
+    /// Replace the line with an older command from the history if `older`,
+    /// or else with a newer one. Going newer than the last command clears
+    /// the line.
+    fn recall_history(&self, older: bool) {
+        self.command_buffer.map(|command| {
+            self.history.map(|history| {
+                let position = match (self.history_position.get(), older) {
+                    (None, true) => 0,
+                    (Some(position), true) => position + 1,
+                    (None, false) | (Some(0), false) => {
+                        self.history_position.set(None);
+                        self.command_index.set(0);
+                        command[0] = 0;
+                        let _ = self.rewrite_line(&[]);
+                        return;
+                    }
+                    (Some(position), false) => position - 1,
+                };
+                if let Some(entry) = history.get(position) {
+                    let len = cmp::min(entry.len(), command.len() - 1);
+                    command[..len].copy_from_slice(&entry[..len]);
+                    command[len] = 0;
+                    self.command_index.set(len);
+                    self.history_position.set(Some(position));
+                    let _ = self.rewrite_line(&command[..len]);
+                }
+            });
+        });
+    }
+
+    /// Complete the last word of the line: the name of a command, or of the
+    /// process a command takes.
+    fn complete(&self) {
+        self.command_buffer.map(|command| {
+            let index = self.command_index.get();
+            let line = match str::from_utf8(&command[..index]) {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            let word_start = line.rfind(' ').map_or(0, |space| space + 1);
+            let completion = Completion::new(&line[word_start..]);
+            let mut words = line[..word_start].split_whitespace();
+            match (words.next(), words.next()) {
+                (None, _) => {
+                    for name in COMMANDS.iter() {
+                        completion.add(name);
+                    }
+                    for custom in self.commands.iter() {
+                        completion.add(custom.name);
+                    }
+                }
+                (Some(name), None) if PROCESS_COMMANDS.contains(&name) => {
+                    self.kernel
+                        .process_each_capability(&self.capability, |proc| {
+                            completion.add(proc.get_process_name());
+                        });
+                }
+                _ => {}
+            }
+            if let Some((suffix, unambiguous)) = completion.suffix() {
+                let space = if unambiguous { 1 } else { 0 };
+                // Leave room for the terminating 0.
+                if index + suffix.len() + space < command.len() {
+                    command[index..index + suffix.len()].copy_from_slice(suffix.as_bytes());
+                    let mut end = index + suffix.len();
+                    if unambiguous {
+                        command[end] = b' ';
+                        end += 1;
+                    }
+                    command[end] = 0;
+                    self.command_index.set(end);
+                    let _ = self.write_bytes(&command[index..end]);
+                }
+            }
+        });
+    }
+
+    /// Edit the command with a byte someone typed.
+    fn edit_command(&self, byte: u8) {
+        // Completing uses the command buffer itself.
+        if byte == b'\t' {
+            self.complete();
+            return;
+        }
+        self.command_buffer.map(|command| {
+            let index = self.command_index.get();
+            match byte {
+                b'\n' | b'\r' => {
+                    self.execute.set(true);
+                    let _ = self.write_bytes(&[b'\r', b'\n']);
+                }
+                // Backspace or delete.
+                b'\x08' | b'\x7f' => {
+                    if index > 0 {
+                        // Echo and remove last byte
+                        // Note echo is '\b \b' to erase
+                        let _ = self.write_bytes(&[b'\x08', b' ', b'\x08']);
+                        command[index - 1] = 0;
+                        self.command_index.set(index - 1);
+                    }
+                }
+                // Ctrl-U
+                b'\x15' => {
+                    command[0] = 0;
+                    self.command_index.set(0);
+                    let _ = self.rewrite_line(&[]);
+                }
+                b'\x1b' => self.escape.set(Escape::Started),
+                // Reads sometimes return bytes > 127 but no error, which
+                // breaks utf-8 decoding, so only printable characters are
+                // kept.
+                b' '..=b'~' => {
+                    if index < (command.len() - 1) {
+                        // Echo the byte and store it
+                        let _ = self.write_byte(byte);
+                        command[index] = byte;
+                        self.command_index.set(index + 1);
+                        command[index + 1] = 0;
+                    }
+                }
+                _ => {}
+            }
+        });
+    }
 }
 
 impl<'a, C: ProcessManagementCapability> uart::TransmitClient for ProcessConsole<'a, C> {
@@ -348,6 +1233,8 @@ impl<'a, C: ProcessManagementCapability> uart::TransmitClient for ProcessConsole
         if self.execute.get() {
             self.execute.set(false);
             self.read_command();
+        } else {
+            self.send_memory_map();
         }
     }
 }
@@ -362,30 +1249,25 @@ impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<
         if error == uart::Error::None {
             match rx_len {
                 0 => debug!("ProcessConsole had read of 0 bytes"),
-                1 => {
-                    self.command_buffer.map(|command| {
-                        let index = self.command_index.get() as usize;
-                        if read_buf[0] == ('\n' as u8) || read_buf[0] == ('\r' as u8) {
-                            self.execute.set(true);
-                            let _ = self.write_bytes(&['\r' as u8, '\n' as u8]);
-                        } else if read_buf[0] == ('\x08' as u8) && index > 0 {
-                            // Backspace, echo and remove last byte
-                            // Note echo is '\b \b' to erase
-                            let _ = self.write_bytes(&['\x08' as u8, ' ' as u8, '\x08' as u8]);
-                            command[index - 1] = '\0' as u8;
-                            self.command_index.set(index - 1);
-                        } else if index < (command.len() - 1) && read_buf[0] < 128 {
-                            // For some reason, sometimes reads return > 127 but no error,
-                            // which causes utf-8 decoding failure, so check byte is < 128. -pal
-
-                            // Echo the byte and store it
-                            let _ = self.write_byte(read_buf[0]);
-                            command[index] = read_buf[0];
-                            self.command_index.set(index + 1);
-                            command[index + 1] = 0;
+                1 => match self.escape.get() {
+                    Escape::None => self.edit_command(read_buf[0]),
+                    Escape::Started => self.escape.set(if read_buf[0] == b'[' {
+                        Escape::ControlSequence
+                    } else {
+                        Escape::None
+                    }),
+                    // Parameters come before the byte that ends the sequence.
+                    Escape::ControlSequence => {
+                        if (0x40..=0x7e).contains(&read_buf[0]) {
+                            self.escape.set(Escape::None);
+                            match read_buf[0] {
+                                b'A' => self.recall_history(true),
+                                b'B' => self.recall_history(false),
+                                _ => {}
+                            }
                         }
-                    });
-                }
+                    }
+                },
                 _ => debug!(
                     "ProcessConsole issues reads of 1 byte, but receive_complete was length {}",
                     rx_len
@@ -0,0 +1,413 @@
+//! Software implementation of SHA-256 and HMAC-SHA256.
+//!
+//! `Sha256Software` implements `hil::digest::Digest` for boards without a
+//! hardware digest engine, for example to check app credentials with
+//! `kernel::procs::RequireSha256Credentials`. Like a hardware engine, it
+//! finishes each operation in a callback, which it delivers with a dynamic
+//! deferred call.
+//!
+//! `Sha256`, `HmacSha256` and `hmac_sha256()` compute digests synchronously,
+//! for capsules that only hash a few bytes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use kernel::hil::digest::Digest;
+//!
+//! let sha = static_init!(
+//!     capsules::sha256::Sha256Software<'static>,
+//!     capsules::sha256::Sha256Software::new(dynamic_deferred_caller)
+//! );
+//! sha.initialize_callback_handle(
+//!     dynamic_deferred_caller
+//!         .register(sha)
+//!         .expect("no deferred call slot available for SHA-256"),
+//! );
+//! ```
+
+use core::convert::TryInto;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::digest;
+use kernel::ErrorCode;
+
+const BLOCK_SIZE: usize = 64;
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Incremental SHA-256 hash.
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; BLOCK_SIZE],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha256 {
+    pub const fn new() -> Sha256 {
+        Sha256 {
+            state: INITIAL_STATE,
+            block: [0; BLOCK_SIZE],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Add `data` to the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let count = core::cmp::min(BLOCK_SIZE - self.block_len, data.len());
+            self.block[self.block_len..self.block_len + count].copy_from_slice(&data[..count]);
+            self.block_len += count;
+            data = &data[count..];
+            if self.block_len == BLOCK_SIZE {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Pad the message and return the digest.
+    pub fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len.wrapping_mul(8);
+
+        self.block[self.block_len] = 0x80;
+        self.block_len += 1;
+        if self.block_len > BLOCK_SIZE - 8 {
+            // No room for the length in this block.
+            for byte in self.block[self.block_len..].iter_mut() {
+                *byte = 0;
+            }
+            self.compress();
+            self.block_len = 0;
+        }
+        for byte in self.block[self.block_len..BLOCK_SIZE - 8].iter_mut() {
+            *byte = 0;
+        }
+        self.block[BLOCK_SIZE - 8..].copy_from_slice(&bit_len.to_be_bytes());
+        self.compress();
+
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks_exact(4).enumerate() {
+            // Chunks are exactly four bytes long, so this cannot fail.
+            w[i] = u32::from_be_bytes(chunk.try_into().unwrap_or([0; 4]));
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+/// Incremental HMAC-SHA256.
+pub struct HmacSha256 {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    pub fn new(key: &[u8]) -> HmacSha256 {
+        // Keys longer than a block are hashed first.
+        let mut block_key = [0u8; BLOCK_SIZE];
+        if key.len() > BLOCK_SIZE {
+            let mut sha = Sha256::new();
+            sha.update(key);
+            block_key[..32].copy_from_slice(&sha.finish());
+        } else {
+            block_key[..key.len()].copy_from_slice(key);
+        }
+
+        let mut pad = [0u8; BLOCK_SIZE];
+        for (pad, key) in pad.iter_mut().zip(block_key.iter()) {
+            *pad = key ^ 0x36;
+        }
+        let mut inner = Sha256::new();
+        inner.update(&pad);
+
+        for (pad, key) in pad.iter_mut().zip(block_key.iter()) {
+            *pad = key ^ 0x5c;
+        }
+        let mut outer = Sha256::new();
+        outer.update(&pad);
+
+        HmacSha256 { inner, outer }
+    }
+
+    /// Add `data` to the MAC.
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    /// Return the MAC.
+    pub fn finish(mut self) -> [u8; 32] {
+        self.outer.update(&self.inner.finish());
+        self.outer.finish()
+    }
+}
+
+/// Compute the HMAC-SHA256 of `data` with `key`.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut hmac = HmacSha256::new(key);
+    hmac.update(data);
+    hmac.finish()
+}
+
+/// The digest `Sha256Software` is computing.
+enum Hash {
+    Sha256(Sha256),
+    HmacSha256(HmacSha256),
+}
+
+/// `hil::digest::Digest` computed in software.
+///
+/// The engine computes a SHA-256 unless `set_mode_hmacsha256()` was called.
+/// The mode stays set for the following digests until `clear_data()` or
+/// `set_mode_sha256()` is called.
+pub struct Sha256Software<'a> {
+    hash: MapCell<Hash>,
+    /// The HMAC key, if the engine computes HMAC-SHA256.
+    key: MapCell<[u8; 32]>,
+    /// The data of the last `add_data()` call, until it is handed back.
+    data: TakeCell<'static, [u8]>,
+    /// The digest of the last `run()` call, until it is handed back.
+    digest: TakeCell<'static, [u8; 32]>,
+    client: OptionalCell<&'a dyn digest::Client<'a, [u8; 32]>>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a> Sha256Software<'a> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> Sha256Software<'a> {
+        Sha256Software {
+            hash: MapCell::new(Hash::Sha256(Sha256::new())),
+            key: MapCell::empty(),
+            data: TakeCell::empty(),
+            digest: TakeCell::empty(),
+            client: OptionalCell::empty(),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    /// Initializes a callback handle for deferred callbacks.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    fn busy(&self) -> bool {
+        self.data.is_some() || self.digest.is_some()
+    }
+
+    /// Start a new digest in the current mode.
+    fn restart(&self) {
+        let hash = match self.key.map(|key| HmacSha256::new(key)) {
+            Some(hmac) => Hash::HmacSha256(hmac),
+            None => Hash::Sha256(Sha256::new()),
+        };
+        self.hash.replace(hash);
+    }
+
+    fn schedule_callback(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+}
+
+impl<'a> digest::Digest<'a, [u8; 32]> for Sha256Software<'a> {
+    fn set_client(&'a self, client: &'a dyn digest::Client<'a, [u8; 32]>) {
+        self.client.set(client);
+    }
+
+    fn add_data(
+        &self,
+        data: LeasableBuffer<'static, u8>,
+    ) -> Result<usize, (ErrorCode, &'static mut [u8])> {
+        if self.busy() {
+            return Err((ErrorCode::BUSY, data.take()));
+        }
+        let len = data.len();
+        self.hash.map(|hash| match hash {
+            Hash::Sha256(sha) => sha.update(&data[..]),
+            Hash::HmacSha256(hmac) => hmac.update(&data[..]),
+        });
+        self.data.replace(data.take());
+        self.schedule_callback();
+        Ok(len)
+    }
+
+    fn run(
+        &'a self,
+        digest: &'static mut [u8; 32],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; 32])> {
+        if self.busy() {
+            return Err((ErrorCode::BUSY, digest));
+        }
+        match self.hash.take() {
+            Some(Hash::Sha256(sha)) => *digest = sha.finish(),
+            Some(Hash::HmacSha256(hmac)) => *digest = hmac.finish(),
+            None => return Err((ErrorCode::FAIL, digest)),
+        }
+        self.restart();
+        self.digest.replace(digest);
+        self.schedule_callback();
+        Ok(())
+    }
+
+    fn clear_data(&self) {
+        self.key.take();
+        self.restart();
+    }
+}
+
+impl digest::HMACSha256 for Sha256Software<'_> {
+    fn set_mode_hmacsha256(&self, key: &[u8; 32]) -> Result<(), ErrorCode> {
+        if self.busy() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.key.replace(*key);
+        self.restart();
+        Ok(())
+    }
+}
+
+impl digest::Sha256 for Sha256Software<'_> {
+    fn set_mode_sha256(&self) -> Result<(), ErrorCode> {
+        if self.busy() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.key.take();
+        self.restart();
+        Ok(())
+    }
+}
+
+impl<'a> DynamicDeferredCallClient for Sha256Software<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        self.client.map(|client| {
+            if let Some(data) = self.data.take() {
+                client.add_data_done(Ok(()), data);
+            }
+            if let Some(digest) = self.digest.take() {
+                client.hash_done(Ok(()), digest);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut sha = Sha256::new();
+        sha.update(data);
+        sha.finish()
+    }
+
+    fn from_hex(hex: &str) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn sha256_test_vectors() {
+        assert_eq!(
+            sha256(b""),
+            from_hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            from_hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            from_hex("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1")
+        );
+    }
+
+    #[test]
+    fn sha256_incremental_updates_match() {
+        let data = [0xa5u8; 200];
+        let mut sha = Sha256::new();
+        for chunk in data.chunks(7) {
+            sha.update(chunk);
+        }
+        assert_eq!(sha.finish(), sha256(&data));
+    }
+
+    #[test]
+    fn hmac_sha256_test_vectors() {
+        // RFC 4231 test cases 1 and 6.
+        assert_eq!(
+            hmac_sha256(&[0x0b; 20], b"Hi There"),
+            from_hex("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7")
+        );
+        assert_eq!(
+            hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ),
+            from_hex("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54")
+        );
+    }
+}
@@ -87,8 +87,8 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
     fn generate_key_complete(
         &self,
         _result: Result<(), ErrorCode>,
-        _unhashed_key: &'static [u8],
-        _key_buf: &'static T,
+        _unhashed_key: &'static mut [u8],
+        _key_buf: &'static mut T,
     ) {
         unimplemented!()
     }
@@ -97,7 +97,7 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
         &self,
         result: Result<(), ErrorCode>,
         key: &'static mut T,
-        value: &'static [u8],
+        value: &'static mut [u8],
     ) {
         match result {
             Ok(()) => {
@@ -113,6 +113,15 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
         }
     }
 
+    fn update_key_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _key: &'static mut T,
+        _value: &'static mut [u8],
+    ) {
+        unimplemented!()
+    }
+
     fn get_value_complete(
         &self,
         result: Result<(), ErrorCode>,
@@ -29,11 +29,19 @@
 //! +-----------------------+
 //!
 //!    hil::flash
+//!
+//! Values can optionally be encrypted and authenticated before they are
+//! stored, see `TicKVStore::set_encryption()`. Values are then stored in the
+//! format described in `tickv::encryption`, using the AES hardware through
+//! `hil::symmetric_encryption::AES128CCM` and a random nonce from `hil::rng`.
 
+use crate::sha256::Sha256;
 use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::flash::{self, Flash};
 use kernel::hil::kv_system::{self, KVSystem};
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
 use kernel::ErrorCode;
 use tickv::{self, AsyncTicKV};
 
@@ -43,6 +51,7 @@ enum Operation {
     Init,
     GetKey,
     AppendKey,
+    UpdateKey,
     InvalidateKey,
     GarbageCollect,
 }
@@ -97,7 +106,7 @@ impl<'a, F: Flash> tickv::flash_controller::FlashController<512> for TickFSFlast
 
         if self
             .flash
-            .write_page((0x20040000 + address) / 512, data_buf)
+            .write_page(self.region_offset + address / 512, data_buf)
             .is_err()
         {
             return Err(tickv::error_codes::ErrorCode::WriteFail);
@@ -115,15 +124,59 @@ impl<'a, F: Flash> tickv::flash_controller::FlashController<512> for TickFSFlast
 
 pub type TicKVKeyType = [u8; 8];
 
+/// The result of starting an operation that stores a value.
+type StoreResult = Result<
+    (),
+    (
+        &'static mut TicKVKeyType,
+        &'static mut [u8],
+        Result<(), ErrorCode>,
+    ),
+>;
+
+/// Map a TicKV error to the error the `hil::kv_system` interface reports.
+fn error_code(e: tickv::error_codes::ErrorCode) -> ErrorCode {
+    match e {
+        tickv::error_codes::ErrorCode::KeyNotFound
+        | tickv::error_codes::ErrorCode::KeyAlreadyExists => ErrorCode::NOSUPPORT,
+        tickv::error_codes::ErrorCode::RegionFull | tickv::error_codes::ErrorCode::FlashFull => {
+            ErrorCode::NOMEM
+        }
+        tickv::error_codes::ErrorCode::ObjectTooLarge
+        | tickv::error_codes::ErrorCode::BufferTooSmall(_) => ErrorCode::SIZE,
+        _ => ErrorCode::FAIL,
+    }
+}
+
+/// Whether the TicKV operation is waiting for the flash.
+fn not_ready(
+    ret: Result<tickv::success_codes::SuccessCode, tickv::error_codes::ErrorCode>,
+) -> bool {
+    match ret {
+        Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+        | Err(tickv::error_codes::ErrorCode::WriteNotReady(_))
+        | Err(tickv::error_codes::ErrorCode::EraseNotReady(_)) => true,
+        _ => false,
+    }
+}
+
 pub struct TicKVStore<'a, F: Flash + 'static> {
     tickv: AsyncTicKV<'a, TickFSFlastCtrl<'a, F>, 512>,
     operation: Cell<Operation>,
     next_operation: Cell<Operation>,
+    // Set when TicKV has more to do once the current flash write completes.
+    continue_after_write: Cell<bool>,
 
-    value_buffer: Cell<Option<&'static [u8]>>,
+    value_buffer: TakeCell<'static, [u8]>,
+    value_length: Cell<usize>,
     key_buffer: TakeCell<'static, [u8; 8]>,
     ret_buffer: TakeCell<'static, [u8]>,
 
+    // Used to encrypt values, if set
+    aes: OptionalCell<&'a dyn AES128CCM<'a>>,
+    rng: OptionalCell<&'a dyn Rng<'a>>,
+    encryption_key: Cell<[u8; 16]>,
+
     client: OptionalCell<&'a dyn kv_system::Client<TicKVKeyType>>,
 }
 
@@ -145,13 +198,41 @@ impl<'a, F: Flash> TicKVStore<'a, F> {
             tickv,
             operation: Cell::new(Operation::None),
             next_operation: Cell::new(Operation::None),
-            value_buffer: Cell::new(None),
+            continue_after_write: Cell::new(false),
+            value_buffer: TakeCell::empty(),
+            value_length: Cell::new(0),
             key_buffer: TakeCell::empty(),
             ret_buffer: TakeCell::empty(),
+            aes: OptionalCell::empty(),
+            rng: OptionalCell::empty(),
+            encryption_key: Cell::new([0; 16]),
             client: OptionalCell::empty(),
         }
     }
 
+    /// Encrypt and authenticate values with `key` before they are stored.
+    ///
+    /// Values are stored in the format described in `tickv::encryption`,
+    /// which needs `tickv::encryption::OVERHEAD` more bytes than the value.
+    /// The value buffers passed to `append_key()`, `update_key()` and
+    /// `get_value()` need this much space after the value, otherwise `SIZE`
+    /// is returned. The value buffer returned once a value is stored holds
+    /// the encrypted value.
+    ///
+    /// Values that fail authentication are reported as `FAIL`.
+    pub fn set_encryption(
+        &'a self,
+        aes: &'a dyn AES128CCM<'a>,
+        rng: &'a dyn Rng<'a>,
+        key: [u8; 16],
+    ) {
+        aes.set_client(self);
+        rng.set_client(self);
+        self.aes.set(aes);
+        self.rng.set(rng);
+        self.encryption_key.set(key);
+    }
+
     pub fn initalise(&self) {
         let _ret = self.tickv.initalise(0x7bc9f7ff4f76f244);
         self.operation.set(Operation::Init);
@@ -165,6 +246,7 @@ impl<'a, F: Flash> TicKVStore<'a, F> {
                 match self.append_key(
                     self.key_buffer.take().unwrap(),
                     self.value_buffer.take().unwrap(),
+                    self.value_length.get(),
                 ) {
                     Err((key, value, error)) => {
                         self.client.map(move |cb| {
@@ -174,6 +256,20 @@ impl<'a, F: Flash> TicKVStore<'a, F> {
                     _ => {}
                 }
             }
+            Operation::UpdateKey => {
+                match self.update_key(
+                    self.key_buffer.take().unwrap(),
+                    self.value_buffer.take().unwrap(),
+                    self.value_length.get(),
+                ) {
+                    Err((key, value, error)) => {
+                        self.client.map(move |cb| {
+                            cb.update_key_complete(error, key, value);
+                        });
+                    }
+                    _ => {}
+                }
+            }
             Operation::GetKey => {
                 match self.get_value(
                     self.key_buffer.take().unwrap(),
@@ -208,65 +304,257 @@ impl<'a, F: Flash> TicKVStore<'a, F> {
         }
         self.next_operation.set(Operation::None);
     }
-}
 
-impl<'a, F: Flash> flash::Client<F> for TicKVStore<'a, F> {
-    fn read_complete(&self, pagebuffer: &'static mut F::Page, _error: flash::Error) {
-        self.tickv.set_read_buffer(pagebuffer.as_mut());
-        self.tickv
+    /// Start appending the (possibly encrypted) value to TicKV.
+    fn start_append_key(
+        &self,
+        key: &'static mut TicKVKeyType,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> StoreResult {
+        let ret = self
             .tickv
-            .controller
-            .flash_read_buffer
-            .replace(pagebuffer);
+            .append_key(u64::from_le_bytes(*key), value, length);
+        if not_ready(ret) {
+            self.key_buffer.replace(key);
+            Ok(())
+        } else {
+            // The flash always completes asynchronously, so any
+            // other result is an error.
+            self.operation.set(Operation::None);
+            let value = self.tickv.get_stored_value_buffer().unwrap();
+            Err((
+                key,
+                value,
+                Err(ret.map_or_else(error_code, |_| ErrorCode::FAIL)),
+            ))
+        }
+    }
+
+    /// Start replacing the value of a key in TicKV with the (possibly
+    /// encrypted) value.
+    fn start_update_key(
+        &self,
+        key: &'static mut TicKVKeyType,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> StoreResult {
+        let ret = self
+            .tickv
+            .update_key(u64::from_le_bytes(*key), value, length);
+        if not_ready(ret) {
+            if let Err(tickv::error_codes::ErrorCode::WriteNotReady(_)) = ret {
+                self.continue_after_write.set(true);
+            }
+            self.key_buffer.replace(key);
+            Ok(())
+        } else {
+            // The flash always completes asynchronously, so any
+            // other result is an error.
+            self.operation.set(Operation::None);
+            let value = self.tickv.get_stored_value_buffer().unwrap();
+            Err((
+                key,
+                value,
+                Err(ret.map_or_else(error_code, |_| ErrorCode::FAIL)),
+            ))
+        }
+    }
+
+    /// Get a random nonce to encrypt the value with, before it is stored.
+    fn get_nonce(
+        &self,
+        key: &'static mut TicKVKeyType,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> StoreResult {
+        if length + tickv::encryption::OVERHEAD > value.len() || length > 0xFFFF {
+            self.operation.set(Operation::None);
+            return Err((key, value, Err(ErrorCode::SIZE)));
+        }
+
+        match self.rng.map_or(Err(ErrorCode::FAIL), |rng| rng.get()) {
+            Ok(()) => {
+                self.key_buffer.replace(key);
+                self.value_buffer.replace(value);
+                self.value_length.set(length);
+                Ok(())
+            }
+            Err(e) => {
+                self.operation.set(Operation::None);
+                Err((key, value, Err(e)))
+            }
+        }
+    }
+
+    /// Finish storing a value, with an error from before TicKV was used.
+    fn store_complete(&self, result: Result<(), ErrorCode>, value: &'static mut [u8]) {
+        let key = self.key_buffer.take().unwrap();
+        let operation = self.operation.replace(Operation::None);
+
+        self.client.map(move |cb| match operation {
+            Operation::AppendKey => cb.append_key_complete(result, key, value),
+            _ => cb.update_key_complete(result, key, value),
+        });
+    }
+
+    /// Finish getting a value.
+    fn get_complete(&self, result: Result<(), ErrorCode>, ret_buf: &'static mut [u8]) {
+        self.operation.set(Operation::None);
+        self.client.map(move |cb| {
+            cb.get_value_complete(result, self.key_buffer.take().unwrap(), ret_buf);
+        });
+    }
+
+    /// Start the AES hardware on `buf`, which holds an encrypted value of
+    /// `length` bytes after the header.
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        nonce: &[u8],
+        length: usize,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let aes = match self.aes.extract() {
+            Some(aes) => aes,
+            None => return Err((ErrorCode::FAIL, buf)),
+        };
+
+        if let Err(e) = aes
+            .set_key(&self.encryption_key.get())
+            .and_then(|()| aes.set_nonce(nonce))
+        {
+            return Err((e, buf));
+        }
+
+        self.value_length.set(length);
+        aes.crypt(
+            buf,
+            tickv::encryption::HEADER_LEN,
+            tickv::encryption::HEADER_LEN,
+            length,
+            tickv::encryption::TAG_LEN,
+            true,
+            encrypting,
+        )
+    }
+
+    /// Check and decrypt the value that was read.
+    fn decrypt_value(&self, ret_buf: &'static mut [u8]) {
+        let hash = self.key_buffer.map_or(0, |key| u64::from_le_bytes(*key));
+
+        match tickv::encryption::read_header(ret_buf, hash) {
+            Ok((nonce, length)) => {
+                if let Err((e, buf)) = self.crypt(ret_buf, &nonce, length, false) {
+                    self.get_complete(Err(e), buf);
+                }
+            }
+            Err(e) => self.get_complete(Err(error_code(e)), ret_buf),
+        }
+    }
+
+    /// Continue the current operation once a flash read, or a write that
+    /// TicKV is waiting on, has completed.
+    fn continue_operation(&self) {
         let (ret, buf_buffer) = self.tickv.continue_operation();
 
         buf_buffer.map(|buf| {
             self.ret_buffer.replace(buf);
         });
 
+        if let Err(tickv::error_codes::ErrorCode::WriteNotReady(_)) = ret {
+            self.continue_after_write.set(true);
+        }
+
         match self.operation.get() {
             Operation::Init => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
-                    self.operation.set(Operation::None)
+                    self.complete_init();
                 }
                 _ => {}
             },
             Operation::GetKey => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
+                    let ret_buf = self.ret_buffer.take().unwrap();
+                    if self.aes.is_some() {
+                        self.decrypt_value(ret_buf);
+                    } else {
+                        self.get_complete(Ok(()), ret_buf);
+                    }
+                }
+                Err(tickv::error_codes::ErrorCode::EraseNotReady(_)) | Ok(_) => {}
+                Err(e) => {
                     self.operation.set(Operation::None);
                     self.client.map(|cb| {
                         cb.get_value_complete(
-                            Ok(()),
+                            Err(error_code(e)),
                             self.key_buffer.take().unwrap(),
                             self.ret_buffer.take().unwrap(),
                         );
                     });
                 }
-                Err(tickv::error_codes::ErrorCode::EraseNotReady(_)) | Ok(_) => {}
-                _ => {
+            },
+            Operation::AppendKey => match ret {
+                Ok(tickv::success_codes::SuccessCode::Complete)
+                | Ok(tickv::success_codes::SuccessCode::Written) => {
+                    self.operation.set(Operation::None);
+                }
+                Err(e) if !not_ready(ret) => {
                     self.operation.set(Operation::None);
                     self.client.map(|cb| {
-                        cb.get_value_complete(
-                            Err(ErrorCode::FAIL),
+                        cb.append_key_complete(
+                            Err(error_code(e)),
                             self.key_buffer.take().unwrap(),
-                            self.ret_buffer.take().unwrap(),
+                            self.tickv.get_stored_value_buffer().unwrap(),
                         );
                     });
                 }
+                _ => {}
             },
-            Operation::AppendKey => match ret {
+            Operation::UpdateKey => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
                     self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.update_key_complete(
+                            Ok(()),
+                            self.key_buffer.take().unwrap(),
+                            self.tickv.get_stored_value_buffer().unwrap(),
+                        );
+                    });
+                }
+                Err(e) if !not_ready(ret) => {
+                    self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.update_key_complete(
+                            Err(error_code(e)),
+                            self.key_buffer.take().unwrap(),
+                            self.tickv.get_stored_value_buffer().unwrap(),
+                        );
+                    });
                 }
                 _ => {}
             },
             Operation::InvalidateKey => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
+                    // Nothing was left to write, which happens once older
+                    // copies of an updated key have been searched for.
                     self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.invalidate_key_complete(Ok(()), self.key_buffer.take().unwrap());
+                    });
+                }
+                Err(e) if !not_ready(ret) => {
+                    self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.invalidate_key_complete(
+                            Err(error_code(e)),
+                            self.key_buffer.take().unwrap(),
+                        );
+                    });
                 }
                 _ => {}
             },
@@ -278,11 +566,29 @@ impl<'a, F: Flash> flash::Client<F> for TicKVStore<'a, F> {
                         cb.garbage_collect_complete(Ok(()));
                     });
                 }
+                Err(e) if !not_ready(ret) => {
+                    self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.garbage_collect_complete(Err(error_code(e)));
+                    });
+                }
                 _ => {}
             },
             _ => unreachable!(),
         }
     }
+}
+
+impl<'a, F: Flash> flash::Client<F> for TicKVStore<'a, F> {
+    fn read_complete(&self, pagebuffer: &'static mut F::Page, _error: flash::Error) {
+        self.tickv.set_read_buffer(pagebuffer.as_mut());
+        self.tickv
+            .tickv
+            .controller
+            .flash_read_buffer
+            .replace(pagebuffer);
+        self.continue_operation();
+    }
 
     fn write_complete(&self, pagebuffer: &'static mut F::Page, _error: flash::Error) {
         self.tickv
@@ -291,6 +597,11 @@ impl<'a, F: Flash> flash::Client<F> for TicKVStore<'a, F> {
             .flash_read_buffer
             .replace(pagebuffer);
 
+        if self.continue_after_write.take() {
+            self.continue_operation();
+            return;
+        }
+
         match self.operation.get() {
             Operation::Init => {
                 self.complete_init();
@@ -305,6 +616,16 @@ impl<'a, F: Flash> flash::Client<F> for TicKVStore<'a, F> {
                     );
                 });
             }
+            Operation::UpdateKey => {
+                self.operation.set(Operation::None);
+                self.client.map(|cb| {
+                    cb.update_key_complete(
+                        Ok(()),
+                        self.key_buffer.take().unwrap(),
+                        self.tickv.get_stored_value_buffer().unwrap(),
+                    );
+                });
+            }
             Operation::InvalidateKey => {
                 self.operation.set(Operation::None);
                 self.client.map(|cb| {
@@ -345,6 +666,104 @@ impl<'a, F: Flash> flash::Client<F> for TicKVStore<'a, F> {
     }
 }
 
+impl<'a, F: Flash> rng::Client for TicKVStore<'a, F> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> rng::Continue {
+        let value = match self.value_buffer.take() {
+            Some(value) => value,
+            None => return rng::Continue::Done,
+        };
+
+        if let Err(e) = error {
+            self.store_complete(Err(e), value);
+            return rng::Continue::Done;
+        }
+
+        let mut random = [0; 16];
+        for word in random.chunks_mut(4) {
+            match randomness.next() {
+                Some(next) => word.copy_from_slice(&next.to_le_bytes()),
+                None => {
+                    // Wait for more randomness
+                    self.value_buffer.replace(value);
+                    return rng::Continue::More;
+                }
+            }
+        }
+
+        let mut nonce = [0; tickv::encryption::NONCE_LEN];
+        nonce.copy_from_slice(&random[..tickv::encryption::NONCE_LEN]);
+        let hash = self.key_buffer.map_or(0, |key| u64::from_le_bytes(*key));
+        let length = self.value_length.get();
+
+        value.copy_within(..length, tickv::encryption::HEADER_LEN);
+        tickv::encryption::write_header(value, &nonce, length);
+
+        if let Err((e, value)) = self.crypt(
+            value,
+            &tickv::encryption::ccm_nonce(hash, &nonce),
+            length,
+            true,
+        ) {
+            self.store_complete(Err(e), value);
+        }
+
+        rng::Continue::Done
+    }
+}
+
+impl<'a, F: Flash> CCMClient for TicKVStore<'a, F> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        let length = self.value_length.get();
+
+        match self.operation.get() {
+            Operation::AppendKey | Operation::UpdateKey => {
+                if let Err(e) = res {
+                    self.store_complete(Err(e), buf);
+                    return;
+                }
+
+                let key = self.key_buffer.take().unwrap();
+                let length = length + tickv::encryption::OVERHEAD;
+                let (ret, appending) = if self.operation.get() == Operation::AppendKey {
+                    (self.start_append_key(key, buf, length), true)
+                } else {
+                    (self.start_update_key(key, buf, length), false)
+                };
+
+                if let Err((key, value, error)) = ret {
+                    self.client.map(move |cb| {
+                        if appending {
+                            cb.append_key_complete(error, key, value);
+                        } else {
+                            cb.update_key_complete(error, key, value);
+                        }
+                    });
+                }
+            }
+            Operation::GetKey => {
+                if res.is_ok() && tag_is_valid {
+                    buf.copy_within(
+                        tickv::encryption::HEADER_LEN..(tickv::encryption::HEADER_LEN + length),
+                        0,
+                    );
+                    self.get_complete(Ok(()), buf);
+                } else {
+                    // Don't return a value that couldn't be authenticated
+                    for b in buf.iter_mut() {
+                        *b = 0;
+                    }
+                    self.get_complete(Err(ErrorCode::FAIL), buf);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
     type K = TicKVKeyType;
 
@@ -352,10 +771,14 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
         self.client.set(client);
     }
 
+    /// Keys are the first 8 bytes of the SHA-256 digest of the unhashed key.
+    /// Hashing does not wait on the flash, so the client is called before
+    /// this returns.
     fn generate_key(
         &self,
-        _unhashed_key: &'static mut [u8],
-        _key_buf: &'static mut Self::K,
+        unhashed_key: &'static mut [u8],
+        length: usize,
+        key_buf: &'static mut Self::K,
     ) -> Result<
         (),
         (
@@ -364,31 +787,42 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
             Result<(), ErrorCode>,
         ),
     > {
-        unimplemented!()
+        if length > unhashed_key.len() {
+            return Err((unhashed_key, key_buf, Err(ErrorCode::INVAL)));
+        }
+
+        let mut sha = Sha256::new();
+        sha.update(&unhashed_key[..length]);
+        let digest = sha.finish();
+        key_buf.copy_from_slice(&digest[..8]);
+
+        self.client.map(move |cb| {
+            cb.generate_key_complete(Ok(()), unhashed_key, key_buf);
+        });
+        Ok(())
     }
 
     fn append_key(
         &self,
         key: &'static mut Self::K,
-        value: &'static [u8],
-    ) -> Result<(), (&'static mut Self::K, &'static [u8], Result<(), ErrorCode>)> {
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<
+        (),
+        (
+            &'static mut Self::K,
+            &'static mut [u8],
+            Result<(), ErrorCode>,
+        ),
+    > {
         match self.operation.get() {
             Operation::None => {
                 self.operation.set(Operation::AppendKey);
 
-                match self.tickv.append_key(u64::from_le_bytes(*key), value) {
-                    Ok(_ret) => {
-                        self.key_buffer.replace(key);
-                        Ok(())
-                    }
-                    Err(e) => match e {
-                        tickv::error_codes::ErrorCode::ReadNotReady(_)
-                        | tickv::error_codes::ErrorCode::WriteNotReady(_) => {
-                            self.key_buffer.replace(key);
-                            Ok(())
-                        }
-                        _ => Err((key, value, Err(ErrorCode::FAIL))),
-                    },
+                if self.aes.is_some() {
+                    self.get_nonce(key, value, length)
+                } else {
+                    self.start_append_key(key, value, length)
                 }
             }
             Operation::Init => {
@@ -396,7 +830,47 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
                 // We can save this request and start it after init
                 self.next_operation.set(Operation::AppendKey);
                 self.key_buffer.replace(key);
-                self.value_buffer.replace(Some(value));
+                self.value_buffer.replace(value);
+                self.value_length.set(length);
+                Ok(())
+            }
+            _ => {
+                // An operation is already in process.
+                Err((key, value, Err(ErrorCode::BUSY)))
+            }
+        }
+    }
+
+    fn update_key(
+        &self,
+        key: &'static mut Self::K,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<
+        (),
+        (
+            &'static mut Self::K,
+            &'static mut [u8],
+            Result<(), ErrorCode>,
+        ),
+    > {
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::UpdateKey);
+
+                if self.aes.is_some() {
+                    self.get_nonce(key, value, length)
+                } else {
+                    self.start_update_key(key, value, length)
+                }
+            }
+            Operation::Init => {
+                // The init process is still occuring.
+                // We can save this request and start it after init
+                self.next_operation.set(Operation::UpdateKey);
+                self.key_buffer.replace(key);
+                self.value_buffer.replace(value);
+                self.value_length.set(length);
                 Ok(())
             }
             _ => {
@@ -433,7 +907,10 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
                             self.key_buffer.replace(key);
                             Ok(())
                         }
-                        _ => Err((key, buf.unwrap(), Err(ErrorCode::FAIL))),
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((key, buf.unwrap(), Err(error_code(e))))
+                        }
                     },
                 }
             }
@@ -466,12 +943,19 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
                         Ok(())
                     }
                     Err(e) => match e {
-                        tickv::error_codes::ErrorCode::ReadNotReady(_)
-                        | tickv::error_codes::ErrorCode::WriteNotReady(_) => {
+                        tickv::error_codes::ErrorCode::ReadNotReady(_) => {
                             self.key_buffer.replace(key);
                             Ok(())
                         }
-                        _ => Err((key, Err(ErrorCode::FAIL))),
+                        tickv::error_codes::ErrorCode::WriteNotReady(_) => {
+                            self.continue_after_write.set(true);
+                            self.key_buffer.replace(key);
+                            Ok(())
+                        }
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((key, Err(error_code(e))))
+                        }
                     },
                 }
             }
@@ -499,7 +983,10 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
                     Err(e) => match e {
                         tickv::error_codes::ErrorCode::ReadNotReady(_)
                         | tickv::error_codes::ErrorCode::WriteNotReady(_) => Ok(0),
-                        _ => Err(Err(ErrorCode::FAIL)),
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err(Err(error_code(e)))
+                        }
                     },
                 }
             }
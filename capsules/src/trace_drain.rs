@@ -0,0 +1,143 @@
+//! Sends the records of the kernel trace to a host over a UART.
+//!
+//! The kernel records its events into a `kernel::trace::KernelTrace` buffer.
+//! This capsule periodically moves the records out of that buffer and
+//! transmits them unchanged, as raw binary, so that the trace can be streamed
+//! to a host without going through the text console. It is intended to be
+//! used with a Segger RTT channel of its own, since the records would corrupt
+//! any text sent over the same channel. `tools/trace_decoder --binary` turns
+//! the received bytes back into text.
+//!
+//! When a transmission completes and more records are waiting, the next one
+//! starts right away. Otherwise the capsule checks the trace again after
+//! `interval_ms`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use capsules::virtual_alarm::VirtualMuxAlarm;
+//!
+//! let trace = static_init!(
+//!     kernel::trace::KernelTrace,
+//!     kernel::trace::KernelTrace::new(&mut TRACE_BUFFER, quota_clock)
+//! );
+//! board_kernel.set_trace(trace, &process_management_capability);
+//!
+//! let trace_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let trace_drain = static_init!(
+//!     capsules::trace_drain::TraceDrain<'static, VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>>,
+//!     capsules::trace_drain::TraceDrain::new(
+//!         trace,
+//!         trace_rtt,
+//!         trace_alarm,
+//!         &mut capsules::trace_drain::BUFFER,
+//!         100
+//!     )
+//! );
+//! trace_alarm.set_client(trace_drain);
+//! kernel::hil::uart::Transmit::set_transmit_client(trace_rtt, trace_drain);
+//! trace_drain.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::hil::time;
+use kernel::hil::uart;
+use kernel::trace::{KernelTrace, RECORD_LEN};
+use kernel::ErrorCode;
+
+/// Buffer for the records being transmitted, large enough for 16 records.
+pub static mut BUFFER: [u8; 16 * RECORD_LEN] = [0; 16 * RECORD_LEN];
+
+pub struct TraceDrain<'a, A: time::Alarm<'a>> {
+    trace: &'a KernelTrace,
+    uart: &'a dyn uart::Transmit<'a>,
+    alarm: &'a A,
+    // Holds the records being transmitted. Empty while a transmission is in
+    // progress.
+    buffer: TakeCell<'static, [u8]>,
+    // How often to check the trace for new records, in milliseconds.
+    interval_ms: u32,
+    running: Cell<bool>,
+}
+
+impl<'a, A: time::Alarm<'a>> TraceDrain<'a, A> {
+    pub fn new(
+        trace: &'a KernelTrace,
+        uart: &'a dyn uart::Transmit<'a>,
+        alarm: &'a A,
+        buffer: &'static mut [u8],
+        interval_ms: u32,
+    ) -> TraceDrain<'a, A> {
+        TraceDrain {
+            trace: trace,
+            uart: uart,
+            alarm: alarm,
+            buffer: TakeCell::new(buffer),
+            interval_ms: interval_ms,
+            running: Cell::new(false),
+        }
+    }
+
+    /// Start sending trace records.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.running.get() {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.running.set(true);
+        self.drain();
+        Ok(())
+    }
+
+    /// Stop sending trace records. Records keep accumulating in the trace.
+    pub fn stop(&self) {
+        self.running.set(false);
+        let _ = self.alarm.disarm();
+    }
+
+    /// Transmit the oldest records, or wait for some if there are none.
+    fn drain(&self) {
+        if !self.running.get() {
+            return;
+        }
+        self.buffer.take().map(|buffer| {
+            let len = self.trace.drain(buffer);
+            if len == 0 {
+                self.buffer.replace(buffer);
+                self.wait();
+            } else if let Err((_, buffer)) = self.uart.transmit_buffer(buffer, len) {
+                // The records are lost, but the trace goes on.
+                self.buffer.replace(buffer);
+                self.wait();
+            }
+        });
+    }
+
+    fn wait(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(self.interval_ms));
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for TraceDrain<'a, A> {
+    fn alarm(&self) {
+        self.drain();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> uart::TransmitClient for TraceDrain<'a, A> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        _tx_len: usize,
+        _rval: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(tx_buffer);
+        self.drain();
+    }
+}
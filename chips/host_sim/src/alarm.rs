@@ -0,0 +1,125 @@
+//! Simulated alarm peripheral.
+
+use core::cell::Cell;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Counter, Freq1MHz, Ticks, Ticks32, Time};
+use kernel::ErrorCode;
+
+use crate::chip::SimPeripheral;
+use crate::clock::SimClock;
+
+/// A 32-bit, 1 MHz alarm counting simulated time.
+///
+/// The alarm raises its interrupt once the simulated clock passes the
+/// expiration, which lets `SimChip::sleep()` skip straight to it.
+pub struct SimAlarm<'a> {
+    clock: &'a SimClock,
+    reference: Cell<u32>,
+    dt: Cell<u32>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<'a> SimAlarm<'a> {
+    pub fn new(clock: &'a SimClock) -> SimAlarm<'a> {
+        SimAlarm {
+            clock,
+            reference: Cell::new(0),
+            dt: Cell::new(0),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Simulated time at which the alarm fires. An expiration that has
+    /// already passed reports the current time.
+    fn expiration_us(&self) -> u64 {
+        let now = self.clock.now_us();
+        let elapsed = (now as u32).wrapping_sub(self.reference.get());
+        if elapsed >= self.dt.get() {
+            now
+        } else {
+            now + u64::from(self.dt.get() - elapsed)
+        }
+    }
+}
+
+impl Time for SimAlarm<'_> {
+    type Frequency = Freq1MHz;
+    type Ticks = Ticks32;
+
+    fn now(&self) -> Ticks32 {
+        Ticks32::from(self.clock.now_us() as u32)
+    }
+}
+
+/// The counter runs for as long as simulated time does, and its overflows are
+/// not reported.
+impl<'a> Counter<'a> for SimAlarm<'a> {
+    fn set_overflow_client(&'a self, _client: &'a dyn time::OverflowClient) {}
+
+    fn start(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::BUSY)
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::FAIL)
+    }
+
+    fn is_running(&self) -> bool {
+        true
+    }
+}
+
+impl<'a> Alarm<'a> for SimAlarm<'a> {
+    fn set_alarm_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Self::Ticks, dt: Self::Ticks) {
+        self.reference.set(reference.into_u32());
+        self.dt.set(dt.into_u32());
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        Ticks32::from(self.reference.get().wrapping_add(self.dt.get()))
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.armed.set(false);
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Self::Ticks {
+        Ticks32::from(1)
+    }
+}
+
+impl SimPeripheral for SimAlarm<'_> {
+    fn interrupt_pending(&self) -> bool {
+        self.armed.get() && self.expiration_us() <= self.clock.now_us()
+    }
+
+    fn service_interrupt(&self) {
+        self.armed.set(false);
+        self.client.map(|client| client.alarm());
+    }
+
+    fn next_interrupt_us(&self) -> Option<u64> {
+        if self.armed.get() {
+            Some(self.expiration_us())
+        } else {
+            None
+        }
+    }
+}
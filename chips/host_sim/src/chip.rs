@@ -0,0 +1,174 @@
+//! The simulated chip and its interrupt dispatch.
+
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use kernel::capabilities;
+use kernel::ipc;
+use kernel::power::SleepState;
+use kernel::{Chip, Kernel, Platform, Scheduler};
+
+use crate::clock::SimClock;
+use crate::mpu::SimMpu;
+use crate::scheduler_timer::SimSchedulerTimer;
+use crate::syscall::SimSysCall;
+use crate::watchdog::SimWatchDog;
+
+/// Number of regions the simulated MPU provides.
+pub const NUM_MPU_REGIONS: usize = 8;
+
+/// Time the simulated chip takes to wake up from each `SleepState`, in
+/// microseconds. Sleeping itself is the same in every state.
+pub const WAKEUP_LATENCY_US: [u32; 3] = [0, 100, 2000];
+
+/// A peripheral whose interrupts are dispatched by `SimChip`.
+pub trait SimPeripheral {
+    /// Whether the peripheral has an interrupt waiting to be serviced.
+    fn interrupt_pending(&self) -> bool;
+
+    /// Handle the pending interrupt, calling back into any clients.
+    fn service_interrupt(&self);
+
+    /// Simulated time at which the peripheral will next raise an interrupt,
+    /// if it is waiting on time at all.
+    fn next_interrupt_us(&self) -> Option<u64>;
+}
+
+pub struct SimChip {
+    clock: &'static SimClock,
+    mpu: SimMpu,
+    userspace_kernel_boundary: &'static SimSysCall<'static>,
+    scheduler_timer: &'static SimSchedulerTimer<'static>,
+    watchdog: SimWatchDog,
+    peripherals: RefCell<Vec<&'static dyn SimPeripheral>>,
+}
+
+impl SimChip {
+    pub fn new(
+        clock: &'static SimClock,
+        userspace_kernel_boundary: &'static SimSysCall<'static>,
+        scheduler_timer: &'static SimSchedulerTimer<'static>,
+    ) -> SimChip {
+        SimChip {
+            clock,
+            mpu: SimMpu::new(NUM_MPU_REGIONS),
+            userspace_kernel_boundary,
+            scheduler_timer,
+            watchdog: SimWatchDog::new(),
+            peripherals: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Attach a peripheral so its interrupts are serviced by the kernel.
+    pub fn add_peripheral(&self, peripheral: &'static dyn SimPeripheral) {
+        self.peripherals.borrow_mut().push(peripheral);
+    }
+
+    pub fn clock(&self) -> &'static SimClock {
+        self.clock
+    }
+
+    fn peripherals(&self) -> Vec<&'static dyn SimPeripheral> {
+        // Copy the list so that peripheral clients are free to call back into
+        // the chip.
+        self.peripherals.borrow().clone()
+    }
+}
+
+impl Chip for SimChip {
+    type MPU = SimMpu;
+    type UserspaceKernelBoundary = SimSysCall<'static>;
+    type SchedulerTimer = SimSchedulerTimer<'static>;
+    type WatchDog = SimWatchDog;
+
+    fn service_pending_interrupts(&self) {
+        let peripherals = self.peripherals();
+        loop {
+            let mut serviced = false;
+            for peripheral in peripherals.iter() {
+                if peripheral.interrupt_pending() {
+                    peripheral.service_interrupt();
+                    serviced = true;
+                }
+            }
+            if !serviced {
+                break;
+            }
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        self.peripherals()
+            .iter()
+            .any(|peripheral| peripheral.interrupt_pending())
+    }
+
+    fn mpu(&self) -> &Self::MPU {
+        &self.mpu
+    }
+
+    fn scheduler_timer(&self) -> &Self::SchedulerTimer {
+        self.scheduler_timer
+    }
+
+    fn watchdog(&self) -> &Self::WatchDog {
+        &self.watchdog
+    }
+
+    fn userspace_kernel_boundary(&self) -> &Self::UserspaceKernelBoundary {
+        self.userspace_kernel_boundary
+    }
+
+    /// Skip ahead to the earliest peripheral deadline. If no peripheral is
+    /// waiting on time the chip would sleep forever, so the clock is left
+    /// alone.
+    fn sleep(&self) {
+        let next = self
+            .peripherals()
+            .iter()
+            .filter_map(|peripheral| peripheral.next_interrupt_us())
+            .min();
+        if let Some(wakeup) = next {
+            self.clock.advance_to_us(wakeup);
+        }
+    }
+
+    fn wakeup_latency_us(&self, state: SleepState) -> u32 {
+        WAKEUP_LATENCY_US[state as usize]
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        f()
+    }
+
+    unsafe fn print_state(&self, writer: &mut dyn Write) {
+        let _ = writer.write_fmt(format_args!(
+            "\r\n---| Simulated Chip |---\r\n Time: {} us\r\n",
+            self.clock.now_us()
+        ));
+    }
+}
+
+/// Step the kernel loop until `done()` returns true, for at most
+/// `max_iterations` iterations. Returns whether `done()` was satisfied.
+pub fn run_until<P: Platform, SC: Scheduler<SimChip>, F: Fn() -> bool, const NUM_PROCS: usize>(
+    kernel: &Kernel,
+    platform: &P,
+    chip: &SimChip,
+    ipc: Option<&ipc::IPC<NUM_PROCS>>,
+    scheduler: &SC,
+    max_iterations: usize,
+    capability: &dyn capabilities::MainLoopCapability,
+    done: F,
+) -> bool {
+    for _ in 0..max_iterations {
+        if done() {
+            return true;
+        }
+        kernel.kernel_loop_operation(platform, chip, ipc, scheduler, false, capability);
+    }
+    done()
+}
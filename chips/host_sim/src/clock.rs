@@ -0,0 +1,37 @@
+//! Simulated time shared by all simulated peripherals.
+
+use core::cell::Cell;
+
+/// A monotonic clock counting simulated microseconds.
+///
+/// Nothing advances the clock on its own. Simulated processes advance it as
+/// they execute, and `SimChip::sleep()` advances it to the next peripheral
+/// deadline.
+pub struct SimClock {
+    now_us: Cell<u64>,
+}
+
+impl SimClock {
+    pub const fn new() -> SimClock {
+        SimClock {
+            now_us: Cell::new(0),
+        }
+    }
+
+    /// Current simulated time in microseconds.
+    pub fn now_us(&self) -> u64 {
+        self.now_us.get()
+    }
+
+    /// Move the clock forward by `us` microseconds.
+    pub fn advance_us(&self, us: u64) {
+        self.now_us.set(self.now_us.get() + us);
+    }
+
+    /// Move the clock forward to `us`. Does nothing if `us` is in the past.
+    pub fn advance_to_us(&self, us: u64) {
+        if us > self.now_us.get() {
+            self.now_us.set(us);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Simulated flash.
+
+use core::cell::{Cell, RefCell};
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::flash;
+use kernel::ErrorCode;
+
+use crate::chip::SimPeripheral;
+
+/// Size of a flash page in bytes.
+pub const PAGE_SIZE: usize = 512;
+
+/// One page of flash.
+pub struct SimFlashPage(pub [u8; PAGE_SIZE]);
+
+impl Default for SimFlashPage {
+    fn default() -> SimFlashPage {
+        SimFlashPage([0; PAGE_SIZE])
+    }
+}
+
+impl AsMut<[u8]> for SimFlashPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operation {
+    Read(usize),
+    Write(usize),
+    Erase(usize),
+}
+
+/// Flash made of `PAGE_SIZE` pages that start out erased.
+///
+/// Operations complete the next time the chip services interrupts. Writes
+/// replace the whole page, as if it had been erased first.
+pub struct SimFlash {
+    pages: RefCell<Vec<[u8; PAGE_SIZE]>>,
+    operation: Cell<Option<Operation>>,
+    buffer: TakeCell<'static, SimFlashPage>,
+    client: OptionalCell<&'static dyn flash::Client<SimFlash>>,
+}
+
+impl SimFlash {
+    pub fn new(num_pages: usize) -> SimFlash {
+        SimFlash {
+            pages: RefCell::new(vec![[0xff; PAGE_SIZE]; num_pages]),
+            operation: Cell::new(None),
+            buffer: TakeCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Change the contents of flash directly, bypassing the `Flash`
+    /// interface, as someone with access to the chip could.
+    pub fn modify_pages<R>(&self, f: impl FnOnce(&mut [[u8; PAGE_SIZE]]) -> R) -> R {
+        f(&mut self.pages.borrow_mut())
+    }
+
+    fn start(&self, operation: Operation, page_number: usize) -> Result<(), ErrorCode> {
+        if self.operation.get().is_some() {
+            Err(ErrorCode::BUSY)
+        } else if page_number >= self.pages.borrow().len() {
+            Err(ErrorCode::INVAL)
+        } else {
+            self.operation.set(Some(operation));
+            Ok(())
+        }
+    }
+}
+
+impl flash::Flash for SimFlash {
+    type Page = SimFlashPage;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut SimFlashPage,
+    ) -> Result<(), (ErrorCode, &'static mut SimFlashPage)> {
+        match self.start(Operation::Read(page_number), page_number) {
+            Ok(()) => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            Err(e) => Err((e, buf)),
+        }
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut SimFlashPage,
+    ) -> Result<(), (ErrorCode, &'static mut SimFlashPage)> {
+        match self.start(Operation::Write(page_number), page_number) {
+            Ok(()) => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            Err(e) => Err((e, buf)),
+        }
+    }
+
+    fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode> {
+        self.start(Operation::Erase(page_number), page_number)
+    }
+}
+
+impl<C: flash::Client<Self>> flash::HasClient<'static, C> for SimFlash {
+    fn set_client(&self, client: &'static C) {
+        self.client.set(client);
+    }
+}
+
+impl SimPeripheral for SimFlash {
+    fn interrupt_pending(&self) -> bool {
+        self.operation.get().is_some()
+    }
+
+    fn service_interrupt(&self) {
+        match self.operation.take() {
+            Some(Operation::Read(page)) => {
+                let buf = self.buffer.take().unwrap();
+                buf.0.copy_from_slice(&self.pages.borrow()[page]);
+                self.client
+                    .map(move |client| client.read_complete(buf, flash::Error::CommandComplete));
+            }
+            Some(Operation::Write(page)) => {
+                let buf = self.buffer.take().unwrap();
+                self.pages.borrow_mut()[page].copy_from_slice(&buf.0);
+                self.client
+                    .map(move |client| client.write_complete(buf, flash::Error::CommandComplete));
+            }
+            Some(Operation::Erase(page)) => {
+                self.pages.borrow_mut()[page] = [0xff; PAGE_SIZE];
+                self.client
+                    .map(|client| client.erase_complete(flash::Error::CommandComplete));
+            }
+            None => {}
+        }
+    }
+
+    fn next_interrupt_us(&self) -> Option<u64> {
+        None
+    }
+}
@@ -0,0 +1,31 @@
+//! Simulated chip for running the Tock kernel on the host.
+//!
+//! This crate implements the chip-specific traits the kernel depends on
+//! (`Chip`, `UserspaceKernelBoundary`, `MPU`, `SchedulerTimer`) along with
+//! fake `Alarm`, `Uart`, `Flash` and `WatchDog` peripherals, all driven by a
+//! simulated clock. It allows the kernel loop, schedulers, and capsules to be
+//! exercised in `cargo test`.
+//!
+//! Processes do not execute real code. Instead, each process runs a script of
+//! [`syscall::AppAction`]s, and the simulated userspace/kernel boundary records
+//! every system call, return value and upcall so that tests can check how the
+//! kernel and capsules responded.
+
+pub mod alarm;
+pub mod chip;
+pub mod clock;
+pub mod flash;
+pub mod memory;
+pub mod mpu;
+pub mod scheduler_timer;
+pub mod syscall;
+pub mod tbf;
+pub mod uart;
+pub mod watchdog;
+
+pub use crate::chip::{run_until, SimChip, SimPeripheral};
+pub use crate::clock::SimClock;
+pub use crate::syscall::{AppAction, SimEvent, SimSysCall};
+
+#[cfg(test)]
+mod tests;
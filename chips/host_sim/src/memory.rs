@@ -0,0 +1,23 @@
+//! Backing storage for simulated flash and RAM.
+//!
+//! Boards place app flash and app memory in `static` sections. On the host
+//! the equivalent is memory that is allocated once and never freed.
+
+use std::mem;
+use std::slice;
+
+/// Turn a flash image into the `&'static [u8]` expected by
+/// `kernel::procs::load_processes()`.
+pub fn leak_flash(image: Vec<u8>) -> &'static [u8] {
+    Box::leak(image.into_boxed_slice())
+}
+
+/// Allocate `len` bytes of zeroed app memory.
+///
+/// The kernel places the process control block and grant pointers inside app
+/// memory, so unlike a plain `Vec<u8>` this buffer is aligned to eight bytes.
+pub fn leak_app_memory(len: usize) -> &'static mut [u8] {
+    let words = (len + mem::size_of::<u64>() - 1) / mem::size_of::<u64>();
+    let backing: &'static mut [u64] = Box::leak(vec![0u64; words].into_boxed_slice());
+    unsafe { slice::from_raw_parts_mut(backing.as_mut_ptr() as *mut u8, len) }
+}
@@ -0,0 +1,168 @@
+//! Simulated memory protection unit.
+
+use core::cell::{Cell, RefCell};
+use core::cmp;
+use core::fmt;
+
+use kernel::mpu::{self, Permissions, Region};
+use kernel::ProcessId;
+
+/// A protected region as requested by the kernel.
+#[derive(Copy, Clone)]
+pub struct SimRegion {
+    pub start: usize,
+    pub size: usize,
+    pub permissions: Permissions,
+}
+
+/// Region configuration for one process.
+#[derive(Clone, Default)]
+pub struct SimMpuConfig {
+    /// Regions allocated with `allocate_region()`, such as app flash.
+    pub regions: Vec<SimRegion>,
+    /// The process-accessible part of the app memory region, which grows and
+    /// shrinks with the app break.
+    pub app_memory: Option<SimRegion>,
+}
+
+impl fmt::Display for SimMpuConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\r\n Simulated MPU")?;
+        for (i, region) in self
+            .regions
+            .iter()
+            .chain(self.app_memory.iter())
+            .enumerate()
+        {
+            write!(
+                f,
+                "\r\n  Region {}: [{:#010X}:{:#010X}], length: {} bytes",
+                i,
+                region.start,
+                region.start + region.size,
+                region.size,
+            )?;
+        }
+        write!(f, "\r\n")
+    }
+}
+
+/// MPU that hands the kernel exactly the regions it asks for and records the
+/// configuration of the process that was last switched to.
+///
+/// It performs no alignment or size rounding and does not check accesses.
+pub struct SimMpu {
+    num_regions: usize,
+    enabled: Cell<bool>,
+    active: RefCell<Option<SimMpuConfig>>,
+}
+
+impl SimMpu {
+    pub const fn new(num_regions: usize) -> SimMpu {
+        SimMpu {
+            num_regions,
+            enabled: Cell::new(false),
+            active: RefCell::new(None),
+        }
+    }
+
+    /// Whether the app MPU is currently enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// The configuration most recently installed with `configure_mpu()`.
+    pub fn active_config(&self) -> Option<SimMpuConfig> {
+        self.active.borrow().clone()
+    }
+}
+
+impl mpu::MPU for SimMpu {
+    type MpuConfig = SimMpuConfig;
+
+    fn clear_mpu(&self) {
+        self.active.replace(None);
+    }
+
+    fn enable_app_mpu(&self) {
+        self.enabled.set(true);
+    }
+
+    fn disable_app_mpu(&self) {
+        self.enabled.set(false);
+    }
+
+    fn number_total_regions(&self) -> usize {
+        self.num_regions
+    }
+
+    fn allocate_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_region_size: usize,
+        permissions: Permissions,
+        config: &mut Self::MpuConfig,
+    ) -> Option<Region> {
+        // One region is always kept for app memory.
+        if config.regions.len() + 1 >= self.num_regions || min_region_size > unallocated_memory_size
+        {
+            return None;
+        }
+        config.regions.push(SimRegion {
+            start: unallocated_memory_start as usize,
+            size: min_region_size,
+            permissions,
+        });
+        Some(Region::new(unallocated_memory_start, min_region_size))
+    }
+
+    fn allocate_app_memory_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_memory_size: usize,
+        initial_app_memory_size: usize,
+        initial_kernel_memory_size: usize,
+        permissions: Permissions,
+        config: &mut Self::MpuConfig,
+    ) -> Option<(*const u8, usize)> {
+        let memory_size = cmp::max(
+            min_memory_size,
+            initial_app_memory_size + initial_kernel_memory_size,
+        );
+        if memory_size > unallocated_memory_size {
+            return None;
+        }
+        config.app_memory = Some(SimRegion {
+            start: unallocated_memory_start as usize,
+            size: initial_app_memory_size,
+            permissions,
+        });
+        Some((unallocated_memory_start, memory_size))
+    }
+
+    fn update_app_memory_region(
+        &self,
+        app_memory_break: *const u8,
+        kernel_memory_break: *const u8,
+        permissions: Permissions,
+        config: &mut Self::MpuConfig,
+    ) -> Result<(), ()> {
+        if (app_memory_break as usize) > (kernel_memory_break as usize) {
+            return Err(());
+        }
+        match config.app_memory.as_mut() {
+            Some(region) => {
+                region.size = app_memory_break as usize - region.start;
+                region.permissions = permissions;
+                Ok(())
+            }
+            None => Err(()),
+        }
+    }
+
+    fn configure_mpu(&self, config: &Self::MpuConfig, _app_id: &ProcessId) {
+        self.active.replace(Some(config.clone()));
+    }
+}
@@ -0,0 +1,69 @@
+//! Scheduler timer measured on the simulated clock.
+
+use core::cell::Cell;
+
+use kernel::SchedulerTimer;
+
+use crate::clock::SimClock;
+
+/// `SchedulerTimer` that expires a fixed number of simulated microseconds
+/// after `start()`.
+///
+/// The simulated userspace boundary asks this timer when the current
+/// timeslice ends so that it can preempt processes that compute for too long.
+pub struct SimSchedulerTimer<'a> {
+    clock: &'a SimClock,
+    expiration: Cell<Option<u64>>,
+    armed: Cell<bool>,
+}
+
+impl<'a> SimSchedulerTimer<'a> {
+    pub const fn new(clock: &'a SimClock) -> SimSchedulerTimer<'a> {
+        SimSchedulerTimer {
+            clock,
+            expiration: Cell::new(None),
+            armed: Cell::new(false),
+        }
+    }
+
+    /// The simulated time at which the timer will interrupt a running
+    /// process, if it is armed.
+    pub fn armed_expiration_us(&self) -> Option<u64> {
+        if self.armed.get() {
+            self.expiration.get()
+        } else {
+            None
+        }
+    }
+}
+
+impl SchedulerTimer for SimSchedulerTimer<'_> {
+    fn start(&self, us: u32) {
+        self.expiration
+            .set(Some(self.clock.now_us() + u64::from(us)));
+    }
+
+    fn reset(&self) {
+        self.expiration.set(None);
+        self.armed.set(false);
+    }
+
+    fn arm(&self) {
+        self.armed.set(true);
+    }
+
+    fn disarm(&self) {
+        self.armed.set(false);
+    }
+
+    fn get_remaining_us(&self) -> Option<u32> {
+        self.expiration.get().and_then(|expiration| {
+            let now = self.clock.now_us();
+            if expiration > now {
+                Some((expiration - now) as u32)
+            } else {
+                None
+            }
+        })
+    }
+}
@@ -0,0 +1,382 @@
+//! Simulated userspace/kernel boundary.
+//!
+//! Simulated processes do not execute instructions. Each process instead
+//! follows a script of [`AppAction`]s. Every time the kernel switches to the
+//! process, the boundary runs actions until one of them returns control to the
+//! kernel (a system call, a fault, or a timeslice expiration). Once the script
+//! is finished the process yields forever.
+//!
+//! Scripts are assigned to processes in the order the processes first start
+//! executing, unless they were added for the app at a specific address in
+//! flash, and are remembered by the address of the app in flash. A restarted
+//! process, or a process loaded again from the same flash, starts its script
+//! over.
+
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::ptr;
+
+use kernel::procs::{FunctionCall, FunctionCallSource};
+use kernel::syscall::{ContextSwitchReason, Syscall, SyscallReturn, UserspaceKernelBoundary};
+
+use crate::clock::SimClock;
+use crate::scheduler_timer::SimSchedulerTimer;
+
+/// Simulated time charged every time a process runs or makes a system call.
+pub const SWITCH_COST_US: u64 = 10;
+
+/// Process-accessible memory every process starts with.
+pub const INITIAL_APP_BRK_SIZE: usize = 1024;
+
+/// One step of a simulated process.
+pub enum AppAction {
+    /// Make this system call.
+    Syscall(Syscall),
+    /// Share `size` bytes at `offset` into process memory with a read-only
+    /// allow.
+    ReadOnlyAllow {
+        driver_number: usize,
+        subdriver_number: usize,
+        offset: usize,
+        size: usize,
+    },
+    /// Share `size` bytes at `offset` into process memory with a read-write
+    /// allow.
+    ReadWriteAllow {
+        driver_number: usize,
+        subdriver_number: usize,
+        offset: usize,
+        size: usize,
+    },
+    /// Make the memop system call `operand` with the address `offset` into
+    /// process memory as its argument.
+    MemopAddress { operand: usize, offset: usize },
+    /// Store `data` at `offset` into process memory. Writing outside of
+    /// process-accessible memory faults the process.
+    WriteMemory { offset: usize, data: Vec<u8> },
+    /// Run for this many microseconds without calling into the kernel. The
+    /// scheduler timer can preempt this action; the rest of the computation
+    /// happens the next time the process runs.
+    Compute(u64),
+    /// Fault the process.
+    Fault,
+}
+
+/// Something a simulated process did, or the kernel did to it. `app` is the
+/// index of the script the process is running.
+#[derive(Copy, Clone, Debug)]
+pub enum SimEvent {
+    Syscall { app: usize, syscall: Syscall },
+    SyscallReturn { app: usize, value: SyscallReturn },
+    FunctionCall { app: usize, call: FunctionCall },
+    Fault { app: usize },
+}
+
+/// Per-process state saved by the kernel across context switches.
+#[derive(Default)]
+pub struct SimStoredState {
+    app: usize,
+    next_action: usize,
+    computed_us: u64,
+}
+
+struct SimApp {
+    flash_start: Option<usize>,
+    script: Vec<AppAction>,
+}
+
+pub struct SimSysCall<'a> {
+    clock: &'a SimClock,
+    scheduler_timer: &'a SimSchedulerTimer<'a>,
+    apps: RefCell<Vec<SimApp>>,
+    events: RefCell<Vec<SimEvent>>,
+}
+
+impl<'a> SimSysCall<'a> {
+    pub fn new(clock: &'a SimClock, scheduler_timer: &'a SimSchedulerTimer<'a>) -> SimSysCall<'a> {
+        SimSysCall {
+            clock,
+            scheduler_timer,
+            apps: RefCell::new(Vec::new()),
+            events: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Add the script for the next process that starts. Returns the app
+    /// index used in `SimEvent`s for that process.
+    pub fn add_app(&self, script: Vec<AppAction>) -> usize {
+        let mut apps = self.apps.borrow_mut();
+        apps.push(SimApp {
+            flash_start: None,
+            script,
+        });
+        apps.len() - 1
+    }
+
+    /// Add the script for the process of the app whose binary starts at
+    /// `flash_start`, after the TBF header. Returns
+    /// the app index used in `SimEvent`s for that process.
+    pub fn add_app_at(&self, flash_start: usize, script: Vec<AppAction>) -> usize {
+        let mut apps = self.apps.borrow_mut();
+        apps.push(SimApp {
+            flash_start: Some(flash_start),
+            script,
+        });
+        apps.len() - 1
+    }
+
+    /// Pick the script for a process that is starting from `flash_start`.
+    fn bind_app(&self, flash_start: usize) -> usize {
+        let mut apps = self.apps.borrow_mut();
+        let app = match apps
+            .iter()
+            .position(|app| app.flash_start == Some(flash_start))
+        {
+            Some(app) => app,
+            None => match apps.iter().position(|app| app.flash_start.is_none()) {
+                Some(app) => app,
+                None => {
+                    // More processes than scripts. The extra processes just
+                    // wait.
+                    apps.push(SimApp {
+                        flash_start: None,
+                        script: Vec::new(),
+                    });
+                    apps.len() - 1
+                }
+            },
+        };
+        apps[app].flash_start = Some(flash_start);
+        app
+    }
+
+    /// Every event recorded so far.
+    pub fn events(&self) -> Vec<SimEvent> {
+        self.events.borrow().clone()
+    }
+
+    /// The events recorded so far for app `app`.
+    pub fn app_events(&self, app: usize) -> Vec<SimEvent> {
+        self.events
+            .borrow()
+            .iter()
+            .filter(|event| event.app() == app)
+            .copied()
+            .collect()
+    }
+
+    fn record(&self, event: SimEvent) {
+        self.events.borrow_mut().push(event);
+    }
+
+    /// Run the script for a process until control returns to the kernel.
+    fn run(
+        &self,
+        accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &mut SimStoredState,
+    ) -> ContextSwitchReason {
+        let apps = self.apps.borrow();
+        let script = &apps[state.app].script;
+        let accessible_len = app_brk as usize - accessible_memory_start as usize;
+        let fault = |state: &SimStoredState| {
+            self.record(SimEvent::Fault { app: state.app });
+            ContextSwitchReason::Fault
+        };
+
+        loop {
+            self.clock.advance_us(SWITCH_COST_US);
+
+            let action = match script.get(state.next_action) {
+                Some(action) => action,
+                None => {
+                    // The script is done, wait for upcalls forever.
+                    let syscall = Syscall::Yield {
+                        which: 1,
+                        address: ptr::null_mut(),
+                    };
+                    self.record(SimEvent::Syscall {
+                        app: state.app,
+                        syscall,
+                    });
+                    return ContextSwitchReason::SyscallFired { syscall };
+                }
+            };
+
+            if let AppAction::Compute(us) = action {
+                let remaining = us - state.computed_us;
+                let now = self.clock.now_us();
+                match self.scheduler_timer.armed_expiration_us() {
+                    Some(expiration) if now + remaining >= expiration => {
+                        // Preempted part way through.
+                        state.computed_us += expiration.saturating_sub(now);
+                        self.clock.advance_to_us(expiration);
+                        return ContextSwitchReason::Interrupted;
+                    }
+                    _ => {
+                        self.clock.advance_us(remaining);
+                        state.computed_us = 0;
+                        state.next_action += 1;
+                        continue;
+                    }
+                }
+            }
+
+            state.next_action += 1;
+            let syscall = match action {
+                AppAction::Syscall(syscall) => *syscall,
+                AppAction::ReadOnlyAllow {
+                    driver_number,
+                    subdriver_number,
+                    offset,
+                    size,
+                } => Syscall::ReadOnlyAllow {
+                    driver_number: *driver_number,
+                    subdriver_number: *subdriver_number,
+                    allow_address: accessible_memory_start.wrapping_add(*offset),
+                    allow_size: *size,
+                },
+                AppAction::ReadWriteAllow {
+                    driver_number,
+                    subdriver_number,
+                    offset,
+                    size,
+                } => Syscall::ReadWriteAllow {
+                    driver_number: *driver_number,
+                    subdriver_number: *subdriver_number,
+                    allow_address: accessible_memory_start.wrapping_add(*offset) as *mut u8,
+                    allow_size: *size,
+                },
+                AppAction::MemopAddress { operand, offset } => Syscall::Memop {
+                    operand: *operand,
+                    arg0: accessible_memory_start as usize + *offset,
+                },
+                AppAction::WriteMemory { offset, data } => {
+                    if offset + data.len() > accessible_len {
+                        return fault(state);
+                    }
+                    unsafe {
+                        ptr::copy_nonoverlapping(
+                            data.as_ptr(),
+                            (accessible_memory_start as *mut u8).add(*offset),
+                            data.len(),
+                        );
+                    }
+                    continue;
+                }
+                AppAction::Fault => return fault(state),
+                AppAction::Compute(_) => unreachable!(),
+            };
+            self.record(SimEvent::Syscall {
+                app: state.app,
+                syscall,
+            });
+            return ContextSwitchReason::SyscallFired { syscall };
+        }
+    }
+}
+
+impl SimEvent {
+    /// Index of the app this event belongs to.
+    pub fn app(&self) -> usize {
+        match *self {
+            SimEvent::Syscall { app, .. }
+            | SimEvent::SyscallReturn { app, .. }
+            | SimEvent::FunctionCall { app, .. }
+            | SimEvent::Fault { app } => app,
+        }
+    }
+}
+
+impl UserspaceKernelBoundary for SimSysCall<'_> {
+    type StoredState = SimStoredState;
+
+    fn initial_process_app_brk_size(&self) -> usize {
+        INITIAL_APP_BRK_SIZE
+    }
+
+    unsafe fn initialize_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+    ) -> Result<(), ()> {
+        // The script is chosen once the process starts, see
+        // `set_process_function()`.
+        *state = SimStoredState::default();
+        Ok(())
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+        return_value: SyscallReturn,
+    ) -> Result<(), ()> {
+        self.record(SimEvent::SyscallReturn {
+            app: state.app,
+            value: return_value,
+        });
+        Ok(())
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+        upcall: FunctionCall,
+    ) -> Result<(), ()> {
+        if let FunctionCallSource::Kernel = upcall.source {
+            // The kernel only calls into the process directly to start it.
+            // The first argument is the start of the app in flash.
+            *state = SimStoredState {
+                app: self.bind_app(upcall.argument0),
+                next_action: 0,
+                computed_us: 0,
+            };
+        }
+        self.record(SimEvent::FunctionCall {
+            app: state.app,
+            call: upcall,
+        });
+        Ok(())
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &mut Self::StoredState,
+    ) -> (ContextSwitchReason, Option<*const u8>) {
+        (self.run(accessible_memory_start, app_brk, state), None)
+    }
+
+    /// Stores the app and the index of its next action.
+    unsafe fn store_registers(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &Self::StoredState,
+        registers: &mut [u32],
+    ) -> usize {
+        let values = [state.app as u32, state.next_action as u32];
+        let count = values.len().min(registers.len());
+        registers[..count].copy_from_slice(&values[..count]);
+        count
+    }
+
+    unsafe fn print_context(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &Self::StoredState,
+        writer: &mut dyn Write,
+    ) {
+        let _ = writer.write_fmt(format_args!(
+            "\r\n Simulated app {}, next action {}, computed {} us\r\n",
+            state.app, state.next_action, state.computed_us,
+        ));
+    }
+}
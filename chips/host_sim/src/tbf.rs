@@ -0,0 +1,205 @@
+//! Construction of Tock Binary Format images for simulated processes.
+//!
+//! Simulated processes have no code, so an app is just a TBF header followed
+//! by padding. The header is version 2 with a Main TLV and, optionally, a
+//! Package Name TLV and any raw TLVs a test needs. Apps with credentials
+//! footers use a Program TLV instead of the Main TLV so that the footers can
+//! follow the binary.
+
+use std::convert::TryFrom;
+
+use capsules::sha256::{self, Sha256};
+
+const TBF_VERSION: u16 = 2;
+const TBF_BASE_HEADER_SIZE: usize = 16;
+const TBF_FLAG_ENABLED: u32 = 1;
+
+const TLV_MAIN: u16 = 1;
+const TLV_PACKAGE_NAME: u16 = 3;
+const TLV_PROGRAM: u16 = 9;
+const TLV_CREDENTIALS: u16 = 128;
+
+const CREDENTIALS_SHA256: u32 = 1;
+const CREDENTIALS_HMAC_SHA256: u32 = 2;
+
+/// Credentials footer to append to an app.
+enum Footer {
+    /// Credentials with a fixed format and contents.
+    Raw(u32, Vec<u8>),
+    /// SHA-256 of the app.
+    Sha256,
+    /// HMAC-SHA256 of the app with this key.
+    HmacSha256([u8; 32]),
+}
+
+impl Footer {
+    /// Size of the footer in flash, including the TLV header and padding.
+    fn size(&self) -> usize {
+        let data_len = match self {
+            Footer::Raw(_, data) => data.len(),
+            Footer::Sha256 | Footer::HmacSha256(_) => 32,
+        };
+        4 + 4 + ((data_len + 3) & !3)
+    }
+}
+
+/// Builder for a single TBF app.
+pub struct TbfBuilder {
+    package_name: Option<String>,
+    minimum_ram_size: u32,
+    total_size: u32,
+    enabled: bool,
+    tlvs: Vec<(u16, Vec<u8>)>,
+    footers: Vec<Footer>,
+}
+
+impl TbfBuilder {
+    pub fn new() -> TbfBuilder {
+        TbfBuilder {
+            package_name: None,
+            minimum_ram_size: 4096,
+            total_size: 1024,
+            enabled: true,
+            tlvs: Vec::new(),
+            footers: Vec::new(),
+        }
+    }
+
+    pub fn package_name(mut self, name: &str) -> TbfBuilder {
+        self.package_name = Some(String::from(name));
+        self
+    }
+
+    /// RAM requested in the Main TLV.
+    pub fn minimum_ram_size(mut self, size: u32) -> TbfBuilder {
+        self.minimum_ram_size = size;
+        self
+    }
+
+    /// Size of the whole app in flash, header included.
+    pub fn total_size(mut self, size: u32) -> TbfBuilder {
+        self.total_size = size;
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> TbfBuilder {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Append a TLV with arbitrary type and contents. Padding to a 4 byte
+    /// boundary is added automatically.
+    pub fn tlv(mut self, tipe: u16, value: &[u8]) -> TbfBuilder {
+        self.tlvs.push((tipe, value.to_vec()));
+        self
+    }
+
+    /// Append a credentials footer with arbitrary format and contents.
+    pub fn credentials(mut self, format: u32, data: &[u8]) -> TbfBuilder {
+        self.footers.push(Footer::Raw(format, data.to_vec()));
+        self
+    }
+
+    /// Append a credentials footer with the SHA-256 of the app.
+    pub fn sha256_credentials(mut self) -> TbfBuilder {
+        self.footers.push(Footer::Sha256);
+        self
+    }
+
+    /// Append a credentials footer with the HMAC-SHA256 of the app.
+    pub fn hmac_sha256_credentials(mut self, key: [u8; 32]) -> TbfBuilder {
+        self.footers.push(Footer::HmacSha256(key));
+        self
+    }
+
+    /// Produce the app image, with a valid header checksum and credentials.
+    pub fn build(&self) -> Vec<u8> {
+        let mut tlvs = Vec::new();
+
+        let mut main = Vec::new();
+        // init_fn_offset, protected_size (none beyond the header),
+        // minimum_ram_size.
+        main.extend_from_slice(&0u32.to_le_bytes());
+        main.extend_from_slice(&0u32.to_le_bytes());
+        main.extend_from_slice(&self.minimum_ram_size.to_le_bytes());
+        if self.footers.is_empty() {
+            push_tlv(&mut tlvs, TLV_MAIN, &main);
+        } else {
+            // binary_end_offset, filled in below once the size of the header
+            // is known, and version.
+            main.extend_from_slice(&0u32.to_le_bytes());
+            main.extend_from_slice(&0u32.to_le_bytes());
+            push_tlv(&mut tlvs, TLV_PROGRAM, &main);
+        }
+
+        if let Some(name) = &self.package_name {
+            push_tlv(&mut tlvs, TLV_PACKAGE_NAME, name.as_bytes());
+        }
+        for (tipe, value) in self.tlvs.iter() {
+            push_tlv(&mut tlvs, *tipe, value);
+        }
+
+        let header_size = TBF_BASE_HEADER_SIZE + tlvs.len();
+        let footers_size: usize = self.footers.iter().map(Footer::size).sum();
+        let total_size = std::cmp::max(self.total_size as usize, header_size + footers_size);
+        let binary_end = total_size - footers_size;
+        if !self.footers.is_empty() {
+            tlvs[16..20].copy_from_slice(&(binary_end as u32).to_le_bytes());
+        }
+        let flags = if self.enabled { TBF_FLAG_ENABLED } else { 0 };
+
+        let mut image = Vec::with_capacity(total_size);
+        image.extend_from_slice(&TBF_VERSION.to_le_bytes());
+        image.extend_from_slice(&u16::try_from(header_size).unwrap().to_le_bytes());
+        image.extend_from_slice(&(total_size as u32).to_le_bytes());
+        image.extend_from_slice(&flags.to_le_bytes());
+        image.extend_from_slice(&0u32.to_le_bytes());
+        image.extend_from_slice(&tlvs);
+
+        let checksum = image
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(i, _)| *i != 3)
+            .fold(0u32, |checksum, (_, word)| {
+                checksum ^ u32::from_le_bytes([word[0], word[1], word[2], word[3]])
+            });
+        image[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+        image.resize(binary_end, 0);
+        for footer in self.footers.iter() {
+            let (format, data) = match footer {
+                Footer::Raw(format, data) => (*format, data.clone()),
+                Footer::Sha256 => {
+                    let mut sha = Sha256::new();
+                    sha.update(&image[..binary_end]);
+                    (CREDENTIALS_SHA256, sha.finish().to_vec())
+                }
+                Footer::HmacSha256(key) => (
+                    CREDENTIALS_HMAC_SHA256,
+                    sha256::hmac_sha256(key, &image[..binary_end]).to_vec(),
+                ),
+            };
+            let mut value = format.to_le_bytes().to_vec();
+            value.extend_from_slice(&data);
+            push_tlv(&mut image, TLV_CREDENTIALS, &value);
+        }
+        image
+    }
+}
+
+fn push_tlv(buffer: &mut Vec<u8>, tipe: u16, value: &[u8]) {
+    buffer.extend_from_slice(&tipe.to_le_bytes());
+    buffer.extend_from_slice(&u16::try_from(value.len()).unwrap().to_le_bytes());
+    buffer.extend_from_slice(value);
+    while buffer.len() % 4 != 0 {
+        buffer.push(0);
+    }
+}
+
+/// Concatenate apps into a flash image. The image ends with an invalid
+/// header so that process loading stops after the last app.
+pub fn flash_image(apps: &[Vec<u8>]) -> Vec<u8> {
+    let mut image: Vec<u8> = apps.iter().flatten().copied().collect();
+    image.extend_from_slice(&[0; 8]);
+    image
+}
@@ -0,0 +1,2880 @@
+//! Tests that run the kernel loop on the simulated chip.
+
+use capsules::alarm::{self, AlarmDriver};
+use capsules::console::{self, Console};
+use capsules::kv_store::{self, KVStore, KVStoreDriver};
+use capsules::process_console::{ConsoleCommand, ConsoleCommandClient, ProcessConsole};
+use capsules::tickv::{TicKVKeyType, TicKVStore};
+use kernel::capabilities;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::RingBuffer;
+use kernel::crash_dump::{CrashDump, CrashKind};
+use kernel::debug::{self, DebugWriter, DebugWriterWrapper};
+use kernel::heartbeat::{self, HeartbeatResponse, ProcessHeartbeats};
+use kernel::hil::flash::HasClient;
+use kernel::hil::kv_store::KVStore as _;
+use kernel::hil::kv_system::{self, KVSystem};
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
+use kernel::hil::time::{Alarm, Freq32KHz, Ticks24, Time};
+use kernel::hil::uart::{Receive, Transmit};
+use kernel::introspection::KernelInfo;
+use kernel::ipc::{self, IPC};
+use kernel::ipc_mailbox::{self, IPCMailbox};
+use kernel::power::{PowerManager, SleepState, SleepVote};
+use kernel::procs::{self, FaultResponse, FunctionCallSource, Process};
+use kernel::profile::{KernelProfile, LatencyHistogram};
+use kernel::syscall::{Syscall, SyscallReturn, SyscallReturnVariant};
+use kernel::trace::{self, KernelTrace, SwitchFromReason, TraceKind};
+use kernel::upcall_overflow::{self, UpcallOverflow};
+use kernel::{
+    Chip, CustomGrant, Driver, EDFProcessNode, EDFSched, ErrorCode, Grant, Kernel, PinnedAppSlice,
+    Platform, Read, ReadOnlyAppSlice, ReadWriteAppSlice, RealTimePolicy, RoundRobinProcessNode,
+    RoundRobinSched, Scheduler, Upcall,
+};
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tickv::encryption::{Aes128Ccm, HEADER_LEN, OVERHEAD};
+
+use crate::alarm::SimAlarm;
+use crate::chip::{run_until, SimChip, SimPeripheral};
+use crate::clock::SimClock;
+use crate::flash::{SimFlash, SimFlashPage, PAGE_SIZE};
+use crate::memory::leak_app_memory;
+use crate::scheduler_timer::SimSchedulerTimer;
+use crate::syscall::{AppAction, SimEvent, SimSysCall, SWITCH_COST_US};
+use crate::tbf::{flash_image, TbfBuilder};
+use crate::uart::SimUart;
+
+const NUM_PROCS: usize = 4;
+const MAX_ITERATIONS: usize = 1000;
+const APP_FLASH_SIZE: usize = 16 * 1024;
+const APP_MEMORY_SIZE: usize = 64 * 1024;
+
+struct TestCapability;
+unsafe impl capabilities::MainLoopCapability for TestCapability {}
+unsafe impl capabilities::MemoryAllocationCapability for TestCapability {}
+unsafe impl capabilities::ProcessManagementCapability for TestCapability {}
+
+/// Grant state of the tests that use grants directly, rather than through a
+/// capsule.
+#[derive(Default)]
+struct TestGrant {
+    value: u32,
+    buffer: Option<CustomGrant<[u32; 8]>>,
+}
+
+/// Bytes the kernel keeps in front of every grant allocation.
+const GRANT_BLOCK_HEADER_LEN: usize = 8;
+
+/// How many `TestGrant`s were dropped.
+static TEST_GRANTS_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+impl Drop for TestGrant {
+    fn drop(&mut self) {
+        TEST_GRANTS_DROPPED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Driver number of `UpcallSource`.
+const UPCALL_SOURCE_DRIVER_NUM: usize = 0xa000;
+
+#[derive(Default)]
+struct UpcallSourceData {
+    upcall: Upcall,
+}
+
+/// Driver that schedules upcalls on command, as fast as a busy sensor would.
+struct UpcallSource {
+    data: Grant<UpcallSourceData>,
+    clock: &'static SimClock,
+}
+
+impl Driver for UpcallSource {
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut upcall: Upcall,
+        app_id: kernel::ProcessId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        match subscribe_num {
+            0 => match self.data.enter(app_id, |data| {
+                core::mem::swap(&mut data.upcall, &mut upcall)
+            }) {
+                Ok(()) => Ok(upcall),
+                Err(e) => Err((upcall, e.into())),
+            },
+            _ => Err((upcall, ErrorCode::NOSUPPORT)),
+        }
+    }
+
+    /// Command `1` schedules `arg1` upcalls with the arguments 0, 1, ..., and
+    /// coalesces them if `arg2` is set. Command `2` takes `arg1`
+    /// microseconds, as a slow driver would.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        app_id: kernel::ProcessId,
+    ) -> kernel::CommandReturn {
+        match command_num {
+            1 => self
+                .data
+                .enter(app_id, |data| {
+                    for i in 0..arg1 {
+                        if arg2 != 0 {
+                            data.upcall.schedule_coalesced(i, 0, 0);
+                        } else {
+                            data.upcall.schedule(i, 0, 0);
+                        }
+                    }
+                })
+                .map_err(ErrorCode::from)
+                .into(),
+            2 => {
+                self.clock.advance_us(arg1 as u64);
+                kernel::CommandReturn::success()
+            }
+            _ => kernel::CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
+
+/// Driver number of `DmaDevice`.
+const DMA_DEVICE_DRIVER_NUM: usize = 0xa001;
+
+#[derive(Default)]
+struct DmaDeviceData {
+    buffer: ReadWriteAppSlice,
+}
+
+/// Driver that pins the buffer a process allows and lends it to a device
+/// that accesses it directly, until the test finishes the transfer.
+struct DmaDevice {
+    data: Grant<DmaDeviceData>,
+    pinned: MapCell<PinnedAppSlice>,
+    device_buffer: TakeCell<'static, [u8]>,
+}
+
+impl DmaDevice {
+    /// Write `data` into the lent buffer as the device would, give the
+    /// buffer back and unpin it.
+    fn finish_transfer(&self, data: &[u8]) {
+        let buffer = self.device_buffer.take().unwrap();
+        buffer[..data.len()].copy_from_slice(data);
+        let mut pinned = self.pinned.take().unwrap();
+        assert!(pinned.restore(buffer).is_ok());
+    }
+}
+
+impl Driver for DmaDevice {
+    fn allow_readwrite(
+        &self,
+        app_id: kernel::ProcessId,
+        which: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        match which {
+            0 => match self
+                .data
+                .enter(app_id, |data| core::mem::swap(&mut data.buffer, &mut slice))
+            {
+                Ok(()) => Ok(slice),
+                Err(e) => Err((slice, e.into())),
+            },
+            _ => Err((slice, ErrorCode::NOSUPPORT)),
+        }
+    }
+
+    fn allow_readonly(
+        &self,
+        _app_id: kernel::ProcessId,
+        _which: usize,
+        slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        Ok(slice)
+    }
+
+    /// Command `1` pins the allowed buffer and lends it to the device.
+    /// Command `2` returns the length of the allowed buffer the driver can
+    /// access.
+    fn command(
+        &self,
+        command_num: usize,
+        _arg1: usize,
+        _arg2: usize,
+        app_id: kernel::ProcessId,
+    ) -> kernel::CommandReturn {
+        match command_num {
+            1 => self
+                .data
+                .enter(app_id, |data| {
+                    data.buffer.pin().map(|mut pinned| {
+                        self.device_buffer.replace(pinned.take().unwrap());
+                        self.pinned.replace(pinned);
+                    })
+                })
+                .unwrap_or_else(|e| Err(e.into()))
+                .into(),
+            2 => self
+                .data
+                .enter(app_id, |data| data.buffer.map_or(0, |buffer| buffer.len()))
+                .map_or_else(
+                    |e| kernel::CommandReturn::failure(e.into()),
+                    |len| kernel::CommandReturn::success_u32(len as u32),
+                ),
+            _ => kernel::CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
+
+type SimKVStore = KVStore<'static, TicKVStore<'static, SimFlash>, TicKVKeyType>;
+
+/// Number of flash pages the key-value store uses.
+const KV_STORE_PAGES: usize = 16;
+
+struct SimPlatform {
+    alarm: &'static AlarmDriver<'static, SimAlarm<'static>>,
+    console: &'static Console<'static>,
+    ipc_mailbox: &'static IPCMailbox<16, 2>,
+    ipc: &'static IPC<NUM_PROCS>,
+    heartbeats: &'static ProcessHeartbeats,
+    upcall_overflow: &'static UpcallOverflow,
+    upcall_source: &'static UpcallSource,
+    dma_device: &'static DmaDevice,
+    kv_store: &'static KVStoreDriver<'static, SimKVStore>,
+}
+
+impl Platform for SimPlatform {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn Driver>) -> R,
+    {
+        match driver_num {
+            alarm::DRIVER_NUM => f(Some(self.alarm)),
+            console::DRIVER_NUM => f(Some(self.console)),
+            ipc_mailbox::DRIVER_NUM => f(Some(self.ipc_mailbox)),
+            ipc::DRIVER_NUM => f(Some(self.ipc)),
+            heartbeat::DRIVER_NUM => f(Some(self.heartbeats)),
+            upcall_overflow::DRIVER_NUM => f(Some(self.upcall_overflow)),
+            UPCALL_SOURCE_DRIVER_NUM => f(Some(self.upcall_source)),
+            DMA_DEVICE_DRIVER_NUM => f(Some(self.dma_device)),
+            kv_store::DRIVER_NUM => f(Some(self.kv_store)),
+            _ => f(None),
+        }
+    }
+}
+
+struct Sim {
+    kernel: &'static Kernel,
+    chip: &'static SimChip,
+    clock: &'static SimClock,
+    sim_alarm: &'static SimAlarm<'static>,
+    syscall: &'static SimSysCall<'static>,
+    uart: &'static SimUart<'static>,
+    platform: SimPlatform,
+    /// Grants number 2 and 3, after those of the alarm and console drivers.
+    grants: [&'static Grant<TestGrant>; 2],
+    processes: &'static [Option<&'static dyn Process>; NUM_PROCS],
+    scheduler: &'static RoundRobinSched<'static>,
+    // Boards keep these in `static mut`s and hand them to the kernel more
+    // than once. The simulation does the same with raw pointers.
+    processes_mut: *mut [Option<&'static dyn Process>; NUM_PROCS],
+    app_flash: *mut [u8],
+    app_flash_used: Cell<usize>,
+    app_memory: *mut [u8],
+}
+
+fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+impl Sim {
+    /// Load one process per script and set up a round robin scheduler.
+    fn new(scripts: Vec<Vec<AppAction>>, fault_response: FaultResponse) -> Sim {
+        let clock = leak(SimClock::new());
+        let scheduler_timer = leak(SimSchedulerTimer::new(clock));
+        let syscall = leak(SimSysCall::new(clock, scheduler_timer));
+        let chip = leak(SimChip::new(clock, syscall, scheduler_timer));
+
+        // Boards share the processes array between the kernel and
+        // `load_processes()` the same way.
+        let processes: *mut [Option<&'static dyn Process>; NUM_PROCS] =
+            Box::into_raw(Box::new([None; NUM_PROCS]));
+        let kernel = leak(Kernel::new(unsafe { &*processes }));
+
+        let sim_alarm = leak(SimAlarm::new(clock));
+        chip.add_peripheral(sim_alarm);
+        let alarm = leak(AlarmDriver::new(
+            sim_alarm,
+            kernel.create_grant(&TestCapability),
+        ));
+        sim_alarm.set_alarm_client(alarm);
+
+        let uart = leak(SimUart::new());
+        chip.add_peripheral(uart);
+        let console = leak(Console::new(
+            uart,
+            Box::leak(vec![0; 64].into_boxed_slice()),
+            Box::leak(vec![0; 64].into_boxed_slice()),
+            kernel.create_grant(&TestCapability),
+        ));
+        uart.set_transmit_client(console);
+        uart.set_receive_client(console);
+        let grants: [&'static Grant<TestGrant>; 2] = [
+            leak(kernel.create_grant(&TestCapability)),
+            leak(kernel.create_grant(&TestCapability)),
+        ];
+        let ipc_mailbox = leak(IPCMailbox::new(kernel, &TestCapability));
+        kernel.set_ipc_mailbox(ipc_mailbox, &TestCapability);
+        let ipc = leak(IPC::new(kernel, &TestCapability));
+        // Only checked once a test hands it to the kernel.
+        let heartbeats = leak(ProcessHeartbeats::new(
+            kernel,
+            sim_alarm,
+            HeartbeatResponse::FaultProcess,
+            &TestCapability,
+        ));
+        let upcall_overflow = leak(UpcallOverflow::new(kernel, &TestCapability));
+        kernel.set_upcall_overflow(upcall_overflow, &TestCapability);
+        let upcall_source = leak(UpcallSource {
+            data: kernel.create_grant(&TestCapability),
+            clock,
+        });
+        let dma_device = leak(DmaDevice {
+            data: kernel.create_grant(&TestCapability),
+            pinned: MapCell::empty(),
+            device_buffer: TakeCell::empty(),
+        });
+
+        let flash = leak(SimFlash::new(KV_STORE_PAGES));
+        chip.add_peripheral(flash);
+        let tickv = leak(TicKVStore::new(
+            flash,
+            Box::leak(Box::new([0; PAGE_SIZE])),
+            Box::leak(Box::new(SimFlashPage::default())),
+            0,
+            KV_STORE_PAGES * PAGE_SIZE,
+        ));
+        flash.set_client(tickv);
+        tickv.initalise();
+        let kv_store = leak(KVStore::new(
+            tickv,
+            Box::leak(Box::new([0; 8])),
+            Box::leak(vec![0; PAGE_SIZE].into_boxed_slice()),
+        ));
+        tickv.set_client(kv_store);
+        let kv_store_driver = leak(KVStoreDriver::new(
+            kv_store,
+            kernel.create_grant(&TestCapability),
+            Box::leak(vec![0; 32].into_boxed_slice()),
+            Box::leak(vec![0; 64].into_boxed_slice()),
+        ));
+        kv_store.set_client(kv_store_driver);
+
+        let apps: Vec<Vec<u8>> = scripts
+            .into_iter()
+            .map(|script| {
+                let app = syscall.add_app(script);
+                TbfBuilder::new()
+                    .package_name(&format!("app{}", app))
+                    .build()
+            })
+            .collect();
+        // Leave room in flash for apps installed later.
+        let mut image = flash_image(&apps);
+        let app_flash_used = image.len() - 8;
+        image.resize(APP_FLASH_SIZE, 0);
+        let app_flash: *mut [u8] = Box::into_raw(image.into_boxed_slice());
+        let app_memory: *mut [u8] = leak_app_memory(APP_MEMORY_SIZE);
+        procs::load_processes(
+            kernel,
+            chip,
+            unsafe { &*app_flash },
+            unsafe { &mut *app_memory },
+            unsafe { &mut *processes },
+            fault_response,
+            &TestCapability,
+        )
+        .unwrap();
+
+        let processes = unsafe { &*processes };
+        let scheduler = leak(RoundRobinSched::new());
+        for process in processes.iter() {
+            scheduler
+                .processes
+                .push_tail(leak(RoundRobinProcessNode::new(process)));
+        }
+
+        Sim {
+            kernel,
+            chip,
+            clock,
+            sim_alarm,
+            syscall,
+            uart,
+            platform: SimPlatform {
+                alarm,
+                console,
+                ipc_mailbox,
+                ipc,
+                heartbeats,
+                upcall_overflow,
+                upcall_source,
+                dma_device,
+                kv_store: kv_store_driver,
+            },
+            grants,
+            processes,
+            scheduler,
+            processes_mut: processes as *const _ as *mut _,
+            app_flash,
+            app_flash_used: Cell::new(app_flash_used),
+            app_memory,
+        }
+    }
+
+    /// Write an app into free flash after the apps that are already there,
+    /// as an over-the-air update would. Returns the address of the app.
+    fn install_app(&self, app: Vec<u8>) -> usize {
+        let flash = unsafe { &mut *self.app_flash };
+        let start = self.app_flash_used.get();
+        flash[start..start + app.len()].copy_from_slice(&app);
+        self.app_flash_used.set(start + app.len());
+        flash[start..].as_ptr() as usize
+    }
+
+    /// Install an app together with the script its process runs, no matter
+    /// in which order processes start. Returns the app index of the script.
+    fn install_app_with_script(&self, app: Vec<u8>, script: Vec<AppAction>) -> usize {
+        // Processes start at the end of the TBF header.
+        let header_size = u16::from_le_bytes([app[2], app[3]]) as usize;
+        let flash_start = self.install_app(app);
+        self.syscall.add_app_at(flash_start + header_size, script)
+    }
+
+    fn load_new_process(&self) -> Result<Option<&'static dyn Process>, procs::ProcessLoadError> {
+        self.load_new_checked_process(&procs::AllowAllApps::new())
+    }
+
+    fn load_new_process_with(
+        &self,
+        fault_response: FaultResponse,
+    ) -> Result<Option<&'static dyn Process>, procs::ProcessLoadError> {
+        procs::load_new_process(
+            self.kernel,
+            self.chip,
+            unsafe { &*self.app_flash },
+            unsafe { &mut *self.app_memory },
+            unsafe { &mut *self.processes_mut },
+            fault_response,
+            &procs::AllowAllApps::new(),
+            &TestCapability,
+        )
+    }
+
+    fn load_new_checked_process(
+        &self,
+        policy: &dyn procs::AppCredentialsPolicy,
+    ) -> Result<Option<&'static dyn Process>, procs::ProcessLoadError> {
+        procs::load_new_process(
+            self.kernel,
+            self.chip,
+            unsafe { &*self.app_flash },
+            unsafe { &mut *self.app_memory },
+            unsafe { &mut *self.processes_mut },
+            FaultResponse::Stop,
+            policy,
+            &TestCapability,
+        )
+    }
+
+    fn unload_process(&self, processid: kernel::ProcessId) -> Result<(), ErrorCode> {
+        procs::unload_process(
+            unsafe { &mut *self.processes_mut },
+            processid,
+            &TestCapability,
+        )
+    }
+
+    fn run_until<F: Fn() -> bool>(&self, done: F) -> bool {
+        self.run_scheduler_until(self.scheduler, done)
+    }
+
+    /// Run the kernel with a scheduler other than the default round robin
+    /// scheduler.
+    fn run_scheduler_until<SC: Scheduler<SimChip>, F: Fn() -> bool>(
+        &self,
+        scheduler: &SC,
+        done: F,
+    ) -> bool {
+        run_until(
+            self.kernel,
+            &self.platform,
+            self.chip,
+            Some(self.platform.ipc),
+            scheduler,
+            MAX_ITERATIONS,
+            &TestCapability,
+            done,
+        )
+    }
+
+    /// A real-time scheduler for the process slots of the simulation.
+    fn edf_scheduler(
+        &self,
+        policy: RealTimePolicy,
+    ) -> &'static EDFSched<'static, SimAlarm<'static>> {
+        let scheduler = leak(EDFSched::new(self.sim_alarm, policy));
+        for process in self.processes.iter() {
+            scheduler
+                .processes
+                .push_tail(leak(EDFProcessNode::new(process)));
+        }
+        scheduler
+    }
+
+    fn process(&self, index: usize) -> &'static dyn Process {
+        self.processes[index].unwrap()
+    }
+
+    /// The upcalls (as opposed to the initial function call) delivered to
+    /// `app`.
+    fn upcalls(&self, app: usize) -> Vec<procs::FunctionCall> {
+        self.syscall
+            .app_events(app)
+            .into_iter()
+            .filter_map(|event| match event {
+                SimEvent::FunctionCall { call, .. } => match call.source {
+                    FunctionCallSource::Driver(_) => Some(call),
+                    FunctionCallSource::Kernel => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn command(driver_number: usize, subdriver_number: usize, arg0: usize, arg1: usize) -> AppAction {
+    AppAction::Syscall(Syscall::Command {
+        driver_number,
+        subdriver_number,
+        arg0,
+        arg1,
+    })
+}
+
+fn subscribe(
+    driver_number: usize,
+    subdriver_number: usize,
+    upcall: usize,
+    appdata: usize,
+) -> AppAction {
+    AppAction::Syscall(Syscall::Subscribe {
+        driver_number,
+        subdriver_number,
+        upcall_ptr: upcall as *mut (),
+        appdata,
+    })
+}
+
+fn yield_wait() -> AppAction {
+    AppAction::Syscall(Syscall::Yield {
+        which: 1,
+        address: std::ptr::null_mut(),
+    })
+}
+
+fn is_command(event: &SimEvent) -> bool {
+    matches!(
+        event,
+        SimEvent::Syscall {
+            syscall: Syscall::Command { .. },
+            ..
+        }
+    )
+}
+
+fn count_commands(events: &[SimEvent]) -> usize {
+    events.iter().filter(|e| is_command(e)).count()
+}
+
+#[test]
+fn round_robin_preempts_long_running_processes() {
+    // Each process needs one and a half timeslices before it reaches its
+    // system call.
+    let sim = Sim::new(
+        vec![
+            vec![AppAction::Compute(15_000), command(0xbad, 0, 0, 0)],
+            vec![AppAction::Compute(15_000), command(0xbad, 0, 0, 0)],
+        ],
+        FaultResponse::Stop,
+    );
+
+    let commands_issued = || {
+        sim.syscall
+            .events()
+            .iter()
+            .filter(|e| is_command(e))
+            .count()
+    };
+    assert!(sim.run_until(|| commands_issued() == 2));
+
+    let commands: Vec<usize> = sim
+        .syscall
+        .events()
+        .iter()
+        .filter(|e| is_command(e))
+        .map(SimEvent::app)
+        .collect();
+    assert_eq!(commands, vec![0, 1]);
+    assert_eq!(sim.process(0).debug_timeslice_expiration_count(), 1);
+    assert_eq!(sim.process(1).debug_timeslice_expiration_count(), 1);
+    // Together the processes computed for 30 ms.
+    assert!(sim.chip.clock().now_us() >= 30_000);
+    assert!(sim.syscall.app_events(0).iter().any(|e| matches!(
+        e,
+        SimEvent::SyscallReturn {
+            value: SyscallReturn::Failure(ErrorCode::NODEVICE),
+            ..
+        }
+    )));
+}
+
+#[test]
+fn alarm_driver_delivers_upcall() {
+    let sim = Sim::new(
+        vec![vec![
+            subscribe(alarm::DRIVER_NUM, 0, 0x1000, 7),
+            command(alarm::DRIVER_NUM, 5, 1000, 0),
+        ]],
+        FaultResponse::Stop,
+    );
+
+    assert!(sim.run_until(|| !sim.upcalls(0).is_empty()));
+
+    let upcalls = sim.upcalls(0);
+    assert_eq!(upcalls.len(), 1);
+    let upcall = upcalls[0];
+    match upcall.source {
+        FunctionCallSource::Driver(id) => {
+            assert_eq!(id.driver_num, alarm::DRIVER_NUM);
+            assert_eq!(id.subscribe_num, 0);
+        }
+        FunctionCallSource::Kernel => panic!("expected an upcall from the alarm driver"),
+    }
+    assert_eq!(upcall.pc, 0x1000);
+    assert_eq!(upcall.argument3, 7);
+    // The alarm fired no earlier than requested.
+    assert!(upcall.argument0 >= upcall.argument1);
+    assert!(sim.chip.clock().now_us() >= 1000);
+}
+
+#[test]
+fn console_writes_to_uart() {
+    let message = b"Hello from the simulator\r\n";
+    let sim = Sim::new(
+        vec![vec![
+            AppAction::WriteMemory {
+                offset: 0,
+                data: message.to_vec(),
+            },
+            AppAction::ReadOnlyAllow {
+                driver_number: console::DRIVER_NUM,
+                subdriver_number: 1,
+                offset: 0,
+                size: message.len(),
+            },
+            subscribe(console::DRIVER_NUM, 1, 0x2000, 0),
+            command(console::DRIVER_NUM, 1, message.len(), 0),
+        ]],
+        FaultResponse::Stop,
+    );
+
+    assert!(sim.run_until(|| !sim.upcalls(0).is_empty()));
+
+    assert_eq!(sim.uart.output(), message.to_vec());
+    let upcall = sim.upcalls(0)[0];
+    assert_eq!(upcall.pc, 0x2000);
+    assert_eq!(upcall.argument0, message.len());
+}
+
+#[test]
+fn faulting_process_restarts_until_threshold() {
+    static POLICY: procs::ThresholdRestart = procs::ThresholdRestart::new(1);
+    let sim = Sim::new(
+        vec![vec![command(0xbad, 0, 0, 0), AppAction::Fault]],
+        FaultResponse::Restart(&POLICY),
+    );
+
+    assert!(sim.run_until(|| sim.process(0).get_state() == procs::State::Faulted));
+
+    let events = sim.syscall.app_events(0);
+    let faults = events
+        .iter()
+        .filter(|e| matches!(e, SimEvent::Fault { .. }))
+        .count();
+    // The script starts over after every restart.
+    assert_eq!(faults, 3);
+    assert_eq!(events.iter().filter(|e| is_command(e)).count(), 3);
+    assert_eq!(sim.process(0).get_restart_count(), 2);
+}
+
+#[test]
+fn processes_load_and_unload_at_runtime() {
+    let sim = Sim::new(vec![vec![command(0xbad, 0, 0, 0)]], FaultResponse::Stop);
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(0)) == 1));
+
+    // Nothing new has been written to flash yet.
+    assert!(sim.load_new_process().unwrap().is_none());
+
+    let app1 = sim.syscall.add_app(vec![command(0xbad, 1, 0, 0)]);
+    sim.install_app(TbfBuilder::new().package_name("update").build());
+    let process = sim.load_new_process().unwrap().unwrap();
+    assert_eq!(process.get_process_name(), "update");
+    assert_eq!(sim.process(1).processid(), process.processid());
+    assert!(sim.load_new_process().unwrap().is_none());
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(app1)) == 1));
+
+    // Unloading empties the slot and invalidates the old identifier.
+    let old = sim.process(0);
+    let old_memory = old.mem_start();
+    sim.unload_process(old.processid()).unwrap();
+    assert!(sim.processes[0].is_none());
+    assert_eq!(sim.unload_process(old.processid()), Err(ErrorCode::INVAL));
+
+    // The app is still in flash, so it is found again and reuses the slot and
+    // the memory that was freed.
+    let process = sim.load_new_process().unwrap().unwrap();
+    assert_eq!(sim.process(0).processid(), process.processid());
+    assert_eq!(process.mem_start(), old_memory);
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(0)) == 2));
+}
+
+/// A credentials policy whose check never finishes, like one waiting on a
+/// digest engine that hung.
+struct UndecidedPolicy;
+
+impl procs::AppCredentialsPolicy for UndecidedPolicy {
+    fn require_credentials(&self) -> bool {
+        true
+    }
+
+    fn check_credentials(
+        &self,
+        _credentials: &tock_tbf::types::TbfFooterV2Credentials,
+        _binary: &'static [u8],
+    ) -> Option<procs::CheckResult> {
+        None
+    }
+
+    fn check_result(&self) -> Option<procs::CheckResult> {
+        None
+    }
+}
+
+#[test]
+fn apps_whose_credentials_check_never_finishes_do_not_load() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    sim.install_app(TbfBuilder::new().sha256_credentials().build());
+
+    assert!(sim
+        .load_new_checked_process(&UndecidedPolicy)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn syscalls_outside_tbf_permissions_are_filtered() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    let app = sim.syscall.add_app(vec![
+        command(console::DRIVER_NUM, 0, 0, 0),
+        command(alarm::DRIVER_NUM, 0, 0, 0),
+        command(console::DRIVER_NUM, 2, 0, 0),
+        subscribe(alarm::DRIVER_NUM, 0, 0, 0),
+    ]);
+    // Console commands 0 and 1 only.
+    let mut permissions = Vec::new();
+    for word in [console::DRIVER_NUM as u32, 0, 1].iter() {
+        permissions.extend_from_slice(&word.to_le_bytes());
+    }
+    sim.install_app(TbfBuilder::new().tlv(6, &permissions).build());
+    let process = sim.load_new_process().unwrap().unwrap();
+    assert!(sim.run_until(|| sim.syscall.app_events(app).len() >= 8));
+
+    let returns: Vec<SyscallReturn> = sim
+        .syscall
+        .app_events(app)
+        .into_iter()
+        .filter_map(|event| match event {
+            SimEvent::SyscallReturn { value, .. } => Some(value),
+            _ => None,
+        })
+        .collect();
+    assert!(matches!(returns[0], SyscallReturn::Success));
+    assert!(matches!(
+        returns[1],
+        SyscallReturn::Failure(ErrorCode::NODEVICE)
+    ));
+    assert!(matches!(
+        returns[2],
+        SyscallReturn::Failure(ErrorCode::NOSUPPORT)
+    ));
+    assert!(matches!(
+        returns[3],
+        SyscallReturn::SubscribeFailure(ErrorCode::NODEVICE, _, 0)
+    ));
+
+    let info = KernelInfo::new(sim.kernel);
+    assert_eq!(
+        info.number_app_filtered_syscalls(process.processid(), &TestCapability),
+        3
+    );
+    assert!(matches!(
+        info.last_app_filtered_syscall(process.processid(), &TestCapability),
+        Some(Syscall::Subscribe { .. })
+    ));
+    assert_eq!(info.filtered_syscalls(&TestCapability), 3);
+}
+
+#[test]
+fn filtered_allows_and_subscribes_hand_back_the_buffer_and_upcall() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    let app = sim.syscall.add_app(vec![
+        AppAction::ReadWriteAllow {
+            driver_number: alarm::DRIVER_NUM,
+            subdriver_number: 0,
+            offset: 16,
+            size: 8,
+        },
+        AppAction::ReadOnlyAllow {
+            driver_number: alarm::DRIVER_NUM,
+            subdriver_number: 0,
+            offset: 32,
+            size: 4,
+        },
+        subscribe(alarm::DRIVER_NUM, 0, 0x3000, 7),
+    ]);
+    // Console commands only.
+    sim.install_app(
+        TbfBuilder::new()
+            .tlv(6, &le_words(&[console::DRIVER_NUM as u32, 0, 1]))
+            .build(),
+    );
+    let process = sim.load_new_process().unwrap().unwrap();
+    assert!(sim.run_until(|| syscall_returns(&sim, app).len() >= 3));
+
+    let returns = syscall_returns(&sim, app);
+    let read_write_address = match returns[0] {
+        SyscallReturn::AllowReadWriteFailure(ErrorCode::NODEVICE, address, 8) => address,
+        ref other => panic!("unexpected return value {:?}", other),
+    };
+    let read_only_address = match returns[1] {
+        SyscallReturn::AllowReadOnlyFailure(ErrorCode::NODEVICE, address, 4) => address,
+        ref other => panic!("unexpected return value {:?}", other),
+    };
+    assert_eq!(read_only_address as usize - read_write_address as usize, 16);
+    assert!(matches!(
+        returns[2],
+        SyscallReturn::SubscribeFailure(ErrorCode::NODEVICE, upcall, 7) if upcall as usize == 0x3000
+    ));
+    assert_eq!(
+        KernelInfo::new(sim.kernel)
+            .number_app_filtered_syscalls(process.processid(), &TestCapability),
+        3
+    );
+}
+
+/// The value of a TLV made of little-endian words.
+fn le_words(words: &[u32]) -> Vec<u8> {
+    words
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .collect()
+}
+
+#[test]
+fn processes_over_cpu_budget_fault() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    sim.kernel.set_process_quotas(
+        procs::ProcessQuota::unlimited(),
+        sim.sim_alarm,
+        &TestCapability,
+    );
+    // A runaway app with 2 ms of CPU time every 100 ms.
+    let runaway = sim
+        .syscall
+        .add_app(vec![AppAction::Compute(50_000), command(0xbad, 0, 0, 0)]);
+    sim.install_app(
+        TbfBuilder::new()
+            .tlv(10, &le_words(&[2000, 100_000, 0]))
+            .build(),
+    );
+    let well_behaved = sim.syscall.add_app(vec![
+        AppAction::Compute(1000),
+        command(0xbad, 0, 0, 0),
+        AppAction::Compute(1000),
+        command(0xbad, 1, 0, 0),
+    ]);
+    sim.install_app(TbfBuilder::new().build());
+    let runaway_process = sim.load_new_process().unwrap().unwrap();
+    sim.load_new_process().unwrap().unwrap();
+
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(well_behaved)) == 2));
+    assert_eq!(runaway_process.get_state(), procs::State::Faulted);
+    assert_eq!(count_commands(&sim.syscall.app_events(runaway)), 0);
+    // The runaway app was stopped when its budget ran out rather than at the
+    // end of a full timeslice.
+    assert_eq!(runaway_process.debug_timeslice_expiration_count(), 1);
+    assert!(sim.chip.clock().now_us() < 10_000);
+
+    let info = KernelInfo::new(sim.kernel);
+    assert_eq!(
+        info.number_app_quota_violations(runaway_process.processid(), &TestCapability),
+        1
+    );
+    assert_eq!(info.quota_violations(&TestCapability), 1);
+}
+
+#[test]
+fn processes_within_cpu_budget_are_held_rather_than_faulted() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    sim.kernel.set_process_quotas(
+        procs::ProcessQuota::unlimited(),
+        sim.sim_alarm,
+        &TestCapability,
+    );
+    // After computing for 1.6 ms the app has less than
+    // `MIN_QUANTA_THRESHOLD_US` of its 2 ms budget left.
+    let app = sim.syscall.add_app(vec![
+        AppAction::Compute(1600),
+        command(0xbad, 0, 0, 0),
+        command(0xbad, 1, 0, 0),
+    ]);
+    sim.install_app(
+        TbfBuilder::new()
+            .tlv(10, &le_words(&[2000, 100_000, 0]))
+            .build(),
+    );
+    let process = sim.load_new_process().unwrap().unwrap();
+
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(app)) == 1));
+    // The second command waits for the next budget period.
+    assert!(!sim.run_until(|| count_commands(&sim.syscall.app_events(app)) == 2));
+    assert_eq!(process.get_state(), procs::State::Running);
+
+    sim.clock.advance_us(100_000);
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(app)) == 2));
+    assert_ne!(process.get_state(), procs::State::Faulted);
+    assert_eq!(process.debug_quota_exceeded_count(), 0);
+}
+
+/// A 24-bit, 32 kHz counter of simulated time, like the RTC the nRF52 boards
+/// use as their quota clock. It wraps every 512 s.
+struct SimRtc(&'static SimClock);
+
+/// Simulated microsecond at which `SimRtc` wraps.
+const SIM_RTC_WRAP_US: u64 = 512_000_000;
+
+impl Time for SimRtc {
+    type Frequency = Freq32KHz;
+    type Ticks = Ticks24;
+
+    fn now(&self) -> Ticks24 {
+        Ticks24::from((self.0.now_us() * 32_768 / 1_000_000) as u32 & 0xff_ffff)
+    }
+}
+
+#[test]
+fn cpu_budgets_are_charged_correctly_when_the_clock_wraps_mid_timeslice() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    sim.kernel.set_process_quotas(
+        procs::ProcessQuota::unlimited(),
+        leak(SimRtc(sim.clock)),
+        &TestCapability,
+    );
+    // Both apps have 2 ms of CPU time every 100 ms.
+    sim.syscall.add_app(vec![AppAction::Compute(50_000)]);
+    sim.install_app(
+        TbfBuilder::new()
+            .tlv(10, &le_words(&[2000, 100_000, 0]))
+            .build(),
+    );
+    let well_behaved = sim.syscall.add_app(vec![
+        AppAction::Compute(1000),
+        command(0xbad, 0, 0, 0),
+        AppAction::Compute(500),
+        command(0xbad, 1, 0, 0),
+    ]);
+    sim.install_app(
+        TbfBuilder::new()
+            .tlv(10, &le_words(&[2000, 100_000, 0]))
+            .build(),
+    );
+    let runaway_process = sim.load_new_process().unwrap().unwrap();
+    let well_behaved_process = sim.load_new_process().unwrap().unwrap();
+    // The clock wraps during the first timeslice of the runaway app.
+    sim.clock.advance_us(SIM_RTC_WRAP_US - 1000);
+
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(well_behaved)) == 2));
+    assert_ne!(well_behaved_process.get_state(), procs::State::Faulted);
+    assert_eq!(well_behaved_process.debug_quota_exceeded_count(), 0);
+    // The runaway app is held after its first 2 ms until the period ends,
+    // rather than getting a new period when the clock wrapped.
+    assert!(!sim.run_until(|| runaway_process.debug_timeslice_expiration_count() > 1));
+    assert!(sim.clock.now_us() < SIM_RTC_WRAP_US + 10_000);
+}
+
+#[test]
+#[should_panic(expected = "Too many grants")]
+fn creating_more_grants_than_a_process_can_hold_panics() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    // Boards create grants for their capsules one at a time.
+    for _ in 0..0x80 {
+        let _: Grant<TestGrant> = sim.kernel.create_grant(&TestCapability);
+    }
+}
+
+#[test]
+fn processes_over_grant_memory_limit_fault() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    // By default processes may not use enough grant memory for the alarm
+    // driver.
+    sim.kernel.set_process_quotas(
+        procs::ProcessQuota::unlimited().with_grant_memory_limit(8),
+        sim.sim_alarm,
+        &TestCapability,
+    );
+    let limited = sim
+        .syscall
+        .add_app(vec![subscribe(alarm::DRIVER_NUM, 0, 0x1000, 0)]);
+    sim.install_app(TbfBuilder::new().build());
+    // The TBF header of this app raises its limit.
+    let raised = sim
+        .syscall
+        .add_app(vec![subscribe(alarm::DRIVER_NUM, 0, 0x1000, 0)]);
+    sim.install_app(TbfBuilder::new().tlv(10, &le_words(&[0, 0, 1024])).build());
+    let limited_process = sim.load_new_process().unwrap().unwrap();
+    let raised_process = sim.load_new_process().unwrap().unwrap();
+
+    let returns = |app| {
+        sim.syscall
+            .app_events(app)
+            .into_iter()
+            .filter(|event| matches!(event, SimEvent::SyscallReturn { .. }))
+            .count()
+    };
+    assert!(
+        sim.run_until(
+            || returns(raised) == 1 && limited_process.get_state() == procs::State::Faulted
+        )
+    );
+    // The faulted process does not get a return value for the subscribe.
+    assert_eq!(returns(limited), 0);
+    assert_eq!(limited_process.debug_quota_exceeded_count(), 1);
+    assert_eq!(raised_process.debug_quota_exceeded_count(), 0);
+    assert_eq!(raised_process.grant_allocated_count(), Some(1));
+}
+
+#[test]
+fn grant_allocations_over_quota_fault_once_grants_are_no_longer_entered() {
+    let sim = Sim::new(vec![vec![yield_wait()]], FaultResponse::Stop);
+    let process = sim.process(0);
+    let processid = process.processid();
+    let initial_break = process.kernel_memory_break() as usize;
+    let [first, second] = sim.grants;
+
+    first.enter(processid, |grant| grant.value = 1).unwrap();
+    // Only the first grant fits in the quota.
+    sim.kernel.set_process_quotas(
+        procs::ProcessQuota::unlimited()
+            .with_grant_memory_limit(initial_break - process.kernel_memory_break() as usize),
+        sim.sim_alarm,
+        &TestCapability,
+    );
+
+    first
+        .enter(processid, |grant| {
+            assert_eq!(
+                second.enter(processid, |grant| grant.value = 2),
+                Err(procs::Error::OutOfMemory)
+            );
+            // The process has not faulted yet, so the entered grant is still
+            // there.
+            assert_ne!(process.get_state(), procs::State::Faulted);
+            grant.value += 1;
+        })
+        .unwrap();
+    assert_eq!(first.enter(processid, |grant| grant.value), Ok(2));
+    assert_eq!(process.debug_quota_exceeded_count(), 1);
+
+    assert!(sim.run_until(|| process.get_state() == procs::State::Faulted));
+    assert_eq!(process.debug_quota_exceeded_count(), 1);
+}
+
+/// The apps, in order, whose scripts issued commands.
+fn command_order(sim: &Sim) -> Vec<usize> {
+    sim.syscall
+        .events()
+        .iter()
+        .filter(|e| is_command(e))
+        .map(SimEvent::app)
+        .collect()
+}
+
+#[test]
+fn edf_scheduler_runs_earliest_deadline_first_and_counts_misses() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    let background = sim.install_app_with_script(
+        TbfBuilder::new().build(),
+        vec![AppAction::Compute(20_000), command(0xbad, 0, 0, 0)],
+    );
+    // Period, deadline and budget.
+    let on_time = sim.install_app_with_script(
+        TbfBuilder::new()
+            .tlv(11, &le_words(&[20_000, 6000, 3000]))
+            .build(),
+        vec![AppAction::Compute(1000), command(0xbad, 0, 0, 0)],
+    );
+    // Needs far more than its budget, so it finishes in the background after
+    // its deadline.
+    let overrun = sim.install_app_with_script(
+        TbfBuilder::new()
+            .tlv(11, &le_words(&[20_000, 4000, 2000]))
+            .build(),
+        vec![AppAction::Compute(8000), command(0xbad, 0, 0, 0)],
+    );
+    for _ in 0..3 {
+        sim.load_new_process().unwrap().unwrap();
+    }
+
+    let scheduler = sim.edf_scheduler(RealTimePolicy::EarliestDeadlineFirst);
+    assert!(sim.run_scheduler_until(scheduler, || command_order(&sim).len() == 3));
+
+    // The job with the earlier deadline ran first but could not finish within
+    // its budget, so the other job finished first.
+    assert_eq!(command_order(&sim), vec![on_time, overrun, background]);
+    // The overrunning job was stopped once it used up its budget.
+    assert!(sim.process(2).debug_timeslice_expiration_count() >= 1);
+
+    let info = KernelInfo::new(sim.kernel);
+    assert_eq!(
+        info.number_app_deadline_misses(sim.process(1).processid(), &TestCapability),
+        0
+    );
+    assert_eq!(
+        info.number_app_deadline_misses(sim.process(2).processid(), &TestCapability),
+        1
+    );
+    assert_eq!(info.deadline_misses(&TestCapability), 1);
+}
+
+#[test]
+fn edf_background_processes_keep_running_until_the_next_release() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    // Returns to the kernel with a syscall just before the periodic process
+    // is released again.
+    let background = sim.install_app_with_script(
+        TbfBuilder::new().build(),
+        vec![
+            AppAction::Compute(9850),
+            command(UPCALL_SOURCE_DRIVER_NUM, 2, 0, 0),
+            AppAction::Compute(1000),
+            command(0xbad, 0, 0, 0),
+        ],
+    );
+    // Its alarm fires after its first job but shortly before its next period
+    // starts, so it is ready and waits for the period to end.
+    let periodic = sim.install_app_with_script(
+        TbfBuilder::new()
+            .tlv(11, &le_words(&[10_000, 10_000, 5000]))
+            .build(),
+        vec![
+            subscribe(alarm::DRIVER_NUM, 0, 0x1000, 0),
+            command(alarm::DRIVER_NUM, 5, 9800, 0),
+            yield_wait(),
+            AppAction::Compute(1000),
+            command(0xbad, 0, 0, 0),
+        ],
+    );
+    sim.load_new_process().unwrap().unwrap();
+    sim.load_new_process().unwrap().unwrap();
+
+    // The background process is then scheduled with less time to go before
+    // the release than the kernel runs a process for. It still runs, rather
+    // than the kernel spinning on timeslices too short to run in.
+    let scheduler = sim.edf_scheduler(RealTimePolicy::EarliestDeadlineFirst);
+    let done = |app| {
+        sim.syscall.app_events(app).iter().any(|e| {
+            matches!(
+                e,
+                SimEvent::Syscall {
+                    syscall: Syscall::Command {
+                        driver_number: 0xbad,
+                        ..
+                    },
+                    ..
+                }
+            )
+        })
+    };
+    assert!(sim.run_scheduler_until(scheduler, || done(periodic) && done(background)));
+    assert_eq!(
+        KernelInfo::new(sim.kernel).deadline_misses(&TestCapability),
+        0
+    );
+}
+
+#[test]
+fn rate_monotonic_scheduler_runs_shortest_period_first() {
+    let order = |policy| {
+        let sim = Sim::new(vec![], FaultResponse::Stop);
+        // A long period with a short deadline, then a short period.
+        let long_period = sim.install_app_with_script(
+            TbfBuilder::new()
+                .tlv(11, &le_words(&[50_000, 5000, 0]))
+                .build(),
+            vec![AppAction::Compute(1000), command(0xbad, 0, 0, 0)],
+        );
+        let short_period = sim.install_app_with_script(
+            TbfBuilder::new()
+                .tlv(11, &le_words(&[10_000, 0, 0]))
+                .build(),
+            vec![AppAction::Compute(1000), command(0xbad, 0, 0, 0)],
+        );
+        sim.load_new_process().unwrap().unwrap();
+        sim.load_new_process().unwrap().unwrap();
+
+        let scheduler = sim.edf_scheduler(policy);
+        assert!(sim.run_scheduler_until(scheduler, || command_order(&sim).len() == 2));
+        assert_eq!(
+            KernelInfo::new(sim.kernel).deadline_misses(&TestCapability),
+            0
+        );
+        (command_order(&sim), long_period, short_period)
+    };
+
+    let (commands, long_period, short_period) = order(RealTimePolicy::RateMonotonic);
+    assert_eq!(commands, vec![short_period, long_period]);
+    let (commands, long_period, short_period) = order(RealTimePolicy::EarliestDeadlineFirst);
+    assert_eq!(commands, vec![long_period, short_period]);
+}
+
+#[test]
+fn persistent_state_survives_restarts() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    let calibration = vec![0xca, 0x1b, 0x00, 0x42, 0x07];
+    let app = sim.syscall.add_app(vec![
+        AppAction::Syscall(Syscall::Memop {
+            operand: 12,
+            arg0: 0,
+        }),
+        AppAction::MemopAddress {
+            operand: 13,
+            offset: 0x100,
+        },
+        AppAction::WriteMemory {
+            offset: 0x100,
+            data: calibration.clone(),
+        },
+        command(0xbad, 0, 0, 0),
+    ]);
+    sim.install_app(TbfBuilder::new().tlv(12, &le_words(&[5, 0])).build());
+    let process = sim.load_new_process().unwrap().unwrap();
+
+    for run in 1..=3 {
+        if run > 1 {
+            process.try_restart(0);
+        }
+        assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(app)) == run));
+    }
+
+    let values: Vec<u32> = sim
+        .syscall
+        .app_events(app)
+        .into_iter()
+        .filter_map(|event| match event {
+            SimEvent::SyscallReturn {
+                value: SyscallReturn::SuccessU32(value),
+                ..
+            } => Some(value),
+            _ => None,
+        })
+        .collect();
+    // Every run sees the size of its state. The first run has no checkpoint
+    // yet, the restarted runs get the state the run before left.
+    assert_eq!(values, vec![5, 0, 5, 1, 5, 1]);
+
+    let mut state = [0; 8];
+    assert_eq!(
+        process
+            .processid()
+            .read_persistent_state(&mut state, &TestCapability),
+        Ok(5)
+    );
+    assert_eq!(&state[..5], &calibration[..]);
+}
+
+#[test]
+fn persistent_state_larger_than_app_ram_is_rejected() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    sim.install_app(
+        TbfBuilder::new()
+            .minimum_ram_size(4096)
+            .tlv(12, &le_words(&[u32::MAX, 0]))
+            .build(),
+    );
+    assert!(matches!(
+        sim.load_new_process(),
+        Err(procs::ProcessLoadError::TbfHeaderParseFailure(_))
+    ));
+}
+
+#[test]
+fn kernel_trace_records_syscalls_and_context_switches() {
+    let sim = Sim::new(
+        vec![vec![command(console::DRIVER_NUM, 0, 0, 0)]],
+        FaultResponse::Stop,
+    );
+    let trace = leak(KernelTrace::new(
+        Box::leak(vec![0; 64 * trace::RECORD_LEN].into_boxed_slice()),
+        sim.sim_alarm,
+    ));
+    sim.kernel.set_trace(trace, &TestCapability);
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(0)) == 1));
+
+    let info = KernelInfo::new(sim.kernel);
+    let trace = info.kernel_trace(&TestCapability).unwrap();
+    assert_eq!(trace.overwritten(), 0);
+    let mut records = vec![0; trace.len() * trace::RECORD_LEN];
+    assert_eq!(trace.drain(&mut records), records.len());
+    assert!(trace.is_empty());
+
+    // (kind, detail, arg0) of each record.
+    let events: Vec<(u8, u8, u32)> = records
+        .chunks(trace::RECORD_LEN)
+        .map(|record| {
+            let arg0 = u32::from_le_bytes([record[8], record[9], record[10], record[11]]);
+            (record[4], record[5], arg0)
+        })
+        .collect();
+    let command = events
+        .iter()
+        .position(|e| *e == (TraceKind::Syscall as u8, 2, console::DRIVER_NUM as u32))
+        .unwrap();
+    let success = SyscallReturnVariant::Success as u32;
+    assert_eq!(
+        events[command + 1],
+        (TraceKind::SyscallReturn as u8, 0, success)
+    );
+    // The process starts at its entry point, and returns to the kernel with
+    // the command.
+    assert!(events[..command]
+        .iter()
+        .any(|e| e.0 == TraceKind::FunctionCall as u8));
+    assert!(events[..command]
+        .iter()
+        .any(|e| e.0 == TraceKind::SwitchToProcess as u8));
+    assert_eq!(
+        events[command - 1],
+        (
+            TraceKind::SwitchFromProcess as u8,
+            SwitchFromReason::Syscall as u8,
+            0
+        )
+    );
+}
+
+#[test]
+fn kernel_trace_timestamps_continue_across_clock_wraps() {
+    let sim = Sim::new(
+        vec![vec![AppAction::Compute(2000), command(0xbad, 0, 0, 0)]],
+        FaultResponse::Stop,
+    );
+    let trace = leak(KernelTrace::new(
+        Box::leak(vec![0; 64 * trace::RECORD_LEN].into_boxed_slice()),
+        leak(SimRtc(sim.clock)),
+    ));
+    sim.kernel.set_trace(trace, &TestCapability);
+    // The clock wraps while the app computes.
+    sim.clock.advance_us(SIM_RTC_WRAP_US - 1000);
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(0)) == 1));
+
+    let mut records = vec![0; trace.len() * trace::RECORD_LEN];
+    assert_eq!(trace.drain(&mut records), records.len());
+    let times: Vec<u32> = records
+        .chunks(trace::RECORD_LEN)
+        .map(|record| u32::from_le_bytes([record[0], record[1], record[2], record[3]]))
+        .collect();
+    assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+    // The first record is from before the app ran, the last from after.
+    let first = *times.first().unwrap() as u64;
+    let last = *times.last().unwrap() as u64;
+    assert!(first >= SIM_RTC_WRAP_US - 1000 - 100);
+    assert!(last - first >= 2000);
+    assert!(last - first < 10_000);
+}
+
+#[test]
+fn process_fault_is_kept_in_crash_dump_across_reboots() {
+    static POLICY: procs::ThresholdRestart = procs::ThresholdRestart::new(1);
+    let sim = Sim::new(
+        vec![vec![command(0xbad, 0, 0, 0), AppAction::Fault]],
+        FaultResponse::Restart(&POLICY),
+    );
+    // Boards keep the region in memory that is not initialized at boot. The
+    // simulation keeps it in a leaked buffer that a second `CrashDump`, as
+    // created after a reboot, uses again.
+    let region: *mut [u8] = Box::into_raw(vec![0xa5; 256].into_boxed_slice());
+    let crash_dump = leak(CrashDump::new(unsafe { &mut *region }));
+    assert!(crash_dump.map_record(|_| ()).is_none());
+    sim.kernel.set_crash_dump(crash_dump, &TestCapability);
+
+    assert!(sim.run_until(|| sim.process(0).get_state() == procs::State::Faulted));
+
+    let rebooted = leak(CrashDump::new(unsafe { &mut *region }));
+    let recorded = rebooted.map_record(|record| {
+        assert_eq!(record.kind(), CrashKind::ProcessFault);
+        assert!(!record.kernel_panicked());
+        assert_eq!(record.process_name(), "app0");
+        // The record is of the last fault, after two restarts.
+        assert_eq!(record.restart_count(), 2);
+        // The simulated app and the index of its next action.
+        assert_eq!(record.registers().collect::<Vec<u32>>(), vec![0, 2]);
+        let map = record.memory_map();
+        assert_eq!(map.mem_start, sim.process(0).mem_start() as u32);
+        assert_eq!(map.mem_end, sim.process(0).mem_end() as u32);
+    });
+    assert!(recorded.is_some());
+
+    let mut raw = [0; 256];
+    let len = rebooted.read(&mut raw);
+    assert!(len > 0);
+    // A record that does not match its checksum, as memory holds at power
+    // on, is not valid.
+    unsafe { (*region)[len - 1] ^= 0xff };
+    assert!(rebooted.map_record(|_| ()).is_none());
+    unsafe { (*region)[len - 1] ^= 0xff };
+    rebooted.clear();
+    assert!(rebooted.map_record(|_| ()).is_none());
+}
+
+#[test]
+fn freed_grants_shrink_the_grant_region() {
+    let sim = Sim::new(vec![vec![]], FaultResponse::Stop);
+    let info = KernelInfo::new(sim.kernel);
+    let process = sim.process(0);
+    let processid = process.processid();
+    let initial_break = process.kernel_memory_break() as usize;
+    let [first, second] = sim.grants;
+
+    first
+        .enter_with_allocator(processid, |grant, allocator| {
+            grant.value = 1;
+            grant.buffer = Some(allocator.alloc_with(|| [7; 8]).unwrap());
+        })
+        .unwrap();
+    second.enter(processid, |grant| grant.value = 2).unwrap();
+    let buffer_first_word = first.enter(processid, |grant| {
+        grant
+            .buffer
+            .as_mut()
+            .map(|buffer| buffer.enter(|words| words[0]))
+    });
+    assert_eq!(buffer_first_word, Ok(Some(Ok(7))));
+    let first_bytes = info.app_grant_bytes(processid, 2, &TestCapability);
+    let second_bytes = info.app_grant_bytes(processid, 3, &TestCapability);
+    // Every allocation has a header, and the first grant also holds its
+    // custom grant.
+    assert_eq!(
+        second_bytes,
+        GRANT_BLOCK_HEADER_LEN + mem::size_of::<TestGrant>()
+    );
+    assert_eq!(first_bytes, second_bytes + GRANT_BLOCK_HEADER_LEN + 8 * 4);
+    assert_eq!(
+        initial_break - process.kernel_memory_break() as usize,
+        first_bytes + second_bytes
+    );
+
+    // A grant cannot be freed while it is entered.
+    first
+        .enter(processid, |_| {
+            assert_eq!(first.free(processid), Err(procs::Error::AlreadyInUse));
+        })
+        .unwrap();
+
+    let dropped = TEST_GRANTS_DROPPED.load(Ordering::SeqCst);
+    assert_eq!(first.free(processid), Ok(()));
+    assert_eq!(TEST_GRANTS_DROPPED.load(Ordering::SeqCst), dropped + 1);
+    assert_eq!(info.app_grant_bytes(processid, 2, &TestCapability), 0);
+    assert_eq!(process.grant_allocated_count(), Some(1));
+    // The second grant was moved up into the freed memory, so only its
+    // memory is left in the grant region.
+    assert_eq!(
+        initial_break - process.kernel_memory_break() as usize,
+        second_bytes
+    );
+    assert_eq!(second.enter(processid, |grant| grant.value), Ok(2));
+
+    assert_eq!(second.free(processid), Ok(()));
+    assert_eq!(process.kernel_memory_break() as usize, initial_break);
+    // Freeing a grant that is not allocated does nothing.
+    assert_eq!(second.free(processid), Ok(()));
+
+    // Entering a freed grant allocates it again.
+    assert_eq!(first.enter(processid, |grant| grant.value), Ok(0));
+    assert_eq!(process.grant_allocated_count(), Some(1));
+}
+
+#[test]
+fn freed_grant_memory_is_reused_within_the_quota() {
+    let sim = Sim::new(vec![vec![]], FaultResponse::Stop);
+    let process = sim.process(0);
+    let processid = process.processid();
+    let initial_break = process.kernel_memory_break() as usize;
+    let [first, second] = sim.grants;
+
+    first.enter(processid, |grant| grant.value = 1).unwrap();
+    // The custom grant of the second grant is never moved, so freeing the
+    // first grant leaves its memory free above it.
+    second
+        .enter_with_allocator(processid, |grant, allocator| {
+            grant.buffer = Some(allocator.alloc_with(|| [7; 8]).unwrap());
+        })
+        .unwrap();
+    let used = initial_break - process.kernel_memory_break() as usize;
+    sim.kernel.set_process_quotas(
+        procs::ProcessQuota::unlimited().with_grant_memory_limit(used),
+        sim.sim_alarm,
+        &TestCapability,
+    );
+
+    assert_eq!(first.free(processid), Ok(()));
+    assert_eq!(initial_break - process.kernel_memory_break() as usize, used);
+
+    // Allocating the first grant again reuses its memory, so the process
+    // stays within its quota.
+    assert_eq!(first.enter(processid, |grant| grant.value), Ok(0));
+    assert_eq!(initial_break - process.kernel_memory_break() as usize, used);
+    assert_eq!(process.debug_quota_exceeded_count(), 0);
+    assert_ne!(process.get_state(), procs::State::Faulted);
+}
+
+/// The values the system calls of `app` returned.
+fn syscall_returns(sim: &Sim, app: usize) -> Vec<SyscallReturn> {
+    sim.syscall
+        .app_events(app)
+        .into_iter()
+        .filter_map(|event| match event {
+            SimEvent::SyscallReturn { value, .. } => Some(value),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The arguments of the upcalls `app` got from `subscribe_num` of `driver`.
+fn upcall_arguments(sim: &Sim, app: usize, driver: usize, subscribe_num: usize) -> Vec<[usize; 3]> {
+    sim.upcalls(app)
+        .into_iter()
+        .filter(|call| match call.source {
+            FunctionCallSource::Driver(id) => {
+                id.driver_num == driver && id.subscribe_num == subscribe_num
+            }
+            FunctionCallSource::Kernel => false,
+        })
+        .map(|call| [call.argument0, call.argument1, call.argument2])
+        .collect()
+}
+
+fn read_process_memory(process: &dyn Process, offset: usize, len: usize) -> Vec<u8> {
+    unsafe { std::slice::from_raw_parts(process.mem_start().add(offset), len).to_vec() }
+}
+
+#[test]
+fn ipc_mailbox_copies_messages_and_replies() {
+    const MAILBOX: usize = ipc_mailbox::DRIVER_NUM;
+    let receive_buffer = |driver_number| AppAction::ReadWriteAllow {
+        driver_number,
+        subdriver_number: 0,
+        offset: 0x200,
+        size: 16,
+    };
+    let message = |data: &[u8]| {
+        vec![
+            AppAction::WriteMemory {
+                offset: 0x100,
+                data: data.to_vec(),
+            },
+            AppAction::ReadOnlyAllow {
+                driver_number: MAILBOX,
+                subdriver_number: 1,
+                offset: 0x100,
+                size: data.len(),
+            },
+        ]
+    };
+    // The descriptors of the processes are their identifiers plus one.
+    let mut server = vec![
+        receive_buffer(MAILBOX),
+        subscribe(MAILBOX, 0, 0x1000, 0),
+        command(MAILBOX, 3, ipc_mailbox::WAIT_FLAG, 0),
+        yield_wait(),
+    ];
+    server.extend(message(b"pong!"));
+    server.extend(vec![
+        command(MAILBOX, 4, 2, 1),
+        // The mailbox is empty.
+        command(MAILBOX, 3, 0, 0),
+    ]);
+    let mut client = message(b"ping");
+    client.extend(vec![
+        subscribe(MAILBOX, 1, 0x2000, 0),
+        command(
+            MAILBOX,
+            2,
+            1,
+            ipc_mailbox::WAIT_FLAG | ipc_mailbox::EXPECT_REPLY_FLAG,
+        ),
+        receive_buffer(MAILBOX),
+        subscribe(MAILBOX, 0, 0x1000, 0),
+        command(MAILBOX, 3, ipc_mailbox::WAIT_FLAG, 0),
+    ]);
+    let sim = Sim::new(vec![server, client], FaultResponse::Stop);
+
+    assert!(
+        sim.run_until(|| upcall_arguments(&sim, 1, MAILBOX, 0).len() == 1
+            && count_commands(&sim.syscall.app_events(0)) == 3)
+    );
+
+    // The server got the request, with the reply handle 1.
+    assert_eq!(upcall_arguments(&sim, 0, MAILBOX, 0), vec![[2, 4, 1]]);
+    assert_eq!(read_process_memory(sim.process(0), 0x200, 4), b"ping");
+    // The send of the client completed, and returned the same sequence
+    // number.
+    assert_eq!(upcall_arguments(&sim, 1, MAILBOX, 1), vec![[0, 1, 1]]);
+    assert!(syscall_returns(&sim, 1)
+        .iter()
+        .any(|value| matches!(value, SyscallReturn::SuccessU32(1))));
+    // The reply arrived in the mailbox of the client.
+    assert_eq!(
+        upcall_arguments(&sim, 1, MAILBOX, 0),
+        vec![[1, 5, (ipc_mailbox::REPLY_FLAG | 1) as usize]]
+    );
+    assert_eq!(read_process_memory(sim.process(1), 0x200, 5), b"pong!");
+    assert!(matches!(
+        syscall_returns(&sim, 0).last(),
+        Some(SyscallReturn::Failure(ErrorCode::BUSY))
+    ));
+}
+
+#[test]
+fn ipc_mailbox_fails_operations_on_restarted_peers() {
+    const MAILBOX: usize = ipc_mailbox::DRIVER_NUM;
+    let client = vec![
+        AppAction::ReadOnlyAllow {
+            driver_number: MAILBOX,
+            subdriver_number: 1,
+            offset: 0x100,
+            size: 4,
+        },
+        subscribe(MAILBOX, 1, 0x2000, 0),
+        command(MAILBOX, 2, 1, ipc_mailbox::EXPECT_REPLY_FLAG),
+        yield_wait(),
+        command(MAILBOX, 2, 1, 0),
+    ];
+    let sim = Sim::new(vec![vec![], client], FaultResponse::Stop);
+
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(1)) == 1));
+    assert!(matches!(
+        syscall_returns(&sim, 1).last(),
+        Some(SyscallReturn::SuccessU32(1))
+    ));
+
+    // The server restarts before it replies.
+    sim.process(0).try_restart(0);
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(1)) == 2));
+    assert_eq!(
+        upcall_arguments(&sim, 1, MAILBOX, 1),
+        vec![[ErrorCode::NODEVICE as usize, 1, 1]]
+    );
+    // The restarted server has a new descriptor.
+    assert!(matches!(
+        syscall_returns(&sim, 1).last(),
+        Some(SyscallReturn::Failure(ErrorCode::NODEVICE))
+    ));
+}
+
+#[test]
+fn ipc_services_are_only_reachable_by_listed_clients() {
+    const IPC_DRIVER: usize = ipc::DRIVER_NUM;
+    let search = |name: &[u8]| {
+        vec![
+            AppAction::WriteMemory {
+                offset: 0x100,
+                data: name.to_vec(),
+            },
+            AppAction::ReadOnlyAllow {
+                driver_number: IPC_DRIVER,
+                subdriver_number: 0,
+                offset: 0x100,
+                size: name.len(),
+            },
+            command(IPC_DRIVER, 1, 0, 0),
+        ]
+    };
+    // The vault is loaded after the three clients, so its descriptor is 4,
+    // and that of its second service 4 | 1 << 16.
+    let keys = 4;
+    let log = 4 | 1 << 16;
+    let mut client0 = search(b"keys");
+    client0.push(command(IPC_DRIVER, 2, keys, 0));
+    let mut client1 = search(b"keys");
+    client1.extend(search(b"log"));
+    client1.extend(vec![
+        command(IPC_DRIVER, 2, keys, 0),
+        command(IPC_DRIVER, 2, log, 0),
+    ]);
+    let mut client2 = search(b"vault");
+    client2.extend(vec![
+        command(IPC_DRIVER, 2, keys, 0),
+        AppAction::ReadOnlyAllow {
+            driver_number: ipc_mailbox::DRIVER_NUM,
+            subdriver_number: 1,
+            offset: 0x100,
+            size: 4,
+        },
+        command(ipc_mailbox::DRIVER_NUM, 2, keys, 0),
+    ]);
+    let sim = Sim::new(vec![client0, client1, client2], FaultResponse::Stop);
+
+    let mut services = Vec::new();
+    for (name, client) in [(&b"keys"[..], &b"app0"[..]), (b"log", b"app1")].iter() {
+        services.extend_from_slice(&[name.len() as u8, 1]);
+        services.extend_from_slice(name);
+        services.push(client.len() as u8);
+        services.extend_from_slice(client);
+    }
+    let vault = sim.install_app_with_script(
+        TbfBuilder::new()
+            .package_name("vault")
+            .tlv(13, &services)
+            .build(),
+        vec![
+            subscribe(IPC_DRIVER, 0, 0x1000, 0),
+            subscribe(IPC_DRIVER, 1 << 16, 0x2000, 0),
+            yield_wait(),
+            yield_wait(),
+        ],
+    );
+    sim.load_new_process().unwrap();
+
+    assert!(
+        sim.run_until(|| upcall_arguments(&sim, vault, IPC_DRIVER, 0).len() == 1
+            && upcall_arguments(&sim, vault, IPC_DRIVER, 1 << 16).len() == 1
+            && count_commands(&sim.syscall.app_events(2)) == 3)
+    );
+
+    // Each service was notified by its own client only.
+    assert_eq!(upcall_arguments(&sim, vault, IPC_DRIVER, 0)[0][0], 1);
+    assert_eq!(upcall_arguments(&sim, vault, IPC_DRIVER, 1 << 16)[0][0], 2);
+    let returns = syscall_returns(&sim, 0);
+    assert!(matches!(
+        returns[returns.len() - 2],
+        SyscallReturn::SuccessU32(4)
+    ));
+    assert!(matches!(returns[returns.len() - 1], SyscallReturn::Success));
+    // The second client can only find and notify the service it is listed
+    // for.
+    let returns = syscall_returns(&sim, 1);
+    assert!(matches!(
+        returns[returns.len() - 6..],
+        [
+            _,
+            SyscallReturn::Failure(ErrorCode::NODEVICE),
+            _,
+            SyscallReturn::SuccessU32(0x10004),
+            SyscallReturn::Failure(ErrorCode::INVAL),
+            SyscallReturn::Success,
+        ]
+    ));
+    // The vault does not exist for other processes, not even by its package
+    // name or through the mailbox.
+    let returns = syscall_returns(&sim, 2);
+    assert!(matches!(
+        returns[returns.len() - 4..],
+        [
+            SyscallReturn::Failure(ErrorCode::NODEVICE),
+            SyscallReturn::Failure(ErrorCode::INVAL),
+            _,
+            SyscallReturn::Failure(ErrorCode::NODEVICE),
+        ]
+    ));
+}
+
+#[test]
+fn power_manager_sleeps_as_deeply_as_votes_and_alarms_allow() {
+    let set_alarm = |dt| vec![command(alarm::DRIVER_NUM, 5, dt, 0), yield_wait()];
+    let mut script = vec![subscribe(alarm::DRIVER_NUM, 0, 0x1000, 0)];
+    // The chip takes 2000 us to wake up from deep sleep, and 100 us from
+    // sleep.
+    script.extend(set_alarm(10_000));
+    script.extend(set_alarm(10_000));
+    script.extend(set_alarm(1_000));
+    let sim = Sim::new(vec![script], FaultResponse::Stop);
+    let power_manager = leak(PowerManager::new());
+    power_manager.set_wakeup_alarm(sim.sim_alarm);
+    sim.kernel.set_power_manager(power_manager, &TestCapability);
+    // Peripherals vote before the board gives them the power manager, which
+    // then counts the vote already cast.
+    let vote = SleepVote::new();
+    vote.vote(SleepState::Idle);
+    assert_eq!(power_manager.deepest_allowed(), SleepState::DeepSleep);
+    vote.set_power_manager(power_manager);
+    assert_eq!(power_manager.deepest_allowed(), SleepState::Idle);
+    vote.release();
+    let times_entered = || {
+        [SleepState::Idle, SleepState::Sleep, SleepState::DeepSleep]
+            .iter()
+            .map(|state| power_manager.times_entered(*state))
+            .collect::<Vec<_>>()
+    };
+
+    // Nothing keeps the chip from sleeping deeply until the alarm.
+    assert!(sim.run_until(|| sim.upcalls(0).len() == 1));
+    assert_eq!(times_entered(), vec![0, 0, 1]);
+
+    // A voter needs the chip to stay in light sleep.
+    vote.vote(SleepState::Sleep);
+    assert_eq!(power_manager.deepest_allowed(), SleepState::Sleep);
+    assert!(sim.run_until(|| sim.upcalls(0).len() == 2));
+    assert_eq!(times_entered(), vec![0, 1, 1]);
+
+    // The chip could not wake up from deep sleep in time for the last alarm.
+    vote.release();
+    assert_eq!(power_manager.deepest_allowed(), SleepState::DeepSleep);
+    assert!(sim.run_until(|| sim.upcalls(0).len() == 3));
+    assert_eq!(times_entered(), vec![0, 2, 1]);
+}
+
+/// Load an app that checks in with the heartbeat driver every 3 ms, at most
+/// 5 ms apart, and then hangs. Returns the app index and the process.
+fn load_hanging_app(sim: &Sim) -> (usize, &'static dyn Process) {
+    let check_in = || command(heartbeat::DRIVER_NUM, 1, 0, 0);
+    let app = sim.install_app_with_script(
+        TbfBuilder::new().tlv(14, &le_words(&[5])).build(),
+        vec![
+            check_in(),
+            AppAction::Compute(3000),
+            check_in(),
+            AppAction::Compute(3000),
+            check_in(),
+            AppAction::Compute(1_000_000),
+        ],
+    );
+    (app, sim.load_new_process().unwrap().unwrap())
+}
+
+#[test]
+fn hung_process_with_heartbeat_faults() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    sim.kernel
+        .set_process_heartbeats(sim.platform.heartbeats, &TestCapability);
+    let (app, process) = load_hanging_app(&sim);
+
+    assert!(sim.run_until(|| process.get_state() == procs::State::Faulted));
+    assert_eq!(count_commands(&sim.syscall.app_events(app)), 3);
+    // The first timeslice ended 4 ms after the last check in, so the hang
+    // was caught at the end of the second one.
+    assert_eq!(process.debug_timeslice_expiration_count(), 2);
+    // The process was dealt with, so the watchdog is still tickled.
+    let tickles = sim.chip.watchdog().tickles();
+    assert!(sim.run_until(|| sim.chip.watchdog().tickles() > tickles));
+    assert!(!sim.platform.heartbeats.failed());
+}
+
+#[test]
+fn hung_process_with_heartbeat_stops_watchdog_tickles() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    let heartbeats = leak(ProcessHeartbeats::new(
+        sim.kernel,
+        sim.sim_alarm,
+        HeartbeatResponse::ResetSystem,
+        &TestCapability,
+    ));
+    sim.kernel
+        .set_process_heartbeats(heartbeats, &TestCapability);
+    let (app, process) = load_hanging_app(&sim);
+
+    assert!(sim.run_until(|| heartbeats.failed()));
+    assert_eq!(count_commands(&sim.syscall.app_events(app)), 3);
+    // The process keeps running, but the watchdog is never tickled again,
+    // not even when the kernel wakes up from sleep, so it resets the system.
+    let tickles = sim.chip.watchdog().tickles();
+    assert!(
+        !sim.run_until(|| process.get_state() != procs::State::Running
+            && process.get_state() != procs::State::Yielded)
+    );
+    assert_eq!(sim.chip.watchdog().tickles(), tickles);
+}
+
+#[test]
+fn heartbeat_grant_allocation_failure_does_not_stop_watchdog_tickles() {
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    // The heartbeat grant does not fit in the quota.
+    sim.kernel.set_process_quotas(
+        procs::ProcessQuota::unlimited().with_grant_memory_limit(0),
+        sim.sim_alarm,
+        &TestCapability,
+    );
+    let heartbeats = leak(ProcessHeartbeats::new(
+        sim.kernel,
+        sim.sim_alarm,
+        HeartbeatResponse::ResetSystem,
+        &TestCapability,
+    ));
+    sim.install_app_with_script(
+        TbfBuilder::new().tlv(14, &le_words(&[5])).build(),
+        vec![AppAction::Compute(1_000_000)],
+    );
+    let process = sim.load_new_process().unwrap().unwrap();
+    assert!(sim.run_until(|| process.debug_timeslice_expiration_count() > 0));
+    // Heartbeats are checked from when the process is already running.
+    sim.kernel
+        .set_process_heartbeats(heartbeats, &TestCapability);
+
+    // Going over the quota faults the process, but does not reset the
+    // system.
+    assert!(sim.run_until(|| process.get_state() == procs::State::Faulted));
+    let tickles = sim.chip.watchdog().tickles();
+    assert!(sim.run_until(|| sim.chip.watchdog().tickles() > tickles));
+    assert!(!heartbeats.failed());
+}
+
+#[test]
+fn full_upcall_queue_coalesces_and_reports_dropped_upcalls() {
+    const SOURCE: usize = UPCALL_SOURCE_DRIVER_NUM;
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    let mut script = vec![
+        subscribe(upcall_overflow::DRIVER_NUM, 0, 0x2000, 0),
+        subscribe(SOURCE, 0, 0x1000, 0),
+        // Only the last of these waits in the queue.
+        command(SOURCE, 1, 6, 1),
+        // The queue has room for three of these.
+        command(SOURCE, 1, 6, 0),
+    ];
+    // Four upcalls from the source, then the notification.
+    for _ in 0..5 {
+        script.push(yield_wait());
+    }
+    script.push(command(upcall_overflow::DRIVER_NUM, 1, 0, 0));
+    // A queue four upcalls deep.
+    let app = sim.install_app_with_script(
+        TbfBuilder::new().tlv(10, &le_words(&[0, 0, 0, 4])).build(),
+        script,
+    );
+    let process = sim.load_new_process().unwrap().unwrap();
+    assert_eq!(process.upcall_queue_depth(), 4);
+
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(app)) == 3));
+    assert_eq!(
+        upcall_arguments(&sim, app, SOURCE, 0),
+        vec![[5, 0, 0], [0, 0, 0], [1, 0, 0], [2, 0, 0]]
+    );
+    // The process learns that it lost three upcalls of the source once it
+    // handled one.
+    assert_eq!(
+        upcall_arguments(&sim, app, upcall_overflow::DRIVER_NUM, 0),
+        vec![[3, SOURCE, 0]]
+    );
+    let returns = syscall_returns(&sim, app);
+    assert!(matches!(
+        returns[returns.len() - 1],
+        SyscallReturn::SuccessU32U32(4, 3)
+    ));
+    assert_eq!(process.debug_dropped_upcall_count(), 3);
+}
+
+#[test]
+fn profile_counts_process_time_and_syscall_latency() {
+    const SOURCE: usize = UPCALL_SOURCE_DRIVER_NUM;
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    let profile = leak(KernelProfile::new(sim.sim_alarm));
+    sim.kernel.set_profile(profile, &TestCapability);
+    let app = sim.install_app_with_script(
+        TbfBuilder::new().build(),
+        vec![
+            AppAction::Compute(2_000),
+            command(SOURCE, 2, 100, 0),
+            command(SOURCE, 2, 100, 0),
+            command(SOURCE, 2, 100, 0),
+            command(SOURCE, 2, 3_000, 0),
+        ],
+    );
+    let process = sim.load_new_process().unwrap().unwrap();
+
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(app)) == 4));
+    let latency = profile.syscall_latency(SOURCE).unwrap();
+    assert_eq!(latency.count, 4);
+    assert_eq!(latency.max_ticks, 3_000);
+    assert_eq!(latency.mean_ticks(), 825);
+    assert_eq!(latency.buckets[LatencyHistogram::bucket(100)], 3);
+    assert_eq!(latency.buckets[LatencyHistogram::bucket(3_000)], 1);
+    assert_eq!(profile.syscall_latency(console::DRIVER_NUM), None);
+    // The process is charged for its computation and the cost of switching
+    // to it, but not for the time the driver took.
+    let info = KernelInfo::new(sim.kernel);
+    let ticks = info.app_cpu_ticks(process.processid(), &TestCapability);
+    assert!(ticks >= 2_000 && ticks < 2_000 + 10 * SWITCH_COST_US);
+
+    profile.clear();
+    assert_eq!(profile.syscall_latency(SOURCE), None);
+    assert_eq!(
+        info.app_cpu_ticks(process.processid(), &TestCapability),
+        ticks
+    );
+}
+
+#[test]
+fn dependent_processes_restart_with_their_service_after_a_backoff() {
+    static THRESHOLD: procs::ThresholdRestart = procs::ThresholdRestart::new(1);
+    static DEPENDENCIES: [procs::RestartDependency; 1] = [procs::RestartDependency {
+        client: "client",
+        service: "service",
+    }];
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    let restart_alarm = leak(SimAlarm::new(sim.clock));
+    sim.chip.add_peripheral(restart_alarm);
+    let policy = leak(procs::DependentRestart::<_, NUM_PROCS>::new(
+        sim.kernel,
+        restart_alarm,
+        &THRESHOLD,
+        &DEPENDENCIES,
+        procs::RestartBackoff {
+            initial_ms: 100,
+            max_ms: 10_000,
+        },
+    ));
+    restart_alarm.set_alarm_client(policy);
+    sim.kernel.set_dependent_restart(policy, &TestCapability);
+
+    let service_app = sim.install_app_with_script(
+        TbfBuilder::new().package_name("service").build(),
+        vec![command(0xbad, 0, 0, 0), AppAction::Fault],
+    );
+    let client_app = sim.install_app_with_script(
+        TbfBuilder::new().package_name("client").build(),
+        vec![command(0xbad, 1, 0, 0)],
+    );
+    let other_app = sim.install_app_with_script(
+        TbfBuilder::new().package_name("other").build(),
+        vec![command(0xbad, 2, 0, 0)],
+    );
+    let service = sim
+        .load_new_process_with(FaultResponse::Restart(policy))
+        .unwrap()
+        .unwrap();
+    let client = sim
+        .load_new_process_with(FaultResponse::Restart(policy))
+        .unwrap()
+        .unwrap();
+    let other = sim
+        .load_new_process_with(FaultResponse::Restart(policy))
+        .unwrap()
+        .unwrap();
+
+    // The service runs first and faults. The client is not stopped in the
+    // fault path, but before it runs, and is held back until the service
+    // restarts. The service only restarts once the backoff ends.
+    assert!(sim.run_until(|| service.get_state() == procs::State::Terminated));
+    let faulted_us = sim.clock.now_us();
+    assert_eq!(client.get_state(), procs::State::Unstarted);
+    assert!(sim.run_until(|| client.get_state() == procs::State::Terminated));
+    assert_eq!(count_commands(&sim.syscall.app_events(client_app)), 0);
+    assert!(policy.restart_pending(service));
+    assert!(policy.restart_pending(client));
+
+    assert!(sim.run_until(|| service.get_restart_count() == 1));
+    assert!(sim.clock.now_us() >= faulted_us + 100_000);
+    assert_eq!(client.get_restart_count(), 1);
+
+    // The backoff doubles when the service faults again right away, before
+    // the client gets to run, and the client restarts with it again. After
+    // that the threshold stops the service, and the client finally runs.
+    assert!(sim.run_until(|| service.get_state() == procs::State::Faulted));
+    assert!(sim.clock.now_us() >= faulted_us + 300_000);
+    assert_eq!(service.get_restart_count(), 2);
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(client_app)) == 1));
+    assert_eq!(client.get_restart_count(), 2);
+    assert!(!policy.restart_pending(client));
+
+    assert_eq!(count_commands(&sim.syscall.app_events(service_app)), 3);
+    assert_eq!(count_commands(&sim.syscall.app_events(other_app)), 1);
+    assert_eq!(other.get_restart_count(), 0);
+}
+
+#[test]
+fn dependent_processes_restart_when_their_service_is_restarted() {
+    static THRESHOLD: procs::ThresholdRestart = procs::ThresholdRestart::new(1);
+    static DEPENDENCIES: [procs::RestartDependency; 1] = [procs::RestartDependency {
+        client: "client",
+        service: "service",
+    }];
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+    let restart_alarm = leak(SimAlarm::new(sim.clock));
+    sim.chip.add_peripheral(restart_alarm);
+    let policy = leak(procs::DependentRestart::<_, NUM_PROCS>::new(
+        sim.kernel,
+        restart_alarm,
+        &THRESHOLD,
+        &DEPENDENCIES,
+        procs::RestartBackoff {
+            initial_ms: 100,
+            max_ms: 10_000,
+        },
+    ));
+    restart_alarm.set_alarm_client(policy);
+    sim.kernel.set_dependent_restart(policy, &TestCapability);
+
+    let service_app = sim.install_app_with_script(
+        TbfBuilder::new().package_name("service").build(),
+        vec![command(0xbad, 0, 0, 0), yield_wait()],
+    );
+    let client_app = sim.install_app_with_script(
+        TbfBuilder::new().package_name("client").build(),
+        vec![command(0xbad, 1, 0, 0), yield_wait()],
+    );
+    let service = sim
+        .load_new_process_with(FaultResponse::Restart(policy))
+        .unwrap()
+        .unwrap();
+    let client = sim
+        .load_new_process_with(FaultResponse::Restart(policy))
+        .unwrap()
+        .unwrap();
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(client_app)) == 1));
+
+    // Restarting the service, as the process console does, restarts the
+    // client right after it.
+    service.try_restart(0);
+    assert_eq!(client.get_restart_count(), 0);
+    assert!(
+        sim.run_until(|| count_commands(&sim.syscall.app_events(service_app)) == 2
+            && count_commands(&sim.syscall.app_events(client_app)) == 2)
+    );
+    assert_eq!(service.get_restart_count(), 1);
+    assert_eq!(client.get_restart_count(), 1);
+
+    // A service that is stopped for good leaves the client alone.
+    service.terminate(0);
+    assert!(sim.run_until(|| !restart_alarm.is_armed()));
+    assert_eq!(client.get_restart_count(), 1);
+    assert_eq!(client.get_state(), procs::State::Yielded);
+}
+
+#[test]
+fn pinned_buffers_cannot_be_reallowed_or_freed_until_unpinned() {
+    const DMA: usize = DMA_DEVICE_DRIVER_NUM;
+    static POLICY: procs::ThresholdRestart = procs::ThresholdRestart::new(1);
+    let allow = |subdriver_number, offset, size| AppAction::ReadWriteAllow {
+        driver_number: DMA,
+        subdriver_number,
+        offset,
+        size,
+    };
+    let sim = Sim::new(
+        vec![vec![
+            allow(0, 0x200, 16),
+            command(DMA, 1, 0, 0),
+            // The pinned buffer can be neither swapped out nor unallowed.
+            allow(0, 0x180, 8),
+            allow(0, 0x180, 0),
+            // Its memory cannot be shared again, but other memory can.
+            allow(1, 0x208, 4),
+            AppAction::ReadOnlyAllow {
+                driver_number: DMA,
+                subdriver_number: 0,
+                offset: 0x20c,
+                size: 4,
+            },
+            AppAction::ReadOnlyAllow {
+                driver_number: DMA,
+                subdriver_number: 0,
+                offset: 0x100,
+                size: 4,
+            },
+            // The driver cannot access the allowed buffer while it is lent
+            // out.
+            command(DMA, 2, 0, 0),
+            AppAction::Fault,
+        ]],
+        FaultResponse::Restart(&POLICY),
+    );
+    let process = sim.process(0);
+
+    assert!(sim.run_until(|| process.get_state() == procs::State::Terminated));
+    let returns = syscall_returns(&sim, 0);
+    assert!(matches!(
+        returns[0],
+        SyscallReturn::AllowReadWriteSuccess(_, 0)
+    ));
+    assert!(matches!(returns[1], SyscallReturn::Success));
+    for value in &returns[2..5] {
+        assert!(matches!(
+            value,
+            SyscallReturn::AllowReadWriteFailure(ErrorCode::BUSY, _, _)
+        ));
+    }
+    assert!(matches!(
+        returns[5],
+        SyscallReturn::AllowReadOnlyFailure(ErrorCode::BUSY, _, _)
+    ));
+    assert!(matches!(
+        returns[6],
+        SyscallReturn::AllowReadOnlySuccess(..)
+    ));
+    assert!(matches!(returns[7], SyscallReturn::SuccessU32(0)));
+
+    // The process faulted, but its memory stays in use until the transfer
+    // finishes, so it neither restarts nor unloads.
+    assert_eq!(process.pinned_buffer_count(), 1);
+    assert_eq!(process.get_restart_count(), 0);
+    assert_eq!(
+        sim.unload_process(process.processid()),
+        Err(ErrorCode::BUSY)
+    );
+
+    sim.platform.dma_device.finish_transfer(b"dma");
+    assert_eq!(read_process_memory(process, 0x200, 3), b"dma");
+    assert_eq!(process.pinned_buffer_count(), 0);
+    assert_eq!(process.get_restart_count(), 1);
+    assert!(sim.run_until(|| count_commands(&sim.syscall.app_events(0)) == 4));
+}
+
+/// The value of a storage permissions TLV.
+fn storage_permissions(write_id: u32, read_ids: &[u32], modify_ids: &[u32]) -> Vec<u8> {
+    let mut value = write_id.to_le_bytes().to_vec();
+    value.extend_from_slice(&(read_ids.len() as u16).to_le_bytes());
+    value.extend_from_slice(&(modify_ids.len() as u16).to_le_bytes());
+    value.extend(le_words(read_ids));
+    value.extend(le_words(modify_ids));
+    value
+}
+
+/// A script that allows `key` at 0x100, and makes the value of its key-value
+/// store operations be read into 0x180.
+fn kv_store_script(key: &[u8]) -> Vec<AppAction> {
+    vec![
+        AppAction::WriteMemory {
+            offset: 0x100,
+            data: key.to_vec(),
+        },
+        AppAction::ReadOnlyAllow {
+            driver_number: kv_store::DRIVER_NUM,
+            subdriver_number: 0,
+            offset: 0x100,
+            size: key.len(),
+        },
+        AppAction::ReadWriteAllow {
+            driver_number: kv_store::DRIVER_NUM,
+            subdriver_number: 0,
+            offset: 0x180,
+            size: 16,
+        },
+        subscribe(kv_store::DRIVER_NUM, 0, 0x1000, 0),
+    ]
+}
+
+/// Store `value`, allowed at 0x140, and wait for the upcall.
+fn kv_store_set(value: &[u8]) -> Vec<AppAction> {
+    vec![
+        AppAction::WriteMemory {
+            offset: 0x140,
+            data: value.to_vec(),
+        },
+        AppAction::ReadOnlyAllow {
+            driver_number: kv_store::DRIVER_NUM,
+            subdriver_number: 1,
+            offset: 0x140,
+            size: value.len(),
+        },
+        command(kv_store::DRIVER_NUM, 2, 0, 0),
+        yield_wait(),
+    ]
+}
+
+#[test]
+fn kv_store_enforces_storage_permissions() {
+    const FAIL: usize = ErrorCode::FAIL as usize;
+    let get = || vec![command(kv_store::DRIVER_NUM, 1, 0, 0), yield_wait()];
+    let delete = || vec![command(kv_store::DRIVER_NUM, 3, 0, 0), yield_wait()];
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+
+    // The owner stores a value, replaces it and reads it back.
+    let mut script = kv_store_script(b"config");
+    script.extend(kv_store_set(b"abcd"));
+    script.extend(kv_store_set(b"hello!"));
+    script.extend(get());
+    let owner = sim.install_app_with_script(
+        TbfBuilder::new()
+            .tlv(15, &storage_permissions(1, &[], &[]))
+            .build(),
+        script,
+    );
+    sim.load_new_process().unwrap();
+    assert!(sim.run_until(|| upcall_arguments(&sim, owner, kv_store::DRIVER_NUM, 0).len() == 3));
+    assert_eq!(
+        upcall_arguments(&sim, owner, kv_store::DRIVER_NUM, 0),
+        vec![[0, 0, 0], [0, 0, 0], [0, 6, 0]]
+    );
+    assert_eq!(
+        read_process_memory(sim.process(0), 0x180, 6),
+        b"hello!".to_vec()
+    );
+
+    // A process that may read the value cannot change it, and one without
+    // permissions can do neither.
+    let mut script = kv_store_script(b"config");
+    script.extend(get());
+    script.extend(delete());
+    script.extend(kv_store_set(b"mine"));
+    let reader = sim.install_app_with_script(
+        TbfBuilder::new()
+            .tlv(15, &storage_permissions(2, &[1], &[]))
+            .build(),
+        script,
+    );
+    sim.load_new_process().unwrap();
+    let mut script = kv_store_script(b"config");
+    script.extend(get());
+    script.extend(kv_store_set(b"mine"));
+    let stranger = sim.install_app_with_script(TbfBuilder::new().build(), script);
+    sim.load_new_process().unwrap();
+    assert!(sim.run_until(
+        || upcall_arguments(&sim, reader, kv_store::DRIVER_NUM, 0).len() == 3
+            && upcall_arguments(&sim, stranger, kv_store::DRIVER_NUM, 0).len() == 1
+    ));
+    assert_eq!(
+        upcall_arguments(&sim, reader, kv_store::DRIVER_NUM, 0),
+        vec![[0, 6, 0], [FAIL, 0, 0], [FAIL, 0, 0]]
+    );
+    assert_eq!(
+        read_process_memory(sim.process(1), 0x180, 6),
+        b"hello!".to_vec()
+    );
+    assert_eq!(
+        upcall_arguments(&sim, stranger, kv_store::DRIVER_NUM, 0),
+        vec![[FAIL, 0, 0]]
+    );
+    // The stranger may not store values at all.
+    assert!(matches!(
+        syscall_returns(&sim, stranger).last(),
+        Some(SyscallReturn::Failure(ErrorCode::FAIL))
+    ));
+
+    // A process that may modify the value deletes it.
+    let mut script = kv_store_script(b"config");
+    script.extend(delete());
+    script.extend(get());
+    let admin = sim.install_app_with_script(
+        TbfBuilder::new()
+            .tlv(15, &storage_permissions(3, &[], &[1]))
+            .build(),
+        script,
+    );
+    sim.load_new_process().unwrap();
+    assert!(sim.run_until(|| upcall_arguments(&sim, admin, kv_store::DRIVER_NUM, 0).len() == 2));
+    assert_eq!(
+        upcall_arguments(&sim, admin, kv_store::DRIVER_NUM, 0),
+        vec![[0, 0, 0], [FAIL, 0, 0]]
+    );
+}
+
+/// `AES128CCM` done in software, which finishes the next time the chip
+/// services interrupts, as AES hardware would.
+struct SoftwareCcm {
+    key: Cell<[u8; 16]>,
+    nonce: Cell<[u8; tickv::encryption::CCM_NONCE_LEN]>,
+    /// Offsets of the authenticated data and the message, the lengths of the
+    /// message and the tag, and whether the message is being encrypted.
+    operation: Cell<Option<(usize, usize, usize, usize, bool)>>,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'static dyn CCMClient>,
+}
+
+impl SoftwareCcm {
+    fn new() -> SoftwareCcm {
+        SoftwareCcm {
+            key: Cell::new([0; 16]),
+            nonce: Cell::new([0; tickv::encryption::CCM_NONCE_LEN]),
+            operation: Cell::new(None),
+            buffer: TakeCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl AES128CCM<'static> for SoftwareCcm {
+    fn set_client(&'static self, client: &'static dyn CCMClient) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        let mut new_key = [0; 16];
+        if key.len() != new_key.len() {
+            return Err(ErrorCode::INVAL);
+        }
+        new_key.copy_from_slice(key);
+        self.key.set(new_key);
+        Ok(())
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode> {
+        let mut new_nonce = [0; tickv::encryption::CCM_NONCE_LEN];
+        if nonce.len() != new_nonce.len() {
+            return Err(ErrorCode::INVAL);
+        }
+        new_nonce.copy_from_slice(nonce);
+        self.nonce.set(new_nonce);
+        Ok(())
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        mic_len: usize,
+        _confidential: bool,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.buffer.is_some() {
+            Err((ErrorCode::BUSY, buf))
+        } else if a_off > m_off || m_off + m_len + mic_len > buf.len() {
+            Err((ErrorCode::INVAL, buf))
+        } else {
+            self.operation
+                .set(Some((a_off, m_off, m_len, mic_len, encrypting)));
+            self.buffer.replace(buf);
+            Ok(())
+        }
+    }
+}
+
+impl SimPeripheral for SoftwareCcm {
+    fn interrupt_pending(&self) -> bool {
+        self.operation.get().is_some()
+    }
+
+    fn service_interrupt(&self) {
+        let (a_off, m_off, m_len, mic_len, encrypting) = match self.operation.take() {
+            Some(operation) => operation,
+            None => return,
+        };
+        let buf = self.buffer.take().unwrap();
+        let cipher = Aes128Ccm::new(&self.key.get());
+        let nonce = self.nonce.get();
+
+        let (aad, message) = buf[a_off..].split_at_mut(m_off - a_off);
+        let (data, tag) = message.split_at_mut(m_len);
+        let tag_is_valid = if encrypting {
+            cipher.encrypt(&nonce, aad, data, &mut tag[..mic_len]);
+            true
+        } else {
+            cipher.decrypt(&nonce, aad, data, &tag[..mic_len]).is_ok()
+        };
+        self.client
+            .map(move |client| client.crypt_done(buf, Ok(()), tag_is_valid));
+    }
+
+    fn next_interrupt_us(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// `Rng` that counts up, handing out a few numbers each time randomness is
+/// requested.
+struct CountingRng {
+    next: Cell<u32>,
+    requested: Cell<bool>,
+    client: OptionalCell<&'static dyn rng::Client>,
+}
+
+impl CountingRng {
+    fn new() -> CountingRng {
+        CountingRng {
+            next: Cell::new(1),
+            requested: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl Rng<'static> for CountingRng {
+    fn get(&self) -> Result<(), ErrorCode> {
+        self.requested.set(true);
+        Ok(())
+    }
+
+    fn cancel(&self) -> Result<(), ErrorCode> {
+        self.requested.set(false);
+        Ok(())
+    }
+
+    fn set_client(&'static self, client: &'static dyn rng::Client) {
+        self.client.set(client);
+    }
+}
+
+impl SimPeripheral for CountingRng {
+    fn interrupt_pending(&self) -> bool {
+        self.requested.get()
+    }
+
+    fn service_interrupt(&self) {
+        self.requested.set(false);
+        let first = self.next.get();
+        self.next.set(first + 4);
+        let mut randomness = first..first + 4;
+        if let Some(rng::Continue::More) = self
+            .client
+            .map(|client| client.randomness_available(&mut randomness, Ok(())))
+        {
+            self.requested.set(true);
+        }
+    }
+
+    fn next_interrupt_us(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// `kv_system::Client` that keeps the result and buffers of the last
+/// operation.
+struct KVSystemClient {
+    result: Cell<Option<Result<(), ErrorCode>>>,
+    key: TakeCell<'static, TicKVKeyType>,
+    value: TakeCell<'static, [u8]>,
+}
+
+impl KVSystemClient {
+    fn new() -> KVSystemClient {
+        KVSystemClient {
+            result: Cell::new(None),
+            key: TakeCell::empty(),
+            value: TakeCell::empty(),
+        }
+    }
+
+    fn complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut TicKVKeyType,
+        value: Option<&'static mut [u8]>,
+    ) {
+        self.result.set(Some(result));
+        self.key.replace(key);
+        value.map(|value| self.value.replace(value));
+    }
+}
+
+impl kv_system::Client<TicKVKeyType> for KVSystemClient {
+    fn generate_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        unhashed_key: &'static mut [u8],
+        key_buf: &'static mut TicKVKeyType,
+    ) {
+        self.complete(result, key_buf, Some(unhashed_key));
+    }
+
+    fn append_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut TicKVKeyType,
+        value: &'static mut [u8],
+    ) {
+        self.complete(result, key, Some(value));
+    }
+
+    fn update_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut TicKVKeyType,
+        value: &'static mut [u8],
+    ) {
+        self.complete(result, key, Some(value));
+    }
+
+    fn get_value_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut TicKVKeyType,
+        ret_buf: &'static mut [u8],
+    ) {
+        self.complete(result, key, Some(ret_buf));
+    }
+
+    fn invalidate_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut TicKVKeyType,
+    ) {
+        self.complete(result, key, None);
+    }
+
+    fn garbage_collect_complete(&self, result: Result<(), ErrorCode>) {
+        self.result.set(Some(result));
+    }
+}
+
+/// The check sum TicKV stores after each object.
+fn tickv_check_sum(object: &[u8]) -> [u8; 4] {
+    let mut crc: u32 = 0;
+    for byte in object {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    (crc ^ 0xffff_ffff).to_ne_bytes()
+}
+
+#[test]
+fn tickv_store_encrypts_and_authenticates_values() {
+    const TICKV_PAGES: usize = 4;
+    const VALUE: &[u8] = b"secret value";
+    // Header and check sum of a TicKV object.
+    const OBJECT_HEADER_LEN: usize = 11;
+    const CHECK_SUM_LEN: usize = 4;
+    let sim = Sim::new(vec![], FaultResponse::Stop);
+
+    let flash = leak(SimFlash::new(TICKV_PAGES));
+    sim.chip.add_peripheral(flash);
+    let tickv = leak(TicKVStore::new(
+        flash,
+        Box::leak(Box::new([0; PAGE_SIZE])),
+        Box::leak(Box::new(SimFlashPage::default())),
+        0,
+        TICKV_PAGES * PAGE_SIZE,
+    ));
+    flash.set_client(tickv);
+    let aes = leak(SoftwareCcm::new());
+    sim.chip.add_peripheral(aes);
+    let rng = leak(CountingRng::new());
+    sim.chip.add_peripheral(rng);
+    tickv.set_encryption(aes, rng, *b"0123456789abcdef");
+    let client = leak(KVSystemClient::new());
+    tickv.set_client(client);
+    tickv.initalise();
+
+    // A buffer without room for the nonce, length and tag is refused.
+    let value = Box::leak(vec![0; VALUE.len() + OVERHEAD - 1].into_boxed_slice());
+    value[..VALUE.len()].copy_from_slice(VALUE);
+    assert!(sim.run_until(|| !flash.interrupt_pending()));
+    match tickv.append_key(Box::leak(Box::new(*b"tickvkey")), value, VALUE.len()) {
+        Err((key, value, result)) => {
+            assert_eq!(result, Err(ErrorCode::SIZE));
+            client.key.replace(key);
+            client.value.replace(value);
+        }
+        Ok(()) => panic!("value stored without room to encrypt it"),
+    }
+
+    // The value is encrypted before it is stored, and decrypted when it is
+    // read back.
+    let value = Box::leak(vec![0; VALUE.len() + OVERHEAD].into_boxed_slice());
+    value[..VALUE.len()].copy_from_slice(VALUE);
+    assert!(tickv
+        .append_key(client.key.take().unwrap(), value, VALUE.len())
+        .is_ok());
+    assert!(sim.run_until(|| client.result.get().is_some()));
+    assert_eq!(client.result.take(), Some(Ok(())));
+    let stored = client.value.map(|value| value.to_vec()).unwrap();
+    assert!(!stored.windows(VALUE.len()).any(|window| window == VALUE));
+
+    let ret_buf = Box::leak(vec![0; 64].into_boxed_slice());
+    assert!(tickv.get_value(client.key.take().unwrap(), ret_buf).is_ok());
+    assert!(sim.run_until(|| client.result.get().is_some()));
+    assert_eq!(client.result.take(), Some(Ok(())));
+    assert_eq!(
+        client.value.map(|value| value[..VALUE.len()].to_vec()),
+        Some(VALUE.to_vec())
+    );
+
+    // Change the stored value and fix up TicKV's check sum, so that only the
+    // authentication tag can tell.
+    flash.modify_pages(|pages| {
+        let page = pages
+            .iter_mut()
+            .find(|page| {
+                page.windows(stored.len())
+                    .any(|window| window == &stored[..])
+            })
+            .unwrap();
+        let start = page
+            .windows(stored.len())
+            .position(|window| window == &stored[..])
+            .unwrap();
+        let object = start - OBJECT_HEADER_LEN..start + stored.len();
+        let check_sum = object.end..object.end + CHECK_SUM_LEN;
+        assert_eq!(
+            tickv_check_sum(&page[object.clone()]),
+            page[check_sum.clone()]
+        );
+
+        page[start + HEADER_LEN] ^= 1;
+        let new_check_sum = tickv_check_sum(&page[object]);
+        page[check_sum].copy_from_slice(&new_check_sum);
+    });
+
+    let ret_buf = client.value.take().unwrap();
+    assert!(tickv.get_value(client.key.take().unwrap(), ret_buf).is_ok());
+    assert!(sim.run_until(|| client.result.get().is_some()));
+    assert_eq!(client.result.take(), Some(Err(ErrorCode::FAIL)));
+    assert!(client
+        .value
+        .map(|value| value.iter().all(|b| *b == 0))
+        .unwrap());
+}
+
+/// Custom process console command that keeps the arguments it was run with.
+#[derive(Default)]
+struct RecordingCommand {
+    args: RefCell<Vec<String>>,
+}
+
+impl ConsoleCommandClient for RecordingCommand {
+    fn execute(&self, args: &str) {
+        self.args.borrow_mut().push(args.to_string());
+    }
+}
+
+#[test]
+fn process_console_edits_and_runs_typed_commands() {
+    let sim = Sim::new(
+        vec![
+            vec![],
+            vec![
+                AppAction::ReadWriteAllow {
+                    driver_number: DMA_DEVICE_DRIVER_NUM,
+                    subdriver_number: 0,
+                    offset: 0x200,
+                    size: 16,
+                },
+                command(DMA_DEVICE_DRIVER_NUM, 1, 0, 0),
+            ],
+        ],
+        FaultResponse::Stop,
+    );
+
+    // The console prints with `debug!()`, so its output goes to a second
+    // UART. No other test prints, so this one owns the global debug writer.
+    let debug_uart = leak(SimUart::new());
+    sim.chip.add_peripheral(debug_uart);
+    let debug_writer = leak(DebugWriter::new(
+        debug_uart,
+        Box::leak(vec![0; 64].into_boxed_slice()),
+        Box::leak(Box::new(RingBuffer::new(Box::leak(
+            vec![0; 4096].into_boxed_slice(),
+        )))),
+    ));
+    debug_uart.set_transmit_client(debug_writer);
+    unsafe {
+        debug::set_debug_writer_wrapper(Box::leak(Box::new(DebugWriterWrapper::new(debug_writer))));
+    }
+
+    let uart = leak(SimUart::new());
+    sim.chip.add_peripheral(uart);
+    let pconsole = leak(ProcessConsole::new(
+        uart,
+        Box::leak(vec![0; 64].into_boxed_slice()),
+        Box::leak(vec![0; 1].into_boxed_slice()),
+        Box::leak(vec![0; 32].into_boxed_slice()),
+        sim.kernel,
+        TestCapability,
+    ));
+    uart.set_transmit_client(pconsole);
+    uart.set_receive_client(pconsole);
+    let radio = leak(RecordingCommand::default());
+    pconsole.add_command(leak(ConsoleCommand::new(
+        "radio",
+        "radio c: switch to channel c",
+        radio,
+    )));
+    pconsole.start().unwrap();
+
+    // Processes are not scheduled, so that they stay unstarted. Only the
+    // UARTs run.
+    let type_keys = |keys: &[u8]| {
+        uart.inject_input(keys);
+        sim.chip.service_pending_interrupts();
+    };
+    let echo = || String::from_utf8(uart.output()).unwrap();
+    let printed = || String::from_utf8(debug_uart.output()).unwrap();
+
+    // Tab completes the command, and backspace edits it.
+    type_keys(b"lis\t\r");
+    assert_eq!(echo(), "list \r\n");
+    assert_eq!(printed().matches(" PID").count(), 1);
+    assert!(printed().contains("app0"));
+    assert!(printed().contains("app1"));
+    type_keys(b"helq\x08p\r");
+    assert!(printed().contains(
+        "Valid commands are: help status list stop start fault terminate boot restart \
+         process kernel trace crash profile radio\r\n radio c: switch to channel c"
+    ));
+
+    // Custom commands complete too, and get their arguments.
+    type_keys(b"rad\t 7 \r");
+    assert_eq!(*radio.args.borrow(), vec!["7".to_string()]);
+
+    // The up arrow steps back through the history, which rewrites the line.
+    let echoed = echo().len();
+    type_keys(b"\x1b[A\x1b[A\x1b[A\r");
+    assert_eq!(
+        &echo()[echoed..],
+        "\r\x1b[Kradio  7 \r\x1b[Khelp\r\x1b[Klist \r\n"
+    );
+    assert_eq!(printed().matches(" PID").count(), 2);
+    // The down arrow past the newest command clears the line.
+    type_keys(b"\x1b[A\x1b[B\x1b[Bhelp\r");
+    assert_eq!(printed().matches("Valid commands").count(), 2);
+
+    // Process names complete as far as they are unambiguous.
+    let process = sim.process(0);
+    let echoed = echo().len();
+    type_keys(b"boot a\t0\r");
+    assert_eq!(&echo()[echoed..], "boot app0\r\n");
+    assert!(printed().contains("Process app0 has not started yet"));
+    assert_eq!(process.get_restart_count(), 0);
+    type_keys(b"terminate app0\rboot app0\r");
+    assert!(printed().contains("Process app0 booted"));
+    assert_eq!(process.get_restart_count(), 1);
+    assert_eq!(process.get_state(), procs::State::Unstarted);
+
+    // The memory map is longer than the write buffer, so it is sent in
+    // pieces.
+    let mut memory_map = String::new();
+    sim.process(1).print_memory_map(&mut memory_map);
+    assert!(memory_map.len() > 64);
+    let echoed = echo().len();
+    type_keys(b"process app1\r");
+    assert_eq!(echo()[echoed..], format!("process app1\r\n{}", memory_map));
+
+    // A process cannot restart while a device has its buffer, and the
+    // console says so rather than that it restarted.
+    let process = sim.process(1);
+    assert!(sim.run_until(|| process.pinned_buffer_count() == 1));
+    type_keys(b"restart app1\r");
+    assert!(printed().contains("Process app1 restarts once its DMA buffers are unpinned"));
+    assert!(!printed().contains("Process app1 restarted"));
+    sim.platform.dma_device.finish_transfer(&[]);
+    assert_eq!(process.get_state(), procs::State::Unstarted);
+}
@@ -0,0 +1,180 @@
+//! Simulated UART.
+
+use core::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart;
+use kernel::ErrorCode;
+
+use crate::chip::SimPeripheral;
+
+/// A UART whose transmitted bytes are collected for inspection and whose
+/// received bytes are injected by the test.
+///
+/// Transmissions complete the next time the chip services interrupts.
+/// Receptions complete once enough bytes have been injected with
+/// `inject_input()` to fill the requested length.
+pub struct SimUart<'a> {
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_abort: Cell<bool>,
+    output: RefCell<Vec<u8>>,
+    input: RefCell<VecDeque<u8>>,
+}
+
+impl<'a> SimUart<'a> {
+    pub fn new() -> SimUart<'a> {
+        SimUart {
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_abort: Cell::new(false),
+            output: RefCell::new(Vec::new()),
+            input: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Every byte transmitted so far.
+    pub fn output(&self) -> Vec<u8> {
+        self.output.borrow().clone()
+    }
+
+    /// Queue bytes as if they arrived on the receive line.
+    pub fn inject_input(&self, bytes: &[u8]) {
+        self.input.borrow_mut().extend(bytes);
+    }
+
+    fn rx_ready(&self) -> bool {
+        self.rx_buffer.is_some()
+            && (self.rx_abort.get() || self.input.borrow().len() >= self.rx_len.get())
+    }
+}
+
+impl uart::Configure for SimUart<'_> {
+    fn configure(&self, _params: uart::Parameters) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+}
+
+impl<'a> uart::Transmit<'a> for SimUart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.tx_buffer.is_some() {
+            Err((ErrorCode::BUSY, tx_buffer))
+        } else if tx_len > tx_buffer.len() {
+            Err((ErrorCode::SIZE, tx_buffer))
+        } else {
+            self.tx_len.set(tx_len);
+            self.tx_buffer.replace(tx_buffer);
+            Ok(())
+        }
+    }
+
+    fn transmit_word(&self, _word: u32) -> Result<(), ErrorCode> {
+        Err(ErrorCode::FAIL)
+    }
+
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        if self.tx_buffer.is_some() {
+            // The transmission always completes on the next service, so it
+            // cannot be cancelled.
+            Err(ErrorCode::FAIL)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> uart::Receive<'a> for SimUart<'a> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.rx_buffer.is_some() {
+            Err((ErrorCode::BUSY, rx_buffer))
+        } else if rx_len > rx_buffer.len() {
+            Err((ErrorCode::SIZE, rx_buffer))
+        } else {
+            self.rx_len.set(rx_len);
+            self.rx_abort.set(false);
+            self.rx_buffer.replace(rx_buffer);
+            Ok(())
+        }
+    }
+
+    fn receive_word(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::FAIL)
+    }
+
+    fn receive_abort(&self) -> Result<(), ErrorCode> {
+        if self.rx_buffer.is_some() {
+            // The cancelled buffer is returned on the next service.
+            self.rx_abort.set(true);
+            Err(ErrorCode::BUSY)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> uart::UartData<'a> for SimUart<'a> {}
+impl<'a> uart::Uart<'a> for SimUart<'a> {}
+
+impl SimPeripheral for SimUart<'_> {
+    fn interrupt_pending(&self) -> bool {
+        self.tx_buffer.is_some() || self.rx_ready()
+    }
+
+    fn service_interrupt(&self) {
+        self.tx_buffer.take().map(|buffer| {
+            let len = self.tx_len.get();
+            self.output.borrow_mut().extend_from_slice(&buffer[..len]);
+            self.tx_client
+                .map(move |client| client.transmitted_buffer(buffer, len, Ok(())));
+        });
+
+        if self.rx_ready() {
+            self.rx_buffer.take().map(|buffer| {
+                let (len, rval, error) = if self.rx_abort.get() {
+                    (0, Err(ErrorCode::CANCEL), uart::Error::Aborted)
+                } else {
+                    let len = self.rx_len.get();
+                    let mut input = self.input.borrow_mut();
+                    for (byte, received) in buffer.iter_mut().zip(input.drain(..len)) {
+                        *byte = received;
+                    }
+                    (len, Ok(()), uart::Error::None)
+                };
+                self.rx_abort.set(false);
+                self.rx_client
+                    .map(move |client| client.received_buffer(buffer, len, rval, error));
+            });
+        }
+    }
+
+    fn next_interrupt_us(&self) -> Option<u64> {
+        // Transfers never wait on simulated time. Anything outstanding is
+        // already reported by `interrupt_pending()`.
+        None
+    }
+}
@@ -0,0 +1,42 @@
+//! A watchdog that counts what the kernel does with it.
+
+use core::cell::Cell;
+
+use kernel::watchdog::WatchDog;
+
+/// Watchdog that never resets anything, but counts how often it was tickled
+/// so tests can tell whether the kernel kept it from firing.
+pub struct SimWatchDog {
+    tickles: Cell<usize>,
+    suspended: Cell<bool>,
+}
+
+impl SimWatchDog {
+    pub const fn new() -> SimWatchDog {
+        SimWatchDog {
+            tickles: Cell::new(0),
+            suspended: Cell::new(false),
+        }
+    }
+
+    /// How many times the watchdog was tickled, including by `resume()`.
+    pub fn tickles(&self) -> usize {
+        self.tickles.get()
+    }
+
+    /// Whether the watchdog is suspended for sleep.
+    pub fn suspended(&self) -> bool {
+        self.suspended.get()
+    }
+}
+
+impl WatchDog for SimWatchDog {
+    fn tickle(&self) {
+        self.tickles.set(self.tickles.get() + 1);
+        self.suspended.set(false);
+    }
+
+    fn suspend(&self) {
+        self.suspended.set(true);
+    }
+}
@@ -0,0 +1,113 @@
+//! Checking app credentials with the software SHA-256 capsule.
+//!
+//! `Sha256Software` delivers its callbacks with a dynamic deferred call,
+//! which the kernel only services on the global `DynamicDeferredCall`
+//! instance. That instance is shared by the whole test binary, so this test
+//! lives in its own binary rather than next to the tests in `src/tests.rs`,
+//! which run in parallel.
+
+use capsules::sha256::Sha256Software;
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
+use kernel::hil::digest::Digest;
+use kernel::procs::{self, FaultResponse, Process};
+use kernel::Kernel;
+
+use host_sim::memory::leak_app_memory;
+use host_sim::scheduler_timer::SimSchedulerTimer;
+use host_sim::tbf::{flash_image, TbfBuilder};
+use host_sim::{SimChip, SimClock, SimSysCall};
+
+const NUM_PROCS: usize = 4;
+const APP_MEMORY_SIZE: usize = 64 * 1024;
+
+struct TestCapability;
+unsafe impl capabilities::ProcessManagementCapability for TestCapability {}
+
+fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+#[test]
+fn only_apps_with_approved_credentials_load() {
+    let key = [0x5a; 32];
+    let mut tampered = TbfBuilder::new()
+        .package_name("tampered")
+        .hmac_sha256_credentials(key)
+        .build();
+    tampered[512] ^= 1;
+    let app_flash: &'static [u8] = Box::leak(
+        flash_image(&[
+            TbfBuilder::new().package_name("unsigned").build(),
+            TbfBuilder::new()
+                .package_name("hashed")
+                .sha256_credentials()
+                .build(),
+            TbfBuilder::new()
+                .package_name("forged")
+                .hmac_sha256_credentials([0xa5; 32])
+                .build(),
+            tampered,
+            // Credentials the policy does not check are skipped.
+            TbfBuilder::new()
+                .package_name("signed")
+                .credentials(0, &[0; 32])
+                .hmac_sha256_credentials(key)
+                .build(),
+        ])
+        .into_boxed_slice(),
+    );
+    let app_memory = leak_app_memory(APP_MEMORY_SIZE);
+
+    let clock = leak(SimClock::new());
+    let scheduler_timer = leak(SimSchedulerTimer::new(clock));
+    let syscall = leak(SimSysCall::new(clock, scheduler_timer));
+    let chip = leak(SimChip::new(clock, syscall, scheduler_timer));
+    let processes: &'static mut [Option<&'static dyn Process>; NUM_PROCS] =
+        Box::leak(Box::new([None; NUM_PROCS]));
+    let processes_ptr: *mut [Option<&'static dyn Process>; NUM_PROCS] = processes;
+    let kernel = leak(Kernel::new(unsafe { &*processes_ptr }));
+    let mut load = move |policy: &dyn procs::AppCredentialsPolicy| {
+        procs::load_new_process(
+            kernel,
+            chip,
+            app_flash,
+            app_memory,
+            unsafe { &mut *processes_ptr },
+            FaultResponse::Stop,
+            policy,
+            &TestCapability,
+        )
+        .unwrap()
+    };
+
+    let dynamic_deferred_call = leak(DynamicDeferredCall::new(Box::leak(Box::new([
+        DynamicDeferredCallClientState::default(),
+    ]))));
+    assert!(unsafe { DynamicDeferredCall::set_global_instance(dynamic_deferred_call) });
+    let engine = leak(Sha256Software::new(dynamic_deferred_call));
+    engine.initialize_callback_handle(dynamic_deferred_call.register(engine).unwrap());
+
+    // The digest engine takes the app in pieces smaller than the app.
+    let policy = leak(procs::RequireHmacSha256Credentials::new(
+        engine,
+        Box::leak(Box::new([0; 100])),
+        Box::leak(Box::new([0; 32])),
+        key,
+    ));
+    engine.set_client(policy);
+    let process = load(policy).unwrap();
+    assert_eq!(process.get_process_name(), "signed");
+    assert!(load(policy).is_none());
+
+    // A hash only shows the app is intact.
+    let policy = leak(procs::RequireSha256Credentials::new(
+        engine,
+        Box::leak(Box::new([0; 100])),
+        Box::leak(Box::new([0; 32])),
+    ));
+    engine.set_client(policy);
+    let process = load(policy).unwrap();
+    assert_eq!(process.get_process_name(), "hashed");
+    assert!(load(policy).is_none());
+}
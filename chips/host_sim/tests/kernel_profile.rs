@@ -0,0 +1,125 @@
+//! Profiling the time the kernel spends on interrupts and deferred calls.
+//!
+//! The kernel only services deferred calls on the global
+//! `DynamicDeferredCall` instance, which is shared by the whole test binary,
+//! so this test lives in its own binary rather than next to the tests in
+//! `src/tests.rs`, which run in parallel.
+
+use std::cell::Cell;
+
+use kernel::capabilities;
+use kernel::common::cells::OptionalCell;
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+    DynamicDeferredCallClientState,
+};
+use kernel::procs::Process;
+use kernel::profile::KernelProfile;
+use kernel::{Driver, Kernel, Platform, RoundRobinSched};
+
+use host_sim::alarm::SimAlarm;
+use host_sim::scheduler_timer::SimSchedulerTimer;
+use host_sim::{run_until, SimChip, SimClock, SimPeripheral, SimSysCall};
+
+const NUM_PROCS: usize = 4;
+const MAX_ITERATIONS: usize = 100;
+
+struct TestCapability;
+unsafe impl capabilities::MainLoopCapability for TestCapability {}
+unsafe impl capabilities::ProcessManagementCapability for TestCapability {}
+
+fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+struct NoDrivers;
+
+impl Platform for NoDrivers {
+    fn with_driver<F, R>(&self, _driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn Driver>) -> R,
+    {
+        f(None)
+    }
+}
+
+/// Peripheral whose interrupt bottom half takes `interrupt_us` and then
+/// defers work that takes `deferred_us`.
+struct SlowPeripheral {
+    clock: &'static SimClock,
+    dynamic_deferred_call: &'static DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+    interrupt_us: u64,
+    deferred_us: u64,
+    pending: Cell<bool>,
+    done: Cell<bool>,
+}
+
+impl SimPeripheral for SlowPeripheral {
+    fn interrupt_pending(&self) -> bool {
+        self.pending.get()
+    }
+
+    fn service_interrupt(&self) {
+        self.pending.set(false);
+        self.clock.advance_us(self.interrupt_us);
+        self.handle
+            .map(|handle| self.dynamic_deferred_call.set(*handle));
+    }
+
+    fn next_interrupt_us(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl DynamicDeferredCallClient for SlowPeripheral {
+    fn call(&self, _handle: DeferredCallHandle) {
+        self.clock.advance_us(self.deferred_us);
+        self.done.set(true);
+    }
+}
+
+#[test]
+fn interrupts_and_deferred_calls_are_profiled_separately() {
+    let clock = leak(SimClock::new());
+    let scheduler_timer = leak(SimSchedulerTimer::new(clock));
+    let syscall = leak(SimSysCall::new(clock, scheduler_timer));
+    let chip = leak(SimChip::new(clock, syscall, scheduler_timer));
+    let processes: &'static [Option<&'static dyn Process>; NUM_PROCS] = leak([None; NUM_PROCS]);
+    let kernel = leak(Kernel::new(processes));
+    let scheduler = leak(RoundRobinSched::new());
+
+    let profile = leak(KernelProfile::new(leak(SimAlarm::new(clock))));
+    kernel.set_profile(profile, &TestCapability);
+
+    let dynamic_deferred_call = leak(DynamicDeferredCall::new(Box::leak(Box::new([
+        DynamicDeferredCallClientState::default(),
+    ]))));
+    assert!(unsafe { DynamicDeferredCall::set_global_instance(dynamic_deferred_call) });
+    let peripheral = leak(SlowPeripheral {
+        clock,
+        dynamic_deferred_call,
+        handle: OptionalCell::empty(),
+        interrupt_us: 700,
+        deferred_us: 300,
+        pending: Cell::new(true),
+        done: Cell::new(false),
+    });
+    peripheral
+        .handle
+        .set(dynamic_deferred_call.register(peripheral).unwrap());
+    chip.add_peripheral(peripheral);
+
+    assert!(run_until(
+        kernel,
+        &NoDrivers,
+        chip,
+        None::<&kernel::ipc::IPC<NUM_PROCS>>,
+        scheduler,
+        MAX_ITERATIONS,
+        &TestCapability,
+        || peripheral.done.get(),
+    ));
+    assert_eq!(profile.interrupt_ticks(), 700);
+    assert_eq!(profile.deferred_call_ticks(), 300);
+}
@@ -41,6 +41,7 @@ use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOn
 use kernel::common::StaticRef;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
+use kernel::power::{PowerManager, SleepState, SleepVote};
 use kernel::ErrorCode;
 use nrf5x::constants::TxPower;
 
@@ -535,6 +536,8 @@ pub struct Radio<'a> {
     rx_client: OptionalCell<&'a dyn ble_advertising::RxClient>,
     tx_client: OptionalCell<&'a dyn ble_advertising::TxClient>,
     buffer: TakeCell<'static, [u8]>,
+    // The radio needs the crystal oscillator while it is on.
+    sleep_vote: SleepVote<'a>,
 }
 
 impl<'a> Radio<'a> {
@@ -545,9 +548,14 @@ impl<'a> Radio<'a> {
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
             buffer: TakeCell::empty(),
+            sleep_vote: SleepVote::new(),
         }
     }
 
+    pub fn set_power_manager(&self, manager: &'a PowerManager) {
+        self.sleep_vote.set_power_manager(manager);
+    }
+
     pub fn is_enabled(&self) -> bool {
         self.registers.mode.matches_all(Mode::MODE::BLE_1MBIT)
     }
@@ -578,10 +586,12 @@ impl<'a> Radio<'a> {
         // reset and enable power
         self.registers.power.write(Task::ENABLE::CLEAR);
         self.registers.power.write(Task::ENABLE::SET);
+        self.sleep_vote.vote(SleepState::Sleep);
     }
 
     fn radio_off(&self) {
         self.registers.power.write(Task::ENABLE::CLEAR);
+        self.sleep_vote.release();
     }
 
     fn set_tx_power(&self) {
@@ -1,19 +1,33 @@
+use crate::clock::HighClockSource;
 use crate::deferred_call_tasks::DeferredCallTask;
 use core::fmt::Write;
 use cortexm4::{self, nvic};
 use kernel::common::deferred_call;
 use kernel::hil::time::Alarm;
+use kernel::power::{PowerManager, SleepState};
 use kernel::InterruptService;
 
+/// Time the CPU takes to wake up in low power mode, in microseconds.
+const SLEEP_WAKEUP_LATENCY_US: u32 = 5;
+/// Time to wake up and restart the crystal oscillator, in microseconds.
+const DEEP_SLEEP_WAKEUP_LATENCY_US: u32 = 400;
+
 pub struct NRF52<'a, I: InterruptService<DeferredCallTask> + 'a> {
     mpu: cortexm4::mpu::MPU,
     userspace_kernel_boundary: cortexm4::syscall::SysCall,
     scheduler_timer: cortexm4::systick::SysTick,
     interrupt_service: &'a I,
+    // Used to choose how deeply the chip sleeps.
+    power: &'a crate::power::Power<'a>,
+    clock: &'a crate::clock::Clock,
 }
 
 impl<'a, I: InterruptService<DeferredCallTask> + 'a> NRF52<'a, I> {
-    pub unsafe fn new(interrupt_service: &'a I) -> Self {
+    pub unsafe fn new(
+        interrupt_service: &'a I,
+        power: &'a crate::power::Power<'a>,
+        clock: &'a crate::clock::Clock,
+    ) -> Self {
         Self {
             mpu: cortexm4::mpu::MPU::new(),
             userspace_kernel_boundary: cortexm4::syscall::SysCall::new(),
@@ -21,6 +35,8 @@ impl<'a, I: InterruptService<DeferredCallTask> + 'a> NRF52<'a, I> {
             // 64Mhz CPU clock.
             scheduler_timer: cortexm4::systick::SysTick::new_with_calibration(64000000),
             interrupt_service,
+            power,
+            clock,
         }
     }
 }
@@ -84,6 +100,14 @@ impl<'a> Nrf52DefaultPeripherals<'a> {
         self.ieee802154_radio.set_timer_ref(&self.timer0);
         self.timer0.set_alarm_client(&self.ieee802154_radio);
     }
+
+    /// Let the peripherals that need the crystal oscillator keep the chip
+    /// out of deep sleep.
+    pub fn set_power_manager(&'a self, manager: &'a PowerManager) {
+        self.ieee802154_radio.set_power_manager(manager);
+        self.ble_radio.set_power_manager(manager);
+        self.uarte0.set_power_manager(manager);
+    }
 }
 impl<'a> kernel::InterruptService<DeferredCallTask> for Nrf52DefaultPeripherals<'a> {
     unsafe fn service_interrupt(&self, interrupt: u32) -> bool {
@@ -206,6 +230,40 @@ impl<'a, I: InterruptService<DeferredCallTask> + 'a> kernel::Chip for NRF52<'a,
         }
     }
 
+    /// `Idle` sleeps in constant latency mode, `Sleep` in low power mode, and
+    /// `DeepSleep` also stops the crystal oscillator. Peripherals that need
+    /// the crystal oscillator, such as the radios, USBD and UARTE, vote for
+    /// `Sleep` while they use it. Other peripherals that need the high
+    /// frequency clock while it is stopped run from the less accurate
+    /// internal oscillator, so the crystal oscillator is restarted before the
+    /// chip services the interrupt that woke it.
+    fn sleep_in(&self, state: SleepState) {
+        self.power.set_constant_latency(state == SleepState::Idle);
+        let stop_hfxo = state == SleepState::DeepSleep
+            && self.clock.high_running()
+            && matches!(self.clock.high_source(), HighClockSource::XTAL);
+        if stop_hfxo {
+            self.clock.high_stop();
+        }
+        unsafe {
+            cortexm4::support::wfi();
+        }
+        if stop_hfxo {
+            self.clock.high_start();
+            while !(self.clock.high_running()
+                && matches!(self.clock.high_source(), HighClockSource::XTAL))
+            {}
+        }
+    }
+
+    fn wakeup_latency_us(&self, state: SleepState) -> u32 {
+        match state {
+            SleepState::Idle => 0,
+            SleepState::Sleep => SLEEP_WAKEUP_LATENCY_US,
+            SleepState::DeepSleep => DEEP_SLEEP_WAKEUP_LATENCY_US,
+        }
+    }
+
     unsafe fn atomic<F, R>(&self, f: F) -> R
     where
         F: FnOnce() -> R,
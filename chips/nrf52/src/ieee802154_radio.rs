@@ -8,6 +8,7 @@ use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOn
 use kernel::common::StaticRef;
 use kernel::hil::radio::{self, PowerClient};
 use kernel::hil::time::{Alarm, AlarmClient};
+use kernel::power::{PowerManager, SleepState, SleepVote};
 use kernel::ErrorCode;
 
 use nrf5x;
@@ -673,6 +674,8 @@ pub struct Radio<'p> {
     channel: Cell<RadioChannel>,
     transmitting: Cell<bool>,
     timer0: OptionalCell<&'p crate::timer::TimerAlarm<'p>>,
+    // The radio needs the crystal oscillator while it is on.
+    sleep_vote: SleepVote<'p>,
 }
 
 impl<'a> AlarmClient for Radio<'a> {
@@ -699,6 +702,7 @@ impl<'p> Radio<'p> {
             channel: Cell::new(RadioChannel::DataChannel26),
             transmitting: Cell::new(false),
             timer0: OptionalCell::empty(),
+            sleep_vote: SleepVote::new(),
         }
     }
 
@@ -706,6 +710,10 @@ impl<'p> Radio<'p> {
         self.timer0.set(timer);
     }
 
+    pub fn set_power_manager(&self, manager: &'p PowerManager) {
+        self.sleep_vote.set_power_manager(manager);
+    }
+
     pub fn is_enabled(&self) -> bool {
         self.registers
             .mode
@@ -751,10 +759,12 @@ impl<'p> Radio<'p> {
         // reset and enable power
         self.registers.power.write(Task::ENABLE::CLEAR);
         self.registers.power.write(Task::ENABLE::SET);
+        self.sleep_vote.vote(SleepState::Sleep);
     }
 
     fn radio_off(&self) {
         self.registers.power.write(Task::ENABLE::CLEAR);
+        self.sleep_vote.release();
     }
 
     fn set_tx_power(&self) {
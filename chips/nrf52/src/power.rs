@@ -322,6 +322,16 @@ impl<'a> Power<'a> {
         self.registers.intenclr.set(0xffffffff);
     }
 
+    /// Keep the regulators running while the CPU sleeps, so that it wakes up
+    /// as fast as possible, or let them turn off to save current.
+    pub fn set_constant_latency(&self, constant_latency: bool) {
+        if constant_latency {
+            self.registers.task_constlat.write(Task::ENABLE::SET);
+        } else {
+            self.registers.task_lowpwr.write(Task::ENABLE::SET);
+        }
+    }
+
     pub fn get_main_supply_status(&self) -> MainVoltage {
         match self
             .registers
@@ -13,6 +13,7 @@ use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil::uart;
+use kernel::power::{PowerManager, SleepState, SleepVote};
 use kernel::ErrorCode;
 use nrf5x::pinmux;
 
@@ -169,6 +170,9 @@ pub struct Uarte<'a> {
     rx_remaining_bytes: Cell<usize>,
     rx_abort_in_progress: Cell<bool>,
     offset: Cell<usize>,
+    // The baud rate is only accurate enough when the high frequency clock
+    // runs from the crystal oscillator, so keep it running during transfers.
+    sleep_vote: SleepVote<'a>,
 }
 
 #[derive(Copy, Clone)]
@@ -191,6 +195,21 @@ impl<'a> Uarte<'a> {
             rx_remaining_bytes: Cell::new(0),
             rx_abort_in_progress: Cell::new(false),
             offset: Cell::new(0),
+            sleep_vote: SleepVote::new(),
+        }
+    }
+
+    pub fn set_power_manager(&self, manager: &'a PowerManager) {
+        self.sleep_vote.set_power_manager(manager);
+    }
+
+    // Keep the crystal oscillator running while a transmit or a receive is in
+    // progress.
+    fn update_sleep_vote(&self) {
+        if self.tx_buffer.is_some() || self.rx_buffer.is_some() {
+            self.sleep_vote.vote(SleepState::Sleep);
+        } else {
+            self.sleep_vote.release();
         }
     }
 
@@ -286,6 +305,11 @@ impl<'a> Uarte<'a> {
     /// UART interrupt handler that listens for both tx_end and rx_end events
     #[inline(never)]
     pub fn handle_interrupt(&self) {
+        self.handle_events();
+        self.update_sleep_vote();
+    }
+
+    fn handle_events(&self) {
         if self.tx_ready() {
             self.disable_tx_interrupts();
             self.registers.event_endtx.write(Event::READY::CLEAR);
@@ -432,6 +456,7 @@ impl<'a> Uarte<'a> {
         self.registers.task_starttx.write(Task::ENABLE::SET);
 
         self.enable_tx_interrupts();
+        self.update_sleep_vote();
     }
 }
 
@@ -517,6 +542,7 @@ impl<'a> uart::Receive<'a> for Uarte<'a> {
         self.registers.task_startrx.write(Task::ENABLE::SET);
 
         self.enable_rx_interrupts();
+        self.update_sleep_vote();
         Ok(())
     }
 
@@ -10,6 +10,7 @@ use kernel::common::registers::{
 use kernel::common::StaticRef;
 use kernel::hil;
 use kernel::hil::usb::TransferType;
+use kernel::power::{PowerManager, SleepState, SleepVote};
 
 use crate::power;
 
@@ -710,6 +711,8 @@ pub struct Usbd<'a> {
     client: OptionalCell<&'a dyn hil::usb::Client<'a>>,
     descriptors: [Endpoint<'a>; NUM_ENDPOINTS],
     power: OptionalCell<&'a power::Power<'a>>,
+    // The USB PHY needs the crystal oscillator while it is enabled.
+    sleep_vote: SleepVote<'a>,
 }
 
 impl<'a> Usbd<'a> {
@@ -730,6 +733,7 @@ impl<'a> Usbd<'a> {
                 Endpoint::new(),
             ],
             power: OptionalCell::empty(),
+            sleep_vote: SleepVote::new(),
         }
     }
 
@@ -737,6 +741,10 @@ impl<'a> Usbd<'a> {
         self.power.set(power);
     }
 
+    pub fn set_power_manager(&self, manager: &'a PowerManager) {
+        self.sleep_vote.set_power_manager(manager);
+    }
+
     fn has_errata_166(&self) -> bool {
         true
     }
@@ -823,6 +831,7 @@ impl<'a> Usbd<'a> {
         self.apply_errata_187(3);
         self.apply_errata_171(0xc0);
         self.registers.enable.write(Usb::ENABLE::ON);
+        self.sleep_vote.vote(SleepState::Sleep);
         while !self.registers.eventcause.is_set(EventCause::READY) {}
         self.registers.eventcause.modify(EventCause::READY::CLEAR);
         self.apply_errata_171(0);
@@ -966,6 +975,7 @@ impl<'a> Usbd<'a> {
         debug_info!("usbc::disable() - State={:?}", self.get_state());
         self.stop();
         self.registers.enable.write(Usb::ENABLE::OFF);
+        self.sleep_vote.release();
         self.state.set(UsbState::Initialized);
         self.clear_pending_dma();
     }
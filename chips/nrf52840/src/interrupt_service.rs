@@ -26,6 +26,13 @@ impl<'a> Nrf52840DefaultPeripherals<'a> {
         self.usbd.set_power_ref(&self.nrf52.pwr_clk);
         self.nrf52.init();
     }
+
+    /// Let the peripherals that need the crystal oscillator keep the chip
+    /// out of deep sleep.
+    pub fn set_power_manager(&'a self, manager: &'a kernel::power::PowerManager) {
+        self.nrf52.set_power_manager(manager);
+        self.usbd.set_power_manager(manager);
+    }
 }
 impl<'a> kernel::InterruptService<DeferredCallTask> for Nrf52840DefaultPeripherals<'a> {
     unsafe fn service_interrupt(&self, interrupt: u32) -> bool {
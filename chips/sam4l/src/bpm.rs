@@ -200,3 +200,20 @@ pub unsafe fn set_power_scaling(ps_value: PowerScaling) {
             + PowerModeControl::PSCREQ::PowerScalingRequested,
     );
 }
+
+/// Whether deep sleep enters RETENTION mode rather than WAIT mode. RETENTION
+/// mode also stops the internal regulator, so it saves more current but takes
+/// longer to wake up from.
+pub unsafe fn set_retention(retention: bool) {
+    let value = if retention {
+        PowerModeControl::RET::PowerSave
+    } else {
+        PowerModeControl::RET::NoPowerSave
+    };
+    if BPM.pmcon.matches_all(value) {
+        return;
+    }
+    let control = BPM.pmcon.extract();
+    unlock_register(0x1c); // Control
+    BPM.pmcon.modify_no_read(control, value);
+}
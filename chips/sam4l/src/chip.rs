@@ -1,13 +1,20 @@
 //! Interrupt mapping and DMA channel setup.
 
+use crate::bpm;
 use crate::deferred_call_tasks::Task;
 use crate::pm;
 
 use core::fmt::Write;
 use cortexm4;
 use kernel::common::deferred_call;
+use kernel::power::SleepState;
 use kernel::{Chip, InterruptService};
 
+/// Time to wake up from WAIT mode, in microseconds.
+const WAIT_WAKEUP_LATENCY_US: u32 = 10;
+/// Time to wake up from RETENTION mode, in microseconds.
+const RETENTION_WAKEUP_LATENCY_US: u32 = 1500;
+
 pub struct Sam4l<I: InterruptService<Task> + 'static> {
     mpu: cortexm4::mpu::MPU,
     userspace_kernel_boundary: cortexm4::syscall::SysCall,
@@ -153,6 +160,17 @@ impl Sam4lDefaultPeripherals {
         self.adc.set_dma(&self.dma_channels[13]);
         self.dma_channels[13].initialize(&self.adc, dma::DMAWidth::Width16Bit);
     }
+
+    /// Let the peripherals that cannot wake the chip from RETENTION mode keep
+    /// it out of deep sleep.
+    pub fn set_power_manager(&self, manager: &'static kernel::power::PowerManager) {
+        self.usart0.set_power_manager(manager);
+        self.usart1.set_power_manager(manager);
+        self.usart2.set_power_manager(manager);
+        self.usart3.set_power_manager(manager);
+        self.i2c0.set_power_manager(manager);
+        self.i2c1.set_power_manager(manager);
+    }
 }
 impl kernel::InterruptService<Task> for Sam4lDefaultPeripherals {
     unsafe fn service_interrupt(&self, interrupt: u32) -> bool {
@@ -300,6 +318,32 @@ impl<I: InterruptService<Task> + 'static> Chip for Sam4l<I> {
         }
     }
 
+    /// `Idle` only stops the CPU. `Sleep` enters WAIT mode and `DeepSleep`
+    /// RETENTION mode, if the enabled clocks allow deep sleep at all, as with
+    /// `sleep()`. Fewer interrupts wake the chip from RETENTION mode than from
+    /// WAIT mode, so the peripherals that rely on the others, the USARTs and
+    /// the TWI slaves, vote for `Sleep` while they are active.
+    fn sleep_in(&self, state: SleepState) {
+        let deep_sleep = state != SleepState::Idle && pm::deep_sleep_ready();
+        unsafe {
+            if deep_sleep {
+                bpm::set_retention(state == SleepState::DeepSleep);
+                cortexm4::scb::set_sleepdeep();
+            } else {
+                cortexm4::scb::unset_sleepdeep();
+            }
+            cortexm4::support::wfi();
+        }
+    }
+
+    fn wakeup_latency_us(&self, state: SleepState) -> u32 {
+        match state {
+            SleepState::Idle => 0,
+            SleepState::Sleep => WAIT_WAKEUP_LATENCY_US,
+            SleepState::DeepSleep => RETENTION_WAKEUP_LATENCY_US,
+        }
+    }
+
     unsafe fn atomic<F, R>(&self, f: F) -> R
     where
         F: FnOnce() -> R,
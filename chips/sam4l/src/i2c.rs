@@ -17,6 +17,7 @@ use kernel::common::peripherals::{PeripheralManagement, PeripheralManager};
 use kernel::common::registers::{register_bitfields, FieldValue, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil;
+use kernel::power::{SleepState, SleepVote};
 use kernel::ClockInterface;
 
 // Listing of all registers related to the TWIM peripheral.
@@ -561,6 +562,9 @@ pub struct I2CHw {
     slave_write_buffer_len: Cell<u8>,
     slave_write_buffer_index: Cell<u8>,
     pm: &'static pm::PowerManager,
+    // An address match wakes the chip from WAIT mode, but not necessarily
+    // from RETENTION mode, so keep the chip out of it while listening.
+    sleep_vote: SleepVote<'static>,
 }
 
 impl PeripheralManagement<TWIMClock> for I2CHw {
@@ -658,9 +662,14 @@ impl I2CHw {
             slave_write_buffer_len: Cell::new(0),
             slave_write_buffer_index: Cell::new(0),
             pm,
+            sleep_vote: SleepVote::new(),
         }
     }
 
+    pub fn set_power_manager(&self, manager: &'static kernel::power::PowerManager) {
+        self.sleep_vote.set_power_manager(manager);
+    }
+
     pub const fn new_i2c0(pm: &'static pm::PowerManager) -> Self {
         I2CHw::new(
             I2C_BASE_ADDRS[0],
@@ -1411,11 +1420,13 @@ impl hil::i2c::I2CSlave for I2CHw {
         }
 
         self.slave_enabled.set(true);
+        self.sleep_vote.vote(SleepState::Sleep);
     }
 
     /// This disables the entire I2C peripheral
     fn disable(&self) {
         self.slave_enabled.set(false);
+        self.sleep_vote.release();
 
         if self.slave_mmio_address.is_some() {
             let twis = &TWISRegisterManager::new(&self);
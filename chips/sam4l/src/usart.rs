@@ -11,6 +11,7 @@ use kernel::common::StaticRef;
 use kernel::hil;
 use kernel::hil::spi;
 use kernel::hil::uart;
+use kernel::power::{SleepState, SleepVote};
 use kernel::ErrorCode;
 
 use crate::dma;
@@ -287,6 +288,7 @@ pub struct USARTRegManager<'a> {
     clock: pm::Clock,
     rx_dma: Option<&'a dma::DMAChannel>,
     tx_dma: Option<&'a dma::DMAChannel>,
+    sleep_vote: &'a SleepVote<'static>,
 }
 
 static IS_PANICING: AtomicBool = AtomicBool::new(false);
@@ -302,6 +304,7 @@ impl<'a> USARTRegManager<'a> {
             clock: usart.clock,
             rx_dma: usart.rx_dma.get(),
             tx_dma: usart.tx_dma.get(),
+            sleep_vote: &usart.sleep_vote,
         }
     }
 
@@ -339,6 +342,10 @@ impl Drop for USARTRegManager<'_> {
         let is_panic = IS_PANICING.load(Ordering::Relaxed);
         if !(rx_active || tx_active || ints_active || is_panic) {
             pm::disable_clock(self.clock);
+            self.sleep_vote.release();
+        } else {
+            // Not every interrupt wakes the chip from RETENTION mode.
+            self.sleep_vote.vote(SleepState::Sleep);
         }
     }
 }
@@ -394,6 +401,7 @@ pub struct USART<'a> {
 
     spi_chip_select: OptionalCell<&'a dyn hil::gpio::Pin>,
     pm: &'a pm::PowerManager,
+    sleep_vote: SleepVote<'static>,
 }
 
 impl<'a> USART<'a> {
@@ -427,9 +435,14 @@ impl<'a> USART<'a> {
             // This is only used if the USART is in SPI mode.
             spi_chip_select: OptionalCell::empty(),
             pm,
+            sleep_vote: SleepVote::new(),
         }
     }
 
+    pub fn set_power_manager(&self, manager: &'static kernel::power::PowerManager) {
+        self.sleep_vote.set_power_manager(manager);
+    }
+
     pub const fn new_usart0(pm: &'a pm::PowerManager) -> Self {
         USART::new(
             USART_BASE_ADDRS[0],
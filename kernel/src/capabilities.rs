@@ -90,3 +90,8 @@ pub unsafe trait CreatePortTableCapability {}
 /// of the networking stack. A capsule would never hold this capability although
 /// it may hold capabilities created via this capability.
 pub unsafe trait NetworkCapabilityCreationCapability {}
+
+/// The `KernelStorageCapability` allows the holder to access the persistent
+/// storage shared with processes with the kernel's permissions, that is to
+/// read, overwrite and delete any value.
+pub unsafe trait KernelStorageCapability {}
@@ -50,6 +50,23 @@ impl<'a, T: Copy> RingBuffer<'a, T> {
             (None, None)
         }
     }
+
+    /// Returns the oldest element for which `f` returns true, so that it can
+    /// be modified in place.
+    pub fn find_mut<F>(&mut self, mut f: F) -> Option<&mut T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let len = self.ring.len();
+        let mut index = self.head;
+        while index != self.tail {
+            if f(&self.ring[index]) {
+                return Some(&mut self.ring[index]);
+            }
+            index = (index + 1) % len;
+        }
+        None
+    }
 }
 
 impl<T: Copy> queue::Queue<T> for RingBuffer<'_, T> {
@@ -280,4 +297,22 @@ mod test {
         assert_eq!(buf.dequeue(), Some(9));
         assert_eq!(buf.dequeue(), None);
     }
+
+    #[test]
+    fn test_find_mut() {
+        const LEN: usize = 10;
+        let mut ring = [0; LEN];
+        let mut buf = RingBuffer::new(&mut ring);
+
+        move_head(&mut buf, LEN - 2);
+        enqueue_iota(&mut buf, LEN);
+
+        *buf.find_mut(|x| x % 3 == 0).unwrap() = 30;
+        assert!(buf.find_mut(|x| *x == 10).is_none());
+
+        assert_eq!(buf.dequeue(), Some(1));
+        assert_eq!(buf.dequeue(), Some(2));
+        assert_eq!(buf.dequeue(), Some(30));
+        assert_eq!(buf.dequeue(), Some(4));
+    }
 }
@@ -28,12 +28,6 @@
 /// To change the configuration, modify the relevant values in the `CONFIG` constant object defined
 /// at the end of this file.
 pub(crate) struct Config {
-    /// Whether the kernel should trace syscalls to the debug output.
-    ///
-    /// If enabled, the kernel will print a message in the debug output for each system call and
-    /// upcall, with details including the application ID, and system call or upcall parameters.
-    pub(crate) trace_syscalls: bool,
-
     /// Whether the kernel should show debugging output when loading processes.
     ///
     /// If enabled, the kernel will show from which addresses processes are loaded in flash and
@@ -45,6 +39,5 @@ pub(crate) struct Config {
 /// A unique instance of `Config` where compile-time configuration options are defined. These
 /// options are available in the kernel crate to be used for relevant configuration.
 pub(crate) const CONFIG: Config = Config {
-    trace_syscalls: false,
     debug_load_processes: false,
 };
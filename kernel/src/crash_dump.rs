@@ -0,0 +1,361 @@
+//! Crash records that survive a reboot.
+//!
+//! When a process faults or the kernel panics, the details are normally only
+//! printed to the debug console and are lost if nobody is attached. A
+//! `CrashDump` keeps a compact record of the last crash in a region of RAM
+//! that the board does not initialize at boot, so the record can be read
+//! after the board restarts, for example with the `crash` command of
+//! `capsules::process_console`.
+//!
+//! The kernel records process faults once the board calls
+//! `Kernel::set_crash_dump()`. Kernel panics are recorded by the board's
+//! panic handler with `debug::panic_crash_dump()`.
+//!
+//! A record is laid out as follows, with all fields little endian:
+//!
+//! ```text
+//! 0      magic (u32)
+//! 4      length of the record in bytes (u32)
+//! 8      checksum of the bytes from offset 12 to the end (u32)
+//! 12     kind (u8), flags (u8), register count (u8), name length (u8)
+//! 16     restart count (u32)
+//! 20     flash start, flash end, RAM start, app break, grant region start,
+//!        RAM end, stack pointer (u32 each)
+//! 48     registers (u32 each)
+//! ...    process name
+//! ...    data: the process stack above the stack pointer, or the panic
+//!        message
+//! ```
+//!
+//! Panic records only have a message. The process fields are zero.
+
+use core::cell::Cell;
+use core::cmp;
+use core::fmt;
+use core::str;
+
+use crate::common::cells::TakeCell;
+
+/// Marks the start of a valid crash record.
+const MAGIC: u32 = 0x4443_4b54;
+/// Length of the fixed part of a record.
+const HEADER_LEN: usize = 48;
+/// Most registers a record holds.
+pub const MAX_REGISTERS: usize = 34;
+/// Most bytes of the process stack a record holds.
+pub const STACK_WINDOW: usize = 64;
+
+/// Flag of process fault records whose fault response panics the kernel. The
+/// record of the panic does not replace them, since it only says that the
+/// process faulted.
+const FLAG_KERNEL_PANICKED: u8 = 1;
+
+/// What crashed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrashKind {
+    /// A process faulted.
+    ProcessFault = 1,
+    /// The kernel panicked.
+    KernelPanic = 2,
+}
+
+/// The memory of the process when it faulted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CrashMemoryMap {
+    pub flash_start: u32,
+    pub flash_end: u32,
+    pub mem_start: u32,
+    pub app_break: u32,
+    pub kernel_memory_break: u32,
+    pub mem_end: u32,
+    pub stack_pointer: u32,
+}
+
+/// What the kernel records about a faulted process.
+pub(crate) struct ProcessFault<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) restart_count: usize,
+    pub(crate) memory_map: CrashMemoryMap,
+    pub(crate) registers: &'a [u32],
+    pub(crate) stack: &'a [u8],
+    pub(crate) kernel_panics: bool,
+}
+
+/// A valid crash record.
+pub struct CrashRecord<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> CrashRecord<'a> {
+    fn word(&self, offset: usize) -> u32 {
+        read_u32(self.bytes, offset)
+    }
+
+    fn name_start(&self) -> usize {
+        HEADER_LEN + self.bytes[14] as usize * 4
+    }
+
+    fn data_start(&self) -> usize {
+        self.name_start() + self.bytes[15] as usize
+    }
+
+    pub fn kind(&self) -> CrashKind {
+        if self.bytes[12] == CrashKind::KernelPanic as u8 {
+            CrashKind::KernelPanic
+        } else {
+            CrashKind::ProcessFault
+        }
+    }
+
+    /// Whether the process fault made the kernel panic.
+    pub fn kernel_panicked(&self) -> bool {
+        self.bytes[13] & FLAG_KERNEL_PANICKED != 0
+    }
+
+    /// How many times the process had been restarted before it faulted.
+    pub fn restart_count(&self) -> u32 {
+        self.word(16)
+    }
+
+    pub fn memory_map(&self) -> CrashMemoryMap {
+        CrashMemoryMap {
+            flash_start: self.word(20),
+            flash_end: self.word(24),
+            mem_start: self.word(28),
+            app_break: self.word(32),
+            kernel_memory_break: self.word(36),
+            mem_end: self.word(40),
+            stack_pointer: self.word(44),
+        }
+    }
+
+    /// The registers of the process, in the order of the architecture's
+    /// `UserspaceKernelBoundary::store_registers()`.
+    pub fn registers(&self) -> impl Iterator<Item = u32> + '_ {
+        (HEADER_LEN..self.name_start())
+            .step_by(4)
+            .map(move |offset| self.word(offset))
+    }
+
+    pub fn process_name(&self) -> &'a str {
+        str::from_utf8(&self.bytes[self.name_start()..self.data_start()]).unwrap_or("")
+    }
+
+    /// The process stack from the stack pointer up, for process faults.
+    pub fn stack(&self) -> &'a [u8] {
+        match self.kind() {
+            CrashKind::ProcessFault => &self.bytes[self.data_start()..],
+            CrashKind::KernelPanic => &[],
+        }
+    }
+
+    /// The panic message, for kernel panics.
+    pub fn message(&self) -> &'a str {
+        match self.kind() {
+            CrashKind::ProcessFault => "",
+            CrashKind::KernelPanic => {
+                str::from_utf8(&self.bytes[self.data_start()..]).unwrap_or("")
+            }
+        }
+    }
+}
+
+/// Holds the record of the last crash in a region of memory that survives a
+/// reboot.
+pub struct CrashDump {
+    region: TakeCell<'static, [u8]>,
+    /// Whether a record that a kernel panic must not replace was written
+    /// since boot.
+    keep_record: Cell<bool>,
+}
+
+impl CrashDump {
+    /// Keep crash records in `region`. The board must place the region in
+    /// memory that is not initialized at boot, so that a record written
+    /// before the board restarted is still there. The region must be large
+    /// enough for a record header; a process fault record is cut short if it
+    /// does not fit.
+    pub fn new(region: &'static mut [u8]) -> CrashDump {
+        CrashDump {
+            region: TakeCell::new(region),
+            keep_record: Cell::new(false),
+        }
+    }
+
+    /// Call `f` with the crash record, if there is a valid one.
+    pub fn map_record<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&CrashRecord) -> R,
+    {
+        self.region.map_or(None, |region| {
+            valid_record_len(region).map(|len| {
+                f(&CrashRecord {
+                    bytes: &region[..len],
+                })
+            })
+        })
+    }
+
+    /// Copy the raw crash record into `buf`. Returns the length of the
+    /// record, or 0 if there is none or it does not fit.
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        self.region
+            .map_or(0, |region| match valid_record_len(region) {
+                Some(len) if len <= buf.len() => {
+                    buf[..len].copy_from_slice(&region[..len]);
+                    len
+                }
+                _ => 0,
+            })
+    }
+
+    /// Forget the crash record.
+    pub fn clear(&self) {
+        self.region.map(|region| {
+            if region.len() >= 4 {
+                region[0..4].copy_from_slice(&0u32.to_le_bytes());
+            }
+        });
+    }
+
+    pub(crate) fn record_process_fault(&self, fault: ProcessFault) {
+        self.region.map(|region| {
+            if region.len() < HEADER_LEN {
+                return;
+            }
+            let num_registers = cmp::min(fault.registers.len(), MAX_REGISTERS);
+            let mut end = HEADER_LEN;
+            for register in &fault.registers[..num_registers] {
+                if end + 4 > region.len() {
+                    break;
+                }
+                region[end..end + 4].copy_from_slice(&register.to_le_bytes());
+                end += 4;
+            }
+            let num_registers = (end - HEADER_LEN) / 4;
+            let name = fault.name.as_bytes();
+            let name_len = append(region, &mut end, &name[..cmp::min(name.len(), 255)]);
+            append(region, &mut end, fault.stack);
+
+            let map = fault.memory_map;
+            region[12] = CrashKind::ProcessFault as u8;
+            region[13] = if fault.kernel_panics {
+                FLAG_KERNEL_PANICKED
+            } else {
+                0
+            };
+            region[14] = num_registers as u8;
+            region[15] = name_len as u8;
+            for (i, value) in [
+                fault.restart_count as u32,
+                map.flash_start,
+                map.flash_end,
+                map.mem_start,
+                map.app_break,
+                map.kernel_memory_break,
+                map.mem_end,
+                map.stack_pointer,
+            ]
+            .iter()
+            .enumerate()
+            {
+                region[16 + i * 4..20 + i * 4].copy_from_slice(&value.to_le_bytes());
+            }
+            seal(region, end);
+        });
+        if fault.kernel_panics {
+            self.keep_record.set(true);
+        }
+    }
+
+    pub(crate) fn record_panic(&self, message: &fmt::Arguments) {
+        if self.keep_record.get() {
+            return;
+        }
+        self.region.map(|region| {
+            if region.len() < HEADER_LEN {
+                return;
+            }
+            for byte in region[12..HEADER_LEN].iter_mut() {
+                *byte = 0;
+            }
+            region[12] = CrashKind::KernelPanic as u8;
+            let mut writer = RegionWriter {
+                region: &mut region[..],
+                end: HEADER_LEN,
+            };
+            let _ = fmt::write(&mut writer, *message);
+            let end = writer.end;
+            seal(region, end);
+        });
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Copy as much of `data` as fits into `region` at `end`. Returns how many
+/// bytes were copied.
+fn append(region: &mut [u8], end: &mut usize, data: &[u8]) -> usize {
+    let len = cmp::min(data.len(), region.len() - *end);
+    region[*end..*end + len].copy_from_slice(&data[..len]);
+    *end += len;
+    len
+}
+
+/// FNV-1a hash, to tell a record apart from whatever the memory held at
+/// power on.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash: u32, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Mark the record of `len` bytes in `region` as valid.
+fn seal(region: &mut [u8], len: usize) {
+    let sum = checksum(&region[12..len]);
+    region[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+    region[8..12].copy_from_slice(&sum.to_le_bytes());
+    region[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+}
+
+/// The length of the record in `region`, if it holds a valid one.
+fn valid_record_len(region: &[u8]) -> Option<usize> {
+    if region.len() < HEADER_LEN || read_u32(region, 0) != MAGIC {
+        return None;
+    }
+    let len = read_u32(region, 4) as usize;
+    if len < HEADER_LEN || len > region.len() || checksum(&region[12..len]) != read_u32(region, 8) {
+        return None;
+    }
+    let names_end = HEADER_LEN + region[14] as usize * 4 + region[15] as usize;
+    if names_end > len {
+        return None;
+    }
+    Some(len)
+}
+
+/// Formats a panic message into the region, cutting it short if it does not
+/// fit.
+struct RegionWriter<'a> {
+    region: &'a mut [u8],
+    end: usize,
+}
+
+impl fmt::Write for RegionWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Only keep whole characters, so that the message stays valid UTF-8.
+        let mut len = cmp::min(s.len(), self.region.len() - self.end);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        append(self.region, &mut self.end, &s.as_bytes()[..len]);
+        Ok(())
+    }
+}
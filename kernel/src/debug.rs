@@ -59,6 +59,7 @@ use crate::common::cells::NumericCellExt;
 use crate::common::cells::{MapCell, TakeCell};
 use crate::common::queue::Queue;
 use crate::common::ring_buffer::RingBuffer;
+use crate::crash_dump::CrashDump;
 use crate::hil;
 use crate::process::Process;
 use crate::Chip;
@@ -173,6 +174,21 @@ pub unsafe fn panic_cpu_state<W: Write, C: Chip>(
     });
 }
 
+/// Keep a record of the panic in the crash dump, so that it can be inspected
+/// after the board restarts.
+///
+/// Boards that keep crash records call this from their panic handler before
+/// `panic()`. If the panic comes from a process whose fault response is to
+/// panic, the record of the process fault is kept instead.
+pub unsafe fn panic_crash_dump(
+    crash_dump: &'static Option<&'static CrashDump>,
+    panic_info: &PanicInfo,
+) {
+    crash_dump.map(|crash_dump| {
+        crash_dump.record_panic(&format_args!("{}", panic_info));
+    });
+}
+
 /// More detailed prints about all processes.
 ///
 /// **NOTE:** The supplied `writer` must be synchronous.
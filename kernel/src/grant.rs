@@ -30,6 +30,11 @@
 //! operation, they can use an `Allocator` to request additional memory from
 //! the process's grant region.
 //!
+//! A capsule that no longer needs its state for a process can free the grant
+//! with `Grant::free()`. The memory of the object `T` and of the custom grants
+//! allocated with it is reused for later grants, and given back to the process
+//! when the kernel can shrink the grant region.
+//!
 //! ```text,ignore
 //!                            ┌──────────────────┐
 //!                            │                  │
@@ -110,7 +115,7 @@
 use core::marker::PhantomData;
 use core::mem::{align_of, size_of};
 use core::ops::{Deref, DerefMut};
-use core::ptr::{write, NonNull};
+use core::ptr::{drop_in_place, write, NonNull};
 
 use crate::process::{Error, Process, ProcessCustomGrantIdentifer, ProcessId};
 use crate::sched::Kernel;
@@ -226,9 +231,8 @@ impl<'a, T: Default> ProcessGrant<'a, T> {
                 // Allocate space in the process's memory for something of type
                 // `T` for the grant.
                 //
-                // Note: This allocation is intentionally never freed. A grant
-                // region is valid once allocated for the lifetime of the
-                // process.
+                // Note: This allocation is valid until the process restarts or
+                // the capsule frees it with `Grant::free()`.
                 //
                 // If the grant could not be allocated this will cause the
                 // `new()` function to return with an error.
@@ -588,6 +592,7 @@ impl<'a, T: Default> ProcessGrant<'a, T> {
         // grant space.
         let mut allocator = GrantRegionAllocator {
             processid: self.process.processid(),
+            grant_num: self.grant_num,
         };
 
         // Allow the capsule to access the grant.
@@ -683,6 +688,9 @@ impl<T> CustomGrant<T> {
 pub struct GrantRegionAllocator {
     /// The process the allocator will allocate memory from.
     processid: ProcessId,
+
+    /// The grant the allocations belong to. They are freed with it.
+    grant_num: usize,
 }
 
 impl GrantRegionAllocator {
@@ -775,7 +783,7 @@ impl GrantRegionAllocator {
             .kernel
             .process_map_or(Err(Error::NoSuchApp), self.processid, |process| {
                 process
-                    .allocate_custom_grant(alloc_size, align_of::<T>())
+                    .allocate_custom_grant(self.grant_num, alloc_size, align_of::<T>())
                     .map_or(
                         Err(Error::OutOfMemory),
                         |(custom_grant_identifier, raw_ptr)| {
@@ -882,6 +890,46 @@ impl<T: Default> Grant<T> {
             })
     }
 
+    /// Free the memory of the grant for a specific process, together with the
+    /// custom grants allocated while it was entered.
+    ///
+    /// The `T` stored in the grant is dropped. The next time the grant is
+    /// entered for the process, a new `T::default()` is allocated. Custom
+    /// grants of the freed grant can no longer be entered.
+    ///
+    /// Freed memory at the end of the grant region is given back to the
+    /// process, and the kernel moves other grants of the process to make the
+    /// grant region as small as it can.
+    ///
+    /// Returns `Ok(())` if the grant was not allocated for the process, and
+    /// `Err(Error::AlreadyInUse)` if it is currently entered.
+    pub fn free(&self, processid: ProcessId) -> Result<(), Error> {
+        // Verify that this process actually exists.
+        processid
+            .kernel
+            .process_map_or(Err(Error::NoSuchApp), processid, |process| {
+                match process.grant_is_allocated(self.grant_num) {
+                    Some(true) => {}
+                    Some(false) => return Ok(()),
+                    None => return Err(Error::InactiveApp),
+                }
+
+                // Entering the grant ensures no other reference to it exists.
+                let grant_ptr = process.enter_grant(self.grant_num)?;
+
+                // # Safety
+                //
+                // The grant is allocated and entered, so `grant_ptr` points to
+                // a valid `T` that nothing else refers to. The memory is freed
+                // right after, so the dropped `T` is never used again.
+                unsafe {
+                    drop_in_place(grant_ptr as *mut T);
+                }
+                process.free_grant(self.grant_num);
+                Ok(())
+            })
+    }
+
     /// Run a function on the grant for each active process if the grant has
     /// been allocated for that process.
     ///
@@ -0,0 +1,175 @@
+//! Process heartbeats for the hardware watchdog.
+//!
+//! The kernel normally tickles the hardware watchdog on every iteration of
+//! its main loop, so the watchdog only catches a kernel that hangs. A process
+//! stuck in a loop is preempted at the end of its timeslice, the kernel keeps
+//! running, and the hang goes unnoticed.
+//!
+//! With `ProcessHeartbeats`, a process that declares a heartbeat interval in
+//! its TBF header must check in with this driver at least once per interval
+//! while it is running or yielded. The kernel only tickles the watchdog while
+//! every such process is on time. When one misses its heartbeat, the board's
+//! `HeartbeatResponse` decides what happens:
+//!
+//! - `FaultProcess`: the process faults, so its `FaultResponse` and restart
+//!   policy apply, and the watchdog keeps being tickled.
+//! - `ResetSystem`: the kernel stops tickling the watchdog for good, so the
+//!   watchdog resets the system.
+//!
+//! The interval of a process starts when it first runs, is restarted or is
+//! resumed after being stopped. Processes without a heartbeat interval are
+//! not checked, and neither are processes the kernel cannot allocate the
+//! heartbeat grant for: running out of grant memory must not reset the
+//! system.
+//!
+//! The kernel checks heartbeats once the board calls
+//! `Kernel::set_process_heartbeats()`. The syscall interface is described in
+//! `doc/syscalls/10002_heartbeat.md`.
+//!
+//! Usage
+//! -----
+//!
+//! ```ignore
+//! let heartbeats = static_init!(
+//!     kernel::heartbeat::ProcessHeartbeats,
+//!     kernel::heartbeat::ProcessHeartbeats::new(
+//!         board_kernel,
+//!         &peripherals.rtc,
+//!         kernel::heartbeat::HeartbeatResponse::FaultProcess,
+//!         &memory_allocation_capability,
+//!     )
+//! );
+//! board_kernel.set_process_heartbeats(heartbeats, &process_management_capability);
+//! ```
+
+use core::cell::Cell;
+
+use crate::capabilities::MemoryAllocationCapability;
+use crate::grant::Grant;
+use crate::process::{self, ProcessId};
+use crate::process_policies::QuotaClock;
+use crate::sched::Kernel;
+use crate::{CommandReturn, Driver, ErrorCode};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x10002;
+
+/// What the kernel does when a process misses its heartbeat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeartbeatResponse {
+    /// Fault the process, and keep tickling the watchdog.
+    FaultProcess,
+    /// Stop tickling the watchdog, so that it resets the system.
+    ResetSystem,
+}
+
+#[derive(Default)]
+struct HeartbeatData {
+    /// When the process last checked in, or when its interval started, in
+    /// ticks of the clock. `None` if the process is not running.
+    last: Option<u32>,
+}
+
+/// Checks that processes with a heartbeat interval check in on time.
+pub struct ProcessHeartbeats {
+    data: Grant<HeartbeatData>,
+    clock: &'static dyn QuotaClock,
+    response: HeartbeatResponse,
+    /// Whether a process missed its heartbeat and the response is
+    /// `ResetSystem`.
+    failed: Cell<bool>,
+}
+
+impl ProcessHeartbeats {
+    /// `clock` must keep running while processes execute. Elapsed time is
+    /// measured in ticks of its counter, so heartbeat intervals must be shorter
+    /// than one wrap of the counter, 512 s for a 24-bit counter at 32 kHz.
+    pub fn new(
+        kernel: &'static Kernel,
+        clock: &'static dyn QuotaClock,
+        response: HeartbeatResponse,
+        capability: &dyn MemoryAllocationCapability,
+    ) -> ProcessHeartbeats {
+        ProcessHeartbeats {
+            data: kernel.create_grant(capability),
+            clock,
+            response,
+            failed: Cell::new(false),
+        }
+    }
+
+    /// Whether a process missed its heartbeat and the watchdog is no longer
+    /// tickled.
+    pub fn failed(&self) -> bool {
+        self.failed.get()
+    }
+
+    /// Check the heartbeats of all processes, applying the response to those
+    /// that missed theirs. Returns whether the kernel may tickle the
+    /// watchdog.
+    pub(crate) fn check(&self) -> bool {
+        if self.failed.get() {
+            return false;
+        }
+        self.data.kernel.process_each(|process| {
+            let interval_ms = match process.get_heartbeat_interval_ms() {
+                Some(interval_ms) => interval_ms,
+                None => return,
+            };
+            let active = matches!(
+                process.get_state(),
+                process::State::Running | process::State::Yielded
+            );
+            let missed = self
+                .data
+                .enter(process.processid(), |data| {
+                    if !active {
+                        data.last = None;
+                        return false;
+                    }
+                    let last = *data.last.get_or_insert_with(|| self.clock.now_ticks());
+                    self.clock.us_since(last) > interval_ms.saturating_mul(1000)
+                })
+                // A process whose heartbeat cannot be tracked because its grant
+                // could not be allocated is not checked. A grant allocation
+                // failure must never fault the process or reset the system.
+                .unwrap_or(false);
+            if missed {
+                match self.response {
+                    HeartbeatResponse::FaultProcess => process.set_fault_state(),
+                    HeartbeatResponse::ResetSystem => self.failed.set(true),
+                }
+            }
+        });
+        !self.failed.get()
+    }
+
+    fn check_in(&self, appid: ProcessId) -> Result<(), ErrorCode> {
+        let now = self.clock.now_ticks();
+        self.data
+            .enter(appid, |data| data.last = Some(now))
+            .map_err(ErrorCode::from)
+    }
+}
+
+impl Driver for ProcessHeartbeats {
+    /// Check in.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check, always returns Ok(())
+    /// - `1`: Check in, starting a new heartbeat interval.
+    fn command(
+        &self,
+        command_number: usize,
+        _arg1: usize,
+        _arg2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        match command_number {
+            0 => CommandReturn::success(),
+            1 => self.check_in(appid).into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
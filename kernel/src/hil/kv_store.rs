@@ -0,0 +1,129 @@
+//! Interface for Key-Value (KV) Stores with permissions
+//!
+//! This is level 3 of the KV store implementation described in
+//! `hil::kv_system`. It stores values under unhashed keys and enforces the
+//! `StoragePermissions` of whoever accesses them.
+//!
+//! Each value is stored with a header recording the write ID of whoever
+//! stored it. To avoid copying, the header is kept in the same buffer as the
+//! value: the first `header_size()` bytes of every value buffer passed to or
+//! returned from this interface are reserved for it, and the value itself
+//! follows.
+//!
+//! Operations on keys the caller may not access fail with `FAIL`, the same
+//! as for keys that do not exist, so that callers cannot tell which keys other
+//! processes use.
+
+use crate::storage_permissions::StoragePermissions;
+use crate::ErrorCode;
+
+/// Implement this trait and use `set_client()` in order to receive callbacks.
+pub trait StoreClient {
+    /// This callback is called when the get operation completes
+    ///
+    /// `result`: The length of the value on success, 'ErrorCode' on error
+    /// `key`: The key buffer
+    /// `value`: The value buffer. The value starts `header_size()` bytes in.
+    fn get_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    );
+
+    /// This callback is called when the set operation completes
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error
+    /// `key`: The key buffer
+    /// `value`: The value buffer
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut [u8],
+        value: &'static mut [u8],
+    );
+
+    /// This callback is called when the delete operation completes
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error
+    /// `key`: The key buffer
+    fn delete_complete(&self, result: Result<(), ErrorCode>, key: &'static mut [u8]);
+}
+
+pub trait KVStore<'a> {
+    /// Set the client
+    fn set_client(&self, client: &'a dyn StoreClient);
+
+    /// The number of bytes at the start of each value buffer that are
+    /// reserved for the header.
+    fn header_size(&self) -> usize;
+
+    /// Retrieves the value stored under a key.
+    ///
+    /// `key`: A buffer containing the unhashed key.
+    /// `key_len`: How many bytes at the start of `key` make up the key.
+    /// `value`: A buffer to store the value to, after `header_size()` bytes.
+    /// `permissions`: The permissions of the caller.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key, value and an `ErrorCode` will be returned.
+    ///
+    /// The possible `ErrorCode`s are:
+    ///    `BUSY`: An operation is already in progress
+    ///    `INVAL`: An invalid parameter was passed
+    ///    `FAIL`: The key does not exist or the caller may not read it
+    ///    `SIZE`: `value` is too small for the value
+    fn get(
+        &self,
+        key: &'static mut [u8],
+        key_len: usize,
+        value: &'static mut [u8],
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], &'static mut [u8], ErrorCode)>;
+
+    /// Stores a value under a key, replacing any value stored under it.
+    ///
+    /// `key`: A buffer containing the unhashed key.
+    /// `key_len`: How many bytes at the start of `key` make up the key.
+    /// `value`: A buffer containing the value, after `header_size()` bytes.
+    /// `value_len`: The length of the value, not including the header.
+    /// `permissions`: The permissions of the caller.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key, value and an `ErrorCode` will be returned.
+    ///
+    /// The possible `ErrorCode`s are:
+    ///    `BUSY`: An operation is already in progress
+    ///    `INVAL`: An invalid parameter was passed
+    ///    `FAIL`: The caller may not store values, or may not replace the
+    ///            value stored under the key
+    ///    `NOMEM`: There is no more space for the value.
+    fn set(
+        &self,
+        key: &'static mut [u8],
+        key_len: usize,
+        value: &'static mut [u8],
+        value_len: usize,
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], &'static mut [u8], ErrorCode)>;
+
+    /// Deletes the value stored under a key.
+    ///
+    /// `key`: A buffer containing the unhashed key.
+    /// `key_len`: How many bytes at the start of `key` make up the key.
+    /// `permissions`: The permissions of the caller.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key and an `ErrorCode` will be returned.
+    ///
+    /// The possible `ErrorCode`s are:
+    ///    `BUSY`: An operation is already in progress
+    ///    `INVAL`: An invalid parameter was passed
+    ///    `FAIL`: The key does not exist or the caller may not delete it
+    fn delete(
+        &self,
+        key: &'static mut [u8],
+        key_len: usize,
+        permissions: StoragePermissions,
+    ) -> Result<(), (&'static mut [u8], ErrorCode)>;
+}
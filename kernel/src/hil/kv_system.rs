@@ -30,7 +30,7 @@
 //! This level is also in charge of generating the key hash by calling into
 //! level 2.
 //!
-//! There is currently no implementation of this HIL in Tock.
+//! This HIL is described in `hil::kv_store`.
 //!
 //! The expected setup inside Tock will look like this:
 //! +-----------------------+
@@ -39,7 +39,7 @@
 //! |                       |
 //! +-----------------------+
 //!
-//!    hil::kv_store (not written yet)
+//!    hil::kv_store
 //!
 //! +-----------------------+
 //! |                       |
@@ -47,7 +47,7 @@
 //! |                       |
 //! +-----------------------+
 //!
-//!    hil::kv_system (this PR)
+//!    hil::kv_system (this file)
 //!
 //! +-----------------------+
 //! |                       |
@@ -67,7 +67,7 @@ impl KeyType for [u8; 8] {}
 
 /// Implement this trait and use `set_client()` in order to receive callbacks.
 pub trait Client<K: KeyType> {
-    /// This callback is called when the append_key operation completes
+    /// This callback is called when the generate_key operation completes
     ///
     /// `result`: Nothing on success, 'ErrorCode' on error
     /// `unhashed_key`: The unhashed_key buffer
@@ -75,8 +75,8 @@ pub trait Client<K: KeyType> {
     fn generate_key_complete(
         &self,
         result: Result<(), ErrorCode>,
-        unhashed_key: &'static [u8],
-        key_buf: &'static K,
+        unhashed_key: &'static mut [u8],
+        key_buf: &'static mut K,
     );
 
     /// This callback is called when the append_key operation completes
@@ -88,7 +88,19 @@ pub trait Client<K: KeyType> {
         &self,
         result: Result<(), ErrorCode>,
         key: &'static mut K,
-        value: &'static [u8],
+        value: &'static mut [u8],
+    );
+
+    /// This callback is called when the update_key operation completes
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error
+    /// `key`: The key buffer
+    /// `value`: The value buffer
+    fn update_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut K,
+        value: &'static mut [u8],
     );
 
     /// This callback is called when the get_value operation completes
@@ -125,6 +137,7 @@ pub trait KVSystem<'a> {
     /// Generate key
     ///
     /// `unhashed_key`: A unhashed key that should be hashed.
+    /// `length`: How many bytes at the start of `unhashed_key` to hash.
     /// `key_buf`: A buffer to store the hashed key output.
     ///
     /// On success returns nothing.
@@ -132,6 +145,7 @@ pub trait KVSystem<'a> {
     fn generate_key(
         &self,
         unhashed_key: &'static mut [u8],
+        length: usize,
         key_buf: &'static mut Self::K,
     ) -> Result<
         (),
@@ -147,6 +161,7 @@ pub trait KVSystem<'a> {
     /// `key`: A hashed key. This key will be used in future to retrieve
     ///        or remove the `value`.
     /// `value`: A buffer containing the data to be stored to flash.
+    /// `length`: How many bytes at the start of `value` to store.
     ///
     /// On success nothing will be returned.
     /// On error the key, value and a `Result<(), ErrorCode>` will be returned.
@@ -160,13 +175,55 @@ pub trait KVSystem<'a> {
     fn append_key(
         &self,
         key: &'static mut Self::K,
-        value: &'static [u8],
-    ) -> Result<(), (&'static mut Self::K, &'static [u8], Result<(), ErrorCode>)>;
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<
+        (),
+        (
+            &'static mut Self::K,
+            &'static mut [u8],
+            Result<(), ErrorCode>,
+        ),
+    >;
+
+    /// Replaces the value of an existing key.
+    ///
+    /// `key`: A hashed key.
+    /// `value`: A buffer containing the new data to be stored to flash.
+    /// `length`: How many bytes at the start of `value` to store.
+    ///
+    /// The new value must be stored before the old one is removed, so that
+    /// if power is lost during the update either the old or the new value
+    /// is kept.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key, value and a `Result<(), ErrorCode>` will be returned.
+    ///
+    /// The possible `Result<(), ErrorCode>`s are:
+    ///    `BUSY`: An operation is already in progress
+    ///    `INVAL`: An invalid parameter was passed
+    ///    `NODEVICE`: No KV store was setup
+    ///    `ENOSUPPORT`: The key could not be found.
+    ///    `NOMEM`: The key could not be updated due to no more space.
+    fn update_key(
+        &self,
+        key: &'static mut Self::K,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<
+        (),
+        (
+            &'static mut Self::K,
+            &'static mut [u8],
+            Result<(), ErrorCode>,
+        ),
+    >;
 
     /// Retrieves the value from a specified key.
     ///
     /// `key`: A hashed key. This key will be used to retrieve the `value`.
-    /// `ret_buf`: A buffer to store the value to.
+    /// `ret_buf`: A buffer to store the value to. It must be large enough for
+    ///            the whole value.
     ///
     /// On success nothing will be returned.
     /// On error the key, ret_buf and a `Result<(), ErrorCode>` will be returned.
@@ -176,6 +233,7 @@ pub trait KVSystem<'a> {
     ///    `INVAL`: An invalid parameter was passed
     ///    `NODEVICE`: No KV store was setup
     ///    `ENOSUPPORT`: The key could not be found.
+    ///    `SIZE`: `ret_buf` is smaller than the value.
     fn get_value(
         &self,
         key: &'static mut Self::K,
@@ -14,9 +14,13 @@ use core::cell::Cell;
 
 use crate::capabilities::ProcessManagementCapability;
 use crate::common::cells::NumericCellExt;
+use crate::crash_dump::CrashDump;
 use crate::process;
 use crate::process::ProcessId;
+use crate::profile::KernelProfile;
 use crate::sched::Kernel;
+use crate::syscall::Syscall;
+use crate::trace::KernelTrace;
 
 /// This struct provides the inspection functions.
 pub struct KernelInfo {
@@ -103,6 +107,28 @@ impl KernelInfo {
             .process_map_or(0, app, |process| process.debug_dropped_upcall_count())
     }
 
+    /// Returns the number of syscalls of this app that the platform's syscall
+    /// filter rejected.
+    pub fn number_app_filtered_syscalls(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_filtered_syscall_count())
+    }
+
+    /// Returns the most recent syscall of this app that the platform's
+    /// syscall filter rejected, if any.
+    pub fn last_app_filtered_syscall(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<Syscall> {
+        self.kernel
+            .process_map_or(None, app, |process| process.debug_last_filtered_syscall())
+    }
+
     /// Returns the number of time this app has been restarted.
     pub fn number_app_restarts(
         &self,
@@ -123,6 +149,39 @@ impl KernelInfo {
             .process_map_or(0, app, |process| process.debug_timeslice_expiration_count())
     }
 
+    /// Returns the number of jobs of this app that missed their deadline
+    /// under a real-time scheduler.
+    pub fn number_app_deadline_misses(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_deadline_miss_count())
+    }
+
+    /// Returns the number of times this app has exceeded one of its quotas.
+    pub fn number_app_quota_violations(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_quota_exceeded_count())
+    }
+
+    /// Returns how many ticks of the profile counter this app has run for
+    /// since it last started, or 0 if the board did not set a profile with
+    /// `Kernel::set_profile()`.
+    pub fn app_cpu_ticks(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> u64 {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_cpu_ticks())
+    }
+
     /// Returns a tuple of the (the number of grants in the grant region this
     /// app has allocated, total number of grants that exist in the system).
     pub fn number_app_grant_uses(
@@ -143,6 +202,21 @@ impl KernelInfo {
         (used, number_of_grants)
     }
 
+    /// Returns the number of bytes of the grant region of this app used by
+    /// the grant with number `grant_num` and its custom grants. Grants are
+    /// numbered in the order they were created, from 0 up to the total
+    /// returned by `number_app_grant_uses()`.
+    pub fn app_grant_bytes(
+        &self,
+        app: ProcessId,
+        grant_num: usize,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel.process_map_or(0, app, |process| {
+            process.grant_allocated_bytes(grant_num).unwrap_or(0)
+        })
+    }
+
     /// Returns the total number of times all processes have exceeded
     /// their timeslices.
     pub fn timeslice_expirations(&self, _capability: &dyn ProcessManagementCapability) -> usize {
@@ -152,4 +226,61 @@ impl KernelInfo {
         });
         count.get()
     }
+
+    /// Returns the total number of syscalls of all processes that the
+    /// platform's syscall filter rejected.
+    pub fn filtered_syscalls(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_filtered_syscall_count());
+        });
+        count.get()
+    }
+
+    /// Returns the total number of jobs of all processes that missed their
+    /// deadline under a real-time scheduler.
+    pub fn deadline_misses(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_deadline_miss_count());
+        });
+        count.get()
+    }
+
+    /// Returns the total number of times all processes have exceeded one of
+    /// their quotas.
+    pub fn quota_violations(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_quota_exceeded_count());
+        });
+        count.get()
+    }
+
+    /// Returns the trace the kernel records its events into, if the board set
+    /// one with `Kernel::set_trace()`.
+    pub fn kernel_trace(
+        &self,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<&'static KernelTrace> {
+        self.kernel.get_trace()
+    }
+
+    /// Returns where the kernel keeps the record of the last crash, if the
+    /// board set it with `Kernel::set_crash_dump()`.
+    pub fn crash_dump(
+        &self,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<&'static CrashDump> {
+        self.kernel.get_crash_dump()
+    }
+
+    /// Returns where the kernel measures where the CPU time goes, if the
+    /// board set it with `Kernel::set_profile()`.
+    pub fn kernel_profile(
+        &self,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<&'static KernelProfile> {
+        self.kernel.get_profile()
+    }
 }
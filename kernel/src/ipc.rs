@@ -2,12 +2,23 @@
 //!
 //! This is a special syscall driver that allows userspace applications to
 //! share memory.
+//!
+//! A process provides IPC services that other processes find by name. By
+//! default a process provides one service, named after its package, that any
+//! process may use. A process can instead list its services in the IPC
+//! Services section of its TBF header, together with the package names of the
+//! processes that may use each of them. The kernel only lets those processes
+//! find and notify the service.
+//!
+//! Services are named by descriptors. The low 16 bits of a descriptor are the
+//! identifier of the process plus one, and the bits above are the index of the
+//! service in the TBF header of the process. The first service of a process
+//! has the same descriptor as the process itself.
 
 use crate::capabilities::MemoryAllocationCapability;
 use crate::grant::Grant;
 use crate::mem::Read;
-use crate::process;
-use crate::process::ProcessId;
+use crate::process::{self, Process, ProcessId};
 use crate::sched::Kernel;
 use crate::upcall::Upcall;
 use crate::{CommandReturn, Driver, ErrorCode, ReadOnlyAppSlice, ReadWriteAppSlice};
@@ -15,12 +26,53 @@ use crate::{CommandReturn, Driver, ErrorCode, ReadOnlyAppSlice, ReadWriteAppSlic
 /// Syscall number
 pub const DRIVER_NUM: usize = 0x10000;
 
+/// Most services a process can provide. Services listed after these in the
+/// TBF header are ignored.
+pub const MAX_SERVICES: usize = 4;
+
+/// Position of the service index in a descriptor.
+const SERVICE_SHIFT: usize = 16;
+/// Bits of a descriptor that name the process.
+const PROCESS_MASK: usize = (1 << SERVICE_SHIFT) - 1;
+
+/// The descriptor of service `service` of a process, if the identifier of the
+/// process fits.
+fn descriptor(processid: ProcessId, service: usize) -> Option<usize> {
+    let process = processid.id() + 1;
+    if process > PROCESS_MASK {
+        None
+    } else {
+        Some(process | service << SERVICE_SHIFT)
+    }
+}
+
+/// Whether `client` may use service `service` of `server`.
+fn may_use_service(server: &dyn Process, service: usize, client: &dyn Process) -> bool {
+    match server.get_ipc_services() {
+        Some(services) => {
+            service < MAX_SERVICES
+                && services
+                    .iter()
+                    .nth(service)
+                    .map_or(false, |s| s.allows(client.get_process_name()))
+        }
+        None => service == 0,
+    }
+}
+
+/// Whether `client` may use any service of `server`. Other kernel IPC
+/// mechanisms use this to keep processes from reaching services they may not
+/// use.
+pub(crate) fn may_use_any_service(server: &dyn Process, client: &dyn Process) -> bool {
+    (0..MAX_SERVICES).any(|service| may_use_service(server, service, client))
+}
+
 /// Enum to mark which type of upcall is scheduled for the IPC mechanism.
 #[derive(Copy, Clone, Debug)]
 pub enum IPCUpcallType {
-    /// Indicates that the upcall is for the service upcall handler this
-    /// process has setup.
-    Service,
+    /// Indicates that the upcall is for the upcall handler this process has
+    /// setup for the service with this index.
+    Service(usize),
     /// Indicates that the upcall is from a different service app and will
     /// call one of the client upcalls setup by this process.
     Client,
@@ -35,8 +87,8 @@ struct IPCData<const NUM_PROCS: usize> {
     /// An array of upcalls this process has registered to receive upcalls
     /// from other services.
     client_upcalls: [Upcall; NUM_PROCS],
-    /// The upcall setup by a service. Each process can only be one service.
-    upcall: Upcall,
+    /// The upcalls setup for each of the services of this process.
+    service_upcalls: [Upcall; MAX_SERVICES],
 }
 
 impl<const NUM_PROCS: usize> Default for IPCData<NUM_PROCS> {
@@ -46,7 +98,7 @@ impl<const NUM_PROCS: usize> Default for IPCData<NUM_PROCS> {
             shared_memory: [DEFAULT_RW_APP_SLICE; NUM_PROCS],
             search_slice: ReadOnlyAppSlice::default(),
             client_upcalls: [Upcall::default(); NUM_PROCS],
-            upcall: Upcall::default(),
+            service_upcalls: [Upcall::default(); MAX_SERVICES],
         }
     }
 }
@@ -64,6 +116,50 @@ impl<const NUM_PROCS: usize> IPC<NUM_PROCS> {
         }
     }
 
+    /// Find the process and the service a descriptor names.
+    fn lookup(&self, descriptor: usize) -> Option<(ProcessId, usize)> {
+        match descriptor & PROCESS_MASK {
+            0 => None,
+            process => self
+                .data
+                .kernel
+                .lookup_app_by_identifier(process - 1)
+                .map(|processid| (processid, descriptor >> SERVICE_SHIFT)),
+        }
+    }
+
+    /// Find the service named `name` that `client` may use. Returns its
+    /// descriptor.
+    fn discover(&self, client: ProcessId, name: &[u8]) -> Option<usize> {
+        self.data.kernel.process_map_or(None, client, |client| {
+            self.data.kernel.process_until(|server| {
+                let service = match server.get_ipc_services() {
+                    Some(services) => services.find(name).map(|(service, _)| service),
+                    None if server.get_process_name().as_bytes() == name => Some(0),
+                    None => None,
+                }?;
+                if may_use_service(server, service, client) {
+                    descriptor(server.processid(), service)
+                } else {
+                    None
+                }
+            })
+        })
+    }
+
+    /// Whether `from` may notify `to`: either `from` is a client of a
+    /// service of `to`, or the other way around.
+    fn may_notify(&self, from: ProcessId, to: ProcessId, cb_type: IPCUpcallType) -> bool {
+        self.data.kernel.process_map_or(false, from, |from| {
+            self.data
+                .kernel
+                .process_map_or(false, to, |to| match cb_type {
+                    IPCUpcallType::Service(service) => may_use_service(to, service, from),
+                    IPCUpcallType::Client => may_use_any_service(from, to),
+                })
+        })
+    }
+
     /// Schedule an IPC upcall for a process. This is called by the main
     /// scheduler loop if an IPC task was queued for the process.
     pub(crate) unsafe fn schedule_upcall(
@@ -75,7 +171,10 @@ impl<const NUM_PROCS: usize> IPC<NUM_PROCS> {
         self.data
             .enter(schedule_on, |mydata| {
                 let mut upcall = match cb_type {
-                    IPCUpcallType::Service => mydata.upcall,
+                    IPCUpcallType::Service(service) => *mydata
+                        .service_upcalls
+                        .get(service)
+                        .unwrap_or(&Upcall::default()),
                     IPCUpcallType::Client => match called_from.index() {
                         Some(i) => *mydata.client_upcalls.get(i).unwrap_or(&Upcall::default()),
                         None => Upcall::default(),
@@ -131,42 +230,50 @@ impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
         mut upcall: Upcall,
         app_id: ProcessId,
     ) -> Result<Upcall, (Upcall, ErrorCode)> {
-        match subscribe_num {
-            // subscribe(0)
+        match subscribe_num & PROCESS_MASK {
+            // subscribe(service << 16)
             //
-            // Subscribe with subscribe_num == 0 is how a process registers
-            // itself as an IPC service. Each process can only register as a
-            // single IPC service. The identifier for the IPC service is the
-            // application name stored in the TBF header of the application.
-            // The upcall that is passed to subscribe is called when another
-            // process notifies the server process.
-            0 => self
-                .data
-                .enter(app_id, |data| {
-                    core::mem::swap(&mut data.upcall, &mut upcall);
-                    upcall
-                })
-                .map_err(|e| (upcall, e.into())),
+            // Subscribe with no process in subscribe_num is how a process
+            // registers the upcall of one of its IPC services. The bits above
+            // the process are the index of the service in the TBF header of
+            // the application, so subscribe_num == 0 registers the first
+            // service. The identifier for the IPC service is its name in the
+            // TBF header, or the application name if the header does not list
+            // any services. The upcall that is passed to subscribe is called
+            // when another process notifies that service.
+            0 => {
+                let service = subscribe_num >> SERVICE_SHIFT;
+                let result: Result<Result<Upcall, ErrorCode>, process::Error> =
+                    self.data
+                        .enter(app_id, |data| match data.service_upcalls.get_mut(service) {
+                            Some(service_upcall) => {
+                                core::mem::swap(service_upcall, &mut upcall);
+                                Ok(upcall)
+                            }
+                            None => Err(ErrorCode::INVAL),
+                        });
+                result
+                    .map_err(|e| e.into())
+                    .and_then(|x| x)
+                    .map_err(|e| (upcall, e))
+            }
 
             // subscribe(>=1)
             //
-            // Subscribe with subscribe_num >= 1 is how a client registers
-            // a upcall for a given service. The service number (passed
-            // here as subscribe_num) is returned from the allow() call.
-            // Once subscribed, the client will receive upcalls when the
-            // service process calls notify_client().
-            svc_id => {
-                // The app passes in a number which is the app identifier of the
-                // other app (shifted by one).
-                let app_identifier = svc_id - 1;
-                // We first have to see if that identifier corresponds to a
+            // Subscribe with a process in subscribe_num is how a client
+            // registers a upcall for a given service. The service descriptor
+            // (passed here as subscribe_num) is returned from discovery. Once
+            // subscribed, the client will receive upcalls when the service
+            // process calls notify_client().
+            _ => {
+                // We first have to see if the descriptor corresponds to a
                 // valid application by asking the kernel to do a lookup for us.
-                let otherapp = self.data.kernel.lookup_app_by_identifier(app_identifier);
+                let otherapp = self.lookup(subscribe_num);
 
                 // This type annotation is here for documentation, it's not actually necessary
                 let result: Result<Result<Upcall, ErrorCode>, process::Error> =
                     self.data.enter(app_id, |data| {
-                        match otherapp.map_or(None, |oa| oa.index()) {
+                        match otherapp.map_or(None, |(oa, _)| oa.index()) {
                             Some(i) => {
                                 if i >= NUM_PROCS {
                                     Err(ErrorCode::INVAL)
@@ -193,18 +300,20 @@ impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
     /// In either case, the target_id is the same number as provided in a notify
     /// upcall or as returned by allow.
     ///
-    /// Returns INVAL if the other process doesn't exist.
+    /// Returns INVAL if the other process doesn't exist, or may not be
+    /// notified by this process.
 
     /// Initiates a service discovery or notifies a client or service.
     ///
     /// ### `command_num`
     ///
     /// - `0`: Driver check, always returns Ok(())
-    /// - `1`: Perform discovery on the package name passed to `allow_readonly`. Returns the
-    ///        service descriptor if the service is found, otherwise returns an error.
+    /// - `1`: Perform discovery on the service name passed to `allow_readonly`. Returns the
+    ///        service descriptor if the service is found and this process may use it,
+    ///        otherwise returns an error.
     /// - `2`: Notify a service previously discovered to have the service descriptor in
-    ///        `target_id`. Returns an error if `target_id` refers to an invalid service or the
-    ///        notify fails to enqueue.
+    ///        `target_id`. Returns an error if `target_id` refers to an invalid service, one
+    ///        this process may not use, or the notify fails to enqueue.
     /// - `3`: Notify a client with descriptor `target_id`, typically in response to a previous
     ///        notify from the client. Returns an error if `target_id` refers to an invalid client
     ///        or the notify fails to enqueue.
@@ -224,80 +333,54 @@ impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
                     .enter(appid, |data| {
                         data.search_slice.map_or(
                             CommandReturn::failure(ErrorCode::INVAL),
-                            |slice| {
-                                self.data
-                                    .kernel
-                                    .process_until(|p| {
-                                        let s = p.get_process_name().as_bytes();
-                                        // are slices equal?
-                                        if s.len() == slice.len()
-                                            && s.iter().zip(slice.iter()).all(|(c1, c2)| c1 == c2)
-                                        {
-                                            Some(CommandReturn::success_u32(
-                                                p.processid().id() as u32 + 1,
-                                            ))
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                    .unwrap_or(CommandReturn::failure(ErrorCode::NODEVICE))
+                            |slice| match self.discover(appid, slice) {
+                                Some(descriptor) => CommandReturn::success_u32(descriptor as u32),
+                                None => CommandReturn::failure(ErrorCode::NODEVICE),
                             },
                         )
                     })
                     .unwrap_or(CommandReturn::failure(ErrorCode::NOMEM))
             }
-            2 =>
-            /* Service notify */
+            2 | 3 =>
+            /* Service or client notify */
             {
-                let cb_type = IPCUpcallType::Service;
-                let app_identifier = target_id - 1;
-
-                self.data
-                    .kernel
-                    .lookup_app_by_identifier(app_identifier)
-                    .map_or(CommandReturn::failure(ErrorCode::INVAL), |otherapp| {
-                        self.data.kernel.process_map_or(
-                            CommandReturn::failure(ErrorCode::INVAL),
-                            otherapp,
-                            |target| {
-                                let ret = target.enqueue_task(process::Task::IPC((appid, cb_type)));
-                                match ret {
-                                    true => CommandReturn::success(),
-                                    false => CommandReturn::failure(ErrorCode::FAIL),
-                                }
-                            },
-                        )
-                    })
-            }
-            3 =>
-            /* Client notify */
-            {
-                let cb_type = IPCUpcallType::Client;
-                let app_identifier = target_id - 1;
-
-                self.data
-                    .kernel
-                    .lookup_app_by_identifier(app_identifier)
-                    .map_or(CommandReturn::failure(ErrorCode::INVAL), |otherapp| {
-                        self.data.kernel.process_map_or(
-                            CommandReturn::failure(ErrorCode::INVAL),
-                            otherapp,
-                            |target| {
-                                let ret = target.enqueue_task(process::Task::IPC((appid, cb_type)));
-                                match ret {
-                                    true => CommandReturn::success(),
-                                    false => CommandReturn::failure(ErrorCode::FAIL),
-                                }
-                            },
-                        )
+                self.lookup(target_id)
+                    .and_then(|(otherapp, service)| {
+                        let cb_type = if command_number == 2 {
+                            IPCUpcallType::Service(service)
+                        } else {
+                            IPCUpcallType::Client
+                        };
+                        if self.may_notify(appid, otherapp, cb_type) {
+                            Some((otherapp, cb_type))
+                        } else {
+                            None
+                        }
                     })
+                    .map_or(
+                        CommandReturn::failure(ErrorCode::INVAL),
+                        |(otherapp, cb_type)| {
+                            self.data.kernel.process_map_or(
+                                CommandReturn::failure(ErrorCode::INVAL),
+                                otherapp,
+                                |target| {
+                                    let ret =
+                                        target.enqueue_task(process::Task::IPC((appid, cb_type)));
+                                    match ret {
+                                        true => CommandReturn::success(),
+                                        false => CommandReturn::failure(ErrorCode::FAIL),
+                                    }
+                                },
+                            )
+                        },
+                    )
             }
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
 
     /// allow_readonly with subdriver number `0` stores the provided buffer for service discovery.
-    /// The buffer should contain the package name of a process that exports an IPC service.
+    /// The buffer should contain the name of an IPC service.
     fn allow_readonly(
         &self,
         appid: ProcessId,
@@ -305,7 +388,7 @@ impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
         mut slice: ReadOnlyAppSlice,
     ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
         if subdriver == 0 {
-            // Package name for discovery
+            // Service name for discovery
             let res = self.data.enter(appid, |data| {
                 core::mem::swap(&mut data.search_slice, &mut slice);
             });
@@ -324,7 +407,8 @@ impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
     /// If allow is called with target_id >= 1, it is a share command where the
     /// application is explicitly sharing a slice with an IPC service (as
     /// specified by the target_id). allow() simply allows both processes to
-    /// access the buffer, it does not signal the service.
+    /// access the buffer, it does not signal the service. The buffer is shared
+    /// with the process, whichever of its services target_id names.
     ///
     /// target_id == 0 is currently unsupported and reserved for future use.
     fn allow_readwrite(
@@ -333,16 +417,15 @@ impl<const NUM_PROCS: usize> Driver for IPC<NUM_PROCS> {
         target_id: usize,
         mut slice: ReadWriteAppSlice,
     ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
-        if target_id == 0 {
+        if target_id & PROCESS_MASK == 0 {
             Err((slice, ErrorCode::NOSUPPORT))
         } else {
             match self.data.enter(appid, |data| {
                 // Lookup the index of the app based on the passed in
-                // identifier. This also let's us check that the other app is
+                // descriptor. This also let's us check that the other app is
                 // actually valid.
-                let app_identifier = target_id - 1;
-                let otherapp = self.data.kernel.lookup_app_by_identifier(app_identifier);
-                if let Some(oa) = otherapp {
+                let otherapp = self.lookup(target_id);
+                if let Some((oa, _)) = otherapp {
                     if let Some(i) = oa.index() {
                         if let Some(smem) = data.shared_memory.get_mut(i) {
                             core::mem::swap(smem, &mut slice);
@@ -0,0 +1,552 @@
+//! Message-passing inter-process communication for Tock.
+//!
+//! This is a special syscall driver, like `ipc::IPC`, that lets processes
+//! exchange messages rather than share memory. Each process has a mailbox in
+//! its grant region holding up to `DEPTH` messages of at most `MESSAGE_LEN`
+//! bytes. The kernel copies a message from the buffer the sender allowed into
+//! the mailbox of the receiver, and from there into the buffer the receiver
+//! allowed. Neither process ever has access to the memory of the other, so no
+//! MPU regions are set up, and a process that restarts only loses what is in
+//! its own mailbox.
+//!
+//! Peers are identified by descriptors, as with `ipc::IPC`: the identifier of
+//! the process plus one. A process gets a new identifier when it restarts, so
+//! sending to or replying to a peer that restarted fails with `NODEVICE`, and
+//! operations that were waiting on it complete with `NODEVICE`. A process
+//! that lists its IPC services in its TBF header only takes messages from the
+//! processes that may use one of them, and other processes can neither
+//! discover it nor send to it.
+//!
+//! Sending and receiving can both either complete right away or wait:
+//!
+//! - A send that does not wait fails with `BUSY` if the mailbox of the
+//!   receiver is full. A send that waits stays pending until the receiver
+//!   takes a message out of its mailbox, and completes with an upcall.
+//! - A receive that does not wait fails with `BUSY` if the mailbox is empty.
+//!   A receive that waits completes with an upcall when a message arrives.
+//!
+//! A sender can ask for a reply. Its send returns a sequence number, and the
+//! receiver gets the same number as the reply handle of the message. The
+//! receiver replies with the descriptor of the sender and the handle, and the
+//! reply arrives in the mailbox of the sender with the handle and
+//! `REPLY_FLAG` set. Each process can wait for one reply at a time.
+//!
+//! The kernel must be told about the driver with `Kernel::set_ipc_mailbox()`
+//! so that it can end waiting operations when a process stops. The syscall
+//! interface is described in `doc/syscalls/10001_ipc_mailbox.md`.
+
+use core::cmp;
+
+use crate::capabilities::MemoryAllocationCapability;
+use crate::grant::Grant;
+use crate::ipc;
+use crate::mem::{Read, ReadWrite};
+use crate::process::{self, ProcessId};
+use crate::sched::Kernel;
+use crate::upcall::Upcall;
+use crate::{CommandReturn, Driver, ErrorCode, ReadOnlyAppSlice, ReadWriteAppSlice};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x10001;
+
+/// Flag of the send and receive commands to wait for the operation to
+/// complete, rather than fail with `BUSY`.
+pub const WAIT_FLAG: usize = 1;
+/// Flag of the send command to ask the receiver for a reply.
+pub const EXPECT_REPLY_FLAG: usize = 2;
+/// Set in the handle of a received message that is a reply.
+pub const REPLY_FLAG: u32 = 1 << 31;
+
+/// A message in a mailbox.
+#[derive(Clone, Copy)]
+struct Message<const MESSAGE_LEN: usize> {
+    from: Option<ProcessId>,
+    len: usize,
+    /// The reply handle, or 0 if the sender does not expect a reply.
+    handle: u32,
+    data: [u8; MESSAGE_LEN],
+}
+
+impl<const MESSAGE_LEN: usize> Message<MESSAGE_LEN> {
+    const EMPTY: Self = Message {
+        from: None,
+        len: 0,
+        handle: 0,
+        data: [0; MESSAGE_LEN],
+    };
+}
+
+/// A send waiting for room in the mailbox of the receiver.
+#[derive(Clone, Copy)]
+struct PendingSend {
+    to: ProcessId,
+    sequence: u32,
+    expects_reply: bool,
+}
+
+/// State that is stored in each process's grant region for its mailbox.
+struct MailboxData<const MESSAGE_LEN: usize, const DEPTH: usize> {
+    /// Messages waiting to be received, oldest first from `head`.
+    messages: [Message<MESSAGE_LEN>; DEPTH],
+    head: usize,
+    len: usize,
+    /// Package name of a process to discover.
+    search_slice: ReadOnlyAppSlice,
+    /// The message to send, or the reply.
+    send_slice: ReadOnlyAppSlice,
+    /// Where received messages are copied to.
+    receive_slice: ReadWriteAppSlice,
+    receive_upcall: Upcall,
+    send_upcall: Upcall,
+    /// Whether the process waits for a message.
+    waiting_receive: bool,
+    pending_send: Option<PendingSend>,
+    /// The peer and sequence number of the request this process waits for a
+    /// reply to.
+    awaiting_reply: Option<(ProcessId, u32)>,
+    next_sequence: u32,
+}
+
+impl<const MESSAGE_LEN: usize, const DEPTH: usize> Default for MailboxData<MESSAGE_LEN, DEPTH> {
+    fn default() -> Self {
+        MailboxData {
+            messages: [Message::EMPTY; DEPTH],
+            head: 0,
+            len: 0,
+            search_slice: ReadOnlyAppSlice::default(),
+            send_slice: ReadOnlyAppSlice::default(),
+            receive_slice: ReadWriteAppSlice::default(),
+            receive_upcall: Upcall::default(),
+            send_upcall: Upcall::default(),
+            waiting_receive: false,
+            pending_send: None,
+            awaiting_reply: None,
+            next_sequence: 1,
+        }
+    }
+}
+
+impl<const MESSAGE_LEN: usize, const DEPTH: usize> MailboxData<MESSAGE_LEN, DEPTH> {
+    /// A new sequence number for a message that expects a reply. Sequence
+    /// numbers are never 0 and never have `REPLY_FLAG` set.
+    fn take_sequence(&mut self) -> u32 {
+        let sequence = self.next_sequence;
+        self.next_sequence = (sequence % (REPLY_FLAG - 1)) + 1;
+        sequence
+    }
+
+    /// Copy a message into the receive buffer and tell the process about it.
+    fn receive_now(&mut self, from: ProcessId, message: &[u8], handle: u32) {
+        self.copy_to_receive_slice(message);
+        self.waiting_receive = false;
+        self.receive_upcall
+            .schedule(descriptor(from), message.len(), handle as usize);
+    }
+
+    /// Copy as much of a message as fits into the receive buffer. The process
+    /// learns the full length of the message, so it can tell if it did not
+    /// fit.
+    fn copy_to_receive_slice(&mut self, message: &[u8]) {
+        self.receive_slice.mut_map_or((), |buffer| {
+            let len = cmp::min(buffer.len(), message.len());
+            buffer[..len].copy_from_slice(&message[..len]);
+        });
+    }
+
+    /// Deliver a message to this mailbox, straight to the process if it
+    /// waits for one. Fails with `BUSY` if the mailbox is full.
+    fn deliver(&mut self, from: ProcessId, message: &[u8], handle: u32) -> Result<(), ErrorCode> {
+        if self.waiting_receive {
+            self.receive_now(from, message, handle);
+            Ok(())
+        } else if self.len < DEPTH {
+            let slot = &mut self.messages[(self.head + self.len) % DEPTH];
+            slot.from = Some(from);
+            slot.len = message.len();
+            slot.handle = handle;
+            slot.data[..message.len()].copy_from_slice(message);
+            self.len += 1;
+            Ok(())
+        } else {
+            Err(ErrorCode::BUSY)
+        }
+    }
+
+    /// Take the oldest message out of the mailbox, with its sender.
+    fn pop(&mut self) -> Option<(ProcessId, Message<MESSAGE_LEN>)> {
+        if self.len == 0 {
+            return None;
+        }
+        let message = self.messages[self.head];
+        self.messages[self.head] = Message::EMPTY;
+        self.head = (self.head + 1) % DEPTH;
+        self.len -= 1;
+        message.from.map(|from| (from, message))
+    }
+}
+
+/// The descriptor userspace uses for a process.
+fn descriptor(processid: ProcessId) -> usize {
+    processid.id() + 1
+}
+
+/// Lets the kernel end the operations that wait on a process that stopped.
+pub(crate) trait MailboxPeers {
+    fn process_terminated(&self, processid: ProcessId);
+}
+
+/// The message-passing IPC mechanism struct.
+pub struct IPCMailbox<const MESSAGE_LEN: usize, const DEPTH: usize> {
+    /// The grant regions for each process that holds the per-process mailbox.
+    data: Grant<MailboxData<MESSAGE_LEN, DEPTH>>,
+}
+
+impl<const MESSAGE_LEN: usize, const DEPTH: usize> IPCMailbox<MESSAGE_LEN, DEPTH> {
+    pub fn new(kernel: &'static Kernel, capability: &dyn MemoryAllocationCapability) -> Self {
+        Self {
+            data: kernel.create_grant(capability),
+        }
+    }
+
+    /// Look up the process with the descriptor `target`.
+    fn peer(&self, target: usize) -> Result<ProcessId, ErrorCode> {
+        target
+            .checked_sub(1)
+            .and_then(|identifier| self.data.kernel.lookup_app_by_identifier(identifier))
+            .ok_or(ErrorCode::NODEVICE)
+    }
+
+    /// Whether `from` may send messages to `to`, as with `ipc::IPC`.
+    fn may_send(&self, from: ProcessId, to: ProcessId) -> bool {
+        self.data.kernel.process_map_or(false, from, |from| {
+            self.data
+                .kernel
+                .process_map_or(false, to, |to| ipc::may_use_any_service(to, from))
+        })
+    }
+
+    /// Copy the message `from` allowed into the mailbox of `to`, with the
+    /// given handle.
+    fn copy_message(&self, from: ProcessId, to: ProcessId, handle: u32) -> Result<(), ErrorCode> {
+        if from == to {
+            return Err(ErrorCode::INVAL);
+        }
+        self.data
+            .enter(from, |data| {
+                data.send_slice.map_or(Err(ErrorCode::INVAL), |message| {
+                    if message.len() > MESSAGE_LEN {
+                        return Err(ErrorCode::SIZE);
+                    }
+                    self.data
+                        .enter(to, |peer| peer.deliver(from, message, handle))
+                        .map_err(|err| match err {
+                            process::Error::OutOfMemory => ErrorCode::NOMEM,
+                            _ => ErrorCode::NODEVICE,
+                        })
+                        .and_then(|result| result)
+                })
+            })
+            .map_err(ErrorCode::from)
+            .and_then(|result| result)
+    }
+
+    fn send(&self, from: ProcessId, target: usize, flags: usize) -> CommandReturn {
+        let to = match self.peer(target) {
+            Ok(to) if self.may_send(from, to) => to,
+            Ok(_) => return CommandReturn::failure(ErrorCode::NODEVICE),
+            Err(err) => return CommandReturn::failure(err),
+        };
+        let expects_reply = flags & EXPECT_REPLY_FLAG != 0;
+        let wait = flags & WAIT_FLAG != 0;
+        let sequence = self.data.enter(from, |data| {
+            if data.pending_send.is_some() || (expects_reply && data.awaiting_reply.is_some()) {
+                Err(ErrorCode::BUSY)
+            } else if expects_reply {
+                Ok(data.take_sequence())
+            } else {
+                Ok(0)
+            }
+        });
+        let sequence = match sequence.map_err(ErrorCode::from).and_then(|s| s) {
+            Ok(sequence) => sequence,
+            Err(err) => return CommandReturn::failure(err),
+        };
+
+        let result = self.copy_message(from, to, sequence);
+        let _ = self.data.enter(from, |data| match result {
+            Ok(()) => {
+                if expects_reply {
+                    data.awaiting_reply = Some((to, sequence));
+                }
+                if wait {
+                    data.send_upcall
+                        .schedule(0, descriptor(to), sequence as usize);
+                }
+            }
+            Err(ErrorCode::BUSY) if wait => {
+                data.pending_send = Some(PendingSend {
+                    to,
+                    sequence,
+                    expects_reply,
+                });
+            }
+            Err(_) => {}
+        });
+        match result {
+            Ok(()) => CommandReturn::success_u32(sequence),
+            Err(ErrorCode::BUSY) if wait => CommandReturn::success_u32(sequence),
+            Err(err) => CommandReturn::failure(err),
+        }
+    }
+
+    fn receive(&self, processid: ProcessId, flags: usize) -> CommandReturn {
+        let result = self.data.enter(processid, |data| {
+            if data.receive_slice.len() == 0 {
+                return CommandReturn::failure(ErrorCode::INVAL);
+            }
+            match data.pop() {
+                Some((from, message)) => {
+                    let contents = &message.data[..message.len];
+                    if flags & WAIT_FLAG != 0 {
+                        data.receive_now(from, contents, message.handle);
+                        CommandReturn::success()
+                    } else {
+                        data.copy_to_receive_slice(contents);
+                        CommandReturn::success_u32_u32_u32(
+                            descriptor(from) as u32,
+                            message.len as u32,
+                            message.handle,
+                        )
+                    }
+                }
+                None if flags & WAIT_FLAG != 0 => {
+                    data.waiting_receive = true;
+                    CommandReturn::success()
+                }
+                None => CommandReturn::failure(ErrorCode::BUSY),
+            }
+        });
+        // Taking a message out of the mailbox makes room for a send that
+        // waits for it.
+        self.resume_pending_send(processid);
+        result.unwrap_or_else(|err| CommandReturn::failure(err.into()))
+    }
+
+    fn reply(&self, from: ProcessId, target: usize, handle: usize) -> CommandReturn {
+        let to = match self.peer(target) {
+            Ok(to) => to,
+            Err(err) => return CommandReturn::failure(err),
+        };
+        let sequence = handle as u32;
+        let awaited = self
+            .data
+            .enter(to, |data| data.awaiting_reply == Some((from, sequence)))
+            .unwrap_or(false);
+        if !awaited {
+            return CommandReturn::failure(ErrorCode::INVAL);
+        }
+        match self.copy_message(from, to, sequence | REPLY_FLAG) {
+            Ok(()) => {
+                let _ = self.data.enter(to, |data| data.awaiting_reply = None);
+                CommandReturn::success()
+            }
+            Err(err) => CommandReturn::failure(err),
+        }
+    }
+
+    /// Send the message of a process that waits for room in the mailbox of
+    /// `to`, if there is one.
+    fn resume_pending_send(&self, to: ProcessId) {
+        let waiting = self.data.iter().find_map(|pg| {
+            let sender = pg.processid();
+            pg.try_enter(|data| match data.pending_send {
+                Some(pending) if pending.to == to => Some((sender, pending)),
+                _ => None,
+            })
+            .flatten()
+        });
+        if let Some((sender, pending)) = waiting {
+            let result = self.copy_message(sender, to, pending.sequence);
+            if result == Err(ErrorCode::BUSY) {
+                return;
+            }
+            let _ = self.data.enter(sender, |data| {
+                data.pending_send = None;
+                if result.is_ok() && pending.expects_reply {
+                    data.awaiting_reply = Some((to, pending.sequence));
+                }
+                data.send_upcall.schedule(
+                    crate::into_statuscode(result),
+                    descriptor(to),
+                    pending.sequence as usize,
+                );
+            });
+        }
+    }
+}
+
+impl<const MESSAGE_LEN: usize, const DEPTH: usize> MailboxPeers for IPCMailbox<MESSAGE_LEN, DEPTH> {
+    fn process_terminated(&self, processid: ProcessId) {
+        for pg in self.data.iter() {
+            // A grant that is entered belongs to a process using the mailbox
+            // right now, which finds out about the peer itself.
+            pg.try_enter(|data| {
+                let status = crate::into_statuscode(Err(ErrorCode::NODEVICE));
+                if let Some(pending) = data.pending_send {
+                    if pending.to == processid {
+                        data.pending_send = None;
+                        data.send_upcall.schedule(
+                            status,
+                            descriptor(processid),
+                            pending.sequence as usize,
+                        );
+                    }
+                }
+                if let Some((peer, sequence)) = data.awaiting_reply {
+                    if peer == processid {
+                        data.awaiting_reply = None;
+                        data.send_upcall
+                            .schedule(status, descriptor(processid), sequence as usize);
+                    }
+                }
+            });
+        }
+    }
+}
+
+impl<const MESSAGE_LEN: usize, const DEPTH: usize> Driver for IPCMailbox<MESSAGE_LEN, DEPTH> {
+    /// Setup upcalls.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A message was received by a receive that waited.
+    /// - `1`: A send that waited completed, or a reply will not arrive
+    ///        because the peer stopped.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut upcall: Upcall,
+        app_id: ProcessId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = self.data.enter(app_id, |data| match subscribe_num {
+            0 => {
+                core::mem::swap(&mut data.receive_upcall, &mut upcall);
+                Ok(())
+            }
+            1 => {
+                core::mem::swap(&mut data.send_upcall, &mut upcall);
+                Ok(())
+            }
+            _ => Err(ErrorCode::NOSUPPORT),
+        });
+        match res.map_err(ErrorCode::from).and_then(|x| x) {
+            Ok(()) => Ok(upcall),
+            Err(e) => Err((upcall, e)),
+        }
+    }
+
+    /// Discover processes, and send, receive and reply to messages.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check, always returns Ok(())
+    /// - `1`: Perform discovery on the package name passed to `allow_readonly`
+    ///        0. Returns the descriptor of the process if it is found.
+    /// - `2`: Send the message passed to `allow_readonly` 1 to the process
+    ///        with descriptor `arg1`. `arg2` holds `WAIT_FLAG` and
+    ///        `EXPECT_REPLY_FLAG`. Returns the sequence number of the message.
+    /// - `3`: Receive a message into the buffer passed to `allow_readwrite`
+    ///        0. `arg1` holds `WAIT_FLAG`. Without it, returns the descriptor
+    ///        of the sender, the length of the message and its reply handle.
+    /// - `4`: Reply with the message passed to `allow_readonly` 1 to the
+    ///        process with descriptor `arg1`, whose message had the reply
+    ///        handle `arg2`.
+    fn command(
+        &self,
+        command_number: usize,
+        arg1: usize,
+        arg2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        match command_number {
+            0 => CommandReturn::success(),
+            1 => self
+                .data
+                .enter(appid, |data| {
+                    data.search_slice
+                        .map_or(CommandReturn::failure(ErrorCode::INVAL), |slice| {
+                            self.data
+                                .kernel
+                                .process_until(|p| {
+                                    if p.get_process_name().as_bytes() == slice
+                                        && self.may_send(appid, p.processid())
+                                    {
+                                        Some(CommandReturn::success_u32(
+                                            descriptor(p.processid()) as u32
+                                        ))
+                                    } else {
+                                        None
+                                    }
+                                })
+                                .unwrap_or(CommandReturn::failure(ErrorCode::NODEVICE))
+                        })
+                })
+                .unwrap_or(CommandReturn::failure(ErrorCode::NOMEM)),
+            2 => self.send(appid, arg1, arg2),
+            3 => self.receive(appid, arg1),
+            4 => self.reply(appid, arg1, arg2),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    /// Allow read-only buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The package name of a process to discover.
+    /// - `1`: The message to send or reply with.
+    fn allow_readonly(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = self.data.enter(appid, |data| match allow_num {
+            0 => {
+                core::mem::swap(&mut data.search_slice, &mut slice);
+                Ok(())
+            }
+            1 => {
+                core::mem::swap(&mut data.send_slice, &mut slice);
+                Ok(())
+            }
+            _ => Err(ErrorCode::NOSUPPORT),
+        });
+        match res.map_err(ErrorCode::from).and_then(|x| x) {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Allow a read-write buffer.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Where received messages are copied to.
+    fn allow_readwrite(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        if allow_num != 0 {
+            return Err((slice, ErrorCode::NOSUPPORT));
+        }
+        match self.data.enter(appid, |data| {
+            core::mem::swap(&mut data.receive_slice, &mut slice);
+        }) {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e.into())),
+        }
+    }
+}
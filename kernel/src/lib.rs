@@ -92,11 +92,19 @@
 pub mod capabilities;
 pub mod common;
 pub mod component;
+pub mod crash_dump;
 pub mod debug;
+pub mod heartbeat;
 pub mod hil;
 pub mod introspection;
 pub mod ipc;
+pub mod ipc_mailbox;
+pub mod power;
+pub mod profile;
+pub mod storage_permissions;
 pub mod syscall;
+pub mod trace;
+pub mod upcall_overflow;
 
 mod config;
 mod driver;
@@ -106,6 +114,7 @@ mod mem;
 mod memop;
 mod platform;
 mod process;
+mod process_checker;
 mod process_policies;
 mod process_standard;
 mod process_utilities;
@@ -115,14 +124,20 @@ mod upcall;
 pub use crate::driver::{CommandReturn, Driver};
 pub use crate::errorcode::into_statuscode;
 pub use crate::errorcode::ErrorCode;
-pub use crate::grant::{Grant, ProcessGrant};
-pub use crate::mem::{Read, ReadOnlyAppSlice, ReadWrite, ReadWriteAppSlice};
+pub use crate::grant::{CustomGrant, Grant, ProcessGrant};
+pub use crate::mem::{
+    AllowId, PinnedAppSlice, PinnedBuffer, Read, ReadOnlyAppSlice, ReadWrite, ReadWriteAppSlice,
+    MAX_PINNED_BUFFERS,
+};
 pub use crate::platform::scheduler_timer::{SchedulerTimer, VirtualSchedulerTimer};
 pub use crate::platform::watchdog;
-pub use crate::platform::{mpu, Chip, InterruptService, Platform};
+pub use crate::platform::{
+    mpu, mpu_check, tbf_permissions_filter, Chip, InterruptService, Platform,
+};
 pub use crate::platform::{ClockInterface, NoClockControl, NO_CLOCK_CONTROL};
 pub use crate::process::ProcessId;
 pub use crate::sched::cooperative::{CoopProcessNode, CooperativeSched};
+pub use crate::sched::edf::{EDFProcessNode, EDFSched, RealTimePolicy};
 pub use crate::sched::mlfq::{MLFQProcessNode, MLFQSched};
 pub use crate::sched::priority::PrioritySched;
 pub use crate::sched::round_robin::{RoundRobinProcessNode, RoundRobinSched};
@@ -138,9 +153,18 @@ pub mod procs {
     pub use crate::process::{
         Error, FaultResponse, FunctionCall, FunctionCallSource, Process, State, Task,
     };
+    pub use crate::process_checker::{
+        AllowAllApps, AppCredentialsPolicy, CheckResult, RequireHmacSha256Credentials,
+        RequireSha256Credentials,
+    };
     pub use crate::process_policies::{
-        AlwaysRestart, ProcessRestartPolicy, ThresholdRestart, ThresholdRestartThenPanic,
+        AlwaysRestart, CpuBudget, DependentRestart, ProcessQuota, ProcessRestartPolicy, QuotaClock,
+        RestartBackoff, RestartDependency, ThresholdRestart, ThresholdRestartThenPanic,
+        DEFAULT_UPCALL_QUEUE_DEPTH, MAX_UPCALL_QUEUE_DEPTH,
     };
     pub use crate::process_standard::ProcessStandard;
-    pub use crate::process_utilities::{load_processes, ProcessLoadError};
+    pub use crate::process_utilities::{
+        load_and_check_processes, load_new_process, load_processes, unload_process,
+        ProcessLoadError,
+    };
 }
@@ -15,6 +15,10 @@
 
 use crate::capabilities;
 use crate::process::ProcessId;
+use crate::ErrorCode;
+
+/// How many buffers each process can have pinned at once.
+pub const MAX_PINNED_BUFFERS: usize = 4;
 
 /// Convert an AppSlice's internal representation to a Rust slice.
 ///
@@ -150,11 +154,38 @@ pub trait ReadWrite: Read {
         F: FnOnce(&mut [u8]) -> R;
 }
 
+/// The read-write allow a buffer was shared with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllowId {
+    pub driver_num: usize,
+    pub allow_num: usize,
+}
+
+/// A read-write buffer of a process that is pinned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PinnedBuffer {
+    pub allow_id: AllowId,
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl PinnedBuffer {
+    /// Whether an allow of `len` bytes at `ptr` to `allow_id` would touch
+    /// this buffer: it takes the buffer back or shares some of its memory.
+    pub fn conflicts_with(&self, allow_id: Option<AllowId>, ptr: *const u8, len: usize) -> bool {
+        let start = ptr as usize;
+        let pinned_start = self.ptr as usize;
+        allow_id == Some(self.allow_id)
+            || (len > 0 && start < pinned_start + self.len && pinned_start < start + len)
+    }
+}
+
 /// Read-writable memory region of a process, shared with the kernel
 pub struct ReadWriteAppSlice {
     ptr: *mut u8,
     len: usize,
     process_id: Option<ProcessId>,
+    allow_id: Option<AllowId>,
 }
 
 impl ReadWriteAppSlice {
@@ -170,6 +201,7 @@ impl ReadWriteAppSlice {
             ptr,
             len,
             process_id: Some(process_id),
+            allow_id: None,
         }
     }
 
@@ -231,6 +263,54 @@ impl ReadWriteAppSlice {
         (self.ptr, self.len)
     }
 
+    /// Record the allow the process shared this buffer with, so that it
+    /// can be pinned.
+    pub(crate) fn set_allow_id(&mut self, driver_num: usize, allow_num: usize) {
+        self.allow_id = Some(AllowId {
+            driver_num,
+            allow_num,
+        });
+    }
+
+    /// Pin the buffer, so that hardware can access it directly until the
+    /// returned [`PinnedAppSlice`] is dropped.
+    ///
+    /// While the buffer is pinned, this AppSlice behaves as if the process
+    /// no longer existed: it cannot be mapped.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ErrorCode::INVAL`] if the AppSlice is empty, or was not shared
+    ///   by the process with a read-write allow,
+    /// - [`ErrorCode::FAIL`] if the process is no longer active,
+    /// - [`ErrorCode::BUSY`] if the buffer, or memory overlapping it, is
+    ///   already pinned,
+    /// - [`ErrorCode::NOMEM`] if the process already has
+    ///   [`MAX_PINNED_BUFFERS`] buffers pinned.
+    pub fn pin(&self) -> Result<PinnedAppSlice, ErrorCode> {
+        let (process_id, allow_id) = match (self.process_id, self.allow_id) {
+            (Some(process_id), Some(allow_id)) if self.len > 0 => (process_id, allow_id),
+            _ => return Err(ErrorCode::INVAL),
+        };
+        let buffer = PinnedBuffer {
+            allow_id,
+            ptr: self.ptr,
+            len: self.len,
+        };
+        process_id
+            .kernel
+            .process_map_or(Err(ErrorCode::FAIL), process_id, |process| {
+                process.pin_buffer(buffer)
+            })?;
+        Ok(PinnedAppSlice {
+            ptr: self.ptr,
+            len: self.len,
+            process_id,
+            allow_id,
+            lent: false,
+        })
+    }
+
     /// This is a `const` version of `Default::default` with the same semantics.
     ///
     /// Having a const initializer allows initializing a fixed-size array with default values
@@ -246,6 +326,7 @@ impl ReadWriteAppSlice {
             ptr: 0x0 as *mut u8,
             len: 0,
             process_id: None,
+            allow_id: None,
         }
     }
 }
@@ -263,14 +344,23 @@ impl ReadWrite for ReadWriteAppSlice {
     {
         match self.process_id {
             None => default,
-            Some(pid) => pid.kernel.process_map_or(default, pid, |_| {
-                // Safety: `kernel.process_map_or()` validates that the process still exists
-                // and its memory is still valid. `Process` tracks the "high water mark" of
-                // memory that the process has `allow`ed to the kernel, and will not permit
-                // the process to free any memory after it has been `allow`ed. This guarantees
-                // that the buffer is safe to convert into a slice here.
-                fun(unsafe { raw_appslice_to_slice_mut(self.ptr, self.len) })
-            }),
+            Some(pid) => pid
+                .kernel
+                .process_map_or(None, pid, |process| {
+                    // Hardware may be accessing pinned memory.
+                    if process.pinned_buffer_conflict(self.allow_id, self.ptr, self.len) {
+                        return None;
+                    }
+                    // Safety: `kernel.process_map_or()` validates that the process still exists
+                    // and its memory is still valid. `Process` tracks the "high water mark" of
+                    // memory that the process has `allow`ed to the kernel, and will not permit
+                    // the process to free any memory after it has been `allow`ed. This guarantees
+                    // that the buffer is safe to convert into a slice here.
+                    Some(fun(unsafe {
+                        raw_appslice_to_slice_mut(self.ptr, self.len)
+                    }))
+                })
+                .unwrap_or(default),
         }
     }
 }
@@ -295,14 +385,21 @@ impl Read for ReadWriteAppSlice {
     {
         match self.process_id {
             None => default,
-            Some(pid) => pid.kernel.process_map_or(default, pid, |_| {
-                // Safety: `kernel.process_map_or()` validates that the process still exists
-                // and its memory is still valid. `Process` tracks the "high water mark" of
-                // memory that the process has `allow`ed to the kernel, and will not permit
-                // the process to free any memory after it has been `allow`ed. This guarantees
-                // that the buffer is safe to convert into a slice here.
-                fun(unsafe { raw_appslice_to_slice(self.ptr, self.len) })
-            }),
+            Some(pid) => pid
+                .kernel
+                .process_map_or(None, pid, |process| {
+                    // Hardware may be accessing pinned memory.
+                    if process.pinned_buffer_conflict(self.allow_id, self.ptr, self.len) {
+                        return None;
+                    }
+                    // Safety: `kernel.process_map_or()` validates that the process still exists
+                    // and its memory is still valid. `Process` tracks the "high water mark" of
+                    // memory that the process has `allow`ed to the kernel, and will not permit
+                    // the process to free any memory after it has been `allow`ed. This guarantees
+                    // that the buffer is safe to convert into a slice here.
+                    Some(fun(unsafe { raw_appslice_to_slice(self.ptr, self.len) }))
+                })
+                .unwrap_or(default),
         }
     }
 }
@@ -419,14 +516,100 @@ impl Read for ReadOnlyAppSlice {
     {
         match self.process_id {
             None => default,
-            Some(pid) => pid.kernel.process_map_or(default, pid, |_| {
-                // Safety: `kernel.process_map_or()` validates that the process still exists
-                // and its memory is still valid. `Process` tracks the "high water mark" of
-                // memory that the process has `allow`ed to the kernel, and will not permit
-                // the process to free any memory after it has been `allow`ed. This guarantees
-                // that the buffer is safe to convert into a slice here.
-                fun(unsafe { raw_appslice_to_slice(self.ptr, self.len) })
-            }),
+            Some(pid) => pid
+                .kernel
+                .process_map_or(None, pid, |process| {
+                    // Hardware may be accessing pinned memory.
+                    if process.pinned_buffer_conflict(None, self.ptr, self.len) {
+                        return None;
+                    }
+                    // Safety: `kernel.process_map_or()` validates that the process still exists
+                    // and its memory is still valid. `Process` tracks the "high water mark" of
+                    // memory that the process has `allow`ed to the kernel, and will not permit
+                    // the process to free any memory after it has been `allow`ed. This guarantees
+                    // that the buffer is safe to convert into a slice here.
+                    Some(fun(unsafe { raw_appslice_to_slice(self.ptr, self.len) }))
+                })
+                .unwrap_or(default),
+        }
+    }
+}
+
+/// A read-write buffer of a process, pinned with
+/// [`ReadWriteAppSlice::pin`] so that hardware can access it directly.
+///
+/// Drivers that use DMA can pin a buffer and hand the memory to the
+/// hardware, rather than copying it through a kernel buffer. While a
+/// buffer is pinned:
+///
+/// - the process cannot allow a buffer to the same allow number, which
+///   would take the pinned buffer back, nor allow memory overlapping the
+///   pinned buffer (these allows fail with `BUSY`),
+/// - the AppSlices that share the pinned memory cannot be accessed,
+/// - the process is not restarted, and `procs::unload_process()` fails,
+///   so that its memory is not given to a new process. A restart that was
+///   due happens once the last buffer is unpinned.
+///
+/// Only read-write buffers can be pinned: read-only buffers may be in
+/// flash, and the HILs that use DMA take mutable buffers.
+///
+/// The buffer is unpinned when this is dropped, unless it is lent out
+/// with [`take`](PinnedAppSlice::take) and was not given back with
+/// [`restore`](PinnedAppSlice::restore). As the hardware may then still
+/// access it, the buffer stays pinned for good.
+pub struct PinnedAppSlice {
+    ptr: *mut u8,
+    len: usize,
+    process_id: ProcessId,
+    allow_id: AllowId,
+    lent: bool,
+}
+
+impl PinnedAppSlice {
+    /// Length of the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The process the buffer belongs to.
+    pub fn processid(&self) -> ProcessId {
+        self.process_id
+    }
+
+    /// Lend out the buffer, for example to a peripheral that accesses it
+    /// with DMA. Returns `None` if it is already lent out.
+    pub fn take(&mut self) -> Option<&'static mut [u8]> {
+        if self.lent {
+            return None;
+        }
+        self.lent = true;
+        // Safety: the memory belongs to the process, which cannot free it
+        // nor be restarted while the buffer is pinned. No other slice of
+        // it can be created: AppSlices that share it cannot be mapped, the
+        // process cannot allow it again, and it is lent out only once.
+        Some(unsafe { raw_appslice_to_slice_mut(self.ptr, self.len) })
+    }
+
+    /// Give back the buffer lent out with [`take`](PinnedAppSlice::take).
+    /// Returns `buffer` as the error if it is not that buffer.
+    pub fn restore(&mut self, buffer: &'static mut [u8]) -> Result<(), &'static mut [u8]> {
+        if self.lent && buffer.as_ptr() == self.ptr as *const u8 && buffer.len() == self.len {
+            self.lent = false;
+            Ok(())
+        } else {
+            Err(buffer)
+        }
+    }
+}
+
+impl Drop for PinnedAppSlice {
+    fn drop(&mut self) {
+        if !self.lent {
+            self.process_id
+                .kernel
+                .process_map_or((), self.process_id, |process| {
+                    process.unpin_buffer(self.allow_id)
+                });
         }
     }
 }
@@ -37,6 +37,11 @@ use crate::ErrorCode;
 ///   where the app has put the start of its heap. This is not strictly
 ///   necessary for correct operation, but allows for better debugging if the
 ///   app crashes.
+/// - `12`: Get the number of bytes of state the app keeps across restarts.
+/// - `13`: Mark the region of app memory starting at r1 as the app's
+///   persistent state, and copy the last checkpoint of the state into it.
+///   Returns 1 if there was a checkpoint to copy and 0 otherwise.
+/// - `14`: Checkpoint the app's persistent state now.
 pub(crate) fn memop(process: &dyn Process, op_type: usize, r1: usize) -> SyscallReturn {
     match op_type {
         // Op Type 0: BRK
@@ -107,6 +112,21 @@ pub(crate) fn memop(process: &dyn Process, op_type: usize, r1: usize) -> Syscall
             SyscallReturn::Success
         }
 
+        // Op Type 12: Size of the persistent state.
+        12 => SyscallReturn::SuccessU32(process.persistent_state_len() as u32),
+
+        // Op Type 13: Register the persistent region and restore it.
+        13 => match process.register_persistent_region(r1 as *mut u8) {
+            Ok(restored) => SyscallReturn::SuccessU32(restored as u32),
+            Err(e) => SyscallReturn::Failure(e),
+        },
+
+        // Op Type 14: Checkpoint the persistent region.
+        14 => match process.checkpoint_persistent_region() {
+            Ok(()) => SyscallReturn::Success,
+            Err(e) => SyscallReturn::Failure(e),
+        },
+
         _ => SyscallReturn::Failure(ErrorCode::NOSUPPORT),
     }
 }
@@ -2,14 +2,64 @@
 
 use crate::driver::Driver;
 use crate::errorcode;
+use crate::power;
 use crate::process;
 use crate::syscall;
+use core::convert::TryFrom;
 use core::fmt::Write;
 
 pub mod mpu;
+pub mod mpu_check;
 pub(crate) mod scheduler_timer;
 pub mod watchdog;
 
+/// System call filter that enforces the permissions a process declares in the
+/// Permissions TLV of its TBF header.
+///
+/// A process that declares permissions may only subscribe, allow buffers to
+/// and call commands on the drivers it lists, and may only call the commands
+/// in the ranges it lists for each driver. Other calls fail with `NODEVICE`
+/// if the driver is not listed, and `NOSUPPORT` if only the command is not.
+/// Processes without a Permissions TLV are not restricted.
+///
+/// This is the default `Platform::filter_syscall()`. Boards that add their
+/// own rules can call it from their filter to keep enforcing the TBF
+/// permissions.
+pub fn tbf_permissions_filter(
+    process: &dyn process::Process,
+    syscall: &syscall::Syscall,
+) -> Result<(), errorcode::ErrorCode> {
+    let permissions = match process.get_syscall_permissions() {
+        Some(permissions) => permissions,
+        None => return Ok(()),
+    };
+
+    let driver_number = match *syscall {
+        syscall::Syscall::Subscribe { driver_number, .. }
+        | syscall::Syscall::Command { driver_number, .. }
+        | syscall::Syscall::ReadWriteAllow { driver_number, .. }
+        | syscall::Syscall::ReadOnlyAllow { driver_number, .. } => driver_number,
+        _ => return Ok(()),
+    };
+    // Driver numbers that do not fit in the TBF field cannot be listed.
+    let driver_number = u32::try_from(driver_number).or(Err(errorcode::ErrorCode::NODEVICE))?;
+    if !permissions.allows_driver(driver_number) {
+        return Err(errorcode::ErrorCode::NODEVICE);
+    }
+
+    match *syscall {
+        syscall::Syscall::Command {
+            subdriver_number, ..
+        } => match u32::try_from(subdriver_number) {
+            Ok(command_number) if permissions.allows_command(driver_number, command_number) => {
+                Ok(())
+            }
+            _ => Err(errorcode::ErrorCode::NOSUPPORT),
+        },
+        _ => Ok(()),
+    }
+}
+
 /// Interface for individual boards.
 ///
 /// Each board should define a struct which implements this trait. This trait is
@@ -50,17 +100,21 @@ pub trait Platform {
     /// Check the platform-provided system call filter for all non-yield system
     /// calls. If the system call is allowed for the provided process then
     /// return `Ok(())`. Otherwise, return `Err()` with an `ErrorCode` that will
-    /// be returned to the calling application. The default implementation
-    /// allows all system calls.
+    /// be returned to the calling application. The kernel counts rejected
+    /// system calls for each process, see `introspection`.
+    ///
+    /// The default implementation enforces the permissions each process
+    /// declares in its TBF header with `tbf_permissions_filter()`, and allows
+    /// all system calls from processes that do not declare any.
     ///
     /// This API should be considered unstable, and is likely to change in the
     /// future.
     fn filter_syscall(
         &self,
-        _process: &dyn process::Process,
-        _syscall: &syscall::Syscall,
+        process: &dyn process::Process,
+        syscall: &syscall::Syscall,
     ) -> Result<(), errorcode::ErrorCode> {
-        Ok(())
+        tbf_permissions_filter(process, syscall)
     }
 
     /// This function is called when an app faults.
@@ -158,6 +212,25 @@ pub trait Chip {
     /// chip and resumes the scheduler.
     fn sleep(&self);
 
+    /// Called instead of `sleep()` when the board has a power manager, to
+    /// enter the sleep state it chose. `SleepState::Idle` should do what
+    /// `sleep()` does, and deeper states may turn off more of the chip, as
+    /// long as the chip still wakes up on the next interrupt. A chip may sleep
+    /// less deeply than asked if its peripherals need it. Chips with a single
+    /// sleep mode do not need to implement this.
+    #[allow(unused_variables)]
+    fn sleep_in(&self, state: power::SleepState) {
+        self.sleep();
+    }
+
+    /// How long, in microseconds, the chip takes to wake up from `state` and
+    /// be ready to service the interrupt that woke it. The power manager does
+    /// not enter a state the chip cannot wake up from before the next alarm.
+    #[allow(unused_variables)]
+    fn wakeup_latency_us(&self, state: power::SleepState) -> u32 {
+        0
+    }
+
     /// Run a function in an atomic state, which means that interrupts are
     /// disabled so that an interrupt will not fire during the passed in
     /// function's execution.
@@ -14,6 +14,47 @@ pub enum Permissions {
     ExecuteOnly,
 }
 
+/// What user mode can do with a memory address.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Access {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Access {
+    /// No access at all.
+    pub const NONE: Access = Access {
+        read: false,
+        write: false,
+        execute: false,
+    };
+
+    /// Whether everything `other` allows is also allowed by `self`.
+    pub fn includes(&self, other: Access) -> bool {
+        (self.read || !other.read)
+            && (self.write || !other.write)
+            && (self.execute || !other.execute)
+    }
+}
+
+impl From<Permissions> for Access {
+    fn from(permissions: Permissions) -> Access {
+        let (read, write, execute) = match permissions {
+            Permissions::ReadWriteExecute => (true, true, true),
+            Permissions::ReadWriteOnly => (true, true, false),
+            Permissions::ReadExecuteOnly => (true, false, true),
+            Permissions::ReadOnly => (true, false, false),
+            Permissions::ExecuteOnly => (false, false, true),
+        };
+        Access {
+            read,
+            write,
+            execute,
+        }
+    }
+}
+
 /// MPU region.
 ///
 /// This is one contiguous address space protected by the MPU.
@@ -270,6 +311,19 @@ impl MPU for () {
     type MpuConfig = MpuConfigDefault;
 }
 
+/// A model of the protection an MPU configuration actually enforces.
+///
+/// Implementations decode a configuration the way the hardware would once
+/// `configure_mpu()` wrote it, rather than from the regions the
+/// implementation meant to allocate, so that `mpu_check::check_mpu()` can
+/// test on the host whether the `MPU` implementation keeps the promises of
+/// this trait.
+pub trait ProtectionModel: MPU {
+    /// The access user mode has to `address` while the MPU is enabled and
+    /// configured with `config`.
+    fn user_access(&self, config: &Self::MpuConfig, address: usize) -> Access;
+}
+
 /// The generic trait that particular kernel level memory protection unit
 /// implementations need to implement.
 ///
@@ -0,0 +1,515 @@
+//! Randomized checks of an MPU implementation against the `MPU` trait.
+//!
+//! `check_mpu()` calls an MPU the way the kernel does when it loads a
+//! process, shares buffers with it and grows its memory, with random but
+//! reproducible layouts. After every call it asks the MPU's
+//! `mpu::ProtectionModel` what access user mode has to the addresses at the
+//! edges of and inside the memory involved, and checks that:
+//!
+//! - regions and process memory blocks lie within the unallocated memory they
+//!   were allocated from, and are as large as requested,
+//! - app flash can be read and executed, but not written,
+//! - app-owned memory and shared buffers can be read and written, but not
+//!   executed,
+//! - kernel-owned memory at the end of the process memory block, where the
+//!   grants are, cannot be accessed,
+//! - all other memory cannot be accessed,
+//! - a failed `update_app_memory_region()` does not change the protection.
+//!
+//! Like the kernel, it asks for `Permissions::ReadExecuteOnly` for flash and
+//! `Permissions::ReadWriteOnly` for memory. The MPU may decline any call, but
+//! `Coverage` tells how many calls succeeded, so that a test can make sure the
+//! MPU was actually checked.
+//!
+//! The checks do not need the hardware, so MPU implementations can run them
+//! in their unit tests.
+//!
+//! Usage
+//! -----
+//!
+//! ```ignore
+//! #[test]
+//! fn follows_the_mpu_contract() {
+//!     let mpu = unsafe { MPU::<8>::new() };
+//!     let coverage = kernel::mpu_check::check_mpu(&mpu, 1, 1000).unwrap();
+//!     assert!(coverage.updates > 0);
+//! }
+//! ```
+
+use core::cmp;
+
+use crate::mpu::{Access, Permissions, ProtectionModel};
+
+/// Where the checks place app flash.
+const FLASH_BASE: usize = 0x0004_0000;
+/// Where the checks place process memory.
+const MEMORY_BASE: usize = 0x2000_0000;
+/// Where the checks place shared buffers. Buffer `n` is placed in the
+/// `SHARED_SPACING` bytes from `SHARED_BASE + n * SHARED_SPACING`.
+const SHARED_BASE: usize = 0x3000_0000;
+const SHARED_SPACING: usize = 0x0004_0000;
+
+/// The most shared buffers allocated in one round.
+const MAX_SHARED: usize = 4;
+/// Calls to `update_app_memory_region()` in one round.
+const UPDATES_PER_ROUND: usize = 8;
+
+/// Slots of the memory ranges the checks keep track of.
+const FLASH_SLOT: usize = 0;
+const APP_SLOT: usize = 1;
+const GAP_SLOT: usize = 2;
+const KERNEL_SLOT: usize = 3;
+const SHARED_SLOT: usize = 4;
+const NUM_SLOTS: usize = SHARED_SLOT + MAX_SHARED;
+
+/// Addresses sampled around and inside every range, plus the random ones
+/// anywhere near the ranges.
+const SAMPLES_PER_RANGE: usize = 7;
+const RANDOM_SAMPLES: usize = 8;
+const MAX_SAMPLES: usize = NUM_SLOTS * SAMPLES_PER_RANGE + RANDOM_SAMPLES;
+
+/// An MPU call that broke the contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    AllocateRegion {
+        start: usize,
+        size: usize,
+        min_size: usize,
+    },
+    AllocateAppMemoryRegion {
+        start: usize,
+        size: usize,
+        min_size: usize,
+        app_size: usize,
+        kernel_size: usize,
+    },
+    UpdateAppMemoryRegion {
+        app_break: usize,
+        kernel_break: usize,
+    },
+}
+
+/// How the call broke the contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Property {
+    /// The allocated memory does not lie within the unallocated memory.
+    NotContained { start: usize, size: usize },
+    /// The allocated memory is smaller than asked for.
+    TooSmall { size: usize },
+    /// User mode has the wrong access to `address`.
+    WrongAccess {
+        address: usize,
+        expected: Access,
+        actual: Access,
+    },
+    /// A call that failed changed the access to `address`.
+    ChangedOnError {
+        address: usize,
+        before: Access,
+        after: Access,
+    },
+}
+
+/// A broken promise of the `MPU` trait.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// The round of `check_mpu()` the call was made in.
+    pub round: usize,
+    pub operation: Operation,
+    pub property: Property,
+}
+
+/// How many calls of each kind the MPU did not decline.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub regions: usize,
+    pub app_memory_regions: usize,
+    pub updates: usize,
+}
+
+/// Check `mpu` for `rounds` rounds, each with a new configuration, with the
+/// layouts chosen by `seed`. Returns the first broken promise found.
+pub fn check_mpu<M: ProtectionModel>(
+    mpu: &M,
+    seed: u32,
+    rounds: usize,
+) -> Result<Coverage, Violation> {
+    let mut rng = Rng::new(seed);
+    let mut coverage = Coverage::default();
+    for round in 0..rounds {
+        check_round(mpu, round, &mut rng, &mut coverage)?;
+    }
+    Ok(coverage)
+}
+
+fn check_round<M: ProtectionModel>(
+    mpu: &M,
+    round: usize,
+    rng: &mut Rng,
+    coverage: &mut Coverage,
+) -> Result<(), Violation> {
+    let mut config = M::MpuConfig::default();
+    let mut memory = Memory::new();
+
+    // App flash, either the power of two sized and aligned block most boards
+    // use, or anything.
+    let (start, size, min_size) = if rng.one_in(2) {
+        let size = 1 << (8 + rng.below(9));
+        (FLASH_BASE + size * rng.below(4), size, size)
+    } else {
+        let min_size = 1 + rng.below(0x4000);
+        (
+            FLASH_BASE + rng.below(0x1_0000),
+            min_size + rng.below(0x1_0000),
+            min_size,
+        )
+    };
+    let operation = Operation::AllocateRegion {
+        start,
+        size,
+        min_size,
+    };
+    let violation = |property| Violation {
+        round,
+        operation,
+        property,
+    };
+    if let Some(region) = mpu.allocate_region(
+        start as *const u8,
+        size,
+        min_size,
+        Permissions::ReadExecuteOnly,
+        &mut config,
+    ) {
+        coverage.regions += 1;
+        let region_start = region.start_address() as usize;
+        check_allocation(start, size, min_size, region_start, region.size()).map_err(violation)?;
+        memory.set(
+            FLASH_SLOT,
+            region_start,
+            region_start + region.size(),
+            Expected::Exactly(Permissions::ReadExecuteOnly.into()),
+        );
+        memory.check(mpu, &config, rng).map_err(violation)?;
+    }
+
+    // Process memory.
+    let app_size = rng.below(0x4000);
+    let kernel_size = rng.below(0x1000) & !3;
+    let min_size = rng.below(0x8000);
+    let needed = cmp::max(min_size, app_size + kernel_size);
+    let start = MEMORY_BASE + (rng.below(0x1_0000) & !3);
+    let size = needed + rng.below(0x2_0000);
+    let operation = Operation::AllocateAppMemoryRegion {
+        start,
+        size,
+        min_size,
+        app_size,
+        kernel_size,
+    };
+    let violation = |property| Violation {
+        round,
+        operation,
+        property,
+    };
+    let (memory_start, memory_end) = match mpu.allocate_app_memory_region(
+        start as *const u8,
+        size,
+        min_size,
+        app_size,
+        kernel_size,
+        Permissions::ReadWriteOnly,
+        &mut config,
+    ) {
+        Some((memory_start, memory_size)) => {
+            coverage.app_memory_regions += 1;
+            let memory_start = memory_start as usize;
+            check_allocation(start, size, needed, memory_start, memory_size).map_err(violation)?;
+            memory.set_app_memory(
+                memory_start,
+                memory_start + app_size,
+                memory_start + memory_size - kernel_size,
+                memory_start + memory_size,
+            );
+            memory.check(mpu, &config, rng).map_err(violation)?;
+            (memory_start, memory_start + memory_size)
+        }
+        // Without process memory there is nothing left to check.
+        None => return Ok(()),
+    };
+
+    // Buffers shared with the process.
+    for slot in SHARED_SLOT..SHARED_SLOT + rng.below(MAX_SHARED + 1) {
+        let min_size = 1 + rng.below(0x1000);
+        let start = SHARED_BASE + (slot - SHARED_SLOT) * SHARED_SPACING + rng.below(0x1_0000);
+        let size = if rng.one_in(2) {
+            min_size
+        } else {
+            min_size + rng.below(0x2_0000)
+        };
+        let operation = Operation::AllocateRegion {
+            start,
+            size,
+            min_size,
+        };
+        let violation = |property| Violation {
+            round,
+            operation,
+            property,
+        };
+        if let Some(region) = mpu.allocate_region(
+            start as *const u8,
+            size,
+            min_size,
+            Permissions::ReadWriteOnly,
+            &mut config,
+        ) {
+            coverage.regions += 1;
+            let region_start = region.start_address() as usize;
+            check_allocation(start, size, min_size, region_start, region.size())
+                .map_err(violation)?;
+            memory.set(
+                slot,
+                region_start,
+                region_start + region.size(),
+                Expected::Exactly(Permissions::ReadWriteOnly.into()),
+            );
+            memory.check(mpu, &config, rng).map_err(violation)?;
+        }
+    }
+
+    // The process moves its break, and the kernel allocates grants.
+    let mut app_break = memory_start + app_size;
+    let mut kernel_break = memory_end - kernel_size;
+    for _ in 0..UPDATES_PER_ROUND {
+        let (new_app_break, new_kernel_break) = if rng.one_in(2) {
+            (
+                memory_start + rng.below(kernel_break - memory_start + 1),
+                kernel_break,
+            )
+        } else {
+            let grant_size = rng.below(kernel_break - app_break + 1) & !3;
+            (app_break, kernel_break - grant_size)
+        };
+        let operation = Operation::UpdateAppMemoryRegion {
+            app_break: new_app_break,
+            kernel_break: new_kernel_break,
+        };
+        let violation = |property| Violation {
+            round,
+            operation,
+            property,
+        };
+
+        let (samples, num_samples) = memory.samples(rng);
+        let mut before = [Access::NONE; MAX_SAMPLES];
+        for (access, &address) in before.iter_mut().zip(&samples[..num_samples]) {
+            *access = mpu.user_access(&config, address);
+        }
+        match mpu.update_app_memory_region(
+            new_app_break as *const u8,
+            new_kernel_break as *const u8,
+            Permissions::ReadWriteOnly,
+            &mut config,
+        ) {
+            Ok(()) => {
+                coverage.updates += 1;
+                app_break = new_app_break;
+                kernel_break = new_kernel_break;
+                memory.set_app_memory(memory_start, app_break, kernel_break, memory_end);
+                memory.check(mpu, &config, rng).map_err(violation)?;
+            }
+            Err(()) => {
+                for (&before, &address) in before.iter().zip(&samples[..num_samples]) {
+                    let after = mpu.user_access(&config, address);
+                    if after != before {
+                        return Err(violation(Property::ChangedOnError {
+                            address,
+                            before,
+                            after,
+                        }));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that `size` bytes from `start` were allocated within `unallocated_size`
+/// bytes from `unallocated_start`, and that at least `min_size` were.
+fn check_allocation(
+    unallocated_start: usize,
+    unallocated_size: usize,
+    min_size: usize,
+    start: usize,
+    size: usize,
+) -> Result<(), Property> {
+    if start < unallocated_start || start + size > unallocated_start + unallocated_size {
+        Err(Property::NotContained { start, size })
+    } else if size < min_size {
+        Err(Property::TooSmall { size })
+    } else {
+        Ok(())
+    }
+}
+
+/// The access user mode should have to a range of memory.
+#[derive(Clone, Copy)]
+enum Expected {
+    /// Exactly this access.
+    Exactly(Access),
+    /// At most this access. The MPU may, for example, round regions up.
+    AtMost(Access),
+}
+
+#[derive(Clone, Copy)]
+struct Range {
+    start: usize,
+    end: usize,
+    expected: Expected,
+}
+
+/// The memory the MPU allocated, and what user mode may do with it.
+/// Everything else must be inaccessible.
+struct Memory {
+    ranges: [Option<Range>; NUM_SLOTS],
+}
+
+impl Memory {
+    fn new() -> Memory {
+        Memory {
+            ranges: [None; NUM_SLOTS],
+        }
+    }
+
+    fn set(&mut self, slot: usize, start: usize, end: usize, expected: Expected) {
+        self.ranges[slot] = Some(Range {
+            start,
+            end,
+            expected,
+        });
+    }
+
+    /// The process memory block from `start` to `end`, with app-owned memory
+    /// up to `app_break` and kernel-owned memory from `kernel_break`.
+    fn set_app_memory(&mut self, start: usize, app_break: usize, kernel_break: usize, end: usize) {
+        let read_write = Permissions::ReadWriteOnly.into();
+        self.set(APP_SLOT, start, app_break, Expected::Exactly(read_write));
+        self.set(
+            GAP_SLOT,
+            app_break,
+            kernel_break,
+            Expected::AtMost(read_write),
+        );
+        self.set(
+            KERNEL_SLOT,
+            kernel_break,
+            end,
+            Expected::Exactly(Access::NONE),
+        );
+    }
+
+    fn expected(&self, address: usize) -> Expected {
+        self.ranges
+            .iter()
+            .flatten()
+            .find(|range| range.start <= address && address < range.end)
+            .map_or(Expected::Exactly(Access::NONE), |range| range.expected)
+    }
+
+    /// Addresses at the edges of and inside every range, and at random
+    /// around them.
+    fn samples(&self, rng: &mut Rng) -> ([usize; MAX_SAMPLES], usize) {
+        let mut samples = [0; MAX_SAMPLES];
+        let mut count = 0;
+        let mut add = |address: usize| {
+            samples[count] = address;
+            count += 1;
+        };
+        let mut low = usize::MAX;
+        let mut high = 0;
+        for range in self.ranges.iter().flatten() {
+            low = cmp::min(low, range.start);
+            high = cmp::max(high, range.end);
+            let length = range.end - range.start;
+            add(range.start.saturating_sub(1));
+            add(range.start);
+            add(range.start + 1);
+            add(range.end.saturating_sub(1));
+            add(range.end);
+            add(range.start + rng.below(length));
+            add(range.start + rng.below(length));
+        }
+        if low < high {
+            low = low.saturating_sub(0x1000);
+            high += 0x1000;
+            for _ in 0..RANDOM_SAMPLES {
+                add(low + rng.below(high - low));
+            }
+        }
+        (samples, count)
+    }
+
+    fn check<M: ProtectionModel>(
+        &self,
+        mpu: &M,
+        config: &M::MpuConfig,
+        rng: &mut Rng,
+    ) -> Result<(), Property> {
+        let (samples, count) = self.samples(rng);
+        for &address in samples[..count].iter() {
+            let actual = mpu.user_access(config, address);
+            let allowed = match self.expected(address) {
+                Expected::Exactly(expected) => actual == expected,
+                Expected::AtMost(expected) => expected.includes(actual),
+            };
+            if !allowed {
+                let expected = match self.expected(address) {
+                    Expected::Exactly(expected) | Expected::AtMost(expected) => expected,
+                };
+                return Err(Property::WrongAccess {
+                    address,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Xorshift pseudorandom numbers, so that a seed always picks the same
+/// layouts.
+struct Rng(u32);
+
+impl Rng {
+    fn new(seed: u32) -> Rng {
+        // Xorshift never leaves zero.
+        match seed ^ 0x9e37_79b9 {
+            0 => Rng(0x9e37_79b9),
+            state => Rng(state),
+        }
+    }
+
+    fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    /// A number below `n`, or 0 if `n` is 0.
+    fn below(&mut self, n: usize) -> usize {
+        match n {
+            0 => 0,
+            n => self.next() as usize % n,
+        }
+    }
+
+    fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+}
@@ -0,0 +1,220 @@
+//! Choosing how deeply the chip sleeps.
+//!
+//! When there is nothing to do, the kernel puts the chip to sleep. Deeper
+//! sleep states save more current, but turn off more of the chip and take
+//! longer to wake up from. A `PowerManager` picks the deepest state that is
+//! safe:
+//!
+//! - Capsules and chip peripherals that need part of the chip while it sleeps,
+//!   such as a clock or a radio, each hold a `SleepVote` and vote for the
+//!   deepest state they can tolerate. The kernel never sleeps deeper than the
+//!   shallowest vote. Boards give each voter the power manager, chips usually
+//!   through a `set_power_manager()` on their default peripherals.
+//! - If the board gives the power manager the alarm that wakes the chip, the
+//!   kernel does not enter a state the chip cannot wake up from before the
+//!   alarm fires, according to `Chip::wakeup_latency_us()`.
+//!
+//! The kernel uses the power manager once the board calls
+//! `Kernel::set_power_manager()`, and enters the state it chooses with
+//! `Chip::sleep_in()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```ignore
+//! let power_manager = static_init!(
+//!     kernel::power::PowerManager,
+//!     kernel::power::PowerManager::new()
+//! );
+//! power_manager.set_wakeup_alarm(&peripherals.rtc);
+//! board_kernel.set_power_manager(power_manager, &process_management_capability);
+//!
+//! // A capsule that needs the high frequency clock while it waits for a
+//! // transfer to complete.
+//! let vote = static_init!(kernel::power::SleepVote, kernel::power::SleepVote::new());
+//! vote.set_power_manager(power_manager);
+//! vote.vote(kernel::power::SleepState::Sleep);
+//! // ...
+//! vote.release();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use crate::common::cells::OptionalCell;
+use crate::hil::time::{self, Frequency, Ticks};
+use crate::platform::Chip;
+
+/// Number of `SleepState`s.
+const NUM_SLEEP_STATES: usize = 3;
+
+/// Sleep states, from the shallowest to the deepest. What each state turns
+/// off is up to the chip, but a deeper state must not take less time to wake
+/// up from than a shallower one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SleepState {
+    /// Only the CPU stops. This is what `Chip::sleep()` does.
+    Idle = 0,
+    /// The CPU and the clocks that no active peripheral needs stop.
+    Sleep = 1,
+    /// As much of the chip stops as can while still waking up on an
+    /// interrupt, including the fast clocks.
+    DeepSleep = 2,
+}
+
+impl SleepState {
+    /// The next shallower state, or `Idle`.
+    fn shallower(self) -> SleepState {
+        match self {
+            SleepState::Idle | SleepState::Sleep => SleepState::Idle,
+            SleepState::DeepSleep => SleepState::Sleep,
+        }
+    }
+}
+
+/// Something that knows when the chip next has to wake up.
+///
+/// This is implemented for every `hil::time::Alarm`, so boards can pass the
+/// alarm their virtual alarms are multiplexed on.
+pub trait WakeupDeadline {
+    /// Microseconds until the chip has to be awake, or `None` if it can
+    /// sleep for as long as it wants.
+    fn us_until_wakeup(&self) -> Option<u32>;
+}
+
+impl<'a, A: time::Alarm<'a>> WakeupDeadline for A {
+    fn us_until_wakeup(&self) -> Option<u32> {
+        if !self.is_armed() {
+            return None;
+        }
+        let ticks = self.get_alarm().wrapping_sub(self.now()).into_u32() as u64;
+        let us = ticks * 1_000_000 / A::Frequency::frequency() as u64;
+        Some(cmp::min(us, u32::MAX as u64) as u32)
+    }
+}
+
+/// Chooses the sleep state the kernel puts the chip in.
+pub struct PowerManager {
+    /// How many `SleepVote`s currently vote for each state.
+    votes: [Cell<usize>; NUM_SLEEP_STATES],
+    wakeup_alarm: OptionalCell<&'static dyn WakeupDeadline>,
+    /// How many times the chip entered each state.
+    entered: [Cell<u32>; NUM_SLEEP_STATES],
+}
+
+impl PowerManager {
+    pub const fn new() -> PowerManager {
+        PowerManager {
+            votes: [Cell::new(0), Cell::new(0), Cell::new(0)],
+            wakeup_alarm: OptionalCell::empty(),
+            entered: [Cell::new(0), Cell::new(0), Cell::new(0)],
+        }
+    }
+
+    /// Do not enter sleep states the chip cannot wake up from before
+    /// `alarm` fires.
+    pub fn set_wakeup_alarm(&self, alarm: &'static dyn WakeupDeadline) {
+        self.wakeup_alarm.set(alarm);
+    }
+
+    /// The deepest state all votes allow.
+    pub fn deepest_allowed(&self) -> SleepState {
+        [SleepState::Idle, SleepState::Sleep]
+            .iter()
+            .copied()
+            .find(|state| self.votes[*state as usize].get() > 0)
+            .unwrap_or(SleepState::DeepSleep)
+    }
+
+    /// How many times the chip entered `state`.
+    pub fn times_entered(&self, state: SleepState) -> u32 {
+        self.entered[state as usize].get()
+    }
+
+    /// Choose the deepest state the votes allow and the chip can wake up
+    /// from in time.
+    pub fn choose<C: Chip>(&self, chip: &C) -> SleepState {
+        let mut state = self.deepest_allowed();
+        if let Some(us) = self
+            .wakeup_alarm
+            .map_or(None, |alarm| alarm.us_until_wakeup())
+        {
+            while state > SleepState::Idle && chip.wakeup_latency_us(state) >= us {
+                state = state.shallower();
+            }
+        }
+        state
+    }
+
+    /// Put the chip to sleep in the state `choose()` returns.
+    pub(crate) fn sleep<C: Chip>(&self, chip: &C) {
+        let state = self.choose(chip);
+        let entered = &self.entered[state as usize];
+        entered.set(entered.get().wrapping_add(1));
+        chip.sleep_in(state);
+    }
+
+    fn add_vote(&self, state: SleepState) {
+        let votes = &self.votes[state as usize];
+        votes.set(votes.get() + 1);
+    }
+
+    fn remove_vote(&self, state: SleepState) {
+        let votes = &self.votes[state as usize];
+        votes.set(votes.get() - 1);
+    }
+}
+
+/// One voter's limit on how deeply the chip may sleep.
+///
+/// A new vote allows every state. Voters vote for a shallower state while
+/// they need part of the chip, and release the vote when they are done.
+/// Votes only count once the board gives the voter its power manager, so
+/// chip peripherals can hold a `SleepVote` from the start and vote whether or
+/// not the board uses a power manager.
+pub struct SleepVote<'a> {
+    manager: OptionalCell<&'a PowerManager>,
+    vote: Cell<SleepState>,
+}
+
+impl<'a> SleepVote<'a> {
+    pub const fn new() -> SleepVote<'a> {
+        SleepVote {
+            manager: OptionalCell::empty(),
+            vote: Cell::new(SleepState::DeepSleep),
+        }
+    }
+
+    /// Count this vote, including the one already cast, in `manager`.
+    pub fn set_power_manager(&self, manager: &'a PowerManager) {
+        let vote = self.vote.get();
+        if vote != SleepState::DeepSleep {
+            self.manager.map(|previous| previous.remove_vote(vote));
+            manager.add_vote(vote);
+        }
+        self.manager.set(manager);
+    }
+
+    /// Do not let the chip sleep deeper than `deepest`, until the next vote.
+    pub fn vote(&self, deepest: SleepState) {
+        let previous = self.vote.replace(deepest);
+        self.manager.map(|manager| {
+            if previous != SleepState::DeepSleep {
+                manager.remove_vote(previous);
+            }
+            if deepest != SleepState::DeepSleep {
+                manager.add_vote(deepest);
+            }
+        });
+    }
+
+    /// Let the chip sleep as deeply as it can.
+    pub fn release(&self) {
+        self.vote(SleepState::DeepSleep);
+    }
+
+    /// The state this voter currently allows at most.
+    pub fn current(&self) -> SleepState {
+        self.vote.get()
+    }
+}
@@ -9,10 +9,11 @@ use core::str;
 use crate::capabilities;
 use crate::errorcode::ErrorCode;
 use crate::ipc;
-use crate::mem::{ReadOnlyAppSlice, ReadWriteAppSlice};
+use crate::mem::{AllowId, PinnedBuffer, ReadOnlyAppSlice, ReadWriteAppSlice};
 use crate::platform::mpu::{self};
-use crate::process_policies::ProcessRestartPolicy;
+use crate::process_policies::{ProcessQuota, ProcessRestartPolicy, QuotaClock};
 use crate::sched::Kernel;
+use crate::storage_permissions::StoragePermissions;
 use crate::syscall::{self, Syscall, SyscallReturn};
 use crate::upcall::UpcallId;
 
@@ -159,6 +160,50 @@ impl ProcessId {
             (start, end)
         })
     }
+
+    /// Returns the persistent state parameters the app declared in its TBF
+    /// header: how much state it keeps across restarts, and the slot of the
+    /// board's checkpoint storage it saves the state to.
+    pub fn get_persistent_ram(&self) -> Option<tock_tbf::types::TbfHeaderV2PersistentRam> {
+        self.kernel
+            .process_map_or(None, *self, |process| process.get_persistent_ram())
+    }
+
+    /// Returns which values in persistent storage the app may read and
+    /// modify, as declared in its TBF header.
+    pub fn get_storage_permissions(&self) -> StoragePermissions {
+        self.kernel.process_map_or(
+            StoragePermissions::new_null_permissions(),
+            *self,
+            |process| StoragePermissions::from_tbf(process.get_storage_permissions()),
+        )
+    }
+
+    /// Copy the last checkpoint of the app's persistent state into `buf`. See
+    /// `Process::read_persistent_state()`.
+    pub fn read_persistent_state(
+        &self,
+        buf: &mut [u8],
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) -> Result<usize, ErrorCode> {
+        self.kernel
+            .process_map_or(Err(ErrorCode::FAIL), *self, |process| {
+                process.read_persistent_state(buf)
+            })
+    }
+
+    /// Replace the checkpoint of the app's persistent state. See
+    /// `Process::write_persistent_state()`.
+    pub fn write_persistent_state(
+        &self,
+        data: &[u8],
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) -> Result<(), ErrorCode> {
+        self.kernel
+            .process_map_or(Err(ErrorCode::FAIL), *self, |process| {
+                process.write_persistent_state(data)
+            })
+    }
 }
 
 /// This trait represents a generic process that the Tock scheduler can
@@ -179,6 +224,16 @@ pub trait Process {
     /// execute any new tasks.
     fn enqueue_task(&self, task: Task) -> bool;
 
+    /// Queue a `Task` like `enqueue_task()`, but if a function call from the
+    /// same upcall is already waiting, give it the arguments of `task` rather
+    /// than queueing another one. Drivers use this for upcalls where only the
+    /// latest one matters, so that they do not fill the queue.
+    fn enqueue_coalesced_task(&self, task: Task) -> bool;
+
+    /// Returns how many `Task`s may wait for the process at once. Tasks
+    /// queued while that many wait are dropped.
+    fn upcall_queue_depth(&self) -> usize;
+
     /// Returns whether this process is ready to execute.
     fn ready(&self) -> bool;
 
@@ -229,6 +284,49 @@ pub trait Process {
     /// Get the name of the process. Used for IPC.
     fn get_process_name(&self) -> &'static str;
 
+    /// Get the system calls this process declared it needs in its TBF
+    /// header. `None` means the process did not restrict itself.
+    fn get_syscall_permissions(&self) -> Option<tock_tbf::types::TbfHeaderV2Permissions>;
+
+    /// Get the real-time parameters this process declared in its TBF header,
+    /// if any. Real-time schedulers use these.
+    fn get_scheduling_parameters(&self) -> Option<tock_tbf::types::TbfHeaderV2Scheduling>;
+
+    /// Get the IPC services this process declared in its TBF header, and the
+    /// processes that may use them. `None` means the process provides one
+    /// service, named after its package, that any process may use.
+    fn get_ipc_services(&self) -> Option<tock_tbf::types::TbfHeaderV2IpcServices>;
+
+    /// Get the longest time, in milliseconds, this process may go without
+    /// checking in with the heartbeat driver, if its TBF header sets one.
+    fn get_heartbeat_interval_ms(&self) -> Option<u32>;
+
+    /// Get which stored values this process may access, as declared in its
+    /// TBF header.
+    fn get_storage_permissions(&self) -> Option<tock_tbf::types::TbfHeaderV2StoragePermissions>;
+
+    /// Get the resource limits of this process. Limits the process declared
+    /// in its TBF header take precedence over the board's default limits.
+    fn get_quota(&self) -> ProcessQuota;
+
+    /// Returns how much of its CPU budget, in microseconds, the process has
+    /// left in the current period, or `None` if the process has no CPU
+    /// budget. `clock` is the kernel's `QuotaClock`; if the current period has
+    /// ended this starts a new one.
+    fn cpu_budget_remaining_us(&self, clock: &dyn QuotaClock) -> Option<u32>;
+
+    /// Charge `used_us` microseconds of CPU time to the current CPU budget
+    /// period of the process.
+    fn consume_cpu_budget_us(&self, used_us: u32);
+
+    /// Fault the process if it went over its grant memory quota since this
+    /// was last called.
+    ///
+    /// Going over the quota only makes the grant allocation fail, as
+    /// faulting the process would reset grants the allocating capsule may
+    /// still have entered. The kernel calls this once no capsule is running.
+    fn fault_if_grant_quota_exceeded(&self);
+
     /// Stop and clear a process's state, putting it into the `Terminated`
     /// state.
     ///
@@ -312,6 +410,44 @@ pub trait Process {
     /// Also optional.
     fn update_heap_start_pointer(&self, heap_pointer: *const u8);
 
+    /// Get the persistent state parameters the process declared in its TBF
+    /// header, if any.
+    fn get_persistent_ram(&self) -> Option<tock_tbf::types::TbfHeaderV2PersistentRam>;
+
+    /// The number of bytes of state the process keeps across restarts. This
+    /// is `0` if the process does not keep any state.
+    fn persistent_state_len(&self) -> usize;
+
+    /// Mark the region of process memory starting at `region` as holding the
+    /// process's persistent state, and copy the last checkpoint of the state
+    /// into it. Returns whether there was a checkpoint to copy.
+    ///
+    /// Fails with `NOSUPPORT` if the process does not keep any state, and with
+    /// `INVAL` if the region is not in process-accessible memory.
+    fn register_persistent_region(&self, region: *mut u8) -> Result<bool, ErrorCode>;
+
+    /// Checkpoint the process's persistent region, so that its contents are
+    /// kept if the process restarts. The kernel also does this when
+    /// restarting the process.
+    ///
+    /// Fails with `NOSUPPORT` if the process does not keep any state, and with
+    /// `RESERVE` if the process has not registered a persistent region.
+    fn checkpoint_persistent_region(&self) -> Result<(), ErrorCode>;
+
+    /// Copy the last checkpoint of the process's persistent state into `buf`,
+    /// returning the number of bytes copied.
+    ///
+    /// Fails with `NOSUPPORT` if the process does not keep any state, and with
+    /// `RESERVE` if there is no checkpoint.
+    fn read_persistent_state(&self, buf: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Replace the checkpoint of the process's persistent state with `data`.
+    /// The process gets it when it next registers its persistent region.
+    ///
+    /// Fails with `NOSUPPORT` if the process does not keep any state, and with
+    /// `SIZE` if `data` is longer than the state.
+    fn write_persistent_state(&self, data: &[u8]) -> Result<(), ErrorCode>;
+
     // additional memop like functions
 
     /// Creates a `ReadWriteAppSlice` from the given offset and size
@@ -358,6 +494,30 @@ pub trait Process {
         size: usize,
     ) -> Result<ReadOnlyAppSlice, ErrorCode>;
 
+    /// Pin a read-write buffer the process has allowed, so that hardware can
+    /// access it directly. See [`PinnedAppSlice`](crate::PinnedAppSlice).
+    ///
+    /// ## Returns
+    ///
+    /// - if the process is not active: [`ErrorCode::FAIL`]
+    /// - if the buffer conflicts with a pinned buffer: [`ErrorCode::BUSY`]
+    /// - if [`MAX_PINNED_BUFFERS`](crate::MAX_PINNED_BUFFERS) buffers are
+    ///   already pinned: [`ErrorCode::NOMEM`]
+    fn pin_buffer(&self, buffer: PinnedBuffer) -> Result<(), ErrorCode>;
+
+    /// Unpin the buffer shared with `allow_id`. If it was the last pinned
+    /// buffer and the process was due to restart, it restarts now.
+    fn unpin_buffer(&self, allow_id: AllowId);
+
+    /// Whether allowing `len` bytes at `ptr` to `allow_id`, or accessing
+    /// such an allowed buffer, conflicts with a pinned buffer. Read-only
+    /// allows have no `allow_id`.
+    fn pinned_buffer_conflict(&self, allow_id: Option<AllowId>, ptr: *const u8, len: usize)
+        -> bool;
+
+    /// How many buffers the process has pinned.
+    fn pinned_buffer_count(&self) -> usize;
+
     /// Set a single byte within the process address space at
     /// `addr` to `value`. Return true if `addr` is within the RAM
     /// bounds currently exposed to the process (thereby writable
@@ -430,8 +590,12 @@ pub trait Process {
     /// If successful, return a Some() with an identifier that can be used with
     /// `enter_custom_grant()` to get access to the memory and the pointer to
     /// the memory which must be used to initialize the memory.
+    ///
+    /// The custom grant belongs to the grant `grant_num`, and is freed with
+    /// it by `free_grant()`.
     fn allocate_custom_grant(
         &self,
+        grant_num: usize,
         size: usize,
         align: usize,
     ) -> Option<(ProcessCustomGrantIdentifer, NonNull<u8>)>;
@@ -466,6 +630,27 @@ pub trait Process {
     /// will do nothing.
     fn leave_grant(&self, grant_num: usize);
 
+    /// Free the memory of the grant `grant_num` and of the custom grants that
+    /// belong to it.
+    ///
+    /// The caller must have entered the grant with `enter_grant()` and
+    /// dropped the object stored in it. After this the grant is no longer
+    /// allocated, and its custom grants can no longer be entered.
+    ///
+    /// Free memory at the kernel memory break is given back to the process.
+    /// Other grants may be moved within the grant region to make more of it
+    /// free, so grant pointers must not be kept while a grant is not entered.
+    ///
+    /// If the process is inactive this function does nothing.
+    fn free_grant(&self, grant_num: usize);
+
+    /// Return the number of bytes of the grant region used by the grant
+    /// `grant_num` and the custom grants that belong to it, if the process is
+    /// active.
+    ///
+    /// Useful for debugging/inspecting the system.
+    fn grant_allocated_bytes(&self, grant_num: usize) -> Option<usize>;
+
     /// Return the count of the number of allocated grant pointers if the
     /// process is active. This does not count custom grants.
     ///
@@ -527,9 +712,44 @@ pub trait Process {
     /// Increment the number of times the process has exceeded its timeslice.
     fn debug_timeslice_expired(&self);
 
+    /// Returns how many ticks of the kernel's profile counter this process
+    /// has run for.
+    fn debug_cpu_ticks(&self) -> u64;
+
+    /// Add `ticks` of the kernel's profile counter to the time this process
+    /// has run for.
+    fn debug_ran(&self, ticks: u32);
+
     /// Increment the number of times the process called a syscall and record
     /// the last syscall that was called.
     fn debug_syscall_called(&self, last_syscall: Syscall);
+
+    /// Returns how many syscalls of this process the platform's syscall
+    /// filter rejected.
+    fn debug_filtered_syscall_count(&self) -> usize;
+
+    /// Returns the most recent syscall the platform's syscall filter
+    /// rejected.
+    fn debug_last_filtered_syscall(&self) -> Option<Syscall>;
+
+    /// Increment the number of rejected syscalls and record the syscall that
+    /// was rejected.
+    fn debug_syscall_filtered(&self, filtered_syscall: Syscall);
+
+    /// Returns how many jobs of this process did not finish by their
+    /// deadline.
+    fn debug_deadline_miss_count(&self) -> usize;
+
+    /// Increment the number of jobs of this process that did not finish by
+    /// their deadline.
+    fn debug_deadline_missed(&self);
+
+    /// Returns how many times this process has exceeded one of its quotas.
+    fn debug_quota_exceeded_count(&self) -> usize;
+
+    /// Increment the number of times the process has exceeded one of its
+    /// quotas.
+    fn debug_quota_exceeded(&self);
 }
 
 /// Opaque identifier for custom grants allocated dynamically from a process's
@@ -548,6 +768,9 @@ pub trait Process {
 #[derive(Copy, Clone)]
 pub struct ProcessCustomGrantIdentifer {
     pub(crate) offset: usize,
+    /// Tells this custom grant apart from one allocated at the same address
+    /// after it was freed.
+    pub(crate) serial: u32,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -0,0 +1,336 @@
+//! Policies for deciding whether the kernel runs an app based on its
+//! credentials.
+//!
+//! Apps can carry credentials, such as a hash or a MAC of the app, in TBF
+//! footers after the app binary. Before creating a process for an app, the
+//! process loading functions pass each of its credentials to an
+//! `AppCredentialsPolicy`. The first credentials the policy accepts let the
+//! app run, and the first credentials the policy rejects stop it from running.
+//! If the policy neither accepts nor rejects any credentials, the app runs
+//! only if the policy does not require credentials.
+//!
+//! Policies that check a hash or a MAC compute it with a
+//! `hil::digest::Digest`, so boards can use a hardware digest engine, or
+//! `capsules::sha256::Sha256Software` if they have none. Processes are
+//! loaded synchronously, so while a policy is checking credentials the
+//! loading functions service interrupts and deferred calls until it decides.
+//!
+//! Usage
+//! -----
+//!
+//! ```ignore
+//! let policy = static_init!(
+//!     kernel::procs::RequireHmacSha256Credentials<'static, Sha256Software<'static>>,
+//!     kernel::procs::RequireHmacSha256Credentials::new(
+//!         sha,
+//!         &mut CREDENTIALS_BUFFER,
+//!         &mut CREDENTIALS_DIGEST,
+//!         KEY
+//!     )
+//! );
+//! sha.set_client(policy);
+//! ```
+
+use core::cell::Cell;
+
+use tock_tbf::types::{TbfFooterV2Credentials, TbfFooterV2CredentialsType};
+
+use crate::common::cells::{OptionalCell, TakeCell};
+use crate::common::leasable_buffer::LeasableBuffer;
+use crate::hil::digest::{self, Digest};
+use crate::ErrorCode;
+
+/// The decision of a policy about one set of credentials.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckResult {
+    /// The credentials are valid, run the app.
+    Accept,
+    /// The policy does not check these credentials. Try the next ones.
+    Pass,
+    /// The credentials are invalid, do not run the app.
+    Reject,
+}
+
+/// Generic trait for implementing app credentials policies.
+///
+/// This policy allows a board to specify which apps the kernel should run.
+pub trait AppCredentialsPolicy {
+    /// Whether an app must have credentials the policy accepts to run.
+    fn require_credentials(&self) -> bool;
+
+    /// Check `credentials` for the app in `binary`. `binary` covers the app
+    /// from the start of its TBF header to the end of the binary, which is
+    /// the part of the app its credentials cover.
+    ///
+    /// Returns the decision if the policy can make it right away. Otherwise
+    /// the policy starts checking the credentials, for example by computing
+    /// a digest, returns `None`, and reports its decision through
+    /// `check_result()`. The kernel treats a check that does not finish in
+    /// time as `Reject`.
+    fn check_credentials(
+        &self,
+        credentials: &TbfFooterV2Credentials,
+        binary: &'static [u8],
+    ) -> Option<CheckResult>;
+
+    /// The decision about the credentials that `check_credentials()` last
+    /// started checking, or `None` if the policy has not decided yet.
+    fn check_result(&self) -> Option<CheckResult>;
+}
+
+/// Implementation of `AppCredentialsPolicy` that runs every app without
+/// checking any credentials. This is the policy of `load_processes()`.
+pub struct AllowAllApps {}
+
+impl AllowAllApps {
+    pub const fn new() -> AllowAllApps {
+        AllowAllApps {}
+    }
+}
+
+impl AppCredentialsPolicy for AllowAllApps {
+    fn require_credentials(&self) -> bool {
+        false
+    }
+
+    fn check_credentials(
+        &self,
+        _credentials: &TbfFooterV2Credentials,
+        _binary: &'static [u8],
+    ) -> Option<CheckResult> {
+        Some(CheckResult::Pass)
+    }
+
+    fn check_result(&self) -> Option<CheckResult> {
+        Some(CheckResult::Pass)
+    }
+}
+
+/// Compares the digest of an app binary with its credentials.
+///
+/// Digest engines only take data in RAM, so the binary is copied from flash
+/// into `buffer` one piece at a time.
+struct DigestCheck<'a, D: Digest<'a, [u8; 32]>> {
+    digest: &'a D,
+    buffer: TakeCell<'static, [u8]>,
+    output: TakeCell<'static, [u8; 32]>,
+    /// The part of the binary not yet passed to the digest engine.
+    binary: Cell<&'static [u8]>,
+    /// The credentials the digest must match.
+    expected: Cell<&'static [u8]>,
+    result: OptionalCell<CheckResult>,
+}
+
+impl<'a, D: Digest<'a, [u8; 32]>> DigestCheck<'a, D> {
+    fn new(
+        digest: &'a D,
+        buffer: &'static mut [u8],
+        output: &'static mut [u8; 32],
+    ) -> DigestCheck<'a, D> {
+        DigestCheck {
+            digest,
+            buffer: TakeCell::new(buffer),
+            output: TakeCell::new(output),
+            binary: Cell::new(&[]),
+            expected: Cell::new(&[]),
+            result: OptionalCell::empty(),
+        }
+    }
+
+    /// Start computing the digest of `binary`, once the digest engine is in
+    /// the right mode.
+    fn start(&self, mode: Result<(), ErrorCode>, binary: &'static [u8], expected: &'static [u8]) {
+        self.binary.set(binary);
+        self.expected.set(expected);
+        self.result.clear();
+        match mode {
+            Ok(()) => self.add_next_data(),
+            Err(_) => self.result.set(CheckResult::Reject),
+        }
+    }
+
+    /// Pass the next piece of the binary to the digest engine, or compute the
+    /// digest once all of it was passed.
+    fn add_next_data(&self) {
+        let binary = self.binary.get();
+        if binary.is_empty() {
+            if let Some(output) = self.output.take() {
+                if let Err((_, output)) = self.digest.run(output) {
+                    self.output.replace(output);
+                    self.result.set(CheckResult::Reject);
+                }
+            }
+            return;
+        }
+
+        if let Some(buffer) = self.buffer.take() {
+            let len = core::cmp::min(buffer.len(), binary.len());
+            buffer[..len].copy_from_slice(&binary[..len]);
+            let mut data = LeasableBuffer::new(buffer);
+            data.slice(..len);
+            if let Err((_, buffer)) = self.digest.add_data(data) {
+                self.buffer.replace(buffer);
+                self.result.set(CheckResult::Reject);
+            }
+        }
+    }
+
+    fn add_data_done(&self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        let binary = self.binary.get();
+        let len = core::cmp::min(data.len(), binary.len());
+        self.buffer.replace(data);
+        match result {
+            Ok(()) => {
+                self.binary.set(&binary[len..]);
+                self.add_next_data();
+            }
+            Err(_) => self.result.set(CheckResult::Reject),
+        }
+    }
+
+    fn hash_done(&self, result: Result<(), ErrorCode>, output: &'static mut [u8; 32]) {
+        let matches = result.is_ok() && digests_equal(&output[..], self.expected.get());
+        self.output.replace(output);
+        self.digest.clear_data();
+        self.result.set(if matches {
+            CheckResult::Accept
+        } else {
+            CheckResult::Reject
+        });
+    }
+}
+
+/// Compare two digests without returning early, so that the time taken does
+/// not reveal how many bytes match.
+fn digests_equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+/// Implementation of `AppCredentialsPolicy` that only runs apps with a
+/// SHA-256 hash of the app. This protects against corrupted apps, not against
+/// malicious ones, since anyone can compute the hash.
+///
+/// `buffer` holds the pieces of the app passed to the digest engine, and
+/// `output` the computed hash.
+pub struct RequireSha256Credentials<'a, D: Digest<'a, [u8; 32]> + digest::Sha256> {
+    check: DigestCheck<'a, D>,
+}
+
+impl<'a, D: Digest<'a, [u8; 32]> + digest::Sha256> RequireSha256Credentials<'a, D> {
+    pub fn new(
+        digest: &'a D,
+        buffer: &'static mut [u8],
+        output: &'static mut [u8; 32],
+    ) -> RequireSha256Credentials<'a, D> {
+        RequireSha256Credentials {
+            check: DigestCheck::new(digest, buffer, output),
+        }
+    }
+}
+
+impl<'a, D: Digest<'a, [u8; 32]> + digest::Sha256> AppCredentialsPolicy
+    for RequireSha256Credentials<'a, D>
+{
+    fn require_credentials(&self) -> bool {
+        true
+    }
+
+    fn check_credentials(
+        &self,
+        credentials: &TbfFooterV2Credentials,
+        binary: &'static [u8],
+    ) -> Option<CheckResult> {
+        match credentials.format() {
+            TbfFooterV2CredentialsType::Sha256 => {
+                let mode = self.check.digest.set_mode_sha256();
+                self.check.start(mode, binary, credentials.data());
+                None
+            }
+            _ => Some(CheckResult::Pass),
+        }
+    }
+
+    fn check_result(&self) -> Option<CheckResult> {
+        self.check.result.extract()
+    }
+}
+
+impl<'a, D: Digest<'a, [u8; 32]> + digest::Sha256> digest::Client<'a, [u8; 32]>
+    for RequireSha256Credentials<'a, D>
+{
+    fn add_data_done(&'a self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.check.add_data_done(result, data);
+    }
+
+    fn hash_done(&'a self, result: Result<(), ErrorCode>, digest: &'static mut [u8; 32]) {
+        self.check.hash_done(result, digest);
+    }
+}
+
+/// Implementation of `AppCredentialsPolicy` that only runs apps with an
+/// HMAC-SHA256 of the app computed with the board's key. Only whoever holds
+/// the key can produce apps that run.
+///
+/// `buffer` holds the pieces of the app passed to the digest engine, and
+/// `output` the computed MAC.
+pub struct RequireHmacSha256Credentials<'a, D: Digest<'a, [u8; 32]> + digest::HMACSha256> {
+    check: DigestCheck<'a, D>,
+    key: [u8; 32],
+}
+
+impl<'a, D: Digest<'a, [u8; 32]> + digest::HMACSha256> RequireHmacSha256Credentials<'a, D> {
+    pub fn new(
+        digest: &'a D,
+        buffer: &'static mut [u8],
+        output: &'static mut [u8; 32],
+        key: [u8; 32],
+    ) -> RequireHmacSha256Credentials<'a, D> {
+        RequireHmacSha256Credentials {
+            check: DigestCheck::new(digest, buffer, output),
+            key,
+        }
+    }
+}
+
+impl<'a, D: Digest<'a, [u8; 32]> + digest::HMACSha256> AppCredentialsPolicy
+    for RequireHmacSha256Credentials<'a, D>
+{
+    fn require_credentials(&self) -> bool {
+        true
+    }
+
+    fn check_credentials(
+        &self,
+        credentials: &TbfFooterV2Credentials,
+        binary: &'static [u8],
+    ) -> Option<CheckResult> {
+        match credentials.format() {
+            TbfFooterV2CredentialsType::HmacSha256 => {
+                let mode = self.check.digest.set_mode_hmacsha256(&self.key);
+                self.check.start(mode, binary, credentials.data());
+                None
+            }
+            _ => Some(CheckResult::Pass),
+        }
+    }
+
+    fn check_result(&self) -> Option<CheckResult> {
+        self.check.result.extract()
+    }
+}
+
+impl<'a, D: Digest<'a, [u8; 32]> + digest::HMACSha256> digest::Client<'a, [u8; 32]>
+    for RequireHmacSha256Credentials<'a, D>
+{
+    fn add_data_done(&'a self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.check.add_data_done(result, data);
+    }
+
+    fn hash_done(&'a self, result: Result<(), ErrorCode>, digest: &'static mut [u8; 32]) {
+        self.check.hash_done(result, digest);
+    }
+}
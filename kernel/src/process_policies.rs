@@ -2,9 +2,20 @@
 //!
 //! This file contains definitions and implementations of policies the Tock
 //! kernel can use when managing processes. For example, these policies control
-//! decisions such as whether a specific process should be restarted.
+//! decisions such as whether a specific process should be restarted, and how
+//! much CPU time and memory a process may use.
 
-use crate::process::Process;
+use core::cell::Cell;
+use core::cmp;
+
+use crate::common::cells::MapCell;
+use crate::hil::time::{self, Alarm, Frequency, Ticks, Time};
+use crate::process::{self, Process, ProcessId};
+use crate::sched::Kernel;
+
+/// Completion code of processes that restart because a process they depend on
+/// restarted.
+const COMPLETION_DEPENDENCY_RESTARTED: u32 = 0;
 
 /// Generic trait for implementing process restart policies.
 ///
@@ -15,6 +26,16 @@ pub trait ProcessRestartPolicy {
     ///
     /// Returns `true` if the process should be restarted, `false` otherwise.
     fn should_restart(&self, process: &dyn Process) -> bool;
+
+    /// Take over restarting `process`, which faulted and which
+    /// `should_restart()` decided to restart.
+    ///
+    /// Returns `true` if the policy restarts the process itself later, with
+    /// `Process::try_restart()`. The kernel then only terminates the process.
+    /// By default the kernel restarts the process right away.
+    fn schedule_restart(&self, _process: &dyn Process) -> bool {
+        false
+    }
 }
 
 /// Implementation of `ProcessRestartPolicy` that uses a threshold to decide
@@ -74,3 +95,528 @@ impl ProcessRestartPolicy for AlwaysRestart {
         true
     }
 }
+
+/// A process that has to restart whenever another process restarts, for
+/// example because it keeps state about an IPC service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestartDependency {
+    /// Package name of the process that depends on `service`.
+    pub client: &'static str,
+    /// Package name of the process `client` depends on.
+    pub service: &'static str,
+}
+
+/// How long `DependentRestart` waits before it restarts a process that
+/// faulted.
+///
+/// The first time a process faults it restarts after `initial_ms`, and every
+/// fault in a row doubles the wait, up to `max_ms`. A process that ran for at
+/// least `max_ms` before it faulted starts over at `initial_ms`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestartBackoff {
+    pub initial_ms: u32,
+    pub max_ms: u32,
+}
+
+impl RestartBackoff {
+    /// How long to wait after the process faulted `faults` times in a row.
+    pub fn delay_ms(&self, faults: u32) -> u32 {
+        let doublings = faults.saturating_sub(1);
+        let delay = if doublings < 32 {
+            (self.initial_ms as u64) << doublings
+        } else {
+            u64::MAX
+        };
+        cmp::min(delay, self.max_ms as u64) as u32
+    }
+}
+
+/// What `DependentRestart` knows about the process in one slot of the
+/// processes array.
+#[derive(Clone, Copy)]
+struct RestartState<T: Ticks> {
+    /// The process waiting to be restarted.
+    process: Option<ProcessId>,
+    /// When the process restarts, as the reference and interval of an alarm.
+    /// `None` if the process restarts with another process.
+    restart_at: Option<(T, T)>,
+    /// The slot of the process this process restarts with, right after it.
+    restart_with: Option<usize>,
+    /// Set when the process `restart_with` stopped. The process is stopped
+    /// in the next `alarm()`, if the process it depends on runs again.
+    stop: bool,
+    /// How many times in a row the process faulted.
+    faults: u32,
+    /// When the policy last restarted the process.
+    started_at: Option<T>,
+}
+
+impl<T: Ticks> RestartState<T> {
+    const IDLE: RestartState<T> = RestartState {
+        process: None,
+        restart_at: None,
+        restart_with: None,
+        stop: false,
+        faults: 0,
+        started_at: None,
+    };
+}
+
+/// Implementation of `ProcessRestartPolicy` that knows which processes depend
+/// on each other, and restarts processes that fault after a backoff.
+///
+/// Whether a process that faulted restarts at all is up to another policy,
+/// such as `ThresholdRestart`. If it does, this policy terminates it and
+/// restarts it once `alarm` fires after the `RestartBackoff`, rather than
+/// right away in the fault path.
+///
+/// Whenever a process stops, whether it faulted or was restarted or stopped
+/// by other means such as the process console, the processes that depend on
+/// it, directly or through other processes, are terminated so that they stop
+/// using stale state, and restart right after it. This happens when `alarm`
+/// fires, which the policy sets to fire right away, as a process may stop
+/// while a capsule is using the grants of its dependents. Dependents of a
+/// process that does not run again are left alone. Dependents restart no
+/// matter how they are configured to respond to faults, and their restarts
+/// count towards their restart count. Processes that faulted and were not
+/// restarted, or that exited, stay stopped.
+///
+/// Processes that use this policy must have `FaultResponse::Restart` with it.
+/// The board must make this policy the client of `alarm`, and tell the kernel
+/// about it so that it learns when processes stop:
+///
+/// ```ignore
+/// let restart_policy = static_init!(
+///     kernel::procs::DependentRestart<'static, VirtualMuxAlarm<'static, Rtc>, NUM_PROCS>,
+///     kernel::procs::DependentRestart::new(
+///         board_kernel,
+///         restart_alarm,
+///         &kernel::procs::ThresholdRestart::new(5),
+///         &[kernel::procs::RestartDependency {
+///             client: "sensor_client",
+///             service: "sensor_service",
+///         }],
+///         kernel::procs::RestartBackoff {
+///             initial_ms: 100,
+///             max_ms: 10_000,
+///         },
+///     )
+/// );
+/// restart_alarm.set_alarm_client(restart_policy);
+/// board_kernel.set_dependent_restart(restart_policy, &process_management_capability);
+/// ```
+pub struct DependentRestart<'a, A: Alarm<'a>, const NUM_PROCS: usize> {
+    kernel: &'static Kernel,
+    alarm: &'a A,
+    policy: &'static dyn ProcessRestartPolicy,
+    dependencies: &'static [RestartDependency],
+    backoff: RestartBackoff,
+    /// Indexed by the slot of the process in the processes array.
+    states: MapCell<[RestartState<A::Ticks>; NUM_PROCS]>,
+    /// Set while the policy stops and restarts processes itself.
+    restarting: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>, const NUM_PROCS: usize> DependentRestart<'a, A, NUM_PROCS> {
+    /// `policy` decides whether a process that faulted restarts.
+    pub fn new(
+        kernel: &'static Kernel,
+        alarm: &'a A,
+        policy: &'static dyn ProcessRestartPolicy,
+        dependencies: &'static [RestartDependency],
+        backoff: RestartBackoff,
+    ) -> DependentRestart<'a, A, NUM_PROCS> {
+        DependentRestart {
+            kernel,
+            alarm,
+            policy,
+            dependencies,
+            backoff,
+            states: MapCell::new([RestartState::IDLE; NUM_PROCS]),
+            restarting: Cell::new(false),
+        }
+    }
+
+    /// Whether `process` waits for the policy to restart it.
+    pub fn restart_pending(&self, process: &dyn Process) -> bool {
+        let processid = process.processid();
+        self.states.map_or(false, |states| {
+            states.get(processid.index).map_or(false, |state| {
+                state.process.map_or(false, |waiting| waiting == processid)
+            })
+        })
+    }
+
+    /// The slots of the processes that depend on the process in slot
+    /// `index`, directly or through other processes.
+    fn find_dependents(&self, index: usize) -> [bool; NUM_PROCS] {
+        let mut names = [None; NUM_PROCS];
+        for (slot, name) in names.iter_mut().enumerate() {
+            *name = self
+                .kernel
+                .process_at(slot)
+                .map(|process| process.get_process_name());
+        }
+
+        let mut reached = [false; NUM_PROCS];
+        reached[index] = true;
+        // Follow the dependencies until no new process is reached. This ends
+        // even if the dependencies form a cycle.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for dependency in self.dependencies {
+                let service_reached = (0..NUM_PROCS)
+                    .any(|slot| reached[slot] && names[slot] == Some(dependency.service));
+                if !service_reached {
+                    continue;
+                }
+                for slot in 0..NUM_PROCS {
+                    if !reached[slot] && names[slot] == Some(dependency.client) {
+                        reached[slot] = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        reached[index] = false;
+        reached
+    }
+
+    /// Restart the process waiting in `slot`. `started_at` is when the
+    /// backoff of the process starts over, if it restarted after a fault.
+    fn restart(&self, slot: usize, started_at: Option<A::Ticks>) {
+        let waiting = self.states.map_or(None, |states| {
+            let state = &mut states[slot];
+            state.restart_with = None;
+            if started_at.is_some() {
+                state.started_at = started_at;
+            }
+            state.process.take()
+        });
+        if let Some(processid) = waiting {
+            // The process is already terminated, so the completion code is
+            // not used.
+            self.kernel.process_map_or((), processid, |process| {
+                process.try_restart(COMPLETION_DEPENDENCY_RESTARTED)
+            });
+        }
+    }
+
+    /// Stop the process in `slot`, which depends on a process that stopped,
+    /// if the process it depends on runs again. It then restarts with it.
+    fn stop_dependent(&self, slot: usize) {
+        let service = match self.states.map_or(None, |states| states[slot].restart_with) {
+            Some(service) => service,
+            None => return,
+        };
+        let service_runs = self.kernel.process_at(service).map_or(false, |service| {
+            is_active(service) || self.restart_pending(service)
+        });
+        let dependent = match self.kernel.process_at(slot) {
+            Some(dependent) if service_runs => dependent,
+            _ => {
+                self.states.map(|states| states[slot].restart_with = None);
+                return;
+            }
+        };
+
+        let waiting = self.restart_pending(dependent);
+        let active = is_active(dependent);
+        if !waiting && !active {
+            self.states.map(|states| states[slot].restart_with = None);
+            return;
+        }
+        if active {
+            dependent.terminate(COMPLETION_DEPENDENCY_RESTARTED);
+        }
+        let dependent_id = dependent.processid();
+        self.states.map(|states| {
+            let state = &mut states[slot];
+            state.process = Some(dependent_id);
+            state.restart_at = None;
+        });
+    }
+
+    /// Set the alarm for the next restart, or disarm it if no process waits.
+    /// Processes that have to be stopped are stopped right away.
+    fn arm(&self) {
+        let now = self.alarm.now();
+        let next = self.states.map_or(None, |states| {
+            states
+                .iter()
+                .filter_map(|state| {
+                    if state.stop {
+                        Some((now, A::Ticks::from(0)))
+                    } else {
+                        state.restart_at
+                    }
+                })
+                .map(|(reference, dt)| {
+                    let elapsed = now.wrapping_sub(reference);
+                    if elapsed >= dt {
+                        A::Ticks::from(0)
+                    } else {
+                        dt.wrapping_sub(elapsed)
+                    }
+                })
+                .min()
+        });
+        match next {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, const NUM_PROCS: usize> ProcessRestartPolicy
+    for DependentRestart<'a, A, NUM_PROCS>
+{
+    fn should_restart(&self, process: &dyn Process) -> bool {
+        self.policy.should_restart(process)
+    }
+
+    fn schedule_restart(&self, process: &dyn Process) -> bool {
+        let processid = process.processid();
+        let index = processid.index;
+        if index >= NUM_PROCS {
+            return false;
+        }
+        let now = self.alarm.now();
+        let max = A::ticks_from_ms(self.backoff.max_ms);
+        self.states.map(|states| {
+            let state = &mut states[index];
+            if state
+                .started_at
+                .map_or(false, |started| now.wrapping_sub(started) >= max)
+            {
+                state.faults = 0;
+            }
+            state.faults = state.faults.saturating_add(1);
+            state.process = Some(processid);
+            state.restart_at = Some((now, A::ticks_from_ms(self.backoff.delay_ms(state.faults))));
+            state.restart_with = None;
+        });
+
+        self.arm();
+        true
+    }
+}
+
+impl<'a, A: Alarm<'a>, const NUM_PROCS: usize> time::AlarmClient
+    for DependentRestart<'a, A, NUM_PROCS>
+{
+    fn alarm(&self) {
+        self.restarting.set(true);
+        let now = self.alarm.now();
+
+        // Stop the processes that depend on processes that stopped. Those
+        // whose service already runs again restart with it right away.
+        let mut stop = [false; NUM_PROCS];
+        self.states.map(|states| {
+            for (slot, state) in states.iter_mut().enumerate() {
+                stop[slot] = state.stop;
+                state.stop = false;
+            }
+        });
+        for (slot, _) in stop.iter().enumerate().filter(|(_, stop)| **stop) {
+            self.stop_dependent(slot);
+        }
+        for (slot, _) in stop.iter().enumerate().filter(|(_, stop)| **stop) {
+            let service_active = self
+                .states
+                .map_or(None, |states| states[slot].restart_with)
+                .and_then(|service| self.kernel.process_at(service))
+                .map_or(false, |service| is_active(service));
+            if service_active {
+                self.restart(slot, None);
+            }
+        }
+
+        // Restart the processes whose backoff ended, then the processes that
+        // depend on them.
+        let mut due = [false; NUM_PROCS];
+        self.states.map(|states| {
+            for (slot, state) in states.iter_mut().enumerate() {
+                if let Some((reference, dt)) = state.restart_at {
+                    if now.wrapping_sub(reference) >= dt {
+                        state.restart_at = None;
+                        due[slot] = true;
+                    }
+                }
+            }
+        });
+        for (slot, _) in due.iter().enumerate().filter(|(_, due)| **due) {
+            // The process that faulted first, so that its dependents find it
+            // running.
+            self.restart(slot, Some(now));
+            for other in 0..NUM_PROCS {
+                let dependent = self
+                    .states
+                    .map_or(false, |states| states[other].restart_with == Some(slot));
+                if dependent {
+                    self.restart(other, None);
+                }
+            }
+        }
+        self.arm();
+        self.restarting.set(false);
+    }
+}
+
+impl<'a, A: Alarm<'a>, const NUM_PROCS: usize> DependentProcesses
+    for DependentRestart<'a, A, NUM_PROCS>
+{
+    fn process_terminated(&self, processid: ProcessId) {
+        let index = processid.index;
+        if self.restarting.get() || index >= NUM_PROCS {
+            return;
+        }
+        let dependents = self.find_dependents(index);
+        let mut any = false;
+        self.states.map(|states| {
+            for (slot, state) in states.iter_mut().enumerate() {
+                if dependents[slot] {
+                    state.restart_with = Some(index);
+                    state.stop = true;
+                    any = true;
+                }
+            }
+        });
+        if any {
+            self.arm();
+        }
+    }
+}
+
+/// Lets the kernel tell `DependentRestart` that a process stopped.
+pub(crate) trait DependentProcesses {
+    fn process_terminated(&self, processid: ProcessId);
+}
+
+/// Whether `process` runs, or will run once it is scheduled.
+fn is_active(process: &dyn Process) -> bool {
+    matches!(
+        process.get_state(),
+        process::State::Unstarted
+            | process::State::Running
+            | process::State::Yielded
+            | process::State::StoppedRunning
+            | process::State::StoppedYielded
+    )
+}
+
+/// CPU time a process may use in each period of time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuBudget {
+    /// CPU time, in microseconds, the process may use in each period. This
+    /// includes time the kernel spends handling the process's system calls.
+    pub budget_us: u32,
+    /// Length of a period, in microseconds.
+    pub period_us: u32,
+}
+
+/// Limits on the resources a process may use.
+///
+/// The kernel faults a process that exceeds one of its limits, so the
+/// process's `FaultResponse` (and with it the board's
+/// `ProcessRestartPolicy`) decides what happens to it.
+///
+/// A process can declare its own limits in the Quotas TLV of its TBF header.
+/// Limits a process does not declare fall back to the board default, see
+/// `Kernel::set_process_quotas()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProcessQuota {
+    /// CPU time limit. Only enforced if the board provided a `QuotaClock`.
+    pub cpu_budget: Option<CpuBudget>,
+    /// Maximum number of bytes the kernel may allocate for grants in the
+    /// process's memory, not counting the memory every process needs for
+    /// kernel bookkeeping.
+    pub grant_memory_limit: Option<usize>,
+    /// Number of upcalls that may wait for the process at once. Processes
+    /// get `DEFAULT_UPCALL_QUEUE_DEPTH` if neither they nor the board set one.
+    pub upcall_queue_depth: Option<usize>,
+}
+
+impl ProcessQuota {
+    /// No limits.
+    pub const fn unlimited() -> ProcessQuota {
+        ProcessQuota {
+            cpu_budget: None,
+            grant_memory_limit: None,
+            upcall_queue_depth: None,
+        }
+    }
+
+    pub const fn with_cpu_budget(mut self, budget_us: u32, period_us: u32) -> ProcessQuota {
+        self.cpu_budget = Some(CpuBudget {
+            budget_us,
+            period_us,
+        });
+        self
+    }
+
+    pub const fn with_grant_memory_limit(mut self, limit: usize) -> ProcessQuota {
+        self.grant_memory_limit = Some(limit);
+        self
+    }
+
+    pub const fn with_upcall_queue_depth(mut self, depth: usize) -> ProcessQuota {
+        self.upcall_queue_depth = Some(depth);
+        self
+    }
+}
+
+/// Number of upcalls that may wait for a process that neither it nor the
+/// board sets a queue depth for.
+pub const DEFAULT_UPCALL_QUEUE_DEPTH: usize = 9;
+
+/// Deepest upcall queue the kernel sets up for a process.
+pub const MAX_UPCALL_QUEUE_DEPTH: usize = 255;
+
+/// Time source the kernel uses to measure CPU time and CPU budget periods, and
+/// to timestamp trace records.
+///
+/// Points in time are raw counter values. Only the ticks between two of them
+/// are converted to microseconds, so a measurement is right across a wrap of
+/// the counter as long as it spans less than one full wrap.
+///
+/// This is implemented for every `hil::time::Time`, so boards can pass an
+/// alarm or counter they already have.
+pub trait QuotaClock {
+    /// The current value of the counter.
+    fn now_ticks(&self) -> u32;
+
+    /// Ticks from counter value `start` to counter value `end`, wrapping in the
+    /// width of the counter.
+    fn ticks_between(&self, start: u32, end: u32) -> u32;
+
+    /// Frequency of the counter in Hz.
+    fn frequency(&self) -> u32;
+
+    /// Microseconds since the counter read `start`, saturating at `u32::MAX`.
+    fn us_since(&self, start: u32) -> u32 {
+        let ticks = self.ticks_between(start, self.now_ticks()) as u64;
+        cmp::min(ticks * 1_000_000 / self.frequency() as u64, u32::MAX as u64) as u32
+    }
+}
+
+impl<T: Time> QuotaClock for T {
+    fn now_ticks(&self) -> u32 {
+        self.now().into_u32()
+    }
+
+    fn ticks_between(&self, start: u32, end: u32) -> u32 {
+        // Subtract in the width of the counter, which may be narrower than
+        // 32 bits.
+        T::Ticks::from(end)
+            .wrapping_sub(T::Ticks::from(start))
+            .into_u32()
+    }
+
+    fn frequency(&self) -> u32 {
+        T::Frequency::frequency()
+    }
+}
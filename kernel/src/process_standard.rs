@@ -12,21 +12,111 @@ use core::{mem, ptr, slice, str};
 use crate::common::cells::{MapCell, NumericCellExt};
 use crate::common::{Queue, RingBuffer};
 use crate::config;
+use crate::crash_dump::{self, CrashDump, CrashMemoryMap};
 use crate::debug;
 use crate::errorcode::ErrorCode;
-use crate::mem::{ReadOnlyAppSlice, ReadWriteAppSlice};
+use crate::ipc;
+use crate::mem::{AllowId, PinnedBuffer, ReadOnlyAppSlice, ReadWriteAppSlice, MAX_PINNED_BUFFERS};
 use crate::platform::mpu::{self, MPU};
 use crate::platform::Chip;
 use crate::process::{Error, FunctionCall, FunctionCallSource, Process, State, Task};
 use crate::process::{FaultResponse, ProcessCustomGrantIdentifer, ProcessId, ProcessStateCell};
+use crate::process_policies::{
+    CpuBudget, ProcessQuota, QuotaClock, DEFAULT_UPCALL_QUEUE_DEPTH, MAX_UPCALL_QUEUE_DEPTH,
+};
 use crate::process_utilities::ProcessLoadError;
 use crate::sched::Kernel;
 use crate::syscall::{self, Syscall, SyscallReturn, UserspaceKernelBoundary};
+use crate::trace::TraceKind;
 use crate::upcall::UpcallId;
 
 // The completion code for a process if it faulted.
 const COMPLETION_FAULT: u32 = 0xffffffff;
 
+/// Header in front of every allocation in the grant region.
+///
+/// Allocations are laid out one after the other from the kernel memory break
+/// up to where the grant region started, so the headers let the kernel walk
+/// them to free grants and to move them. The object of an allocation starts
+/// right after its header, and the allocation may end with padding.
+///
+/// Every grant allocation carries a header, so all fields but the serial are
+/// packed into one word:
+///
+/// - bits 0 to 17: length of the allocation in words, including the header.
+/// - bits 18 to 24: the grant the allocation belongs to. Custom grants belong
+///   to the grant that was entered when they were allocated.
+/// - bits 25 and 26: one of `GRANT_BLOCK_FREE`, `GRANT_BLOCK_GRANT` or
+///   `GRANT_BLOCK_CUSTOM`.
+/// - bits 27 to 31: log2 of the alignment of the object, needed to move it.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GrantBlock {
+    packed: u32,
+    /// Tells custom grants allocated at the same address apart.
+    serial: u32,
+}
+
+const GRANT_BLOCK_FREE: u8 = 0;
+const GRANT_BLOCK_GRANT: u8 = 1;
+const GRANT_BLOCK_CUSTOM: u8 = 2;
+const GRANT_BLOCK_HEADER_LEN: usize = mem::size_of::<GrantBlock>();
+/// The grant region may not grow larger than an allocation can be long, so
+/// that merged free allocations always fit in a header.
+const GRANT_BLOCK_MAX_LEN: usize = 0x3ffff * 4;
+/// Grant numbers must fit in the seven bits a header has for them.
+pub(crate) const GRANT_BLOCK_MAX_GRANTS: usize = 0x80;
+
+impl GrantBlock {
+    /// `len` must be a multiple of four no larger than `GRANT_BLOCK_MAX_LEN`
+    /// and `grant_num` less than `GRANT_BLOCK_MAX_GRANTS`.
+    fn new(len: usize, grant_num: usize, kind: u8, align_log2: u32, serial: u32) -> GrantBlock {
+        GrantBlock {
+            packed: (len / 4) as u32
+                | (grant_num as u32) << 18
+                | (kind as u32) << 25
+                | align_log2 << 27,
+            serial,
+        }
+    }
+
+    fn len(&self) -> usize {
+        (self.packed & 0x3ffff) as usize * 4
+    }
+
+    fn grant_num(&self) -> usize {
+        (self.packed >> 18 & 0x7f) as usize
+    }
+
+    fn kind(&self) -> u8 {
+        (self.packed >> 25 & 0x3) as u8
+    }
+
+    fn align_log2(&self) -> u32 {
+        self.packed >> 27
+    }
+
+    fn with_len(self, len: usize) -> GrantBlock {
+        GrantBlock::new(
+            len,
+            self.grant_num(),
+            self.kind(),
+            self.align_log2(),
+            self.serial,
+        )
+    }
+
+    fn with_kind(self, kind: u8) -> GrantBlock {
+        GrantBlock::new(
+            self.len(),
+            self.grant_num(),
+            kind,
+            self.align_log2(),
+            self.serial,
+        )
+    }
+}
+
 /// State for helping with debugging apps.
 ///
 /// These pointers and counters are not strictly required for kernel operation,
@@ -53,12 +143,22 @@ struct ProcessStandardDebug {
     /// How low have we ever seen the stack pointer.
     app_stack_min_pointer: Option<*const u8>,
 
+    /// Where the stack pointer was when the process last returned to the
+    /// kernel.
+    app_stack_pointer: Option<*const u8>,
+
     /// How many syscalls have occurred since the process started.
     syscall_count: usize,
 
     /// What was the most recent syscall.
     last_syscall: Option<Syscall>,
 
+    /// How many syscalls the platform's syscall filter rejected.
+    filtered_syscall_count: usize,
+
+    /// What was the most recent syscall the filter rejected.
+    last_filtered_syscall: Option<Syscall>,
+
     /// How many upcalls were dropped because the queue was insufficiently
     /// long.
     dropped_upcall_count: usize,
@@ -66,6 +166,19 @@ struct ProcessStandardDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How many ticks of the kernel's profile counter the process has run
+    /// for, if the kernel has a profile.
+    cpu_ticks: u64,
+
+    /// How many jobs of this process a real-time scheduler saw miss their
+    /// deadline.
+    deadline_miss_count: usize,
+
+    /// How many times this process has exceeded one of its quotas. Exceeding
+    /// a quota faults the process, so unlike the other counters this one is
+    /// not reset when the process restarts.
+    quota_exceeded_count: usize,
 }
 
 /// A type for userspace processes in Tock.
@@ -91,6 +204,8 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     ///  ╔═ │ Grant Pointers
     ///  ║  │ ──────
     ///     │ Process Control Block
+    ///     │ ──────
+    ///     │ Persistent State
     ///  D  │ ──────
     ///  Y  │ Grant Regions
     ///  N  │
@@ -130,6 +245,14 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// Pointer to the end of the allocated (and MPU protected) grant region.
     kernel_memory_break: Cell<*const u8>,
 
+    /// Where `kernel_memory_break` was when the process started, before any
+    /// grants were allocated. Grant memory counted against the process's
+    /// quota is the memory between this and `kernel_memory_break`.
+    initial_kernel_memory_break: Cell<*const u8>,
+
+    /// Serial number of the next allocation in the grant region.
+    next_grant_serial: Cell<u32>,
+
     /// Pointer to the end of process RAM that has been sbrk'd to the process.
     app_break: Cell<*const u8>,
 
@@ -172,11 +295,52 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// process.
     tasks: MapCell<RingBuffer<'a, Task>>,
 
+    /// How many tasks may wait in `tasks` at once.
+    upcall_queue_depth: usize,
+
+    /// How many tasks were dropped because `tasks` was full since the process
+    /// was last told, and the upcall of the last one.
+    unreported_upcall_drops: Cell<Option<(usize, UpcallId)>>,
+
     /// Count of how many times this process has entered the fault condition and
     /// been restarted. This is used by some `ProcessRestartPolicy`s to
     /// determine if the process should be restarted or not.
     restart_count: Cell<usize>,
 
+    /// Start of the current CPU budget period, in ticks of the kernel's
+    /// `QuotaClock`, and how much CPU time in microseconds the process used
+    /// in it. `None` until the process first runs with a CPU budget.
+    cpu_budget_period: Cell<Option<(u32, u32)>>,
+
+    /// Set when the process went over its grant memory quota. The process
+    /// faults once the capsule that allocated the grant memory has returned,
+    /// see `fault_if_grant_quota_exceeded()`.
+    grant_quota_exceeded: Cell<bool>,
+
+    /// Kernel-owned copy of the state the process keeps across restarts.
+    /// This sits below the process struct in the process's memory, and is
+    /// `persistent_state_len` bytes long (zero if the process keeps no state).
+    /// Like the process struct, it is not reset when the process restarts.
+    persistent_state: *mut u8,
+
+    /// Number of bytes of state the process keeps across restarts.
+    persistent_state_len: usize,
+
+    /// Whether `persistent_state` holds a checkpoint of the process's state.
+    persistent_state_valid: Cell<bool>,
+
+    /// The region of process memory holding the process's persistent state,
+    /// once the process has registered it.
+    persistent_region: Cell<Option<*mut u8>>,
+
+    /// Buffers hardware may be accessing directly. The process cannot
+    /// restart or be unloaded while any are pinned.
+    pinned_buffers: Cell<[Option<PinnedBuffer>; MAX_PINNED_BUFFERS]>,
+
+    /// Whether the process was due to restart while it had buffers pinned,
+    /// and so restarts when the last one is unpinned.
+    restart_when_unpinned: Cell<bool>,
+
     /// Name of the app.
     process_name: &'static str,
 
@@ -204,6 +368,21 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             self.debug.map(|debug| {
                 debug.dropped_upcall_count += 1;
             });
+            let upcall_id = match task {
+                Task::FunctionCall(FunctionCall {
+                    source: FunctionCallSource::Driver(upcall_id),
+                    ..
+                }) => upcall_id,
+                // Other tasks are either the process entry point, which is
+                // never dropped, or IPC upcalls.
+                _ => UpcallId {
+                    driver_num: ipc::DRIVER_NUM,
+                    subscribe_num: 0,
+                },
+            };
+            let dropped = self.unreported_upcall_drops.get().map_or(0, |(n, _)| n);
+            self.unreported_upcall_drops
+                .set(Some((dropped + 1, upcall_id)));
         } else {
             self.kernel.increment_work();
         }
@@ -211,6 +390,37 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         ret
     }
 
+    fn enqueue_coalesced_task(&self, task: Task) -> bool {
+        let upcall_id = match task {
+            Task::FunctionCall(FunctionCall {
+                source: FunctionCallSource::Driver(upcall_id),
+                ..
+            }) => upcall_id,
+            _ => return self.enqueue_task(task),
+        };
+        if !self.is_active() {
+            return false;
+        }
+
+        let replaced = self.tasks.map_or(false, |tasks| {
+            tasks
+                .find_mut(|pending| match pending {
+                    Task::FunctionCall(FunctionCall {
+                        source: FunctionCallSource::Driver(id),
+                        ..
+                    }) => *id == upcall_id,
+                    _ => false,
+                })
+                .map(|pending| *pending = task)
+                .is_some()
+        });
+        replaced || self.enqueue_task(task)
+    }
+
+    fn upcall_queue_depth(&self) -> usize {
+        self.upcall_queue_depth
+    }
+
     fn ready(&self) -> bool {
         self.tasks.map_or(false, |ring_buf| ring_buf.has_elements())
             || self.state.get() == State::Running
@@ -235,16 +445,18 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                 },
                 _ => true,
             });
-            if config::CONFIG.trace_syscalls {
-                let count_after = tasks.len();
-                debug!(
-                    "[{:?}] remove_pending_upcalls[{:#x}:{}] = {} upcall(s) removed",
-                    self.processid(),
-                    upcall_id.driver_num,
-                    upcall_id.subscribe_num,
-                    count_before - count_after,
-                );
-            }
+            let count_after = tasks.len();
+            self.kernel.trace(
+                TraceKind::UpcallsRemoved,
+                0,
+                Some(self.processid()),
+                [
+                    upcall_id.driver_num as u32,
+                    upcall_id.subscribe_num as u32,
+                    (count_before - count_after) as u32,
+                    0,
+                ],
+            );
         });
     }
 
@@ -275,6 +487,13 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn set_fault_state(&self) {
+        self.kernel.get_crash_dump().map(|crash_dump| {
+            self.record_crash(
+                crash_dump,
+                matches!(self.fault_response, FaultResponse::Panic),
+            );
+        });
+
         match self.fault_response {
             FaultResponse::Panic => {
                 // process faulted. Panic and print status
@@ -288,7 +507,15 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                 // kernel applies its own policy for how to reuse the
                 // process: it may or may not restart the application.
                 if restart_policy.should_restart(self) {
-                    self.try_restart(COMPLETION_FAULT);
+                    if restart_policy.schedule_restart(self) {
+                        // The policy restarts the process later. Keep its
+                        // persistent state from now, as `try_restart()`
+                        // would.
+                        let _ = self.checkpoint_persistent_region();
+                        self.terminate(COMPLETION_FAULT);
+                    } else {
+                        self.try_restart(COMPLETION_FAULT);
+                    }
                 } else {
                     self.terminate(COMPLETION_FAULT);
                     self.state.update(State::Faulted);
@@ -307,6 +534,11 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn try_restart(&self, completion_code: u32) {
+        // Keep the persistent state of the process before its memory is
+        // reset. A process that has not registered a persistent region keeps
+        // its last checkpoint, if any.
+        let _ = self.checkpoint_persistent_region();
+
         // Terminate the process, freeing its state and removing any
         // pending tasks from the scheduler's queue.
         self.terminate(completion_code);
@@ -331,6 +563,9 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         self.tasks.map(|tasks| {
             tasks.empty();
         });
+        self.unreported_upcall_drops.set(None);
+        // The overrun was by the grants that are cleared now.
+        self.grant_quota_exceeded.set(false);
 
         // Clear any grant regions this app has setup with any capsules.
         unsafe {
@@ -339,6 +574,9 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
 
         // Mark the app as stopped so the scheduler won't try to run it.
         self.state.update(State::Terminated);
+
+        // End what other processes wait on this one for.
+        self.kernel.process_terminated(self.processid());
     }
 
     fn get_restart_count(&self) -> usize {
@@ -350,12 +588,21 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn dequeue_task(&self) -> Option<Task> {
-        self.tasks.map_or(None, |tasks| {
+        let task = self.tasks.map_or(None, |tasks| {
             tasks.dequeue().map(|cb| {
                 self.kernel.decrement_work();
                 cb
             })
-        })
+        });
+        // Now that there is room in the queue, tell the process about the
+        // upcalls it lost.
+        if task.is_some() {
+            if let Some((dropped, upcall_id)) = self.unreported_upcall_drops.take() {
+                self.kernel
+                    .upcalls_dropped(self.processid(), dropped, upcall_id);
+            }
+        }
+        task
     }
 
     fn mem_start(&self) -> *const u8 {
@@ -410,6 +657,95 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         }
     }
 
+    fn get_persistent_ram(&self) -> Option<tock_tbf::types::TbfHeaderV2PersistentRam> {
+        self.header.get_persistent_ram()
+    }
+
+    fn persistent_state_len(&self) -> usize {
+        self.persistent_state_len
+    }
+
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn register_persistent_region(&self, region: *mut u8) -> Result<bool, ErrorCode> {
+        if self.persistent_state_len == 0 {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        if !self.in_app_owned_memory(region, self.persistent_state_len) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.persistent_region.set(Some(region));
+
+        let restored = self.persistent_state_valid.get();
+        if restored {
+            // Safe because the region was just checked to be in process
+            // accessible memory, and the persistent state is kernel-owned
+            // memory only this process struct refers to.
+            unsafe {
+                ptr::copy_nonoverlapping(self.persistent_state, region, self.persistent_state_len);
+            }
+        }
+        Ok(restored)
+    }
+
+    fn checkpoint_persistent_region(&self) -> Result<(), ErrorCode> {
+        if self.persistent_state_len == 0 {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        let region = self.persistent_region.get().ok_or(ErrorCode::RESERVE)?;
+        // The process may have moved its break below the region since it
+        // registered it.
+        if !self.in_app_owned_memory(region, self.persistent_state_len) {
+            return Err(ErrorCode::RESERVE);
+        }
+
+        // Safe because the region was just checked to be in process
+        // accessible memory, and the persistent state is kernel-owned memory
+        // only this process struct refers to.
+        unsafe {
+            ptr::copy_nonoverlapping(region, self.persistent_state, self.persistent_state_len);
+        }
+        self.persistent_state_valid.set(true);
+        Ok(())
+    }
+
+    fn read_persistent_state(&self, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        if self.persistent_state_len == 0 {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        if !self.persistent_state_valid.get() {
+            return Err(ErrorCode::RESERVE);
+        }
+
+        let len = cmp::min(buf.len(), self.persistent_state_len);
+        // Safe because the persistent state is kernel-owned memory only this
+        // process struct refers to, and `len` is within it.
+        let state = unsafe { slice::from_raw_parts(self.persistent_state, len) };
+        buf[..len].copy_from_slice(state);
+        Ok(len)
+    }
+
+    fn write_persistent_state(&self, data: &[u8]) -> Result<(), ErrorCode> {
+        if self.persistent_state_len == 0 {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        if data.len() > self.persistent_state_len {
+            return Err(ErrorCode::SIZE);
+        }
+
+        // Safe because the persistent state is kernel-owned memory only this
+        // process struct refers to, and no reference to it outlives this
+        // function.
+        let state =
+            unsafe { slice::from_raw_parts_mut(self.persistent_state, self.persistent_state_len) };
+        let (written, rest) = state.split_at_mut(data.len());
+        written.copy_from_slice(data);
+        for byte in rest.iter_mut() {
+            *byte = 0;
+        }
+        self.persistent_state_valid.set(true);
+        Ok(())
+    }
+
     fn setup_mpu(&self) {
         self.mpu_config.map(|config| {
             self.chip.mpu().configure_mpu(&config, &self.processid());
@@ -626,6 +962,57 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         }
     }
 
+    fn pin_buffer(&self, buffer: PinnedBuffer) -> Result<(), ErrorCode> {
+        if !self.is_active() {
+            return Err(ErrorCode::FAIL);
+        }
+        if self.pinned_buffer_conflict(Some(buffer.allow_id), buffer.ptr, buffer.len) {
+            return Err(ErrorCode::BUSY);
+        }
+        let mut pinned = self.pinned_buffers.get();
+        let slot = pinned
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(ErrorCode::NOMEM)?;
+        *slot = Some(buffer);
+        self.pinned_buffers.set(pinned);
+        Ok(())
+    }
+
+    fn unpin_buffer(&self, allow_id: AllowId) {
+        let mut pinned = self.pinned_buffers.get();
+        for slot in pinned.iter_mut() {
+            if slot.map_or(false, |buffer| buffer.allow_id == allow_id) {
+                *slot = None;
+            }
+        }
+        self.pinned_buffers.set(pinned);
+
+        if self.restart_when_unpinned.get() && self.pinned_buffer_count() == 0 {
+            let _ = self.restart();
+        }
+    }
+
+    fn pinned_buffer_conflict(
+        &self,
+        allow_id: Option<AllowId>,
+        ptr: *const u8,
+        len: usize,
+    ) -> bool {
+        self.pinned_buffers
+            .get()
+            .iter()
+            .any(|slot| slot.map_or(false, |buffer| buffer.conflicts_with(allow_id, ptr, len)))
+    }
+
+    fn pinned_buffer_count(&self) -> usize {
+        self.pinned_buffers
+            .get()
+            .iter()
+            .filter(|slot| slot.is_some())
+            .count()
+    }
+
     unsafe fn set_byte(&self, addr: *mut u8, value: u8) -> bool {
         if self.in_app_owned_memory(addr, 1) {
             // We verify that this will only write process-accessible memory,
@@ -677,7 +1064,9 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
 
         // Use the shared grant allocator function to actually allocate memory.
         // Returns `None` if the allocation cannot be created.
-        if let Some(grant_ptr) = self.allocate_in_grant_region_internal(size, align) {
+        if let Some(grant_ptr) =
+            self.allocate_in_grant_region_internal(grant_num, GRANT_BLOCK_GRANT, size, align)
+        {
             // Update the grant pointer to the address of the new allocation.
             self.grant_pointers.map_or(None, |grant_pointers| {
                 // Implement `grant_pointers[grant_num] = grant_ptr` without a
@@ -700,6 +1089,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
 
     fn allocate_custom_grant(
         &self,
+        grant_num: usize,
         size: usize,
         align: usize,
     ) -> Option<(ProcessCustomGrantIdentifer, NonNull<u8>)> {
@@ -710,7 +1100,9 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
 
         // Use the shared grant allocator function to actually allocate memory.
         // Returns `None` if the allocation cannot be created.
-        if let Some(ptr) = self.allocate_in_grant_region_internal(size, align) {
+        if let Some(ptr) =
+            self.allocate_in_grant_region_internal(grant_num, GRANT_BLOCK_CUSTOM, size, align)
+        {
             // Create the identifier that the caller will use to get access to
             // this custom grant in the future.
             let identifier = self.create_custom_grant_identifier(ptr);
@@ -774,9 +1166,21 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         // Get the address of the custom grant based on the identifier.
         let custom_grant_address = self.get_custom_grant_address(identifier);
 
-        // We never deallocate custom grants and only we can change the
-        // `identifier` so we know this is a valid address for the custom grant.
-        Ok(custom_grant_address as *mut u8)
+        // Custom grants are freed with their grant, and the memory may since
+        // have been used for another allocation. Only we can change the
+        // `identifier`, so the address is valid if an allocation of this
+        // custom grant still starts there.
+        let block_address = custom_grant_address.wrapping_sub(GRANT_BLOCK_HEADER_LEN);
+        let allocated = self.grant_blocks().any(|(address, block)| {
+            address == block_address
+                && block.kind() == GRANT_BLOCK_CUSTOM
+                && block.serial == identifier.serial
+        });
+        if allocated {
+            Ok(custom_grant_address as *mut u8)
+        } else {
+            Err(Error::AddressOutOfBounds)
+        }
     }
 
     fn leave_grant(&self, grant_num: usize) {
@@ -803,6 +1207,44 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         });
     }
 
+    fn free_grant(&self, grant_num: usize) {
+        // Do not modify an inactive process.
+        if !self.is_active() {
+            return;
+        }
+
+        for (address, block) in self.grant_blocks() {
+            if block.kind() != GRANT_BLOCK_FREE && block.grant_num() == grant_num {
+                // Safety: `address` is the start of an allocation in the grant
+                // region.
+                unsafe { Self::write_grant_block(address, block.with_kind(GRANT_BLOCK_FREE)) };
+            }
+        }
+        self.grant_pointers.map(|grant_pointers| {
+            if let Some(grant_pointer_pointer) = grant_pointers.get_mut(grant_num) {
+                *grant_pointer_pointer = ptr::null_mut();
+            }
+        });
+
+        self.compact_grant_region();
+    }
+
+    fn grant_allocated_bytes(&self, grant_num: usize) -> Option<usize> {
+        // Do not modify an inactive process.
+        if !self.is_active() {
+            return None;
+        }
+
+        Some(
+            self.grant_blocks()
+                .filter(|(_, block)| {
+                    block.kind() != GRANT_BLOCK_FREE && block.grant_num() == grant_num
+                })
+                .map(|(_, block)| block.len())
+                .sum(),
+        )
+    }
+
     fn grant_allocated_count(&self) -> Option<usize> {
         // Do not modify an inactive process.
         if !self.is_active() {
@@ -820,11 +1262,88 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         })
     }
 
+    fn get_syscall_permissions(&self) -> Option<tock_tbf::types::TbfHeaderV2Permissions> {
+        self.header.get_permissions()
+    }
+
+    fn get_scheduling_parameters(&self) -> Option<tock_tbf::types::TbfHeaderV2Scheduling> {
+        self.header.get_scheduling_parameters()
+    }
+
+    fn get_ipc_services(&self) -> Option<tock_tbf::types::TbfHeaderV2IpcServices> {
+        self.header.get_ipc_services()
+    }
+
+    fn get_heartbeat_interval_ms(&self) -> Option<u32> {
+        self.header.get_heartbeat_interval_ms()
+    }
+
+    fn get_storage_permissions(&self) -> Option<tock_tbf::types::TbfHeaderV2StoragePermissions> {
+        self.header.get_storage_permissions()
+    }
+
+    fn get_quota(&self) -> ProcessQuota {
+        let default = self.kernel.get_default_quota();
+        ProcessQuota {
+            cpu_budget: self
+                .header
+                .get_cpu_budget()
+                .map(|(budget_us, period_us)| CpuBudget {
+                    budget_us,
+                    period_us,
+                })
+                .or(default.cpu_budget),
+            grant_memory_limit: self
+                .header
+                .get_grant_memory_limit()
+                .map(|limit| limit as usize)
+                .or(default.grant_memory_limit),
+            upcall_queue_depth: Some(self.upcall_queue_depth),
+        }
+    }
+
+    fn cpu_budget_remaining_us(&self, clock: &dyn QuotaClock) -> Option<u32> {
+        let budget = self.get_quota().cpu_budget?;
+        let (period_start, used_us) = match self.cpu_budget_period.get() {
+            Some((period_start, used_us)) if clock.us_since(period_start) < budget.period_us => {
+                (period_start, used_us)
+            }
+            // The process has not run yet or the period is over, start a new
+            // one.
+            _ => (clock.now_ticks(), 0),
+        };
+        self.cpu_budget_period.set(Some((period_start, used_us)));
+        Some(budget.budget_us.saturating_sub(used_us))
+    }
+
+    fn consume_cpu_budget_us(&self, used_us: u32) {
+        if let Some((period_start, used)) = self.cpu_budget_period.get() {
+            self.cpu_budget_period
+                .set(Some((period_start, used.saturating_add(used_us))));
+        }
+    }
+
+    fn fault_if_grant_quota_exceeded(&self) {
+        if self.grant_quota_exceeded.take() {
+            self.set_fault_state();
+        }
+    }
+
     fn get_process_name(&self) -> &'static str {
         self.process_name
     }
 
     fn set_syscall_return_value(&self, return_value: SyscallReturn) {
+        // The capsule that handled the system call has returned, so a process
+        // that exceeded its grant memory quota while it was handled can fault
+        // now. A process that was restarted or stopped is no longer waiting
+        // for the return value.
+        self.fault_if_grant_quota_exceeded();
+        match self.state.get() {
+            State::Unstarted | State::Faulted | State::Terminated => return,
+            _ => {}
+        }
+
         match self.stored_state.map(|stored_state| unsafe {
             // Actually set the return value for a particular process.
             //
@@ -931,6 +1450,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         // debugging state. This is completely optional.
         stack_pointer.map(|sp| {
             self.debug.map(|debug| {
+                debug.app_stack_pointer = Some(sp);
                 match debug.app_stack_min_pointer {
                     None => debug.app_stack_min_pointer = Some(sp),
                     Some(asmp) => {
@@ -964,6 +1484,14 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             .map(|debug| debug.timeslice_expiration_count += 1);
     }
 
+    fn debug_cpu_ticks(&self) -> u64 {
+        self.debug.map_or(0, |debug| debug.cpu_ticks)
+    }
+
+    fn debug_ran(&self, ticks: u32) {
+        self.debug.map(|debug| debug.cpu_ticks += ticks as u64);
+    }
+
     fn debug_syscall_called(&self, last_syscall: Syscall) {
         self.debug.map(|debug| {
             debug.syscall_count += 1;
@@ -971,6 +1499,37 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         });
     }
 
+    fn debug_filtered_syscall_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.filtered_syscall_count)
+    }
+
+    fn debug_last_filtered_syscall(&self) -> Option<Syscall> {
+        self.debug.map_or(None, |debug| debug.last_filtered_syscall)
+    }
+
+    fn debug_syscall_filtered(&self, filtered_syscall: Syscall) {
+        self.debug.map(|debug| {
+            debug.filtered_syscall_count += 1;
+            debug.last_filtered_syscall = Some(filtered_syscall);
+        });
+    }
+
+    fn debug_deadline_miss_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.deadline_miss_count)
+    }
+
+    fn debug_deadline_missed(&self) {
+        self.debug.map(|debug| debug.deadline_miss_count += 1);
+    }
+
+    fn debug_quota_exceeded_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.quota_exceeded_count)
+    }
+
+    fn debug_quota_exceeded(&self) {
+        self.debug.map(|debug| debug.quota_exceeded_count += 1);
+    }
+
     fn print_memory_map(&self, writer: &mut dyn Write) {
         // Flash
         let flash_end = self.flash.as_ptr().wrapping_add(self.flash.len()) as usize;
@@ -1002,20 +1561,25 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         let events_queued = self.tasks.map_or(0, |tasks| tasks.len());
         let syscall_count = self.debug.map_or(0, |debug| debug.syscall_count);
         let last_syscall = self.debug.map(|debug| debug.last_syscall);
+        let filtered_syscall_count = self.debug.map_or(0, |debug| debug.filtered_syscall_count);
+        let last_filtered_syscall = self.debug.map(|debug| debug.last_filtered_syscall);
         let dropped_upcall_count = self.debug.map_or(0, |debug| debug.dropped_upcall_count);
+        let quota_exceeded_count = self.debug.map_or(0, |debug| debug.quota_exceeded_count);
         let restart_count = self.restart_count.get();
 
         let _ = writer.write_fmt(format_args!(
             "\
              𝐀𝐩𝐩: {}   -   [{:?}]\
              \r\n Events Queued: {}   Syscall Count: {}   Dropped Upcall Count: {}\
-             \r\n Restart Count: {}\r\n",
+             \r\n Restart Count: {}   Filtered Syscall Count: {}   Quota Exceeded Count: {}\r\n",
             self.process_name,
             self.state.get(),
             events_queued,
             syscall_count,
             dropped_upcall_count,
             restart_count,
+            filtered_syscall_count,
+            quota_exceeded_count,
         ));
 
         let _ = match last_syscall {
@@ -1023,6 +1587,10 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             None => writer.write_str(" Last Syscall: None\r\n"),
         };
 
+        if let Some(Some(syscall)) = last_filtered_syscall {
+            let _ = writer.write_fmt(format_args!(" Last Filtered Syscall: {:?}\r\n", syscall));
+        }
+
         let _ = writer.write_fmt(format_args!(
             "\
              \r\n\
@@ -1230,9 +1798,11 @@ fn exceeded_check(size: usize, allocated: usize) -> &'static str {
 }
 
 impl<C: 'static + Chip> ProcessStandard<'_, C> {
-    // Memory offset for upcall ring buffer (10 element length).
-    const CALLBACK_LEN: usize = 10;
-    const CALLBACKS_OFFSET: usize = mem::size_of::<Task>() * Self::CALLBACK_LEN;
+    // Memory offset for the upcall ring buffer of a queue `depth` tasks deep.
+    // The ring buffer needs one more element than it holds.
+    fn upcall_queue_offset(depth: usize) -> usize {
+        mem::size_of::<Task>() * (depth + 1)
+    }
 
     // Memory offset to make room for this process's metadata.
     const PROCESS_STRUCT_OFFSET: usize = mem::size_of::<ProcessStandard<C>>();
@@ -1340,11 +1910,33 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         let grant_ptrs_num = kernel.get_grant_count_and_finalize();
         let grant_ptrs_offset = grant_ptrs_num * grant_ptr_size;
 
+        // Make room for the upcall queue, as deep as the TBF header or else
+        // the board asks for.
+        let upcall_queue_depth = cmp::min(
+            tbf_header
+                .get_upcall_queue_depth()
+                .map(|depth| depth as usize)
+                .or(kernel.get_default_quota().upcall_queue_depth)
+                .unwrap_or(DEFAULT_UPCALL_QUEUE_DEPTH),
+            MAX_UPCALL_QUEUE_DEPTH,
+        );
+
+        // Make room for the state the process keeps across restarts, rounded
+        // up so that the kernel memory break stays word aligned.
+        let persistent_state_len = tbf_header
+            .get_persistent_ram()
+            .map_or(0, |persistent_ram| persistent_ram.size() as usize);
+        let persistent_state_offset = Self::persistent_state_offset(persistent_state_len)
+            .ok_or(ProcessLoadError::NotEnoughMemory)?;
+
         // Initial size of the kernel-owned part of process memory can be
         // calculated directly based on the initial size of all kernel-owned
         // data structures.
-        let initial_kernel_memory_size =
-            grant_ptrs_offset + Self::CALLBACKS_OFFSET + Self::PROCESS_STRUCT_OFFSET;
+        let initial_kernel_memory_size = (grant_ptrs_offset
+            + Self::upcall_queue_offset(upcall_queue_depth)
+            + Self::PROCESS_STRUCT_OFFSET)
+            .checked_add(persistent_state_offset)
+            .ok_or(ProcessLoadError::NotEnoughMemory)?;
 
         // By default we start with the initial size of process-accessible
         // memory set to 0. This maximizes the flexibility that processes have
@@ -1371,7 +1963,9 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         let min_process_ram_size = cmp::max(process_ram_requested_size, min_process_memory_size);
 
         // Minimum memory size for the process.
-        let min_total_memory_size = min_process_ram_size + initial_kernel_memory_size;
+        let min_total_memory_size = min_process_ram_size
+            .checked_add(initial_kernel_memory_size)
+            .ok_or(ProcessLoadError::NotEnoughMemory)?;
 
         // Check if this process requires a fixed memory start address. If so,
         // try to adjust the memory region to work for this process.
@@ -1508,7 +2102,8 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
 
         // Now that we know we have the space we can setup the memory for the
         // upcalls.
-        kernel_memory_break = kernel_memory_break.offset(-(Self::CALLBACKS_OFFSET as isize));
+        kernel_memory_break =
+            kernel_memory_break.offset(-(Self::upcall_queue_offset(upcall_queue_depth) as isize));
 
         // This is safe today, as MPU constraints ensure that `memory_start`
         // will always be aligned on at least a word boundary, and that
@@ -1521,13 +2116,18 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         #[allow(clippy::cast_ptr_alignment)]
         // Set up ring buffer for upcalls to the process.
         let upcall_buf =
-            slice::from_raw_parts_mut(kernel_memory_break as *mut Task, Self::CALLBACK_LEN);
+            slice::from_raw_parts_mut(kernel_memory_break as *mut Task, upcall_queue_depth + 1);
         let tasks = RingBuffer::new(upcall_buf);
 
-        // Last thing in the kernel region of process RAM is the process struct.
+        // Next in the kernel region of process RAM is the process struct.
         kernel_memory_break = kernel_memory_break.offset(-(Self::PROCESS_STRUCT_OFFSET as isize));
         let process_struct_memory_location = kernel_memory_break;
 
+        // Below the process struct is the state the process keeps across
+        // restarts. There is no checkpoint of it yet.
+        kernel_memory_break = kernel_memory_break.offset(-(persistent_state_offset as isize));
+        let persistent_state_location = kernel_memory_break;
+
         // Create the Process struct in the app grant region.
         let mut process: &mut ProcessStandard<C> =
             &mut *(process_struct_memory_location as *mut ProcessStandard<'static, C>);
@@ -1551,6 +2151,8 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         process.memory_len = app_memory.len();
         process.header = tbf_header;
         process.kernel_memory_break = Cell::new(kernel_memory_break);
+        process.initial_kernel_memory_break = Cell::new(kernel_memory_break);
+        process.next_grant_serial = Cell::new(0);
         process.app_break = Cell::new(initial_app_brk);
         process.grant_pointers = MapCell::new(opts);
 
@@ -1561,6 +2163,14 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         process.state = ProcessStateCell::new(process.kernel);
         process.fault_response = fault_response;
         process.restart_count = Cell::new(0);
+        process.cpu_budget_period = Cell::new(None);
+        process.grant_quota_exceeded = Cell::new(false);
+        process.persistent_state = persistent_state_location;
+        process.persistent_state_len = persistent_state_len;
+        process.persistent_state_valid = Cell::new(false);
+        process.persistent_region = Cell::new(None);
+        process.pinned_buffers = Cell::new([None; MAX_PINNED_BUFFERS]);
+        process.restart_when_unpinned = Cell::new(false);
 
         process.mpu_config = MapCell::new(mpu_config);
         process.mpu_regions = [
@@ -1572,6 +2182,8 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             Cell::new(None),
         ];
         process.tasks = MapCell::new(tasks);
+        process.upcall_queue_depth = upcall_queue_depth;
+        process.unreported_upcall_drops = Cell::new(None);
         process.process_name = process_name.unwrap_or("");
 
         process.debug = MapCell::new(ProcessStandardDebug {
@@ -1580,10 +2192,16 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             app_heap_start_pointer: None,
             app_stack_start_pointer: None,
             app_stack_min_pointer: None,
+            app_stack_pointer: None,
             syscall_count: 0,
             last_syscall: None,
+            filtered_syscall_count: 0,
+            last_filtered_syscall: None,
             dropped_upcall_count: 0,
             timeslice_expiration_count: 0,
+            cpu_ticks: 0,
+            deadline_miss_count: 0,
+            quota_exceeded_count: 0,
         });
 
         let flash_protected_size = process.header.get_protected_size() as usize;
@@ -1640,6 +2258,14 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
     /// and still has its memory region allocated to it. This implements
     /// the mechanism of restart.
     fn restart(&self) -> Result<(), ErrorCode> {
+        // Hardware may still be accessing the process's memory. Restart once
+        // it is done.
+        if self.pinned_buffer_count() > 0 {
+            self.restart_when_unpinned.set(true);
+            return Err(ErrorCode::BUSY);
+        }
+        self.restart_when_unpinned.set(false);
+
         // We need a new process identifier for this process since the restarted
         // version is in effect a new process. This is also necessary to
         // invalidate any stored `ProcessId`s that point to the old version of the
@@ -1654,8 +2280,12 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         self.debug.map(|debug| {
             debug.syscall_count = 0;
             debug.last_syscall = None;
+            debug.filtered_syscall_count = 0;
+            debug.last_filtered_syscall = None;
             debug.dropped_upcall_count = 0;
             debug.timeslice_expiration_count = 0;
+            debug.cpu_ticks = 0;
+            debug.deadline_miss_count = 0;
         });
 
         // FLASH
@@ -1702,8 +2332,14 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         let grant_ptrs_num = self.kernel.get_grant_count_and_finalize();
         let grant_ptrs_offset = grant_ptrs_num * grant_ptr_size;
 
-        let initial_kernel_memory_size =
-            grant_ptrs_offset + Self::CALLBACKS_OFFSET + Self::PROCESS_STRUCT_OFFSET;
+        // The size of the persistent state was checked when the process was
+        // created.
+        let persistent_state_offset =
+            Self::persistent_state_offset(self.persistent_state_len).ok_or(ErrorCode::NOMEM)?;
+        let initial_kernel_memory_size = grant_ptrs_offset
+            + Self::upcall_queue_offset(self.upcall_queue_depth)
+            + Self::PROCESS_STRUCT_OFFSET
+            + persistent_state_offset;
 
         let app_mpu_mem = self.chip.mpu().allocate_app_memory_region(
             self.mem_start(),
@@ -1738,6 +2374,12 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             .wrapping_add(app_mpu_mem_len)
             .wrapping_sub(initial_kernel_memory_size);
         self.kernel_memory_break.set(kernel_brk);
+        self.initial_kernel_memory_break.set(kernel_brk);
+        // The restarted process starts with a fresh CPU budget.
+        self.cpu_budget_period.set(None);
+        // The persistent state stays where it is, but the restarted process
+        // has to register its persistent region again.
+        self.persistent_region.set(None);
         // High water mark for `allow`ed memory is reset to the start of the
         // process's memory region.
         self.allow_high_water_mark.set(app_mpu_mem_start);
@@ -1795,6 +2437,70 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         Ok(())
     }
 
+    /// Keep a record of the state of the process as it faulted in
+    /// `crash_dump`.
+    fn record_crash(&self, crash_dump: &CrashDump, kernel_panics: bool) {
+        let mut registers = [0; crash_dump::MAX_REGISTERS];
+        let num_registers = self.stored_state.map_or(0, |stored_state| {
+            // We guarantee the memory bounds pointers provided to the UKB are
+            // correct.
+            unsafe {
+                self.chip.userspace_kernel_boundary().store_registers(
+                    self.mem_start(),
+                    self.app_break.get(),
+                    stored_state,
+                    &mut registers,
+                )
+            }
+        });
+
+        // Keep the top of the stack, if the stack pointer is in the memory
+        // the process can access.
+        let stack_pointer = self
+            .debug
+            .map_or(None, |debug| debug.app_stack_pointer)
+            .unwrap_or(ptr::null());
+        let stack = if stack_pointer >= self.mem_start() && stack_pointer < self.app_break.get() {
+            let len = cmp::min(
+                crash_dump::STACK_WINDOW,
+                self.app_break.get() as usize - stack_pointer as usize,
+            );
+            // Safety: the stack window is within the memory of the process
+            // and no references to that memory exist while the kernel
+            // handles a fault.
+            unsafe { slice::from_raw_parts(stack_pointer, len) }
+        } else {
+            &[]
+        };
+
+        crash_dump.record_process_fault(crash_dump::ProcessFault {
+            name: self.process_name,
+            restart_count: self.restart_count.get(),
+            memory_map: CrashMemoryMap {
+                flash_start: self.flash_start() as u32,
+                flash_end: self.flash_end() as u32,
+                mem_start: self.mem_start() as u32,
+                app_break: self.app_break.get() as u32,
+                kernel_memory_break: self.kernel_memory_break.get() as u32,
+                mem_end: self.mem_end() as u32,
+                stack_pointer: stack_pointer as u32,
+            },
+            registers: &registers[..num_registers],
+            stack,
+            kernel_panics,
+        });
+    }
+
+    /// Number of bytes the state a process keeps across restarts takes up in
+    /// the kernel-owned part of process memory, or `None` if rounding it up
+    /// to a whole number of words overflows.
+    fn persistent_state_offset(persistent_state_len: usize) -> Option<usize> {
+        let word = mem::size_of::<usize>();
+        persistent_state_len
+            .checked_add(word - 1)
+            .map(|len| len / word * word)
+    }
+
     /// Checks if the buffer represented by the passed in base pointer and size
     /// is within the RAM bounds currently exposed to the processes (i.e.
     /// ending at `app_break`). If this method returns `true`, the buffer
@@ -1836,65 +2542,306 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         }
     }
 
-    /// Allocate memory in a process's grant region.
+    /// Check that lowering the kernel memory break to `new_break` keeps the
+    /// process within its grant memory quota. Memory reused from freed
+    /// grants is already counted, so only allocations that lower the break
+    /// are checked.
+    ///
+    /// If the quota is exceeded the process does not fault right away, as the
+    /// capsule allocating the memory may have other grants of the process
+    /// entered, which a fault would reset. Instead the overrun is recorded
+    /// and the kernel faults the process with
+    /// `fault_if_grant_quota_exceeded()` once the capsule has returned.
+    fn grant_allocation_within_quota(&self, new_break: *const u8) -> bool {
+        let limit = match self.get_quota().grant_memory_limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+        let used =
+            (self.initial_kernel_memory_break.get() as usize).saturating_sub(new_break as usize);
+        if used > limit {
+            if !self.grant_quota_exceeded.replace(true) {
+                self.debug_quota_exceeded();
+            }
+            self.kernel.grant_quota_exceeded();
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Allocate memory in a process's grant region for the grant `grant_num`.
     ///
     /// Ensures that the allocation is of `size` bytes and aligned to `align`
-    /// bytes.
+    /// bytes. `kind` is `GRANT_BLOCK_GRANT` or `GRANT_BLOCK_CUSTOM`.
     ///
-    /// If there is not enough memory, or the MPU cannot isolate the process
-    /// accessible region from the new kernel memory break after doing the
-    /// allocation, then this will return `None`.
-    fn allocate_in_grant_region_internal(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
-        self.mpu_config.and_then(|mut config| {
-            // First, compute the candidate new pointer. Note that at this
-            // point we have not yet checked whether there is space for
-            // this allocation or that it meets alignment requirements.
-            let new_break_unaligned = self.kernel_memory_break.get().wrapping_sub(size);
-
-            // Our minimum alignment requirement is two bytes, so that the
-            // lowest bit of the address will always be zero and we can use it
-            // as a flag. It doesn't hurt to increase the alignment (except for
-            // potentially a wasted byte) so we make sure `align` is at least
-            // two.
-            let align = cmp::max(align, 2);
-
-            // The alignment must be a power of two, 2^a. The expression
-            // `!(align - 1)` then returns a mask with leading ones,
-            // followed by `a` trailing zeros.
-            let alignment_mask = !(align - 1);
-            let new_break = (new_break_unaligned as usize & alignment_mask) as *const u8;
-
-            // Verify there is space for this allocation
-            if new_break < self.app_break.get() {
-                None
-            // Verify it didn't wrap around
-            } else if new_break > self.kernel_memory_break.get() {
-                None
-            // Verify this is compatible with the MPU.
-            } else if let Err(_) = self.chip.mpu().update_app_memory_region(
-                self.app_break.get(),
-                new_break,
-                mpu::Permissions::ReadWriteOnly,
-                &mut config,
-            ) {
-                None
+    /// Memory freed with an earlier grant is reused if the allocation fits
+    /// in it. Otherwise the kernel memory break is lowered. If there is not
+    /// enough memory, or the MPU cannot isolate the process accessible region
+    /// from the new kernel memory break after doing the allocation, then this
+    /// will return `None`.
+    fn allocate_in_grant_region_internal(
+        &self,
+        grant_num: usize,
+        kind: u8,
+        size: usize,
+        align: usize,
+    ) -> Option<NonNull<u8>> {
+        // Our minimum alignment requirement is the alignment of the header
+        // in front of the allocation. This is at least two bytes, so that the
+        // lowest bit of the address will always be zero and we can use it as
+        // a flag.
+        let align = cmp::max(align, mem::align_of::<GrantBlock>());
+        if grant_num >= GRANT_BLOCK_MAX_GRANTS || !align.is_power_of_two() {
+            return None;
+        }
+        let serial = self.next_grant_serial.get();
+        let block =
+            |len: usize| GrantBlock::new(len, grant_num, kind, align.trailing_zeros(), serial);
+
+        let start = match self.grant_blocks().find_map(|(address, free)| {
+            if free.kind() == GRANT_BLOCK_FREE {
+                Self::fit_in_free_block(address, free.len(), size, align)
             } else {
-                // Allocation is valid.
+                None
+            }
+        }) {
+            Some(start) => {
+                // Safety: `start` was just found inside of a free allocation.
+                unsafe { self.claim_free_block(start, block) };
+                start
+            }
+            None => self.mpu_config.and_then(|mut config| {
+                // First, compute the candidate new pointer. Note that at
+                // this point we have not yet checked whether there is space
+                // for this allocation.
+                let new_break =
+                    Self::block_start(self.kernel_memory_break.get() as usize, size, align)?
+                        as *const u8;
+
+                // Verify there is space for this allocation
+                if new_break < self.app_break.get() {
+                    None
+                // Verify the grant region stays small enough for its headers.
+                } else if self.initial_kernel_memory_break.get() as usize - new_break as usize
+                    > GRANT_BLOCK_MAX_LEN
+                {
+                    None
+                // Verify the process is allowed to use this much memory.
+                } else if !self.grant_allocation_within_quota(new_break) {
+                    None
+                // Verify this is compatible with the MPU.
+                } else if let Err(_) = self.chip.mpu().update_app_memory_region(
+                    self.app_break.get(),
+                    new_break,
+                    mpu::Permissions::ReadWriteOnly,
+                    &mut config,
+                ) {
+                    None
+                } else {
+                    // Allocation is valid.
+                    let len = self.kernel_memory_break.get() as usize - new_break as usize;
 
-                // We always allocate down, so we must lower the
-                // kernel_memory_break.
-                self.kernel_memory_break.set(new_break);
+                    // We always allocate down, so we must lower the
+                    // kernel_memory_break.
+                    self.kernel_memory_break.set(new_break);
+
+                    // Safety: the allocation is in the grant region, which
+                    // only the kernel can access.
+                    unsafe { Self::write_grant_block(new_break as usize, block(len)) };
+                    Some(new_break as usize)
+                }
+            })?,
+        };
+        self.next_grant_serial.set(serial.wrapping_add(1));
+
+        // ### Safety
+        //
+        // Here we are guaranteeing that `grant_ptr` is not null. We can
+        // ensure this because we just created `grant_ptr` based on the
+        // process's allocated memory, and we know it cannot be null.
+        unsafe {
+            Some(NonNull::new_unchecked(
+                (start + GRANT_BLOCK_HEADER_LEN) as *mut u8,
+            ))
+        }
+    }
 
-                // We need `grant_ptr` as a mutable pointer.
-                let grant_ptr = new_break as *mut u8;
+    /// Where an allocation of an object of `size` bytes aligned to `align`
+    /// bytes starts if it ends at `end`. Returns `None` if it would wrap
+    /// around.
+    fn block_start(end: usize, size: usize, align: usize) -> Option<usize> {
+        // The alignment must be a power of two, 2^a. The expression
+        // `!(align - 1)` then returns a mask with leading ones, followed by
+        // `a` trailing zeros.
+        let alignment_mask = !(align - 1);
+        let object = end.checked_sub(size)? & alignment_mask;
+        object.checked_sub(GRANT_BLOCK_HEADER_LEN)
+    }
+
+    /// Where an allocation fits at the end of the free allocation of `len`
+    /// bytes at `free_start`. The free memory left in front of it must be
+    /// large enough to hold a header, or there must be none.
+    fn fit_in_free_block(
+        free_start: usize,
+        len: usize,
+        size: usize,
+        align: usize,
+    ) -> Option<usize> {
+        let start = Self::block_start(free_start + len, size, align)?;
+        let left = start.checked_sub(free_start)?;
+        if left == 0 || left >= GRANT_BLOCK_HEADER_LEN {
+            Some(start)
+        } else {
+            None
+        }
+    }
 
-                // ### Safety
-                //
-                // Here we are guaranteeing that `grant_ptr` is not null. We can
-                // ensure this because we just created `grant_ptr` based on the
-                // process's allocated memory, and we know it cannot be null.
-                unsafe { Some(NonNull::new_unchecked(grant_ptr)) }
+    /// Turn the end of the free allocation that `start` is in into the
+    /// allocation `block(len)`.
+    ///
+    /// # Safety
+    ///
+    /// `start` must be a place returned by `fit_in_free_block()` for a free
+    /// allocation in the grant region.
+    unsafe fn claim_free_block<F: Fn(usize) -> GrantBlock>(&self, start: usize, block: F) {
+        if let Some((free_start, free)) = self
+            .grant_blocks()
+            .find(|(address, free)| *address <= start && start < *address + free.len())
+        {
+            let free_end = free_start + free.len();
+            if start > free_start {
+                Self::write_grant_block(free_start, free.with_len(start - free_start));
             }
+            Self::write_grant_block(start, block(free_end - start));
+        }
+    }
+
+    /// Iterate over the allocations in the grant region, from the kernel
+    /// memory break up, with their addresses.
+    fn grant_blocks(&self) -> GrantBlocks {
+        GrantBlocks {
+            next: self.kernel_memory_break.get() as usize,
+            end: self.initial_kernel_memory_break.get() as usize,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `address` must be the start of an allocation in the grant region.
+    unsafe fn write_grant_block(address: usize, block: GrantBlock) {
+        write_volatile(address as *mut GrantBlock, block);
+    }
+
+    /// Make as much of the grant region free as possible after a grant was
+    /// freed.
+    ///
+    /// Adjacent free allocations are merged, and free allocations at the
+    /// kernel memory break are given back to the process. Then, while the
+    /// allocation at the break is a grant that is not entered, it is moved
+    /// into free memory higher up so that the break can rise further. Custom
+    /// grants are never moved, since capsules hold their addresses.
+    fn compact_grant_region(&self) {
+        loop {
+            self.merge_free_grant_blocks();
+            self.release_free_grant_blocks();
+
+            let (start, lowest) = match self.grant_blocks().next() {
+                Some(first) => first,
+                None => return,
+            };
+            if lowest.kind() != GRANT_BLOCK_GRANT || !self.move_grant_block(start, lowest) {
+                return;
+            }
+        }
+    }
+
+    fn merge_free_grant_blocks(&self) {
+        let mut previous_free: Option<(usize, GrantBlock)> = None;
+        for (address, block) in self.grant_blocks() {
+            if block.kind() != GRANT_BLOCK_FREE {
+                previous_free = None;
+                continue;
+            }
+            previous_free = match previous_free {
+                Some((previous_address, previous)) => {
+                    let previous = previous.with_len(previous.len() + block.len());
+                    // Safety: `previous_address` is the start of an
+                    // allocation in the grant region.
+                    unsafe { Self::write_grant_block(previous_address, previous) };
+                    Some((previous_address, previous))
+                }
+                None => Some((address, block)),
+            };
+        }
+    }
+
+    /// Raise the kernel memory break above the free allocation at it, if any,
+    /// and clear the memory so the process does not see what the kernel kept
+    /// there.
+    fn release_free_grant_blocks(&self) {
+        let (start, free) = match self.grant_blocks().next() {
+            Some((start, free)) if free.kind() == GRANT_BLOCK_FREE => (start, free),
+            _ => return,
+        };
+        let new_break = (start + free.len()) as *const u8;
+        self.mpu_config.map(|config| {
+            if self
+                .chip
+                .mpu()
+                .update_app_memory_region(
+                    self.app_break.get(),
+                    new_break,
+                    mpu::Permissions::ReadWriteOnly,
+                    config,
+                )
+                .is_ok()
+            {
+                // Safety: the memory was a free allocation in the grant region
+                // and nothing refers to it.
+                unsafe { ptr::write_bytes(start as *mut u8, 0, free.len()) };
+                self.kernel_memory_break.set(new_break);
+            }
+        });
+    }
+
+    /// Move the grant allocated at `start` into free memory higher up in the
+    /// grant region. Returns `false` if the grant is entered or there is no
+    /// free memory it fits in.
+    fn move_grant_block(&self, start: usize, block: GrantBlock) -> bool {
+        let grant_num = block.grant_num();
+        let object = start + GRANT_BLOCK_HEADER_LEN;
+        let size = block.len() - GRANT_BLOCK_HEADER_LEN;
+        let align = 1 << block.align_log2();
+        self.grant_pointers.map_or(false, |grant_pointers| {
+            let grant_pointer_pointer = match grant_pointers.get_mut(grant_num) {
+                // The grant pointer has the lowest bit set while the grant
+                // is entered, and then the grant must stay where it is.
+                Some(grant_pointer_pointer) if *grant_pointer_pointer as usize == object => {
+                    grant_pointer_pointer
+                }
+                _ => return false,
+            };
+            let new_start = match self.grant_blocks().skip(1).find_map(|(address, free)| {
+                if free.kind() == GRANT_BLOCK_FREE {
+                    Self::fit_in_free_block(address, free.len(), size, align)
+                } else {
+                    None
+                }
+            }) {
+                Some(new_start) => new_start,
+                None => return false,
+            };
+            let new_object = new_start + GRANT_BLOCK_HEADER_LEN;
+            // Safety: the grant is not entered, so nothing refers to it, and
+            // the new allocation is in free memory that does not overlap it.
+            unsafe {
+                self.claim_free_block(new_start, |len| block.with_len(len));
+                ptr::copy_nonoverlapping(object as *const u8, new_object as *mut u8, size);
+                Self::write_grant_block(start, block.with_kind(GRANT_BLOCK_FREE));
+            }
+            *grant_pointer_pointer = new_object as *mut u8;
+            true
         })
     }
 
@@ -1907,8 +2854,14 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         let custom_grant_address = ptr.as_ptr() as usize;
         let process_memory_end = self.mem_end() as usize;
 
+        // Safety: `ptr` was just allocated in the grant region.
+        let block = unsafe {
+            ptr::read_volatile((custom_grant_address - GRANT_BLOCK_HEADER_LEN) as *const GrantBlock)
+        };
+
         ProcessCustomGrantIdentifer {
             offset: process_memory_end - custom_grant_address,
+            serial: block.serial,
         }
     }
 
@@ -1939,3 +2892,30 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         current_state != State::Terminated && current_state != State::Faulted
     }
 }
+
+/// Iterator over the allocations in the grant region of a process.
+struct GrantBlocks {
+    next: usize,
+    end: usize,
+}
+
+impl Iterator for GrantBlocks {
+    type Item = (usize, GrantBlock);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let address = self.next;
+        // Safety: the allocations in the grant region are laid out one after
+        // the other from the kernel memory break up, each starting with a
+        // header.
+        let block = unsafe { ptr::read_volatile(address as *const GrantBlock) };
+        if block.len() == 0 {
+            // Never happens, but do not loop forever if it did.
+            return None;
+        }
+        self.next += block.len();
+        Some((address, block))
+    }
+}
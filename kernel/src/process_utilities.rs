@@ -4,10 +4,13 @@ use core::convert::TryInto;
 use core::fmt;
 
 use crate::capabilities::ProcessManagementCapability;
+use crate::common::dynamic_deferred_call::DynamicDeferredCall;
 use crate::config;
 use crate::debug;
+use crate::errorcode::ErrorCode;
 use crate::platform::Chip;
-use crate::process::{FaultResponse, Process};
+use crate::process::{FaultResponse, Process, ProcessId};
+use crate::process_checker::{AllowAllApps, AppCredentialsPolicy, CheckResult};
 use crate::process_standard::ProcessStandard;
 use crate::sched::Kernel;
 
@@ -44,6 +47,11 @@ pub enum ProcessLoadError {
         expected_address: u32,
     },
 
+    /// Every slot in the processes array is already in use, so a process
+    /// found at runtime cannot be started. Unload a process first, or
+    /// allocate a larger processes array.
+    NoProcessSlot,
+
     /// Process loading error due (likely) to a bug in the kernel. If you get
     /// this error please open a bug report.
     InternalError,
@@ -97,6 +105,10 @@ impl fmt::Debug for ProcessLoadError {
                 actual_address, expected_address
             ),
 
+            ProcessLoadError::NoProcessSlot => {
+                write!(f, "No free slot in the processes array")
+            }
+
             ProcessLoadError::InternalError => write!(f, "Error in kernel. Likely a bug."),
         }
     }
@@ -116,6 +128,9 @@ impl fmt::Debug for ProcessLoadError {
 /// A reference to each process is stored in the provided `procs` array.
 /// How process faults are handled by the
 /// kernel must be provided and is assigned to every created process.
+/// Apps written to flash after boot can be started later with
+/// `load_new_process()`. Every app is loaded without checking its
+/// credentials; use `load_and_check_processes()` to only run approved apps.
 ///
 /// This function is made `pub` so that board files can use it, but loading
 /// processes from slices of flash an memory is fundamentally unsafe. Therefore,
@@ -131,6 +146,33 @@ pub fn load_processes<C: Chip>(
     app_memory: &mut [u8], // not static, so that process.rs cannot hold on to slice w/o unsafe
     procs: &'static mut [Option<&'static dyn Process>],
     fault_response: FaultResponse,
+    capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_and_check_processes(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_response,
+        &AllowAllApps::new(),
+        capability,
+    )
+}
+
+/// Load processes from flash like `load_processes()`, but only run the apps
+/// whose credentials `policy` approves.
+///
+/// Apps the policy refuses are skipped, and loading continues with the next
+/// app in flash.
+pub fn load_and_check_processes<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &mut [u8], // not static, so that process.rs cannot hold on to slice w/o unsafe
+    procs: &'static mut [Option<&'static dyn Process>],
+    fault_response: FaultResponse,
+    policy: &dyn AppCredentialsPolicy,
     _capability: &dyn ProcessManagementCapability,
 ) -> Result<(), ProcessLoadError> {
     if config::CONFIG.debug_load_processes {
@@ -148,48 +190,14 @@ pub fn load_processes<C: Chip>(
 
     // Try to discover up to `procs.len()` processes in flash.
     for i in 0..procs.len() {
-        // Get the first eight bytes of flash to check if there is another
-        // app.
-        let test_header_slice = match remaining_flash.get(0..8) {
-            Some(s) => s,
+        let (version, header_length, entry_flash) = match discover_process_entry(remaining_flash)? {
+            Some(entry) => entry,
             None => {
-                // Not enough flash to test for another app. This just means
-                // we are at the end of flash, and there are no more apps to
-                // load.
+                // No more apps to load.
                 return Ok(());
             }
         };
 
-        // Pass the first eight bytes to tbfheader to parse out the length of
-        // the tbf header and app. We then use those values to see if we have
-        // enough flash remaining to parse the remainder of the header.
-        let (version, header_length, entry_length) = match tock_tbf::parse::parse_tbf_header_lengths(
-            test_header_slice
-                .try_into()
-                .or(Err(ProcessLoadError::InternalError))?,
-        ) {
-            Ok((v, hl, el)) => (v, hl, el),
-            Err(tock_tbf::types::InitialTbfParseError::InvalidHeader(entry_length)) => {
-                // If we could not parse the header, then we want to skip over
-                // this app and look for the next one.
-                (0, 0, entry_length)
-            }
-            Err(tock_tbf::types::InitialTbfParseError::UnableToParse) => {
-                // Since Tock apps use a linked list, it is very possible the
-                // header we started to parse is intentionally invalid to signal
-                // the end of apps. This is ok and just means we have finished
-                // loading apps.
-                return Ok(());
-            }
-        };
-
-        // Now we can get a slice which only encompasses the length of flash
-        // described by this tbf header.  We will either parse this as an actual
-        // app, or skip over this region.
-        let entry_flash = remaining_flash
-            .get(0..entry_length as usize)
-            .ok_or(ProcessLoadError::NotEnoughFlash)?;
-
         // Advance the flash slice for process discovery beyond this last entry.
         // This will be the start of where we look for a new process since Tock
         // processes are allocated back-to-back in flash.
@@ -199,7 +207,9 @@ pub fn load_processes<C: Chip>(
 
         // Need to reassign remaining_memory in every iteration so the compiler
         // knows it will not be re-borrowed.
-        remaining_memory = if header_length > 0 {
+        remaining_memory = if header_length > 0
+            && check_credentials(chip, entry_flash, header_length, version, policy)?
+        {
             // If we found an actual app header, try to create a `Process`
             // object. We also need to shrink the amount of remaining memory
             // based on whatever is assigned to the new process if one is
@@ -238,11 +248,355 @@ pub fn load_processes<C: Chip>(
             });
             unused_memory
         } else {
-            // We are just skipping over this region of flash, so we have the
-            // same amount of process memory to allocate from.
+            // We are just skipping over this region of flash, either because
+            // it is not a valid app or because the policy refused it, so we
+            // have the same amount of process memory to allocate from.
             remaining_memory
         };
     }
 
     Ok(())
 }
+
+/// Find the next TBF entry at the start of `flash`.
+///
+/// Returns `Ok(None)` if there are no more apps in flash. Otherwise returns
+/// the TBF version, the length of the TBF header, and a slice covering the
+/// entire entry. A header length of zero means the header could not be parsed
+/// and the entry should be skipped.
+fn discover_process_entry(
+    flash: &'static [u8],
+) -> Result<Option<(u16, u16, &'static [u8])>, ProcessLoadError> {
+    // Get the first eight bytes of flash to check if there is another
+    // app.
+    let test_header_slice = match flash.get(0..8) {
+        Some(s) => s,
+        None => {
+            // Not enough flash to test for another app. This just means
+            // we are at the end of flash, and there are no more apps to
+            // load.
+            return Ok(None);
+        }
+    };
+
+    // Pass the first eight bytes to tbfheader to parse out the length of
+    // the tbf header and app. We then use those values to see if we have
+    // enough flash remaining to parse the remainder of the header.
+    let (version, header_length, entry_length) = match tock_tbf::parse::parse_tbf_header_lengths(
+        test_header_slice
+            .try_into()
+            .or(Err(ProcessLoadError::InternalError))?,
+    ) {
+        Ok((v, hl, el)) => (v, hl, el),
+        Err(tock_tbf::types::InitialTbfParseError::InvalidHeader(entry_length)) => {
+            // If we could not parse the header, then we want to skip over
+            // this app and look for the next one.
+            (0, 0, entry_length)
+        }
+        Err(tock_tbf::types::InitialTbfParseError::UnableToParse) => {
+            // Since Tock apps use a linked list, it is very possible the
+            // header we started to parse is intentionally invalid to signal
+            // the end of apps. This is ok and just means we have finished
+            // loading apps.
+            return Ok(None);
+        }
+    };
+
+    // Now we can get a slice which only encompasses the length of flash
+    // described by this tbf header.  We will either parse this as an actual
+    // app, or skip over this region.
+    let entry_flash = flash
+        .get(0..entry_length as usize)
+        .ok_or(ProcessLoadError::NotEnoughFlash)?;
+
+    Ok(Some((version, header_length, entry_flash)))
+}
+
+/// How many times `check_credentials()` services interrupts and deferred
+/// calls while waiting for a policy to decide, before it treats the
+/// credentials as rejected.
+const MAX_CREDENTIALS_CHECK_POLLS: usize = 1_000_000;
+
+/// Decide whether `policy` allows the app in `entry_flash` to run.
+///
+/// Padding and disabled apps are always allowed since no process is created
+/// for them. While the policy checks credentials asynchronously, for example
+/// with a digest engine, this services interrupts and deferred calls so that
+/// the check can finish. A policy that does not decide within
+/// `MAX_CREDENTIALS_CHECK_POLLS` rejects the credentials, so that a hung
+/// digest engine cannot stop the board from booting.
+fn check_credentials<C: Chip>(
+    chip: &C,
+    entry_flash: &'static [u8],
+    header_length: u16,
+    version: u16,
+    policy: &dyn AppCredentialsPolicy,
+) -> Result<bool, ProcessLoadError> {
+    let header_flash = entry_flash
+        .get(0..header_length as usize)
+        .ok_or(ProcessLoadError::NotEnoughFlash)?;
+    let header = tock_tbf::parse::parse_tbf_header(header_flash, version)?;
+    if !header.is_app() || !header.enabled() {
+        return Ok(true);
+    }
+
+    let binary_end = header.get_binary_end() as usize;
+    let binary = entry_flash
+        .get(0..binary_end)
+        .ok_or(ProcessLoadError::NotEnoughFlash)?;
+    let mut footers = entry_flash.get(binary_end..).unwrap_or(&[]);
+
+    // Check each credentials footer until the policy makes a decision. A
+    // footer that does not parse ends the footers.
+    while let Ok((credentials, footer_length)) = tock_tbf::parse::parse_tbf_footer(footers) {
+        let result = match policy.check_credentials(&credentials, binary) {
+            Some(result) => result,
+            None => {
+                let mut polls = 0;
+                loop {
+                    if let Some(result) = policy.check_result() {
+                        break result;
+                    }
+                    if polls == MAX_CREDENTIALS_CHECK_POLLS {
+                        break CheckResult::Reject;
+                    }
+                    polls += 1;
+                    unsafe {
+                        chip.service_pending_interrupts();
+                        DynamicDeferredCall::call_global_instance_while(|| {
+                            !chip.has_pending_interrupts()
+                        });
+                    }
+                }
+            }
+        };
+        match result {
+            CheckResult::Accept => return Ok(true),
+            CheckResult::Reject => {
+                if config::CONFIG.debug_load_processes {
+                    debug!(
+                        "[!] flash={:#010X}-{:#010X} process={:?} - {:?} credentials rejected",
+                        entry_flash.as_ptr() as usize,
+                        entry_flash.as_ptr() as usize + entry_flash.len() - 1,
+                        header.get_package_name(),
+                        credentials.format()
+                    );
+                }
+                return Ok(false);
+            }
+            CheckResult::Pass => {}
+        }
+        footers = footers.get(footer_length as usize..).unwrap_or(&[]);
+    }
+
+    if policy.require_credentials() {
+        if config::CONFIG.debug_load_processes {
+            debug!(
+                "[!] flash={:#010X}-{:#010X} process={:?} - no approved credentials",
+                entry_flash.as_ptr() as usize,
+                entry_flash.as_ptr() as usize + entry_flash.len() - 1,
+                header.get_package_name()
+            );
+        }
+        Ok(false)
+    } else {
+        Ok(true)
+    }
+}
+
+/// Load and start a process that was written into app flash after the board
+/// booted.
+///
+/// This walks the same TBF linked list in `app_flash` as `load_processes()`
+/// and creates a process for the first enabled app that is not already
+/// running from that location in flash. The process is placed in the first
+/// empty slot of `procs`, and its memory is taken from the first gap in
+/// `app_memory` that is not used by an existing process and is large enough.
+/// Memory released by `unload_process()` is reused this way.
+///
+/// `app_flash`, `app_memory` and `procs` must be the same regions the board
+/// passed to `load_processes()`. Apps that `policy` refuses are skipped.
+///
+/// Returns `Ok(Some(process))` if a process was started, and `Ok(None)` if
+/// there was no new app in flash. Call this repeatedly to start several new
+/// apps.
+pub fn load_new_process<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &mut [u8], // not static, so that process.rs cannot hold on to slice w/o unsafe
+    procs: &'static mut [Option<&'static dyn Process>],
+    fault_response: FaultResponse,
+    policy: &dyn AppCredentialsPolicy,
+    _capability: &dyn ProcessManagementCapability,
+) -> Result<Option<&'static dyn Process>, ProcessLoadError> {
+    let mut remaining_flash = app_flash;
+
+    while let Some((version, header_length, entry_flash)) = discover_process_entry(remaining_flash)?
+    {
+        remaining_flash = remaining_flash
+            .get(entry_flash.len()..)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+
+        if header_length == 0 {
+            continue;
+        }
+
+        // Skip apps that are already running.
+        let loaded = procs.iter().any(|slot| {
+            slot.map_or(false, |process| {
+                process.flash_start() == entry_flash.as_ptr()
+            })
+        });
+        if loaded {
+            continue;
+        }
+
+        // Skip padding and disabled apps before claiming a process slot for
+        // them.
+        let header_flash = entry_flash
+            .get(0..header_length as usize)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+        let header = tock_tbf::parse::parse_tbf_header(header_flash, version)?;
+        if !header.is_app() || !header.enabled() {
+            continue;
+        }
+        if !check_credentials(chip, entry_flash, header_length, version, policy)? {
+            continue;
+        }
+
+        let index = procs
+            .iter()
+            .position(|slot| slot.is_none())
+            .ok_or(ProcessLoadError::NoProcessSlot)?;
+
+        let process = create_in_free_memory(
+            kernel,
+            chip,
+            entry_flash,
+            header_length,
+            version,
+            app_memory,
+            procs,
+            fault_response,
+            index,
+        )?;
+
+        if config::CONFIG.debug_load_processes {
+            debug!(
+                "Loaded process[{}] from flash={:#010X}-{:#010X} into sram={:#010X}-{:#010X} = {:?}",
+                index,
+                entry_flash.as_ptr() as usize,
+                entry_flash.as_ptr() as usize + entry_flash.len() - 1,
+                process.mem_start() as usize,
+                process.mem_end() as usize - 1,
+                process.get_process_name()
+            );
+        }
+
+        procs[index] = Some(process);
+        return Ok(Some(process));
+    }
+
+    Ok(None)
+}
+
+/// Create a process in the first gap between existing processes in
+/// `app_memory` that satisfies its memory requirements.
+fn create_in_free_memory<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    entry_flash: &'static [u8],
+    header_length: u16,
+    version: u16,
+    app_memory: &mut [u8],
+    procs: &[Option<&'static dyn Process>],
+    fault_response: FaultResponse,
+    index: usize,
+) -> Result<&'static dyn Process, ProcessLoadError> {
+    let memory_start = app_memory.as_ptr() as usize;
+    let memory_end = memory_start + app_memory.len();
+
+    // Report why the last gap was rejected if none of them work.
+    let mut error = ProcessLoadError::NotEnoughMemory;
+    let mut cursor = memory_start;
+    while cursor < memory_end {
+        // The next region of process memory at or after the cursor, if any.
+        let next_used = procs
+            .iter()
+            .filter_map(|slot| *slot)
+            .map(|process| (process.mem_start() as usize, process.mem_end() as usize))
+            .filter(|(start, _)| *start >= cursor && *start < memory_end)
+            .min_by_key(|(start, _)| *start);
+        let gap_end = next_used.map_or(memory_end, |(start, _)| start);
+
+        if gap_end > cursor {
+            let gap = app_memory
+                .get_mut(cursor - memory_start..gap_end - memory_start)
+                .ok_or(ProcessLoadError::InternalError)?;
+            match unsafe {
+                ProcessStandard::create(
+                    kernel,
+                    chip,
+                    entry_flash,
+                    header_length as usize,
+                    version,
+                    gap,
+                    fault_response,
+                    index,
+                )
+            } {
+                Ok((Some(process), _)) => return Ok(process),
+                // The header was already checked to be an enabled app.
+                Ok((None, _)) => return Err(ProcessLoadError::InternalError),
+                // This gap does not work for the process, try the next one.
+                Err(e @ ProcessLoadError::NotEnoughMemory)
+                | Err(e @ ProcessLoadError::MemoryAddressMismatch { .. }) => error = e,
+                Err(e) => return Err(e),
+            }
+        }
+
+        match next_used {
+            Some((_, end)) => cursor = end,
+            None => break,
+        }
+    }
+
+    Err(error)
+}
+
+/// Stop a process and remove it from the processes array.
+///
+/// The process is terminated, which clears its pending upcalls and grants,
+/// and its slot in `procs` is emptied. Its memory is then free for
+/// `load_new_process()` to reuse. Any `ProcessId` referring to the process
+/// becomes invalid.
+///
+/// This does not change app flash. If the app's TBF is left in flash and
+/// enabled, the next call to `load_new_process()` will start it again.
+///
+/// This fails with `BUSY`, and leaves the process running, while it has
+/// buffers pinned: hardware may still be accessing its memory.
+///
+/// This must not be called while the kernel is iterating over processes, for
+/// example from within `Kernel::process_each()`.
+pub fn unload_process(
+    procs: &'static mut [Option<&'static dyn Process>],
+    processid: ProcessId,
+    _capability: &dyn ProcessManagementCapability,
+) -> Result<(), ErrorCode> {
+    let slot = procs.get_mut(processid.index).ok_or(ErrorCode::INVAL)?;
+    match slot {
+        Some(process) if process.processid() == processid => {
+            if process.pinned_buffer_count() > 0 {
+                return Err(ErrorCode::BUSY);
+            }
+            // The process did not exit on its own, so there is no completion
+            // code to report.
+            process.terminate(0);
+            *slot = None;
+            Ok(())
+        }
+        _ => Err(ErrorCode::INVAL),
+    }
+}
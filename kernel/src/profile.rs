@@ -0,0 +1,261 @@
+//! Where the CPU time goes.
+//!
+//! A `KernelProfile` measures, with a free-running hardware counter, how long
+//! the CPU spends:
+//!
+//! - running each process, counted by the process (see
+//!   `KernelInfo::app_cpu_ticks()`),
+//! - in kernel work, split into interrupt bottom halves and deferred calls,
+//! - handling the system calls of each driver, as a histogram of how long
+//!   each call took.
+//!
+//! All times are in ticks of the counter, so they are as precise as the
+//! counter is; `KernelProfile::frequency()` converts them to seconds. A
+//! single measurement must not last longer than the counter takes to wrap
+//! around.
+//!
+//! Kernel work is timed separately while the kernel runs interrupt bottom
+//! halves and while it runs deferred calls, see
+//! `Kernel::service_interrupts()` and `Kernel::service_deferred_calls_while()`.
+//!
+//! The kernel measures once the board calls `Kernel::set_profile()`, and the
+//! measurements are available through `KernelInfo::kernel_profile()`, for
+//! example with the `profile` command of `capsules::process_console`.
+//!
+//! Usage
+//! -----
+//!
+//! ```ignore
+//! let profile = static_init!(
+//!     kernel::profile::KernelProfile,
+//!     kernel::profile::KernelProfile::new(&peripherals.timer1)
+//! );
+//! board_kernel.set_profile(profile, &process_management_capability);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use crate::common::cells::MapCell;
+use crate::hil::time::{self, Frequency, Ticks};
+use crate::syscall::Syscall;
+
+/// How many drivers the profile keeps syscall latencies for. System calls to
+/// other drivers are only counted by `untracked_syscalls()`.
+pub const MAX_PROFILED_DRIVERS: usize = 8;
+
+/// Number of buckets of a `LatencyHistogram`.
+pub const LATENCY_BUCKETS: usize = 16;
+
+/// A free-running counter to measure time with.
+///
+/// This is implemented for every `hil::time::Counter`.
+pub trait ProfileCounter {
+    /// The current value of the counter.
+    fn now_ticks(&self) -> u32;
+
+    /// Ticks since the counter had the value `start`.
+    fn ticks_since(&self, start: u32) -> u32;
+
+    /// Ticks per second.
+    fn frequency(&self) -> u32;
+}
+
+impl<'a, C: time::Counter<'a>> ProfileCounter for C {
+    fn now_ticks(&self) -> u32 {
+        self.now().into_u32()
+    }
+
+    fn ticks_since(&self, start: u32) -> u32 {
+        // Subtract in the width of the counter, which may be narrower than
+        // 32 bits.
+        self.now().wrapping_sub(C::Ticks::from(start)).into_u32()
+    }
+
+    fn frequency(&self) -> u32 {
+        C::Frequency::frequency()
+    }
+}
+
+/// How long the system calls to a driver took.
+///
+/// Bucket 0 counts calls that took no ticks, and bucket `b` calls that took
+/// from 2^(b-1) up to 2^b - 1 ticks. The last bucket also counts all longer
+/// calls.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    pub count: u32,
+    pub total_ticks: u64,
+    pub max_ticks: u32,
+    pub buckets: [u32; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// The bucket of a call that took `ticks`.
+    pub fn bucket(ticks: u32) -> usize {
+        cmp::min(32 - ticks.leading_zeros() as usize, LATENCY_BUCKETS - 1)
+    }
+
+    /// The fewest ticks a call in `bucket` took.
+    pub fn bucket_start(bucket: usize) -> u32 {
+        match bucket {
+            0 => 0,
+            b => 1 << (b - 1),
+        }
+    }
+
+    /// The average number of ticks a call took.
+    pub fn mean_ticks(&self) -> u32 {
+        match self.count {
+            0 => 0,
+            count => (self.total_ticks / count as u64) as u32,
+        }
+    }
+
+    fn record(&mut self, ticks: u32) {
+        self.count = self.count.wrapping_add(1);
+        self.total_ticks += ticks as u64;
+        self.max_ticks = cmp::max(self.max_ticks, ticks);
+        let bucket = &mut self.buckets[Self::bucket(ticks)];
+        *bucket = bucket.wrapping_add(1);
+    }
+}
+
+/// The syscall latencies of one driver.
+#[derive(Clone, Copy, Default)]
+struct DriverLatency {
+    driver_num: Option<usize>,
+    latency: LatencyHistogram,
+}
+
+/// Measures where the CPU time goes.
+pub struct KernelProfile {
+    counter: &'static dyn ProfileCounter,
+    interrupt_ticks: Cell<u64>,
+    deferred_call_ticks: Cell<u64>,
+    drivers: MapCell<[DriverLatency; MAX_PROFILED_DRIVERS]>,
+    untracked_syscalls: Cell<u32>,
+}
+
+impl KernelProfile {
+    /// `counter` must keep running while the kernel and processes execute.
+    pub fn new(counter: &'static dyn ProfileCounter) -> KernelProfile {
+        KernelProfile {
+            counter,
+            interrupt_ticks: Cell::new(0),
+            deferred_call_ticks: Cell::new(0),
+            drivers: MapCell::new([DriverLatency::default(); MAX_PROFILED_DRIVERS]),
+            untracked_syscalls: Cell::new(0),
+        }
+    }
+
+    /// Ticks of the counter per second.
+    pub fn frequency(&self) -> u32 {
+        self.counter.frequency()
+    }
+
+    /// Ticks spent running interrupt bottom halves.
+    pub fn interrupt_ticks(&self) -> u64 {
+        self.interrupt_ticks.get()
+    }
+
+    /// Ticks spent running deferred calls.
+    pub fn deferred_call_ticks(&self) -> u64 {
+        self.deferred_call_ticks.get()
+    }
+
+    /// The syscall latencies of `driver_num`, if it was called.
+    pub fn syscall_latency(&self, driver_num: usize) -> Option<LatencyHistogram> {
+        self.drivers.map_or(None, |drivers| {
+            drivers
+                .iter()
+                .find(|driver| driver.driver_num == Some(driver_num))
+                .map(|driver| driver.latency)
+        })
+    }
+
+    /// Call `f` with the number and syscall latencies of every driver that was
+    /// called, in the order they were first called.
+    pub fn each_syscall_latency<F: FnMut(usize, &LatencyHistogram)>(&self, mut f: F) {
+        self.drivers.map(|drivers| {
+            for driver in drivers.iter() {
+                if let Some(driver_num) = driver.driver_num {
+                    f(driver_num, &driver.latency);
+                }
+            }
+        });
+    }
+
+    /// System calls that were not measured because the profile already
+    /// tracks `MAX_PROFILED_DRIVERS` other drivers.
+    pub fn untracked_syscalls(&self) -> u32 {
+        self.untracked_syscalls.get()
+    }
+
+    /// Forget all measurements of the kernel. Processes keep counting the
+    /// time they ran for.
+    pub fn clear(&self) {
+        self.interrupt_ticks.set(0);
+        self.deferred_call_ticks.set(0);
+        self.drivers.map(|drivers| {
+            *drivers = [DriverLatency::default(); MAX_PROFILED_DRIVERS];
+        });
+        self.untracked_syscalls.set(0);
+    }
+
+    /// Start a measurement.
+    pub(crate) fn start(&self) -> u32 {
+        self.counter.now_ticks()
+    }
+
+    /// Ticks since `start()` returned `start`.
+    pub(crate) fn ticks_since(&self, start: u32) -> u32 {
+        self.counter.ticks_since(start)
+    }
+
+    /// Count interrupt bottom halves that started running at `start`.
+    pub(crate) fn interrupts(&self, start: u32) {
+        let ticks = self.ticks_since(start) as u64;
+        self.interrupt_ticks.set(self.interrupt_ticks.get() + ticks);
+    }
+
+    /// Count deferred calls that started running at `start`.
+    pub(crate) fn deferred_calls(&self, start: u32) {
+        let ticks = self.ticks_since(start) as u64;
+        self.deferred_call_ticks
+            .set(self.deferred_call_ticks.get() + ticks);
+    }
+
+    /// Count a system call that was made at `start`. Only calls to drivers are
+    /// counted.
+    pub(crate) fn syscall(&self, start: u32, syscall: Syscall) {
+        let ticks = self.ticks_since(start);
+        let driver_num = match syscall {
+            Syscall::Subscribe { driver_number, .. }
+            | Syscall::Command { driver_number, .. }
+            | Syscall::ReadWriteAllow { driver_number, .. }
+            | Syscall::ReadOnlyAllow { driver_number, .. } => driver_number,
+            Syscall::Yield { .. } | Syscall::Memop { .. } | Syscall::Exit { .. } => return,
+        };
+        let tracked = self.drivers.map_or(false, |drivers| {
+            let slot = match drivers
+                .iter()
+                .position(|driver| driver.driver_num == Some(driver_num))
+            {
+                Some(index) => Some(index),
+                None => drivers
+                    .iter()
+                    .position(|driver| driver.driver_num.is_none()),
+            };
+            slot.map(|index| {
+                drivers[index].driver_num = Some(driver_num);
+                drivers[index].latency.record(ticks);
+            })
+            .is_some()
+        });
+        if !tracked {
+            self.untracked_syscalls
+                .set(self.untracked_syscalls.get().wrapping_add(1));
+        }
+    }
+}
@@ -6,6 +6,7 @@
 //! selected by a board.
 
 pub(crate) mod cooperative;
+pub(crate) mod edf;
 pub(crate) mod mlfq;
 pub(crate) mod priority;
 pub(crate) mod round_robin;
@@ -14,24 +15,33 @@ use core::cell::Cell;
 use core::ptr::NonNull;
 
 use crate::capabilities;
-use crate::common::cells::NumericCellExt;
+use crate::common::cells::{NumericCellExt, OptionalCell};
 use crate::common::dynamic_deferred_call::DynamicDeferredCall;
-use crate::config;
-use crate::debug;
+use crate::crash_dump::CrashDump;
 use crate::driver::CommandReturn;
 use crate::errorcode::ErrorCode;
 use crate::grant::Grant;
+use crate::heartbeat::ProcessHeartbeats;
+use crate::hil::time::Alarm;
 use crate::ipc;
+use crate::ipc_mailbox::{IPCMailbox, MailboxPeers};
+use crate::mem::AllowId;
 use crate::memop;
 use crate::platform::mpu::MPU;
 use crate::platform::scheduler_timer::SchedulerTimer;
 use crate::platform::watchdog::WatchDog;
 use crate::platform::{Chip, Platform};
+use crate::power::PowerManager;
 use crate::process::ProcessId;
 use crate::process::{self, Task};
+use crate::process_policies::{DependentProcesses, DependentRestart, ProcessQuota, QuotaClock};
+use crate::process_standard::GRANT_BLOCK_MAX_GRANTS;
+use crate::profile::KernelProfile;
 use crate::syscall::{ContextSwitchReason, SyscallReturn};
 use crate::syscall::{Syscall, YieldCall};
+use crate::trace::{self, KernelTrace, SwitchFromReason, TraceKind};
 use crate::upcall::{Upcall, UpcallId};
+use crate::upcall_overflow::UpcallOverflow;
 
 /// Threshold in microseconds to consider a process's timeslice to be exhausted.
 /// That is, Tock will skip re-scheduling a process if its remaining timeslice
@@ -71,10 +81,13 @@ pub trait Scheduler<C: Chip> {
     /// complete at any time to meet power requirements.
     ///
     /// Custom implementations of this function must be very careful, however,
-    /// as this function is called in the core kernel loop.
-    unsafe fn execute_kernel_work(&self, chip: &C) {
-        chip.service_pending_interrupts();
-        DynamicDeferredCall::call_global_instance_while(|| !chip.has_pending_interrupts());
+    /// as this function is called in the core kernel loop. They should service
+    /// interrupts and deferred calls through `Kernel::service_interrupts()` and
+    /// `Kernel::service_deferred_calls_while()`, so that the time they take is
+    /// profiled.
+    unsafe fn execute_kernel_work(&self, kernel: &Kernel, chip: &C) {
+        kernel.service_interrupts(chip);
+        kernel.service_deferred_calls_while(|| !chip.has_pending_interrupts());
     }
 
     /// Ask the scheduler whether to take a break from executing userspace
@@ -146,6 +159,49 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+
+    /// Resource limits for processes that do not declare their own in their
+    /// TBF header.
+    default_quota: Cell<ProcessQuota>,
+
+    /// Time source for enforcing CPU budgets. CPU budgets are not enforced
+    /// unless the board provides one.
+    quota_clock: OptionalCell<&'static dyn QuotaClock>,
+
+    /// Set when a process went over its grant memory quota, so the kernel
+    /// faults it once the capsule that allocated the memory has returned.
+    grant_quota_exceeded: Cell<bool>,
+
+    /// Where the kernel records what it does, if the board wants a trace.
+    trace: OptionalCell<&'static KernelTrace>,
+
+    /// Where the kernel keeps a record of the last process fault, if the
+    /// board provides one.
+    crash_dump: OptionalCell<&'static CrashDump>,
+
+    /// The message-passing IPC driver, if the board has one, so that it
+    /// learns when processes stop.
+    ipc_mailbox: OptionalCell<&'static dyn MailboxPeers>,
+
+    /// The restart policy that restarts processes with the processes they
+    /// depend on, if the board uses one, so that it learns when processes
+    /// stop.
+    dependent_restart: OptionalCell<&'static dyn DependentProcesses>,
+
+    /// Chooses how deeply the chip sleeps, if the board provides one.
+    /// Otherwise the kernel always uses `Chip::sleep()`.
+    power_manager: OptionalCell<&'static PowerManager>,
+
+    /// Checks that processes check in on time, if the board wants process
+    /// heartbeats. The watchdog is only tickled while they do.
+    heartbeats: OptionalCell<&'static ProcessHeartbeats>,
+
+    /// Tells processes about the upcalls they lost because their queue was
+    /// full, if the board has the driver.
+    upcall_overflow: OptionalCell<&'static UpcallOverflow>,
+
+    /// Measures where the CPU time goes, if the board wants a profile.
+    profile: OptionalCell<&'static KernelProfile>,
 }
 
 /// Enum used to inform scheduler why a process stopped executing (aka why
@@ -171,6 +227,11 @@ pub enum StoppedExecutingReason {
     /// interrupt), or because the scheduler no longer wants to execute that
     /// process.
     KernelPreemption,
+
+    /// The process did not run because it has less than
+    /// `MIN_QUANTA_THRESHOLD_US` of its CPU budget left. It runs again once
+    /// its next budget period starts.
+    BudgetExhausted,
 }
 
 impl Kernel {
@@ -181,9 +242,225 @@ impl Kernel {
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            default_quota: Cell::new(ProcessQuota::unlimited()),
+            quota_clock: OptionalCell::empty(),
+            grant_quota_exceeded: Cell::new(false),
+            trace: OptionalCell::empty(),
+            crash_dump: OptionalCell::empty(),
+            ipc_mailbox: OptionalCell::empty(),
+            dependent_restart: OptionalCell::empty(),
+            power_manager: OptionalCell::empty(),
+            heartbeats: OptionalCell::empty(),
+            upcall_overflow: OptionalCell::empty(),
+            profile: OptionalCell::empty(),
         }
     }
 
+    /// Configure the resource limits of processes.
+    ///
+    /// `default_quota` applies to every limit a process does not declare in
+    /// the Quotas TLV of its TBF header. `clock` is used to measure how much
+    /// CPU time processes use; it must keep running while processes execute.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can change the
+    /// limits of processes.
+    pub fn set_process_quotas(
+        &self,
+        default_quota: ProcessQuota,
+        clock: &'static dyn QuotaClock,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.default_quota.set(default_quota);
+        self.quota_clock.set(clock);
+    }
+
+    /// The resource limits of processes that do not declare their own.
+    pub(crate) fn get_default_quota(&self) -> ProcessQuota {
+        self.default_quota.get()
+    }
+
+    /// Record system calls, upcalls, context switches and kernel work into
+    /// `trace`. See the `trace` module for the format of the records.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can trace what
+    /// processes do.
+    pub fn set_trace(
+        &self,
+        trace: &'static KernelTrace,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.trace.set(trace);
+    }
+
+    /// Let `power_manager` choose the sleep state the chip enters when there
+    /// is nothing to do, rather than always using `Chip::sleep()`.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can change how the
+    /// kernel sleeps.
+    pub fn set_power_manager(
+        &self,
+        power_manager: &'static PowerManager,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.power_manager.set(power_manager);
+    }
+
+    /// Only tickle the watchdog while every process with a heartbeat
+    /// interval checks in with `heartbeats` on time.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can make processes
+    /// responsible for keeping the watchdog from resetting the system.
+    pub fn set_process_heartbeats(
+        &self,
+        heartbeats: &'static ProcessHeartbeats,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.heartbeats.set(heartbeats);
+    }
+
+    /// Tell the kernel that a process went over its grant memory quota.
+    pub(crate) fn grant_quota_exceeded(&self) {
+        self.grant_quota_exceeded.set(true);
+    }
+
+    /// Fault the processes that went over their grant memory quota. This must
+    /// only be called when no capsule is running, as faulting a process
+    /// resets its grants.
+    fn fault_processes_over_grant_quota(&self) {
+        if self.grant_quota_exceeded.take() {
+            for p in self.processes.iter() {
+                p.map(|process| process.fault_if_grant_quota_exceeded());
+            }
+        }
+    }
+
+    /// The trace the kernel records into, if the board set one.
+    pub(crate) fn get_trace(&self) -> Option<&'static KernelTrace> {
+        self.trace.extract()
+    }
+
+    /// Keep a record of process faults in `crash_dump`, so that they can be
+    /// inspected after the board restarts.
+    pub fn set_crash_dump(
+        &self,
+        crash_dump: &'static CrashDump,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.crash_dump.set(crash_dump);
+    }
+
+    /// Where the kernel records process faults, if the board set it.
+    pub(crate) fn get_crash_dump(&self) -> Option<&'static CrashDump> {
+        self.crash_dump.extract()
+    }
+
+    /// Tell the kernel about the board's message-passing IPC driver. The
+    /// kernel tells it when a process stops, so that operations waiting on
+    /// the process end.
+    pub fn set_ipc_mailbox<const MESSAGE_LEN: usize, const DEPTH: usize>(
+        &self,
+        ipc_mailbox: &'static IPCMailbox<MESSAGE_LEN, DEPTH>,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.ipc_mailbox.set(ipc_mailbox);
+    }
+
+    /// Tell the kernel about the board's `DependentRestart` policy, so that
+    /// processes that depend on a process that stopped are restarted with it,
+    /// however it stopped.
+    pub fn set_dependent_restart<A: Alarm<'static>, const NUM_PROCS: usize>(
+        &self,
+        dependent_restart: &'static DependentRestart<'static, A, NUM_PROCS>,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.dependent_restart.set(dependent_restart);
+    }
+
+    /// Tell the message-passing IPC driver and the `DependentRestart` policy,
+    /// if there are any, that a process stopped.
+    pub(crate) fn process_terminated(&self, processid: ProcessId) {
+        self.ipc_mailbox
+            .map(|ipc_mailbox| ipc_mailbox.process_terminated(processid));
+        self.dependent_restart
+            .map(|dependent_restart| dependent_restart.process_terminated(processid));
+    }
+
+    /// Tell the board's upcall overflow driver about the upcalls processes
+    /// lose because their upcall queue is full.
+    pub fn set_upcall_overflow(
+        &self,
+        upcall_overflow: &'static UpcallOverflow,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.upcall_overflow.set(upcall_overflow);
+    }
+
+    /// Tell the upcall overflow driver, if there is one, that a process lost
+    /// `dropped` upcalls, the last of which was `upcall_id`.
+    pub(crate) fn upcalls_dropped(
+        &self,
+        processid: ProcessId,
+        dropped: usize,
+        upcall_id: UpcallId,
+    ) {
+        self.upcall_overflow
+            .map(|upcall_overflow| upcall_overflow.upcalls_dropped(processid, dropped, upcall_id));
+    }
+
+    /// Measure how much CPU time processes, kernel work and system calls take
+    /// with `profile`.
+    pub fn set_profile(
+        &self,
+        profile: &'static KernelProfile,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.profile.set(profile);
+    }
+
+    /// Run the bottom halves of pending interrupts, for
+    /// `Scheduler::execute_kernel_work()`. The time taken is profiled as
+    /// interrupt time.
+    pub unsafe fn service_interrupts<C: Chip>(&self, chip: &C) {
+        let start = self.profile.map(|profile| profile.start());
+        chip.service_pending_interrupts();
+        self.profile
+            .map(|profile| start.map(|start| profile.interrupts(start)));
+    }
+
+    /// Run pending deferred calls while `f` returns `true`, for
+    /// `Scheduler::execute_kernel_work()`. The time taken is profiled as
+    /// deferred call time.
+    pub unsafe fn service_deferred_calls_while<F: Fn() -> bool>(&self, f: F) {
+        let start = self.profile.map(|profile| profile.start());
+        DynamicDeferredCall::call_global_instance_while(f);
+        self.profile
+            .map(|profile| start.map(|start| profile.deferred_calls(start)));
+    }
+
+    /// Where the kernel measures CPU time, if the board set it.
+    pub(crate) fn get_profile(&self) -> Option<&'static KernelProfile> {
+        self.profile.extract()
+    }
+
+    /// Record the value the kernel returns from a system call of `process`,
+    /// if there is a trace.
+    fn trace_syscall_return(&self, process: &dyn process::Process, value: &SyscallReturn) {
+        self.trace
+            .map(|trace| trace.record_syscall_return(process.processid(), value));
+    }
+
+    /// Record an event into the trace, if there is one.
+    pub(crate) fn trace(
+        &self,
+        kind: TraceKind,
+        detail: u8,
+        process: Option<ProcessId>,
+        args: [u32; 4],
+    ) {
+        self.trace
+            .map(|trace| trace.record(kind, detail, process, args));
+    }
+
     /// Something was scheduled for a process, so there is more work to do.
     ///
     /// This is only exposed in the core kernel crate.
@@ -270,6 +547,11 @@ impl Kernel {
             .unwrap_or(default)
     }
 
+    /// The process in slot `index` of the processes array, if there is one.
+    pub(crate) fn process_at(&self, index: usize) -> Option<&'static dyn process::Process> {
+        self.processes.get(index).copied().flatten()
+    }
+
     /// Run a closure on every valid process. This will iterate the array of
     /// processes and call the closure on every process that exists.
     pub(crate) fn process_each<F>(&self, closure: F)
@@ -379,7 +661,8 @@ impl Kernel {
     /// Grants **must** only be created _before_ processes are initialized.
     /// Processes use the number of grants that have been allocated to correctly
     /// initialize the process's memory with a pointer for each grant. If a
-    /// grant is created after processes are initialized this will panic.
+    /// grant is created after processes are initialized this will panic. It
+    /// will also panic if more grants are created than a process can hold.
     ///
     /// Calling this function is restricted to only certain users, and to
     /// enforce this calling this function requires the
@@ -391,6 +674,9 @@ impl Kernel {
         if self.grants_finalized.get() {
             panic!("Grants finalized. Cannot create a new grant.");
         }
+        if self.grant_counter.get() >= GRANT_BLOCK_MAX_GRANTS {
+            panic!("Too many grants. Cannot create a new grant.");
+        }
 
         // Create and return a new grant.
         let grant_index = self.grant_counter.get();
@@ -454,49 +740,105 @@ impl Kernel {
         }
     }
 
-    /// Main loop of the OS.
+    /// Perform one iteration of the core Tock kernel loop.
     ///
-    /// Most of the behavior of this loop is controlled by the `Scheduler`
-    /// implementation in use.
-    pub fn kernel_loop<P: Platform, C: Chip, SC: Scheduler<C>, const NUM_PROCS: usize>(
+    /// This function is responsible for three main operations:
+    ///
+    /// 1. Check if the kernel itself has any work to be done and if the
+    ///    scheduler wants to complete that work now. If so, it allows the
+    ///    kernel to run.
+    /// 2. Check if any processes have any work to be done, and if so if the
+    ///    scheduler wants to allow any processes to run now, and if so which
+    ///    one.
+    /// 3. After ensuring the scheduler does not want to complete any kernel
+    ///    or process work (or there is no work to be done), and there are no
+    ///    outstanding interrupts to handle, put the chip to sleep.
+    ///
+    /// This function has one configuration option: `no_sleep`. If that
+    /// argument is set to true, the kernel will never attempt to put the chip
+    /// to sleep, and this function can be called again immediately.
+    ///
+    /// `kernel_loop()` calls this function in an infinite loop. It is exposed
+    /// separately, restricted by the `MainLoopCapability`, so that other
+    /// environments (such as host-side simulations and tests) can step the
+    /// kernel one iteration at a time.
+    pub fn kernel_loop_operation<P: Platform, C: Chip, SC: Scheduler<C>, const NUM_PROCS: usize>(
         &self,
         platform: &P,
         chip: &C,
         ipc: Option<&ipc::IPC<NUM_PROCS>>,
         scheduler: &SC,
+        no_sleep: bool,
         _capability: &dyn capabilities::MainLoopCapability,
-    ) -> ! {
-        chip.watchdog().setup();
-        loop {
+    ) {
+        // Capsules that ran outside of system calls, for example in interrupt
+        // handlers, may have taken a process over its grant memory quota.
+        self.fault_processes_over_grant_quota();
+
+        // A process that missed its heartbeat keeps the watchdog from being
+        // tickled, so that a hung process is not masked by a healthy kernel.
+        let healthy = self
+            .heartbeats
+            .map_or(true, |heartbeats| heartbeats.check());
+        if healthy {
             chip.watchdog().tickle();
-            unsafe {
-                // Ask the scheduler if we should do tasks inside of the kernel,
-                // such as handle interrupts. A scheduler may want to prioritize
-                // processes instead, or there may be no kernel work to do.
-                match scheduler.do_kernel_work_now(chip) {
-                    true => {
-                        // Execute kernel work. This includes handling
-                        // interrupts and is how code in the chips/ and capsules
-                        // crates is able to execute.
-                        scheduler.execute_kernel_work(chip);
-                    }
-                    false => {
-                        // No kernel work ready, so ask scheduler for a process.
-                        match scheduler.next(self) {
-                            SchedulingDecision::RunProcess((appid, timeslice_us)) => {
-                                self.process_map_or((), appid, |process| {
-                                    let (reason, time_executed) = self.do_process(
-                                        platform,
-                                        chip,
-                                        scheduler,
-                                        process,
-                                        ipc,
-                                        timeslice_us,
-                                    );
-                                    scheduler.result(reason, time_executed);
-                                });
-                            }
-                            SchedulingDecision::TrySleep => {
+        }
+        unsafe {
+            // Ask the scheduler if we should do tasks inside of the kernel,
+            // such as handle interrupts. A scheduler may want to prioritize
+            // processes instead, or there may be no kernel work to do.
+            match scheduler.do_kernel_work_now(chip) {
+                true => {
+                    // Execute kernel work. This includes handling
+                    // interrupts and is how code in the chips/ and capsules
+                    // crates is able to execute.
+                    let trace_start = self.trace.map(|trace| {
+                        (
+                            trace.now_us(),
+                            chip.has_pending_interrupts(),
+                            DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false),
+                        )
+                    });
+                    scheduler.execute_kernel_work(self, chip);
+                    self.trace.map(|trace| {
+                        trace_start.map(|(start_us, interrupts, deferred_calls)| {
+                            trace.record_at(
+                                start_us,
+                                TraceKind::KernelWork,
+                                0,
+                                None,
+                                [
+                                    interrupts as u32,
+                                    deferred_calls as u32,
+                                    trace.now_us().wrapping_sub(start_us),
+                                    0,
+                                ],
+                            );
+                        });
+                    });
+                }
+                false => {
+                    // No kernel work ready, so ask scheduler for a process.
+                    match scheduler.next(self) {
+                        SchedulingDecision::RunProcess((appid, timeslice_us)) => {
+                            self.process_map_or((), appid, |process| {
+                                let (reason, time_executed) = self.do_process_within_quota(
+                                    platform,
+                                    chip,
+                                    scheduler,
+                                    process,
+                                    ipc,
+                                    timeslice_us,
+                                );
+                                scheduler.result(reason, time_executed);
+                            });
+                        }
+                        SchedulingDecision::TrySleep => {
+                            // For testing, it may be helpful to
+                            // disable sleeping the chip in case
+                            // the running test does not generate
+                            // any interrupts.
+                            if !no_sleep {
                                 chip.atomic(|| {
                                     // Cannot sleep if interrupts are pending,
                                     // as on most platforms unhandled interrupts
@@ -511,9 +853,19 @@ impl Kernel {
                                         && !DynamicDeferredCall::global_instance_calls_pending()
                                             .unwrap_or(false)
                                     {
-                                        chip.watchdog().suspend();
-                                        chip.sleep();
-                                        chip.watchdog().resume();
+                                        // Resuming the watchdog tickles it,
+                                        // so leave it running if it must
+                                        // not be tickled.
+                                        if healthy {
+                                            chip.watchdog().suspend();
+                                        }
+                                        match self.power_manager.extract() {
+                                            Some(power_manager) => power_manager.sleep(chip),
+                                            None => chip.sleep(),
+                                        }
+                                        if healthy {
+                                            chip.watchdog().resume();
+                                        }
                                     }
                                 });
                             }
@@ -524,6 +876,82 @@ impl Kernel {
         }
     }
 
+    /// Main loop of the OS.
+    ///
+    /// Most of the behavior of this loop is controlled by the `Scheduler`
+    /// implementation in use.
+    pub fn kernel_loop<P: Platform, C: Chip, SC: Scheduler<C>, const NUM_PROCS: usize>(
+        &self,
+        platform: &P,
+        chip: &C,
+        ipc: Option<&ipc::IPC<NUM_PROCS>>,
+        scheduler: &SC,
+        capability: &dyn capabilities::MainLoopCapability,
+    ) -> ! {
+        chip.watchdog().setup();
+        loop {
+            self.kernel_loop_operation(platform, chip, ipc, scheduler, false, capability);
+        }
+    }
+
+    /// Run a process with `do_process()`, enforcing its CPU budget.
+    ///
+    /// If the process has a CPU budget and the board provided a
+    /// `QuotaClock`, the timeslice of the process is cut down to the budget it
+    /// has left in the current period. This applies to schedulers that run
+    /// processes cooperatively as well. A process with less than
+    /// `MIN_QUANTA_THRESHOLD_US` of budget left is held until its next period
+    /// starts. Only a process that was still running when it had used its
+    /// whole budget faults, so its `FaultResponse` decides what happens to it.
+    fn do_process_within_quota<P: Platform, C: Chip, S: Scheduler<C>, const NUM_PROCS: usize>(
+        &self,
+        platform: &P,
+        chip: &C,
+        scheduler: &S,
+        process: &dyn process::Process,
+        ipc: Option<&crate::ipc::IPC<NUM_PROCS>>,
+        timeslice_us: Option<u32>,
+    ) -> (StoppedExecutingReason, Option<u32>) {
+        let budget = self.quota_clock.map_or(None, |clock| {
+            let start = clock.now_ticks();
+            process
+                .cpu_budget_remaining_us(*clock)
+                .map(|remaining_us| (*clock, start, remaining_us))
+        });
+        let (clock, start, remaining_us) = match budget {
+            Some(budget) => budget,
+            None => return self.do_process(platform, chip, scheduler, process, ipc, timeslice_us),
+        };
+        if remaining_us <= MIN_QUANTA_THRESHOLD_US {
+            return (
+                StoppedExecutingReason::BudgetExhausted,
+                timeslice_us.map(|_| 0),
+            );
+        }
+
+        let budget_limited = timeslice_us.map_or(true, |timeslice| remaining_us < timeslice);
+        let timeslice_us = if budget_limited {
+            Some(remaining_us)
+        } else {
+            timeslice_us
+        };
+
+        let (reason, time_executed) =
+            self.do_process(platform, chip, scheduler, process, ipc, timeslice_us);
+
+        process.consume_cpu_budget_us(clock.us_since(start));
+        // `do_process()` also stops a process that has a little of its
+        // timeslice left, which must not count as going over the budget.
+        if budget_limited
+            && reason == StoppedExecutingReason::TimesliceExpired
+            && process.cpu_budget_remaining_us(clock) == Some(0)
+        {
+            process.debug_quota_exceeded();
+            process.set_fault_state();
+        }
+        (reason, time_executed)
+    }
+
     /// Transfer control from the kernel to a userspace process.
     ///
     /// This function is called by the main kernel loop to run userspace code.
@@ -598,6 +1026,12 @@ impl Kernel {
             if stop_running {
                 // Process ran out of time while the kernel was executing.
                 process.debug_timeslice_expired();
+                self.trace(
+                    TraceKind::TimesliceExpired,
+                    0,
+                    Some(process.processid()),
+                    [0; 4],
+                );
                 return_reason = StoppedExecutingReason::TimesliceExpired;
                 break;
             }
@@ -626,11 +1060,40 @@ impl Kernel {
                     // underlying timer is not affected.
                     process.setup_mpu();
 
+                    self.trace(
+                        TraceKind::SwitchToProcess,
+                        0,
+                        Some(process.processid()),
+                        [
+                            timeslice_us.map_or(u32::MAX, |_| {
+                                scheduler_timer.get_remaining_us().unwrap_or(0)
+                            }),
+                            0,
+                            0,
+                            0,
+                        ],
+                    );
                     chip.mpu().enable_app_mpu();
                     scheduler_timer.arm();
+                    let profile_start = self.profile.map(|profile| profile.start());
                     let context_switch_reason = process.switch_to();
+                    self.profile.map(|profile| {
+                        profile_start.map(|start| process.debug_ran(profile.ticks_since(start)));
+                    });
                     scheduler_timer.disarm();
                     chip.mpu().disable_app_mpu();
+                    let switch_from_reason = match context_switch_reason {
+                        Some(ContextSwitchReason::Fault) => SwitchFromReason::Fault,
+                        Some(ContextSwitchReason::SyscallFired { .. }) => SwitchFromReason::Syscall,
+                        Some(ContextSwitchReason::Interrupted) => SwitchFromReason::Interrupted,
+                        None => SwitchFromReason::Failed,
+                    };
+                    self.trace(
+                        TraceKind::SwitchFromProcess,
+                        switch_from_reason as u8,
+                        Some(process.processid()),
+                        [0; 4],
+                    );
 
                     // Now the process has returned back to the kernel. Check
                     // why and handle the process as appropriate.
@@ -644,12 +1107,25 @@ impl Kernel {
                             }
                         }
                         Some(ContextSwitchReason::SyscallFired { syscall }) => {
+                            let profile_start = self.profile.map(|profile| profile.start());
                             self.handle_syscall(platform, process, syscall);
+                            // Not all system calls set a return value, which
+                            // faults a process over its grant memory quota.
+                            process.fault_if_grant_quota_exceeded();
+                            self.profile.map(|profile| {
+                                profile_start.map(|start| profile.syscall(start, syscall));
+                            });
                         }
                         Some(ContextSwitchReason::Interrupted) => {
                             if scheduler_timer.get_remaining_us().is_none() {
                                 // This interrupt was a timeslice expiration.
                                 process.debug_timeslice_expired();
+                                self.trace(
+                                    TraceKind::TimesliceExpired,
+                                    0,
+                                    Some(process.processid()),
+                                    [0; 4],
+                                );
                                 return_reason = StoppedExecutingReason::TimesliceExpired;
                                 break;
                             }
@@ -675,17 +1151,17 @@ impl Kernel {
                         None => break,
                         Some(cb) => match cb {
                             Task::FunctionCall(ccb) => {
-                                if config::CONFIG.trace_syscalls {
-                                    debug!(
-                                        "[{:?}] function_call @{:#x}({:#x}, {:#x}, {:#x}, {:#x})",
-                                        process.processid(),
-                                        ccb.pc,
-                                        ccb.argument0,
-                                        ccb.argument1,
-                                        ccb.argument2,
-                                        ccb.argument3,
-                                    );
-                                }
+                                self.trace(
+                                    TraceKind::FunctionCall,
+                                    0,
+                                    Some(process.processid()),
+                                    [
+                                        ccb.pc as u32,
+                                        ccb.argument0 as u32,
+                                        ccb.argument1 as u32,
+                                        ccb.argument2 as u32,
+                                    ],
+                                );
                                 process.set_process_function(ccb);
                             }
                             Task::IPC((otherapp, ipc_type)) => {
@@ -766,6 +1242,8 @@ impl Kernel {
     ) {
         // Hook for process debugging.
         process.debug_syscall_called(syscall);
+        self.trace
+            .map(|trace| trace.record_syscall(process.processid(), &syscall));
 
         // Enforce platform-specific syscall filtering here.
         //
@@ -797,7 +1275,47 @@ impl Kernel {
             _ => {
                 // Check all other syscalls for filtering
                 if let Err(response) = platform.filter_syscall(process, &syscall) {
-                    process.set_syscall_return_value(SyscallReturn::Failure(response));
+                    self.trace(
+                        TraceKind::SyscallFiltered,
+                        trace::syscall_class(&syscall),
+                        Some(process.processid()),
+                        [usize::from(response) as u32, 0, 0, 0],
+                    );
+                    process.debug_syscall_filtered(syscall);
+                    // Subscribe and allow calls hand the upcall or buffer
+                    // back to the process, as if the driver rejected them.
+                    let rval = match syscall {
+                        Syscall::Subscribe {
+                            upcall_ptr,
+                            appdata,
+                            ..
+                        } => match NonNull::new(upcall_ptr) {
+                            Some(ptr) => SyscallReturn::SubscribeFailure(
+                                response,
+                                ptr.as_ptr() as *const u8,
+                                appdata,
+                            ),
+                            None => SyscallReturn::SubscribeFailure(response, 0 as *const u8, 0),
+                        },
+                        Syscall::ReadWriteAllow {
+                            allow_address,
+                            allow_size,
+                            ..
+                        } => SyscallReturn::AllowReadWriteFailure(
+                            response,
+                            allow_address,
+                            allow_size,
+                        ),
+                        Syscall::ReadOnlyAllow {
+                            allow_address,
+                            allow_size,
+                            ..
+                        } => {
+                            SyscallReturn::AllowReadOnlyFailure(response, allow_address, allow_size)
+                        }
+                        _ => SyscallReturn::Failure(response),
+                    };
+                    process.set_syscall_return_value(rval);
 
                     return;
                 }
@@ -808,21 +1326,10 @@ impl Kernel {
         match syscall {
             Syscall::Memop { operand, arg0 } => {
                 let rval = memop::memop(process, operand, arg0);
-                if config::CONFIG.trace_syscalls {
-                    debug!(
-                        "[{:?}] memop({}, {:#x}) = {:?}",
-                        process.processid(),
-                        operand,
-                        arg0,
-                        rval
-                    );
-                }
+                self.trace_syscall_return(process, &rval);
                 process.set_syscall_return_value(rval);
             }
             Syscall::Yield { which, address } => {
-                if config::CONFIG.trace_syscalls {
-                    debug!("[{:?}] yield. which: {}", process.processid(), which);
-                }
                 if which > (YieldCall::Wait as usize) {
                     // Only 0 and 1 are valid, so this is not a valid
                     // yield system call, Yield does not have a return
@@ -898,17 +1405,7 @@ impl Kernel {
                     }
                     None => upcall.into_subscribe_failure(ErrorCode::NODEVICE),
                 });
-                if config::CONFIG.trace_syscalls {
-                    debug!(
-                        "[{:?}] subscribe({:#x}, {}, @{:#x}, {:#x}) = {:?}",
-                        process.processid(),
-                        driver_number,
-                        subdriver_number,
-                        upcall_ptr as usize,
-                        appdata,
-                        rval
-                    );
-                }
+                self.trace_syscall_return(process, &rval);
 
                 process.set_syscall_return_value(rval);
             }
@@ -925,17 +1422,7 @@ impl Kernel {
 
                 let res = SyscallReturn::from_command_return(cres);
 
-                if config::CONFIG.trace_syscalls {
-                    debug!(
-                        "[{:?}] cmd({:#x}, {}, {:#x}, {:#x}) = {:?}",
-                        process.processid(),
-                        driver_number,
-                        subdriver_number,
-                        arg0,
-                        arg1,
-                        res,
-                    );
-                }
+                self.trace_syscall_return(process, &res);
                 process.set_syscall_return_value(res);
             }
             Syscall::ReadWriteAllow {
@@ -952,10 +1439,27 @@ impl Kernel {
                         //
                         // TODO: Enforce anti buffer-aliasing guarantees to avoid
                         // undefined behavior in Rust.
-                        match process.build_readwrite_appslice(allow_address, allow_size) {
-                            Ok(appslice) => {
+                        //
+                        // A pinned buffer cannot be taken back, nor shared
+                        // again, until the hardware is done with it.
+                        let allow_id = AllowId {
+                            driver_num: driver_number,
+                            allow_num: subdriver_number,
+                        };
+                        let appslice = if process.pinned_buffer_conflict(
+                            Some(allow_id),
+                            allow_address,
+                            allow_size,
+                        ) {
+                            Err(ErrorCode::BUSY)
+                        } else {
+                            process.build_readwrite_appslice(allow_address, allow_size)
+                        };
+                        match appslice {
+                            Ok(mut appslice) => {
                                 // Creating the [`ReadWriteAppSlice`] worked,
                                 // provide it to the capsule.
+                                appslice.set_allow_id(driver_number, subdriver_number);
                                 match d.allow_readwrite(
                                     process.processid(),
                                     subdriver_number,
@@ -995,17 +1499,7 @@ impl Kernel {
                     ),
                 });
 
-                if config::CONFIG.trace_syscalls {
-                    debug!(
-                        "[{:?}] read-write allow({:#x}, {}, @{:#x}, {:#x}) = {:?}",
-                        process.processid(),
-                        driver_number,
-                        subdriver_number,
-                        allow_address as usize,
-                        allow_size,
-                        res
-                    );
-                }
+                self.trace_syscall_return(process, &res);
                 process.set_syscall_return_value(res);
             }
             Syscall::ReadOnlyAllow {
@@ -1022,7 +1516,16 @@ impl Kernel {
                         //
                         // TODO: Enforce anti buffer-aliasing guarantees to avoid
                         // undefined behavior in Rust.
-                        match process.build_readonly_appslice(allow_address, allow_size) {
+                        //
+                        // Memory that is pinned cannot be shared again until
+                        // the hardware is done with it.
+                        let appslice =
+                            if process.pinned_buffer_conflict(None, allow_address, allow_size) {
+                                Err(ErrorCode::BUSY)
+                            } else {
+                                process.build_readonly_appslice(allow_address, allow_size)
+                            };
+                        match appslice {
                             Ok(appslice) => {
                                 // Creating the [`ReadOnlyAppSlice`] worked,
                                 // provide it to the capsule.
@@ -1071,18 +1574,7 @@ impl Kernel {
                     ),
                 });
 
-                if config::CONFIG.trace_syscalls {
-                    debug!(
-                        "[{:?}] read-only allow({:#x}, {}, @{:#x}, {:#x}) = {:?}",
-                        process.processid(),
-                        driver_number,
-                        subdriver_number,
-                        allow_address as usize,
-                        allow_size,
-                        res
-                    );
-                }
-
+                self.trace_syscall_return(process, &res);
                 process.set_syscall_return_value(res);
             }
             Syscall::Exit {
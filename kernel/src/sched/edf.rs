@@ -0,0 +1,326 @@
+//! Real-time scheduler for Tock
+//!
+//! Processes declare a period, a deadline and a budget in the Scheduling TLV of
+//! their TBF header. This scheduler treats each such process as a sporadic
+//! task: a job of the process is released when the process becomes ready to
+//! run, at most once per period, and the job is done when the process has no
+//! more work to do (it yields waiting for an upcall). Each job must be done
+//! before its deadline, counted from its release.
+//!
+//! This scheduler can be summarized by the following rules:
+//!
+//! - Rule 1: Jobs with budget left run before all other processes. With
+//!           `RealTimePolicy::EarliestDeadlineFirst` the job with the earliest
+//!           deadline runs first. With `RealTimePolicy::RateMonotonic` the job
+//!           of the process with the shortest period runs first.
+//! - Rule 2: A job runs with its remaining budget as its timeslice, so the
+//!           scheduler timer stops a job that overruns its budget.
+//! - Rule 3: When no job can run, ready processes run round robin in the
+//!           background. These are processes without real-time parameters,
+//!           jobs that used up their budget, and processes that became ready
+//!           again before their next period.
+//! - Rule 4: A job that is not done by its deadline is counted as a deadline
+//!           miss, which `introspection::KernelInfo` reports.
+//!
+//! Kernel tasks (bottom half interrupt handling / deferred call handling)
+//! always take priority over userspace processes, so upcalls that release jobs
+//! reach their processes as soon as possible.
+
+use crate::common::cells::OptionalCell;
+use crate::common::dynamic_deferred_call::DynamicDeferredCall;
+use crate::common::list::{List, ListLink, ListNode};
+use crate::hil::time::{self, Frequency, Ticks};
+use crate::platform::Chip;
+use crate::process::{Process, ProcessId};
+use crate::sched::{
+    Kernel, Scheduler, SchedulingDecision, StoppedExecutingReason, MIN_QUANTA_THRESHOLD_US,
+};
+use core::cell::Cell;
+use tock_tbf::types::TbfHeaderV2Scheduling;
+
+/// How the scheduler orders jobs that are ready to run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RealTimePolicy {
+    /// The job with the earliest deadline runs first.
+    EarliestDeadlineFirst,
+    /// The job of the process with the shortest period runs first.
+    RateMonotonic,
+}
+
+/// Nodes store per-process state
+pub struct EDFProcessNode<'a, A: 'static + time::Alarm<'static>> {
+    proc: &'static Option<&'static dyn Process>,
+    /// The process the job state below belongs to. A restarted process has a
+    /// new `ProcessId` and starts without a job.
+    owner: Cell<Option<ProcessId>>,
+    /// Whether a job has been released and is not done yet.
+    active: Cell<bool>,
+    /// When the current (or last) job was released.
+    release: Cell<A::Ticks>,
+    /// When the current job must be done.
+    deadline: Cell<A::Ticks>,
+    /// CPU time used by the current job.
+    used_us: Cell<u32>,
+    /// Whether the current job has already been counted as a deadline miss.
+    missed: Cell<bool>,
+    next: ListLink<'a, EDFProcessNode<'a, A>>,
+}
+
+impl<'a, A: 'static + time::Alarm<'static>> EDFProcessNode<'a, A> {
+    pub fn new(proc: &'static Option<&'static dyn Process>) -> EDFProcessNode<'a, A> {
+        EDFProcessNode {
+            proc,
+            owner: Cell::new(None),
+            active: Cell::new(false),
+            release: Cell::new(A::Ticks::from(0)),
+            deadline: Cell::new(A::Ticks::from(0)),
+            used_us: Cell::new(0),
+            missed: Cell::new(false),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// The process in this slot and its real-time parameters, if it has any.
+    fn realtime_process(&self) -> Option<(&'static dyn Process, TbfHeaderV2Scheduling)> {
+        self.proc.and_then(|proc| {
+            proc.get_scheduling_parameters()
+                .map(|parameters| (proc, parameters))
+        })
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>> ListNode<'a, EDFProcessNode<'a, A>>
+    for EDFProcessNode<'a, A>
+{
+    fn next(&'a self) -> &'static ListLink<'a, EDFProcessNode<'a, A>> {
+        &self.next
+    }
+}
+
+/// A job that can run now: its node, its priority (lower runs first) and the
+/// budget it has left.
+type Job<'a, A> = (&'a EDFProcessNode<'a, A>, u32, u32);
+
+pub struct EDFSched<'a, A: 'static + time::Alarm<'static>> {
+    alarm: &'static A,
+    policy: RealTimePolicy,
+    pub processes: List<'a, EDFProcessNode<'a, A>>,
+    /// The node of the process that is running, and whether it runs as a
+    /// real-time job rather than in the background.
+    running: OptionalCell<(&'a EDFProcessNode<'a, A>, bool)>,
+    /// Position in `processes` where the search for the next background
+    /// process starts, so that background processes take turns.
+    next_background: Cell<usize>,
+}
+
+impl<'a, A: 'static + time::Alarm<'static>> EDFSched<'a, A> {
+    /// How long a process can run in the background before being pre-empted
+    pub const BACKGROUND_TIMESLICE_US: u32 = 10000;
+
+    /// Shortest timeslice a process runs in the background for. The kernel
+    /// does not run a process for a timeslice of `MIN_QUANTA_THRESHOLD_US` or
+    /// less, so a job released within this time of a background process
+    /// being scheduled may start this much late.
+    pub const MIN_BACKGROUND_TIMESLICE_US: u32 = 2 * MIN_QUANTA_THRESHOLD_US;
+
+    pub fn new(alarm: &'static A, policy: RealTimePolicy) -> Self {
+        Self {
+            alarm,
+            policy,
+            processes: List::new(),
+            running: OptionalCell::empty(),
+            next_background: Cell::new(0),
+        }
+    }
+
+    fn ticks_to_us(ticks: A::Ticks) -> u32 {
+        let us = ticks.into_u32() as u64 * 1_000_000 / A::Frequency::frequency() as u64;
+        us as u32
+    }
+
+    /// Count a deadline miss if the job of `node` is not done and its
+    /// deadline has passed.
+    fn check_deadline(&self, node: &EDFProcessNode<'a, A>, proc: &dyn Process, now: A::Ticks) {
+        if node.active.get()
+            && !node.missed.get()
+            && !now.within_range(node.release.get(), node.deadline.get())
+        {
+            node.missed.set(true);
+            proc.debug_deadline_missed();
+        }
+    }
+
+    /// Bring the job state of `node` up to date: forget the job of a process
+    /// that restarted, count deadline misses and release a new job if the
+    /// process is ready and its period has passed.
+    fn update_job(
+        &self,
+        node: &EDFProcessNode<'a, A>,
+        proc: &dyn Process,
+        parameters: &TbfHeaderV2Scheduling,
+        now: A::Ticks,
+    ) {
+        let period = A::ticks_from_us(parameters.period_us());
+        if node.owner.get() != Some(proc.processid()) {
+            node.owner.set(Some(proc.processid()));
+            node.active.set(false);
+            // Allow a job to be released right away.
+            node.release.set(now.wrapping_sub(period));
+        }
+
+        self.check_deadline(node, proc, now);
+
+        let next_release = node.release.get().wrapping_add(period);
+        if !node.active.get() && !now.within_range(node.release.get(), next_release) {
+            if proc.ready() {
+                node.active.set(true);
+                node.release.set(now);
+                node.deadline
+                    .set(now.wrapping_add(A::ticks_from_us(parameters.deadline_us())));
+                node.used_us.set(0);
+                node.missed.set(false);
+            } else {
+                // Keep the last release within one period of now, so that it
+                // does not wrap around while the process waits.
+                node.release.set(now.wrapping_sub(period));
+            }
+        }
+    }
+
+    /// The job that should run now, if any. Also returns how long until the
+    /// next job of a process that is ready but waiting for its period to end
+    /// can be released.
+    fn next_job(&self, now: A::Ticks) -> (Option<Job<'a, A>>, Option<u32>) {
+        let mut best: Option<Job<'a, A>> = None;
+        let mut next_release_us: Option<u32> = None;
+        for node in self.processes.iter() {
+            let (proc, parameters) = match node.realtime_process() {
+                Some(realtime) => realtime,
+                None => continue,
+            };
+            self.update_job(node, proc, &parameters, now);
+
+            if !proc.ready() {
+                continue;
+            }
+            if !node.active.get() {
+                let period = A::ticks_from_us(parameters.period_us());
+                let until_release = node.release.get().wrapping_add(period).wrapping_sub(now);
+                let until_release_us = Self::ticks_to_us(until_release);
+                if next_release_us.map_or(true, |us| until_release_us < us) {
+                    next_release_us = Some(until_release_us);
+                }
+                continue;
+            }
+
+            let budget_left_us = parameters.budget_us().saturating_sub(node.used_us.get());
+            if budget_left_us == 0 {
+                continue;
+            }
+            let priority = match self.policy {
+                RealTimePolicy::EarliestDeadlineFirst => {
+                    if node.missed.get() {
+                        0
+                    } else {
+                        Self::ticks_to_us(node.deadline.get().wrapping_sub(now))
+                    }
+                }
+                RealTimePolicy::RateMonotonic => parameters.period_us(),
+            };
+            if best.map_or(true, |(_, best_priority, _)| priority < best_priority) {
+                best = Some((node, priority, budget_left_us));
+            }
+        }
+        (best, next_release_us)
+    }
+
+    /// The next ready process to run in the background, taking turns.
+    fn next_background_node(&self) -> Option<&'a EDFProcessNode<'a, A>> {
+        let count = self.processes.iter().count();
+        let start = self.next_background.get();
+        for offset in 0..count {
+            let position = (start + offset) % count;
+            let node = self.processes.iter().nth(position);
+            if let Some(node) = node {
+                if node.proc.map_or(false, |proc| proc.ready()) {
+                    self.next_background.set(position + 1);
+                    return Some(node);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>, C: Chip> Scheduler<C> for EDFSched<'a, A> {
+    fn next(&self, kernel: &Kernel) -> SchedulingDecision {
+        if kernel.processes_blocked() {
+            // No processes ready
+            return SchedulingDecision::TrySleep;
+        }
+
+        let now = self.alarm.now();
+        let (job, next_release_us) = self.next_job(now);
+        if let Some((node, _, budget_left_us)) = job {
+            // Panic if fail bc next_job() only returns ready processes!
+            let next = node.proc.unwrap().processid();
+            self.running.set((node, true));
+            return SchedulingDecision::RunProcess((next, Some(budget_left_us)));
+        }
+
+        match self.next_background_node() {
+            Some(node) => {
+                // Stop in time to start the next job that is waiting for its
+                // period to end, but run for long enough to make progress.
+                let timeslice = next_release_us.map_or(Self::BACKGROUND_TIMESLICE_US, |us| {
+                    us.clamp(
+                        Self::MIN_BACKGROUND_TIMESLICE_US,
+                        Self::BACKGROUND_TIMESLICE_US,
+                    )
+                });
+                // Panic if fail bc next_background_node() only returns ready
+                // processes!
+                let next = node.proc.unwrap().processid();
+                self.running.set((node, false));
+                SchedulingDecision::RunProcess((next, Some(timeslice)))
+            }
+            None => SchedulingDecision::TrySleep,
+        }
+    }
+
+    unsafe fn continue_process(&self, _: ProcessId, chip: &C) -> bool {
+        if chip.has_pending_interrupts()
+            || DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false)
+        {
+            return false;
+        }
+        // A system call by this process can make a job that should run first
+        // ready, for example through IPC.
+        let (job, _) = self.next_job(self.alarm.now());
+        self.running.map_or(true, |(running, realtime)| match job {
+            Some((node, _, _)) => *realtime && node as *const _ == *running as *const _,
+            None => true,
+        })
+    }
+
+    fn result(&self, result: StoppedExecutingReason, execution_time_us: Option<u32>) {
+        let execution_time_us = execution_time_us.unwrap_or(0); // we never run cooperatively
+        let now = self.alarm.now();
+        self.running.take().map(|(node, realtime)| {
+            if realtime {
+                node.used_us
+                    .set(node.used_us.get().saturating_add(execution_time_us));
+            }
+            if let Some((proc, _)) = node.realtime_process() {
+                if result == StoppedExecutingReason::NoWorkLeft
+                    && !proc.ready()
+                    && node.owner.get() == Some(proc.processid())
+                {
+                    // The job is done, check whether it was in time.
+                    self.check_deadline(node, proc, now);
+                    node.active.set(false);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,82 @@
+//! Permissions for values in persistent storage shared by processes and the
+//! kernel.
+//!
+//! Every stored value is tagged with the write ID of whoever stored it. A
+//! process gets its write ID, and the write IDs of the values it may read and
+//! modify, from the storage permissions element of its TBF header. A process
+//! may always read and modify the values it stored itself. Processes without
+//! the element may not access storage at all.
+//!
+//! The kernel uses write ID `0`, and may read and modify every value.
+//! Processes may list `0` to access values the kernel stored.
+
+use crate::capabilities;
+
+#[derive(Clone, Copy, Debug)]
+enum Permissions {
+    Kernel,
+    Process(tock_tbf::types::TbfHeaderV2StoragePermissions),
+    None,
+}
+
+/// What a process, or the kernel, may do with stored values.
+#[derive(Clone, Copy, Debug)]
+pub struct StoragePermissions(Permissions);
+
+impl StoragePermissions {
+    /// The write ID values stored by the kernel are tagged with.
+    pub const KERNEL_WRITE_ID: u32 = 0;
+
+    /// Permissions to read and modify every value, tagging new values with
+    /// `KERNEL_WRITE_ID`.
+    pub fn new_kernel_permissions(_capability: &dyn capabilities::KernelStorageCapability) -> Self {
+        StoragePermissions(Permissions::Kernel)
+    }
+
+    /// Permissions that do not allow any access.
+    pub fn new_null_permissions() -> Self {
+        StoragePermissions(Permissions::None)
+    }
+
+    pub(crate) fn from_tbf(
+        permissions: Option<tock_tbf::types::TbfHeaderV2StoragePermissions>,
+    ) -> Self {
+        match permissions {
+            Some(p) => StoragePermissions(Permissions::Process(p)),
+            None => StoragePermissions(Permissions::None),
+        }
+    }
+
+    /// The ID new values are tagged with, or `None` if the holder may not
+    /// store values.
+    pub fn write_id(&self) -> Option<u32> {
+        match self.0 {
+            Permissions::Kernel => Some(Self::KERNEL_WRITE_ID),
+            Permissions::Process(p) => p.write_id(),
+            Permissions::None => None,
+        }
+    }
+
+    /// Whether the holder may read a value tagged with `write_id`.
+    pub fn can_read(&self, write_id: u32) -> bool {
+        match self.0 {
+            Permissions::Kernel => true,
+            Permissions::Process(p) => {
+                p.write_id() == Some(write_id) || p.read_ids().any(|id| id == write_id)
+            }
+            Permissions::None => false,
+        }
+    }
+
+    /// Whether the holder may overwrite or delete a value tagged with
+    /// `write_id`.
+    pub fn can_modify(&self, write_id: u32) -> bool {
+        match self.0 {
+            Permissions::Kernel => true,
+            Permissions::Process(p) => {
+                p.write_id() == Some(write_id) || p.modify_ids().any(|id| id == write_id)
+            }
+            Permissions::None => false,
+        }
+    }
+}
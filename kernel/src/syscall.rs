@@ -586,4 +586,23 @@ pub trait UserspaceKernelBoundary {
         state: &Self::StoredState,
         writer: &mut dyn Write,
     );
+
+    /// Copy the CPU registers of a process identified by the stored state for
+    /// that process into `registers`, so they can be kept in a crash dump.
+    /// The order of the registers is architecture specific. Returns how many
+    /// registers were copied.
+    ///
+    /// ### Safety
+    ///
+    /// This function guarantees that it if needs to read process memory, it
+    /// will only read memory starting at `accessible_memory_start` and before
+    /// `app_brk`. The caller is responsible for guaranteeing that those
+    /// pointers are valid for the process.
+    unsafe fn store_registers(
+        &self,
+        accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &Self::StoredState,
+        registers: &mut [u32],
+    ) -> usize;
 }
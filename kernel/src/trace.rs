@@ -0,0 +1,324 @@
+//! Binary trace of kernel events.
+//!
+//! The kernel records what it does on behalf of processes into a
+//! `KernelTrace` ring buffer, if the board provides one with
+//! `Kernel::set_trace()`. Recording an event only copies a small fixed-size
+//! record into RAM, so unlike printing with `debug!` it does not flood the
+//! console or noticeably change the timing of the system being traced.
+//!
+//! Each record is `RECORD_LEN` bytes, with all fields little endian:
+//!
+//! ```text
+//! 0         4      5        6         8        12       16       20       24
+//! +---------+------+--------+---------+--------+--------+--------+--------+
+//! | time_us | kind | detail | process | arg0   | arg1   | arg2   | arg3   |
+//! +---------+------+--------+---------+--------+--------+--------+--------+
+//! ```
+//!
+//! - `time_us` is the time of the event in microseconds since the trace was
+//!   created, counted with the clock the trace was created with. It wraps
+//!   around after about 71 minutes, not when the counter of the clock does.
+//! - `kind` is a `TraceKind`, which also defines what `detail` and the
+//!   arguments mean.
+//! - `process` is the low 16 bits of the `ProcessId::id()` of the process the
+//!   event is about, or `KERNEL` if it is not about a process.
+//!
+//! When the buffer is full the oldest records are overwritten, and counted,
+//! so that the trace always holds the most recent events. Records can be
+//! drained from the buffer with `KernelTrace::drain()`, for example with the
+//! `trace` command of `capsules::process_console` or over Segger RTT with
+//! `capsules::trace_drain`. `tools/trace_decoder` turns drained records back
+//! into text.
+
+use core::cell::Cell;
+
+use crate::common::cells::MapCell;
+use crate::process::ProcessId;
+use crate::process_policies::QuotaClock;
+use crate::syscall::{Syscall, SyscallReturn};
+
+/// Length of a trace record in bytes.
+pub const RECORD_LEN: usize = 24;
+
+/// Value of the `process` field of records that are not about a process.
+pub const KERNEL: u16 = 0xffff;
+
+/// The kinds of events the kernel records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TraceKind {
+    /// A process made a system call. `detail` is the system call class, and
+    /// the arguments are the arguments of the system call.
+    Syscall = 1,
+    /// The kernel finished a system call. The arguments are the return value
+    /// encoded into registers as the process receives it.
+    SyscallReturn = 2,
+    /// The kernel refused a system call. `detail` is the system call class and
+    /// `arg0` the error code returned to the process.
+    SyscallFiltered = 3,
+    /// A driver scheduled an upcall. `detail` is 1 if the upcall was queued
+    /// and 0 if it was dropped. The arguments are the driver number, the
+    /// subscribe number and the first two upcall arguments.
+    UpcallScheduled = 4,
+    /// Pending upcalls were removed because the process subscribed again. The
+    /// arguments are the driver number, the subscribe number and the number
+    /// of upcalls removed.
+    UpcallsRemoved = 5,
+    /// The kernel set up the process to run a function: an upcall, or the
+    /// entry point when the process starts. The arguments are the address of
+    /// the function and its first three arguments.
+    FunctionCall = 6,
+    /// The kernel switched to the process. `arg0` is how much of its
+    /// timeslice the process has left in microseconds, or `u32::MAX` if it
+    /// runs without a timeslice.
+    SwitchToProcess = 7,
+    /// The process returned to the kernel. `detail` is a
+    /// `SwitchFromReason`.
+    SwitchFromProcess = 8,
+    /// The timeslice of the process expired.
+    TimesliceExpired = 9,
+    /// The kernel handled interrupt bottom halves and deferred calls. `arg0`
+    /// is 1 if interrupts were pending, `arg1` is 1 if deferred calls were
+    /// pending, and `arg2` is how long the kernel took in microseconds. The
+    /// record is timestamped when the kernel started.
+    KernelWork = 10,
+}
+
+/// Why a process returned to the kernel, the `detail` of
+/// `TraceKind::SwitchFromProcess` records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SwitchFromReason {
+    Fault = 0,
+    Syscall = 1,
+    Interrupted = 2,
+    /// The kernel could not switch to the process.
+    Failed = 3,
+}
+
+/// Ring buffer of trace records.
+pub struct KernelTrace {
+    buffer: MapCell<&'static mut [u8]>,
+    clock: &'static dyn QuotaClock,
+    /// Value of the clock's counter when `time_us` was last advanced.
+    last_ticks: Cell<u32>,
+    /// Microseconds since the trace was created, wrapping around.
+    time_us: Cell<u32>,
+    /// Elapsed ticks not counted in `time_us` yet, times 1,000,000. Always
+    /// less than the frequency of the clock.
+    remainder: Cell<u32>,
+    /// Index of the oldest record in the buffer.
+    start: Cell<usize>,
+    /// Number of records in the buffer.
+    len: Cell<usize>,
+    /// Number of records that were overwritten before being drained.
+    overwritten: Cell<usize>,
+}
+
+impl KernelTrace {
+    /// Create a trace that keeps its records in `buffer` and timestamps them
+    /// with `clock`. The buffer holds `buffer.len() / RECORD_LEN` records.
+    ///
+    /// Time is counted from the ticks that elapse between events, so while
+    /// nothing is recorded for longer than one wrap of the clock's counter,
+    /// the time of the wraps is lost.
+    pub fn new(buffer: &'static mut [u8], clock: &'static dyn QuotaClock) -> KernelTrace {
+        KernelTrace {
+            buffer: MapCell::new(buffer),
+            clock,
+            last_ticks: Cell::new(clock.now_ticks()),
+            time_us: Cell::new(0),
+            remainder: Cell::new(0),
+            start: Cell::new(0),
+            len: Cell::new(0),
+            overwritten: Cell::new(0),
+        }
+    }
+
+    /// The current time of the trace, for events that record how long
+    /// something took. Advances the time by the ticks elapsed since it was
+    /// last read.
+    pub(crate) fn now_us(&self) -> u32 {
+        let now = self.clock.now_ticks();
+        let elapsed = self.clock.ticks_between(self.last_ticks.get(), now) as u64;
+        self.last_ticks.set(now);
+        let frequency = self.clock.frequency() as u64;
+        let scaled = elapsed * 1_000_000 + self.remainder.get() as u64;
+        self.remainder.set((scaled % frequency) as u32);
+        self.time_us
+            .set(self.time_us.get().wrapping_add((scaled / frequency) as u32));
+        self.time_us.get()
+    }
+
+    /// Add a record, timestamped now.
+    pub(crate) fn record(
+        &self,
+        kind: TraceKind,
+        detail: u8,
+        process: Option<ProcessId>,
+        args: [u32; 4],
+    ) {
+        self.record_at(self.now_us(), kind, detail, process, args);
+    }
+
+    /// Add a record with the timestamp `time_us`.
+    pub(crate) fn record_at(
+        &self,
+        time_us: u32,
+        kind: TraceKind,
+        detail: u8,
+        process: Option<ProcessId>,
+        args: [u32; 4],
+    ) {
+        self.buffer.map(|buffer| {
+            let capacity = buffer.len() / RECORD_LEN;
+            if capacity == 0 {
+                return;
+            }
+            let index = if self.len.get() < capacity {
+                let index = (self.start.get() + self.len.get()) % capacity;
+                self.len.set(self.len.get() + 1);
+                index
+            } else {
+                // Full, overwrite the oldest record.
+                let index = self.start.get();
+                self.start.set((index + 1) % capacity);
+                self.overwritten.set(self.overwritten.get() + 1);
+                index
+            };
+
+            let record = &mut buffer[index * RECORD_LEN..(index + 1) * RECORD_LEN];
+            let process = process.map_or(KERNEL, |process| process.id() as u16);
+            record[0..4].copy_from_slice(&time_us.to_le_bytes());
+            record[4] = kind as u8;
+            record[5] = detail;
+            record[6..8].copy_from_slice(&process.to_le_bytes());
+            for (i, arg) in args.iter().enumerate() {
+                record[8 + i * 4..12 + i * 4].copy_from_slice(&arg.to_le_bytes());
+            }
+        });
+    }
+
+    /// Record that `process` made `syscall`.
+    pub(crate) fn record_syscall(&self, process: ProcessId, syscall: &Syscall) {
+        let args = match *syscall {
+            Syscall::Yield { which, address } => [which as u32, address as u32, 0, 0],
+            Syscall::Subscribe {
+                driver_number,
+                subdriver_number,
+                upcall_ptr,
+                appdata,
+            } => [
+                driver_number as u32,
+                subdriver_number as u32,
+                upcall_ptr as u32,
+                appdata as u32,
+            ],
+            Syscall::Command {
+                driver_number,
+                subdriver_number,
+                arg0,
+                arg1,
+            } => [
+                driver_number as u32,
+                subdriver_number as u32,
+                arg0 as u32,
+                arg1 as u32,
+            ],
+            Syscall::ReadWriteAllow {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => [
+                driver_number as u32,
+                subdriver_number as u32,
+                allow_address as u32,
+                allow_size as u32,
+            ],
+            Syscall::ReadOnlyAllow {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => [
+                driver_number as u32,
+                subdriver_number as u32,
+                allow_address as u32,
+                allow_size as u32,
+            ],
+            Syscall::Memop { operand, arg0 } => [operand as u32, arg0 as u32, 0, 0],
+            Syscall::Exit {
+                which,
+                completion_code,
+            } => [which as u32, completion_code as u32, 0, 0],
+        };
+        self.record(
+            TraceKind::Syscall,
+            syscall_class(syscall),
+            Some(process),
+            args,
+        );
+    }
+
+    /// Record that the kernel returned `value` from a system call of
+    /// `process`.
+    pub(crate) fn record_syscall_return(&self, process: ProcessId, value: &SyscallReturn) {
+        let mut args = [0; 4];
+        {
+            let [a0, a1, a2, a3] = &mut args;
+            value.encode_syscall_return(a0, a1, a2, a3);
+        }
+        self.record(TraceKind::SyscallReturn, 0, Some(process), args);
+    }
+
+    /// Number of records in the buffer.
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// Whether the buffer holds no records.
+    pub fn is_empty(&self) -> bool {
+        self.len.get() == 0
+    }
+
+    /// Number of records that were overwritten by newer records before they
+    /// were drained.
+    pub fn overwritten(&self) -> usize {
+        self.overwritten.get()
+    }
+
+    /// Move the oldest records out of the buffer into `buf`, as many whole
+    /// records as fit. Returns the number of bytes written to `buf`.
+    pub fn drain(&self, buf: &mut [u8]) -> usize {
+        self.buffer.map_or(0, |buffer| {
+            let capacity = buffer.len() / RECORD_LEN;
+            let count = core::cmp::min(self.len.get(), buf.len() / RECORD_LEN);
+            for i in 0..count {
+                let index = (self.start.get() + i) % capacity;
+                buf[i * RECORD_LEN..(i + 1) * RECORD_LEN]
+                    .copy_from_slice(&buffer[index * RECORD_LEN..(index + 1) * RECORD_LEN]);
+            }
+            if count > 0 {
+                self.start.set((self.start.get() + count) % capacity);
+                self.len.set(self.len.get() - count);
+            }
+            count * RECORD_LEN
+        })
+    }
+}
+
+/// The class of `syscall`, as recorded in the `detail` of system call records:
+/// 0 yield, 1 subscribe, 2 command, 3 read-write allow, 4 read-only allow,
+/// 5 memop and 6 exit.
+pub(crate) fn syscall_class(syscall: &Syscall) -> u8 {
+    match *syscall {
+        Syscall::Yield { .. } => 0,
+        Syscall::Subscribe { .. } => 1,
+        Syscall::Command { .. } => 2,
+        Syscall::ReadWriteAllow { .. } => 3,
+        Syscall::ReadOnlyAllow { .. } => 4,
+        Syscall::Memop { .. } => 5,
+        Syscall::Exit { .. } => 6,
+    }
+}
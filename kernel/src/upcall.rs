@@ -2,11 +2,10 @@
 
 use core::ptr::NonNull;
 
-use crate::config;
-use crate::debug;
 use crate::process;
 use crate::process::ProcessId;
 use crate::syscall::SyscallReturn;
+use crate::trace::TraceKind;
 use crate::ErrorCode;
 
 /// Type to uniquely identify an upcall subscription across all drivers.
@@ -50,7 +49,19 @@ impl Upcall {
     ///
     /// The three arguments are passed to the upcall in userspace.
     pub fn schedule(&mut self, r0: usize, r1: usize, r2: usize) -> bool {
-        self.cb.map_or(true, |mut cb| cb.schedule(r0, r1, r2))
+        self.cb
+            .map_or(true, |mut cb| cb.schedule(r0, r1, r2, false))
+    }
+
+    /// Tell the scheduler to run this upcall for the process, replacing the
+    /// arguments of this upcall if it is already waiting to run.
+    ///
+    /// This is for upcalls where only the latest one matters, such as a new
+    /// sensor reading, so that a driver that schedules them faster than the
+    /// process handles them does not fill its queue and make it lose other
+    /// upcalls.
+    pub fn schedule_coalesced(&mut self, r0: usize, r1: usize, r2: usize) -> bool {
+        self.cb.map_or(true, |mut cb| cb.schedule(r0, r1, r2, true))
     }
 
     pub(crate) fn into_subscribe_success(self) -> SyscallReturn {
@@ -96,35 +107,39 @@ impl ProcessUpcall {
     /// be scheduled.
     ///
     /// The arguments (`r0-r2`) are the values passed back to the process and
-    /// are specific to the individual `Driver` interfaces.
-    fn schedule(&mut self, r0: usize, r1: usize, r2: usize) -> bool {
+    /// are specific to the individual `Driver` interfaces. If `coalesce` is
+    /// set, a pending call of this upcall is updated instead of queueing
+    /// another one.
+    fn schedule(&mut self, r0: usize, r1: usize, r2: usize, coalesce: bool) -> bool {
         let res = self
             .app_id
             .kernel
             .process_map_or(false, self.app_id, |process| {
-                process.enqueue_task(process::Task::FunctionCall(process::FunctionCall {
+                let task = process::Task::FunctionCall(process::FunctionCall {
                     source: process::FunctionCallSource::Driver(self.upcall_id),
                     argument0: r0,
                     argument1: r1,
                     argument2: r2,
                     argument3: self.appdata,
                     pc: self.fn_ptr.as_ptr() as usize,
-                }))
+                });
+                if coalesce {
+                    process.enqueue_coalesced_task(task)
+                } else {
+                    process.enqueue_task(task)
+                }
             });
-        if config::CONFIG.trace_syscalls {
-            debug!(
-                "[{:?}] schedule[{:#x}:{}] @{:#x}({:#x}, {:#x}, {:#x}, {:#x}) = {}",
-                self.app_id,
-                self.upcall_id.driver_num,
-                self.upcall_id.subscribe_num,
-                self.fn_ptr.as_ptr() as usize,
-                r0,
-                r1,
-                r2,
-                self.appdata,
-                res
-            );
-        }
+        self.app_id.kernel.trace(
+            TraceKind::UpcallScheduled,
+            res as u8,
+            Some(self.app_id),
+            [
+                self.upcall_id.driver_num as u32,
+                self.upcall_id.subscribe_num as u32,
+                r0 as u32,
+                r1 as u32,
+            ],
+        );
         res
     }
 }
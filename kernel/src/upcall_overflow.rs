@@ -0,0 +1,117 @@
+//! Notifications of upcalls a process lost.
+//!
+//! Each process has a queue of upcalls waiting to run, as deep as its TBF
+//! header or the board's `ProcessQuota` asks for. Upcalls scheduled while the
+//! queue is full are dropped, and the driver that scheduled them only learns
+//! that `Upcall::schedule()` failed. With this driver, a process can
+//! subscribe to a notification of its lost upcalls, so that it can, for
+//! example, poll the drivers whose events it missed.
+//!
+//! The kernel sends the notification as soon as the process takes an upcall
+//! out of its queue, so that there is room for it. It reports how many
+//! upcalls were dropped since the last notification, and which upcall was
+//! dropped last. IPC upcalls are reported with the driver number of IPC and
+//! subscribe number 0.
+//!
+//! Drivers can keep upcalls where only the latest one matters from filling
+//! the queue with `Upcall::schedule_coalesced()`.
+//!
+//! The kernel sends notifications once the board calls
+//! `Kernel::set_upcall_overflow()`. The syscall interface is described in
+//! `doc/syscalls/10003_upcall_overflow.md`.
+
+use crate::capabilities::MemoryAllocationCapability;
+use crate::grant::Grant;
+use crate::process::ProcessId;
+use crate::sched::Kernel;
+use crate::upcall::{Upcall, UpcallId};
+use crate::{CommandReturn, Driver, ErrorCode};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x10003;
+
+#[derive(Default)]
+struct OverflowData {
+    upcall: Upcall,
+}
+
+/// Tells processes about the upcalls they lost because their queue was full.
+pub struct UpcallOverflow {
+    data: Grant<OverflowData>,
+}
+
+impl UpcallOverflow {
+    pub fn new(kernel: &'static Kernel, capability: &dyn MemoryAllocationCapability) -> Self {
+        Self {
+            data: kernel.create_grant(capability),
+        }
+    }
+
+    pub(crate) fn upcalls_dropped(
+        &self,
+        processid: ProcessId,
+        dropped: usize,
+        upcall_id: UpcallId,
+    ) {
+        let _ = self.data.enter(processid, |data| {
+            data.upcall
+                .schedule(dropped, upcall_id.driver_num, upcall_id.subscribe_num);
+        });
+    }
+}
+
+impl Driver for UpcallOverflow {
+    /// Setup upcalls.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Upcalls of the process were dropped.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut upcall: Upcall,
+        app_id: ProcessId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = self.data.enter(app_id, |data| match subscribe_num {
+            0 => {
+                core::mem::swap(&mut data.upcall, &mut upcall);
+                Ok(())
+            }
+            _ => Err(ErrorCode::NOSUPPORT),
+        });
+        match res.map_err(ErrorCode::from).and_then(|x| x) {
+            Ok(()) => Ok(upcall),
+            Err(e) => Err((upcall, e)),
+        }
+    }
+
+    /// Inspect the upcall queue.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check, always returns Ok(())
+    /// - `1`: Returns the depth of the upcall queue of the process and how
+    ///        many of its upcalls were dropped since it started.
+    fn command(
+        &self,
+        command_number: usize,
+        _arg1: usize,
+        _arg2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        match command_number {
+            0 => CommandReturn::success(),
+            1 => self.data.kernel.process_map_or(
+                CommandReturn::failure(ErrorCode::FAIL),
+                appid,
+                |process| {
+                    CommandReturn::success_u32_u32(
+                        process.upcall_queue_depth() as u32,
+                        process.debug_dropped_upcall_count() as u32,
+                    )
+                },
+            ),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
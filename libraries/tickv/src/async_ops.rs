@@ -100,8 +100,8 @@
 //! // when appending a key:
 //!
 //! // Add a key
-//! static VALUE: [u8; 32] = [0x23; 32];
-//! let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &VALUE) };
+//! static mut VALUE: [u8; 32] = [0x23; 32];
+//! let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
 //!
 //! match ret {
 //!     Err(ErrorCode::ReadNotReady(reg)) => {
@@ -126,7 +126,7 @@
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
 use crate::success_codes::SuccessCode;
-use crate::tickv::{State, TicKV};
+use crate::tickv::{RegionStats, State, StoredKey, TicKV};
 use core::cell::Cell;
 
 /// The return type from the continue operation
@@ -142,8 +142,15 @@ pub struct AsyncTicKV<'a, C: FlashController<S>, const S: usize> {
     /// The main TicKV struct
     pub tickv: TicKV<'a, C, S>,
     key: Cell<Option<u64>>,
-    value: Cell<Option<&'static [u8]>>,
+    value: Cell<Option<&'static mut [u8]>>,
+    value_length: Cell<usize>,
     buf: Cell<Option<&'static mut [u8]>>,
+    /// The length of the expected value, if the operation is a compare and
+    /// swap
+    expected_length: Cell<Option<usize>>,
+    /// The key to continue iterating from, or the key that was found
+    stored_key: Cell<Option<StoredKey>>,
+    region_stats: Cell<Option<RegionStats>>,
 }
 
 impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
@@ -158,12 +165,17 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             tickv: TicKV::<C, S>::new(controller, read_buffer, flash_size),
             key: Cell::new(None),
             value: Cell::new(None),
+            value_length: Cell::new(0),
             buf: Cell::new(None),
+            expected_length: Cell::new(None),
+            stored_key: Cell::new(None),
+            region_stats: Cell::new(None),
         }
     }
 
     /// This function setups the flash region to be used as a key-value store.
-    /// If the region is already initalised this won't make any changes.
+    /// If the region is already initalised this won't make any changes,
+    /// apart from rolling back objects left partially written by a power loss.
     ///
     /// `hashed_main_key`: The u64 hash of the const string `MAIN_KEY`.
     ///
@@ -182,18 +194,27 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
     /// `hash`: A hashed key. This key will be used in future to retrieve
     ///         or remove the `value`.
     /// `value`: A buffer containing the data to be stored to flash.
+    /// `length`: How many bytes at the start of `value` to store.
     ///
-    /// On success nothing will be returned.
+    /// On success a `SuccessCode` will be returned.
     /// On error a `ErrorCode` will be returned.
-    pub fn append_key(&self, hash: u64, value: &'static [u8]) -> Result<SuccessCode, ErrorCode> {
-        match self.tickv.append_key(hash, value) {
-            Ok(code) => Ok(code),
-            Err(e) => {
-                self.key.replace(Some(hash));
-                self.value.replace(Some(value));
-                Err(e)
-            }
-        }
+    ///
+    /// Either way `value` is kept until it is retrieved with
+    /// `get_stored_value_buffer()`.
+    pub fn append_key(
+        &self,
+        hash: u64,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<SuccessCode, ErrorCode> {
+        let ret = match value.get(..length) {
+            Some(data) => self.tickv.append_key(hash, data),
+            None => Err(ErrorCode::BufferTooSmall(length)),
+        };
+        self.key.replace(Some(hash));
+        self.value.replace(Some(value));
+        self.value_length.set(length);
+        ret
     }
 
     /// Retrieves the value from flash storage.
@@ -246,6 +267,81 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         }
     }
 
+    /// Replaces the value of a key in flash storage.
+    ///
+    /// `hash`: A hashed key.
+    /// `value`: A buffer containing the new value to be stored to flash.
+    /// `length`: How many bytes at the start of `value` to store.
+    ///
+    /// The new value is written before the old one is invalidated, see
+    /// `TicKV::update_key()`. `WriteNotReady` is returned while there are
+    /// still writes left to do, `continue_operation()` should be called
+    /// once the write has completed.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    ///
+    /// Either way `value` is kept until it is retrieved with
+    /// `get_stored_value_buffer()`.
+    pub fn update_key(
+        &self,
+        hash: u64,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<SuccessCode, ErrorCode> {
+        let ret = match value.get(..length) {
+            Some(data) => self.tickv.update_key(hash, data),
+            None => Err(ErrorCode::BufferTooSmall(length)),
+        };
+        self.key.replace(Some(hash));
+        self.expected_length.set(None);
+        self.value.replace(Some(value));
+        self.value_length.set(length);
+        ret
+    }
+
+    /// Replaces the value of a key in flash storage, if the value currently
+    /// stored is `expected`.
+    ///
+    /// `hash`: A hashed key.
+    /// `expected`: A buffer containing the value that must currently be
+    ///             stored.
+    /// `expected_length`: How many bytes at the start of `expected` to
+    ///                    compare.
+    /// `value`: A buffer containing the new value to be stored to flash.
+    /// `length`: How many bytes at the start of `value` to store.
+    ///
+    /// If the stored value doesn't match `ValueMismatch` is returned,
+    /// otherwise this behaves like `update_key()`.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    ///
+    /// Either way `expected` and `value` are kept until they are retrieved
+    /// with `get_stored_buffer()` and `get_stored_value_buffer()`.
+    pub fn compare_and_swap_key(
+        &self,
+        hash: u64,
+        expected: &'static mut [u8],
+        expected_length: usize,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<SuccessCode, ErrorCode> {
+        let ret = match (expected.get(..expected_length), value.get(..length)) {
+            (Some(expected_data), Some(data)) => {
+                self.tickv.compare_and_swap_key(hash, expected_data, data)
+            }
+            (None, _) => Err(ErrorCode::BufferTooSmall(expected_length)),
+            (_, None) => Err(ErrorCode::BufferTooSmall(length)),
+        };
+        self.key.replace(Some(hash));
+        self.buf.replace(Some(expected));
+        self.expected_length.set(Some(expected_length));
+        self.value.replace(Some(value));
+        self.value_length.set(length);
+        ret
+    }
+
     /// Perform a garbage collection on TicKV
     ///
     /// On success the number of bytes freed will be returned.
@@ -254,6 +350,37 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         self.tickv.garbage_collect()
     }
 
+    /// Find the next valid key after `after`, or the first valid key if
+    /// `after` is `None`. See `TicKV::next_key()`.
+    ///
+    /// If the operation is continued with `continue_operation()`, the key
+    /// can be retrieved with `get_stored_key()` once it has completed.
+    ///
+    /// On success the key is returned, or `None` if there are no more keys.
+    /// On error a `ErrorCode` will be returned.
+    pub fn next_key(&self, after: Option<StoredKey>) -> Result<Option<StoredKey>, ErrorCode> {
+        self.stored_key.set(after);
+        let ret = self.tickv.next_key(after);
+        if let Ok(key) = ret {
+            self.stored_key.set(key);
+        }
+        ret
+    }
+
+    /// Find out how the space in `region` is used.
+    ///
+    /// If the operation is continued with `continue_operation()`, the stats
+    /// can be retrieved with `get_stored_region_stats()` once it has
+    /// completed.
+    ///
+    /// On success the `RegionStats` of the region will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn region_stats(&self, region: usize) -> Result<RegionStats, ErrorCode> {
+        let ret = self.tickv.region_stats(region);
+        self.region_stats.set(ret.ok());
+        ret
+    }
+
     /// Copy data from `read_buffer` argument to the internal read_buffer.
     /// This should be used to copy the data that the implementation wanted
     /// to read when calling `read_region` after the async operation has
@@ -266,7 +393,7 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
 
     /// Get the `value` buffer that was passed in by previous
     /// commands.
-    pub fn get_stored_value_buffer(&self) -> Option<&'static [u8]> {
+    pub fn get_stored_value_buffer(&self) -> Option<&'static mut [u8]> {
         self.value.take()
     }
 
@@ -276,8 +403,20 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         self.buf.take()
     }
 
+    /// Get the key found by the last `next_key()`, or `None` if there were
+    /// no more keys.
+    pub fn get_stored_key(&self) -> Option<StoredKey> {
+        self.stored_key.get()
+    }
+
+    /// Get the stats found by the last `region_stats()`.
+    pub fn get_stored_region_stats(&self) -> Option<RegionStats> {
+        self.region_stats.get()
+    }
+
     /// Continue the last operation after the async operation has completed.
-    /// This should be called from a read/erase complete callback.
+    /// This should be called from a read/erase complete callback, or from a
+    /// write complete callback if the operation returned `WriteNotReady`.
     /// NOTE: If called from a read callback, `set_read_buffer` should be
     /// called first to update the data.
     ///
@@ -294,9 +433,14 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
     pub fn continue_operation(&self) -> ContinueReturn {
         let ret = match self.tickv.state.get() {
             State::Init(_) => self.tickv.initalise(self.key.get().unwrap()),
-            State::AppendKey(_) => self
-                .tickv
-                .append_key(self.key.get().unwrap(), self.value.get().unwrap()),
+            State::AppendKey(_) => {
+                let value = self.value.take().unwrap();
+                let ret = self
+                    .tickv
+                    .append_key(self.key.get().unwrap(), &value[..self.value_length.get()]);
+                self.value.replace(Some(value));
+                ret
+            }
             State::GetKey(_) => {
                 let buf = self.buf.take().unwrap();
                 let ret = self.tickv.get_key(self.key.get().unwrap(), buf);
@@ -304,10 +448,44 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
                 ret
             }
             State::InvalidateKey(_) => self.tickv.invalidate_key(self.key.get().unwrap()),
+            State::UpdateKey(_) => {
+                let value = self.value.take().unwrap();
+                let ret = match self.expected_length.get() {
+                    Some(expected_length) => {
+                        let expected = self.buf.take().unwrap();
+                        let ret = self.tickv.compare_and_swap_key(
+                            self.key.get().unwrap(),
+                            &expected[..expected_length],
+                            &value[..self.value_length.get()],
+                        );
+                        self.buf.replace(Some(expected));
+                        ret
+                    }
+                    None => self
+                        .tickv
+                        .update_key(self.key.get().unwrap(), &value[..self.value_length.get()]),
+                };
+                self.value.replace(Some(value));
+                ret
+            }
             State::GarbageCollect(_) => match self.tickv.garbage_collect() {
                 Ok(_) => Ok(SuccessCode::Complete),
                 Err(e) => Err(e),
             },
+            State::NextKey(_) => match self.tickv.next_key(self.stored_key.get()) {
+                Ok(key) => {
+                    self.stored_key.set(key);
+                    Ok(SuccessCode::Complete)
+                }
+                Err(e) => Err(e),
+            },
+            State::RegionStats(region) => match self.tickv.region_stats(region) {
+                Ok(stats) => {
+                    self.region_stats.set(Some(stats));
+                    Ok(SuccessCode::Complete)
+                }
+                Err(e) => Err(e),
+            },
             _ => unreachable!(),
         };
 
@@ -317,11 +495,9 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
                 (ret, self.buf.take())
             }
             Err(e) => match e {
-                ErrorCode::ReadNotReady(_) | ErrorCode::EraseNotReady(_) => (ret, None),
-                ErrorCode::WriteNotReady(_) => {
-                    self.tickv.state.set(State::None);
-                    (ret, None)
-                }
+                ErrorCode::ReadNotReady(_)
+                | ErrorCode::EraseNotReady(_)
+                | ErrorCode::WriteNotReady(_) => (ret, None),
                 _ => {
                     self.tickv.state.set(State::None);
                     (ret, self.buf.take())
@@ -337,11 +513,13 @@ mod store_flast_ctrl {
     use crate::async_ops::AsyncTicKV;
     use crate::error_codes::ErrorCode;
     use crate::flash_controller::FlashController;
+    use crate::success_codes::SuccessCode;
     use crate::tickv::{HASH_OFFSET, LEN_OFFSET, MAIN_KEY, VERSION, VERSION_OFFSET};
     use core::hash::{Hash, Hasher};
     use std::cell::Cell;
     use std::cell::RefCell;
     use std::collections::hash_map::DefaultHasher;
+    use std::vec::Vec;
 
     fn check_region_main(buf: &[u8]) {
         // Check the version
@@ -542,9 +720,10 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
+        static mut VALUE: [u8; 32] = [0x23; 32];
 
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -555,7 +734,8 @@ mod store_flast_ctrl {
             _ => unreachable!(),
         }
 
-        let ret = tickv.append_key(get_hashed_key(b"TWO"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"TWO"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -582,11 +762,12 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
+        static mut VALUE: [u8; 32] = [0x23; 32];
         static mut BUF: [u8; 32] = [0; 32];
 
         println!("Add key ONE");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -617,7 +798,8 @@ mod store_flast_ctrl {
         }
 
         println!("Add key ONE again");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -632,7 +814,8 @@ mod store_flast_ctrl {
         }
 
         println!("Add key TWO");
-        let ret = tickv.append_key(get_hashed_key(b"TWO"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"TWO"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -707,11 +890,12 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
+        static mut VALUE: [u8; 32] = [0x23; 32];
         static mut BUF: [u8; 32] = [0; 32];
 
         println!("Add key ONE");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -764,7 +948,7 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
+        static mut VALUE: [u8; 32] = [0x23; 32];
         static mut BUF: [u8; 32] = [0; 32];
 
         println!("Garbage collect empty flash");
@@ -778,7 +962,8 @@ mod store_flast_ctrl {
         }
 
         println!("Add key ONE");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -860,6 +1045,189 @@ mod store_flast_ctrl {
         }
 
         println!("Add Key ONE");
-        tickv.append_key(get_hashed_key(b"ONE"), &VALUE).unwrap();
+        #[allow(unsafe_code)]
+        unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) }.unwrap();
+    }
+
+    #[test]
+    fn test_update_and_compare_and_swap() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+
+        let tickv = AsyncTicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+
+        let mut ret = tickv.initalise(hash_function.finish());
+        while ret.is_err() {
+            // There is no actual delay in the test, just continue now
+            let (r, _buf) = tickv.continue_operation();
+            ret = r;
+        }
+
+        static mut VALUE: [u8; 32] = [0x23; 32];
+        static mut NEW_VALUE: [u8; 32] = [0x42; 32];
+        static mut BUF: [u8; 32] = [0; 32];
+
+        for key in [b"ONE", b"TWO"].iter() {
+            println!("Add key {:?}", key);
+            #[allow(unsafe_code)]
+            let ret = unsafe { tickv.append_key(get_hashed_key(*key), &mut VALUE, 32) };
+            match ret {
+                Err(ErrorCode::ReadNotReady(reg)) => {
+                    // There is no actual delay in the test, just continue now
+                    tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                    tickv.continue_operation().0.unwrap();
+                }
+                Ok(_) => {}
+                _ => unreachable!(),
+            }
+            tickv.get_stored_value_buffer().unwrap();
+        }
+
+        println!("Update key ONE");
+        #[allow(unsafe_code)]
+        let mut ret = unsafe { tickv.update_key(get_hashed_key(b"ONE"), &mut NEW_VALUE, 32) };
+        while let Err(ErrorCode::ReadNotReady(reg)) = ret {
+            // There is no actual delay in the test, just continue now
+            tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+            ret = tickv.continue_operation().0;
+        }
+        ret.unwrap();
+        tickv.get_stored_value_buffer().unwrap();
+
+        println!("Get key ONE");
+        #[allow(unsafe_code)]
+        unsafe {
+            tickv.get_key(get_hashed_key(b"ONE"), &mut BUF).unwrap();
+            assert_eq!(BUF, [0x42; 32]);
+        }
+
+        println!("Swap key TWO with the wrong value");
+        #[allow(unsafe_code)]
+        let mut ret = unsafe {
+            tickv.compare_and_swap_key(get_hashed_key(b"TWO"), &mut NEW_VALUE, 32, &mut VALUE, 32)
+        };
+        while let Err(ErrorCode::ReadNotReady(reg)) = ret {
+            // There is no actual delay in the test, just continue now
+            tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+            let (r, expected) = tickv.continue_operation();
+            // The expected buffer is returned once the operation has finished
+            assert_eq!(
+                expected.is_some(),
+                r.is_ok() || r == Err(ErrorCode::ValueMismatch)
+            );
+            ret = r;
+        }
+        assert_eq!(ret, Err(ErrorCode::ValueMismatch));
+        tickv.get_stored_value_buffer().unwrap();
+
+        println!("Swap key TWO with the right value");
+        #[allow(unsafe_code)]
+        let mut ret = unsafe {
+            tickv.compare_and_swap_key(get_hashed_key(b"TWO"), &mut VALUE, 32, &mut NEW_VALUE, 32)
+        };
+        while let Err(ErrorCode::ReadNotReady(reg)) = ret {
+            // There is no actual delay in the test, just continue now
+            tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+            ret = tickv.continue_operation().0;
+        }
+        ret.unwrap();
+
+        println!("Get key TWO");
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.get_key(get_hashed_key(b"TWO"), &mut BUF) };
+        match ret {
+            Err((_, ErrorCode::ReadNotReady(reg))) => {
+                // There is no actual delay in the test, just continue now
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                tickv.continue_operation().0.unwrap();
+            }
+            Ok(_) => {}
+            _ => unreachable!(),
+        }
+        #[allow(unsafe_code)]
+        unsafe {
+            assert_eq!(BUF, [0x42; 32]);
+        }
+    }
+
+    #[test]
+    fn test_keys_and_region_stats() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = AsyncTicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+
+        let mut ret = tickv.initalise(hash);
+        while ret.is_err() {
+            // There is no actual delay in the test, just continue now
+            let (r, _buf) = tickv.continue_operation();
+            ret = r;
+        }
+
+        static mut VALUE: [u8; 32] = [0x23; 32];
+
+        for key in [b"ONE", b"TWO"].iter() {
+            println!("Add key {:?}", key);
+            #[allow(unsafe_code)]
+            let ret = unsafe { tickv.append_key(get_hashed_key(*key), &mut VALUE, 32) };
+            match ret {
+                Err(ErrorCode::ReadNotReady(reg)) => {
+                    // There is no actual delay in the test, just continue now
+                    tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                    tickv.continue_operation().0.unwrap();
+                }
+                Ok(_) => {}
+                _ => unreachable!(),
+            }
+            tickv.get_stored_value_buffer().unwrap();
+        }
+
+        println!("List the keys");
+        let mut keys = Vec::new();
+        let mut key = None;
+        loop {
+            let mut ret = tickv.next_key(key).map(|_| SuccessCode::Complete);
+            while let Err(ErrorCode::ReadNotReady(reg)) = ret {
+                // There is no actual delay in the test, just continue now
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                ret = tickv.continue_operation().0;
+            }
+            ret.unwrap();
+
+            key = tickv.get_stored_key();
+            match key {
+                Some(key) => keys.push((key.hash, key.value_length)),
+                None => break,
+            }
+        }
+        keys.sort_unstable();
+        let mut expected = vec![
+            (hash, 0),
+            (get_hashed_key(b"ONE"), 32),
+            (get_hashed_key(b"TWO"), 32),
+        ];
+        expected.sort_unstable();
+        assert_eq!(keys, expected);
+
+        println!("Check the region stats");
+        let mut live = 0;
+        for region in 0..(0x10000 / 1024) {
+            let mut ret = tickv.region_stats(region).map(|_| SuccessCode::Complete);
+            while let Err(ErrorCode::ReadNotReady(reg)) = ret {
+                // There is no actual delay in the test, just continue now
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                ret = tickv.continue_operation().0;
+            }
+            ret.unwrap();
+
+            let stats = tickv.get_stored_region_stats().unwrap();
+            assert_eq!(stats.invalidated, 0);
+            assert_eq!(stats.live + stats.free, 1024);
+            live += stats.live;
+        }
+        assert_eq!(live, 15 + 47 + 47);
     }
 }
@@ -0,0 +1,373 @@
+//! Optional authenticated encryption of values
+//!
+//! TicKV stores values in plaintext, protected only by a CRC-32 check sum.
+//! Values can instead be encrypted and authenticated with AES-128 in CCM
+//! mode before they are stored, and checked and decrypted after they are
+//! read. This is done outside of TicKV, so the encrypted value is stored
+//! like any other value.
+//!
+//! An encrypted value is stored as:
+//!
+//! ```text
+//! +---------------------+-------------------+--------------+------------------+
+//! | Nonce (13 bytes)    | Length (2 bytes)  | Ciphertext   | Tag (16 bytes)   |
+//! +---------------------+-------------------+--------------+------------------+
+//! ```
+//!
+//! The length is the length of the value, stored little endian. The CCM
+//! nonce is the stored nonce with the hashed key (8 bytes, big endian)
+//! XORed into its first 8 bytes, so a value can only be decrypted under the
+//! key it was stored with. CCM authenticates the nonce and the length, so
+//! changing any of the stored bytes is detected.
+//!
+//! The stored nonce must never be used twice for the same key and device
+//! key, so it should come from a random number generator. It is as long as
+//! the CCM nonce, so that random nonces are unlikely to repeat even after
+//! very many writes.
+//!
+//! The `Aes128Ccm` software implementation can be used on the host, or on
+//! devices without AES hardware. The Tock TicKV capsule uses the AES hardware
+//! through `hil::symmetric_encryption::AES128CCM` with the same format.
+//!
+//! ```rust
+//! use tickv::encryption::{self, Aes128Ccm};
+//!
+//! let cipher = Aes128Ccm::new(&[0x42; 16]);
+//! let hash = 0x1234_5678_9abc_def0;
+//!
+//! let mut buf = [0; 4 + encryption::OVERHEAD];
+//! buf[..4].copy_from_slice(b"test");
+//! // The nonce should come from a random number generator
+//! let nonce = [0x17; encryption::NONCE_LEN];
+//! let length = encryption::encrypt_value(&cipher, hash, &nonce, &mut buf, 4).unwrap();
+//! assert_eq!(length, 4 + encryption::OVERHEAD);
+//! assert_ne!(&buf[encryption::HEADER_LEN..(encryption::HEADER_LEN + 4)], b"test");
+//!
+//! // Store `buf[..length]` with `append_key()`, read it back with `get_key()`
+//!
+//! let length = encryption::decrypt_value(&cipher, hash, &mut buf).unwrap();
+//! assert_eq!(&buf[..length], b"test");
+//! ```
+
+use crate::error_codes::ErrorCode;
+
+/// The length of the nonce stored with each value
+pub const NONCE_LEN: usize = CCM_NONCE_LEN;
+/// The length of the header stored before the ciphertext
+pub const HEADER_LEN: usize = NONCE_LEN + 2;
+/// The length of the authentication tag stored after the ciphertext
+pub const TAG_LEN: usize = 16;
+/// How many more bytes an encrypted value needs than the value
+pub const OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+/// The length of the CCM nonce
+pub const CCM_NONCE_LEN: usize = 13;
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+/// Multiply by x in GF(2^8)
+fn xtime(x: u8) -> u8 {
+    (x << 1) ^ if x & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+/// A software implementation of AES-128 in CCM mode, as described in
+/// RFC 3610, using a 13 byte nonce.
+pub struct Aes128Ccm {
+    round_keys: [[u8; 16]; 11],
+}
+
+impl Aes128Ccm {
+    /// Create a new cipher using `key`
+    pub fn new(key: &[u8; 16]) -> Self {
+        let mut round_keys = [[0; 16]; 11];
+        round_keys[0] = *key;
+
+        for round in 1..11 {
+            let prev = round_keys[round - 1];
+            let mut temp = [
+                SBOX[prev[13] as usize] ^ RCON[round - 1],
+                SBOX[prev[14] as usize],
+                SBOX[prev[15] as usize],
+                SBOX[prev[12] as usize],
+            ];
+
+            for word in 0..4 {
+                for i in 0..4 {
+                    temp[i] ^= prev[word * 4 + i];
+                    round_keys[round][word * 4 + i] = temp[i];
+                }
+            }
+        }
+
+        Self { round_keys }
+    }
+
+    /// Encrypt a single block in place
+    fn encrypt_block(&self, block: &mut [u8; 16]) {
+        for (b, k) in block.iter_mut().zip(self.round_keys[0].iter()) {
+            *b ^= k;
+        }
+
+        for round in 1..11 {
+            // SubBytes and ShiftRows
+            let state = *block;
+            for column in 0..4 {
+                for row in 0..4 {
+                    block[column * 4 + row] = SBOX[state[((column + row) % 4) * 4 + row] as usize];
+                }
+            }
+
+            // MixColumns, except in the last round
+            if round != 10 {
+                for column in block.chunks_mut(4) {
+                    let all = column[0] ^ column[1] ^ column[2] ^ column[3];
+                    let first = column[0];
+                    column[0] ^= all ^ xtime(column[0] ^ column[1]);
+                    column[1] ^= all ^ xtime(column[1] ^ column[2]);
+                    column[2] ^= all ^ xtime(column[2] ^ column[3]);
+                    column[3] ^= all ^ xtime(column[3] ^ first);
+                }
+            }
+
+            for (b, k) in block.iter_mut().zip(self.round_keys[round].iter()) {
+                *b ^= k;
+            }
+        }
+    }
+
+    /// Check the tag length and data length are supported
+    fn check_lengths(aad: &[u8], data: &[u8], tag_len: usize) {
+        assert!((4..=16).contains(&tag_len) && tag_len % 2 == 0);
+        assert!(aad.len() < 0xFF00);
+        assert!(data.len() <= 0xFFFF);
+    }
+
+    /// The counter block `i` used to encrypt the data and the tag
+    fn counter_block(nonce: &[u8; CCM_NONCE_LEN], i: u16) -> [u8; 16] {
+        let mut block = [0; 16];
+        // The length of the length field, minus 1
+        block[0] = 1;
+        block[1..14].copy_from_slice(nonce);
+        block[14..16].copy_from_slice(&i.to_be_bytes());
+        block
+    }
+
+    /// Encrypt or decrypt `data` with the counter blocks, starting at 1
+    fn ctr(&self, nonce: &[u8; CCM_NONCE_LEN], data: &mut [u8]) {
+        for (i, chunk) in data.chunks_mut(16).enumerate() {
+            let mut block = Self::counter_block(nonce, i as u16 + 1);
+            self.encrypt_block(&mut block);
+            for (d, s) in chunk.iter_mut().zip(block.iter()) {
+                *d ^= s;
+            }
+        }
+    }
+
+    /// Calculate the encrypted tag of the plaintext `data`
+    fn tag(
+        &self,
+        nonce: &[u8; CCM_NONCE_LEN],
+        aad: &[u8],
+        data: &[u8],
+        tag_len: usize,
+    ) -> [u8; 16] {
+        let mut mac = [0; 16];
+        mac[0] = ((tag_len as u8 - 2) / 2) << 3 | 1;
+        if !aad.is_empty() {
+            mac[0] |= 0x40;
+        }
+        mac[1..14].copy_from_slice(nonce);
+        mac[14..16].copy_from_slice(&(data.len() as u16).to_be_bytes());
+        self.encrypt_block(&mut mac);
+
+        if !aad.is_empty() {
+            // The additional data is prefixed with its length
+            let aad_len = (aad.len() as u16).to_be_bytes();
+            let mut position = 0;
+            for (i, b) in aad_len.iter().chain(aad.iter()).enumerate() {
+                mac[i % 16] ^= b;
+                position = i + 1;
+                if position % 16 == 0 {
+                    self.encrypt_block(&mut mac);
+                }
+            }
+            if position % 16 != 0 {
+                self.encrypt_block(&mut mac);
+            }
+        }
+
+        for chunk in data.chunks(16) {
+            for (m, d) in mac.iter_mut().zip(chunk.iter()) {
+                *m ^= d;
+            }
+            self.encrypt_block(&mut mac);
+        }
+
+        let mut s0 = Self::counter_block(nonce, 0);
+        self.encrypt_block(&mut s0);
+        for (m, s) in mac.iter_mut().zip(s0.iter()) {
+            *m ^= s;
+        }
+
+        mac
+    }
+
+    /// Encrypt `data` in place and write the authentication tag to `tag`.
+    ///
+    /// `aad` is additional data that is authenticated but not encrypted.
+    /// The length of `tag` is the length of the tag, which must be even
+    /// and between 4 and 16 bytes.
+    pub fn encrypt(
+        &self,
+        nonce: &[u8; CCM_NONCE_LEN],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &mut [u8],
+    ) {
+        Self::check_lengths(aad, data, tag.len());
+
+        let mac = self.tag(nonce, aad, data, tag.len());
+        tag.copy_from_slice(&mac[..tag.len()]);
+        self.ctr(nonce, data);
+    }
+
+    /// Check the authentication tag `tag` and decrypt `data` in place.
+    ///
+    /// On success nothing will be returned.
+    /// If the tag doesn't match `AuthenticationFailed` is returned and
+    /// `data` is cleared.
+    pub fn decrypt(
+        &self,
+        nonce: &[u8; CCM_NONCE_LEN],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), ErrorCode> {
+        Self::check_lengths(aad, data, tag.len());
+
+        self.ctr(nonce, data);
+        let mac = self.tag(nonce, aad, data, tag.len());
+
+        // Compare every byte, so the time taken doesn't depend on how much
+        // of the tag matches.
+        let diff = mac
+            .iter()
+            .zip(tag.iter())
+            .fold(0, |diff, (m, t)| diff | (m ^ t));
+        if diff != 0 {
+            for d in data.iter_mut() {
+                *d = 0;
+            }
+            return Err(ErrorCode::AuthenticationFailed);
+        }
+
+        Ok(())
+    }
+}
+
+/// The CCM nonce used for the value of the key `hash`, with the stored
+/// nonce `nonce`.
+pub fn ccm_nonce(hash: u64, nonce: &[u8; NONCE_LEN]) -> [u8; CCM_NONCE_LEN] {
+    let mut ccm_nonce = *nonce;
+    for (n, h) in ccm_nonce.iter_mut().zip(hash.to_be_bytes().iter()) {
+        *n ^= h;
+    }
+    ccm_nonce
+}
+
+/// Write the header of an encrypted value of `length` bytes to the start of
+/// `buf`.
+pub fn write_header(buf: &mut [u8], nonce: &[u8; NONCE_LEN], length: usize) {
+    buf[..NONCE_LEN].copy_from_slice(nonce);
+    buf[NONCE_LEN..HEADER_LEN].copy_from_slice(&(length as u16).to_le_bytes());
+}
+
+/// Read the header of the encrypted value in `buf`, stored under the key
+/// `hash`.
+///
+/// On success the CCM nonce and the length of the value are returned.
+/// If `buf` is too small for the value `BufferTooSmall` is returned, with
+/// the length needed.
+pub fn read_header(buf: &[u8], hash: u64) -> Result<([u8; CCM_NONCE_LEN], usize), ErrorCode> {
+    if buf.len() < OVERHEAD {
+        return Err(ErrorCode::BufferTooSmall(OVERHEAD));
+    }
+
+    let mut nonce = [0; NONCE_LEN];
+    nonce.copy_from_slice(&buf[..NONCE_LEN]);
+    let length = u16::from_le_bytes([buf[NONCE_LEN], buf[NONCE_LEN + 1]]) as usize;
+
+    if buf.len() < length + OVERHEAD {
+        return Err(ErrorCode::BufferTooSmall(length + OVERHEAD));
+    }
+
+    Ok((ccm_nonce(hash, &nonce), length))
+}
+
+/// Encrypt the value at the start of `buf` to be stored under the key
+/// `hash`.
+///
+/// `nonce`: A nonce that has never been used for this key before.
+/// `buf`: A buffer with the value in the first `length` bytes, and
+///        `OVERHEAD` more bytes of space.
+///
+/// On success the length of the encrypted value at the start of `buf` is
+/// returned, which is what should be stored.
+/// On error a `ErrorCode` will be returned.
+pub fn encrypt_value(
+    cipher: &Aes128Ccm,
+    hash: u64,
+    nonce: &[u8; NONCE_LEN],
+    buf: &mut [u8],
+    length: usize,
+) -> Result<usize, ErrorCode> {
+    if buf.len() < length + OVERHEAD {
+        return Err(ErrorCode::BufferTooSmall(length + OVERHEAD));
+    }
+    if length > 0xFFFF {
+        return Err(ErrorCode::ObjectTooLarge);
+    }
+
+    buf.copy_within(..length, HEADER_LEN);
+    write_header(buf, nonce, length);
+
+    let (data, tag) = buf[HEADER_LEN..(length + OVERHEAD)].split_at_mut(length);
+    cipher.encrypt(&ccm_nonce(hash, nonce), &[], data, tag);
+
+    Ok(length + OVERHEAD)
+}
+
+/// Check and decrypt the encrypted value at the start of `buf`, stored under
+/// the key `hash`.
+///
+/// On success the value is moved to the start of `buf` and its length is
+/// returned.
+/// On error a `ErrorCode` will be returned. If the value was changed, or
+/// was encrypted with a different key, `AuthenticationFailed` is returned.
+pub fn decrypt_value(cipher: &Aes128Ccm, hash: u64, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+    let (nonce, length) = read_header(buf, hash)?;
+
+    let (data, tag) = buf[HEADER_LEN..(length + OVERHEAD)].split_at_mut(length);
+    cipher.decrypt(&nonce, &[], data, tag)?;
+
+    buf.copy_within(HEADER_LEN..(HEADER_LEN + length), 0);
+    Ok(length)
+}
@@ -40,6 +40,11 @@ pub enum ErrorCode {
     WriteNotReady(usize),
     /// Indicates that the flash erase operation is not yet ready.
     EraseNotReady(usize),
+    /// The stored value doesn't match the expected value
+    ValueMismatch,
+    /// The encrypted value couldn't be authenticated. It was changed, or
+    /// was encrypted with a different key.
+    AuthenticationFailed,
 }
 
 impl From<ErrorCode> for isize {
@@ -60,6 +65,8 @@ impl From<ErrorCode> for isize {
             ErrorCode::ReadNotReady(_) => -13,
             ErrorCode::WriteNotReady(_) => -14,
             ErrorCode::EraseNotReady(_) => -15,
+            ErrorCode::ValueMismatch => -16,
+            ErrorCode::AuthenticationFailed => -17,
         }
     }
 }
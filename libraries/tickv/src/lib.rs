@@ -178,6 +178,15 @@
 //! before it has completed then the operation probably did not complete and
 //! that data is lost.
 //!
+//! The value of an existing key can be replaced with `update_key()`, or with
+//! `compare_and_swap_key()` if it still has an expected value. The new value
+//! is written before the old one is invalidated, so if a power loss occurs
+//! during the update either the old or the new value is kept.
+//!
+//! A power loss can leave an object partially written. `initalise()` checks
+//! every region for these and rolls them back, so that any older value of the
+//! key is used instead.
+//!
 //! To help reduce this time to be as short as possible the `FlashController`
 //! is synchronous. Although flash writes can take a considerable amount of time
 //! and this will stall the application, this still seems like a good idea
@@ -191,6 +200,9 @@
 //! to flash can also read all of the information. Any privacy, security or
 //! authentication measures need to be layered on top of TicKV.
 //!
+//! The `encryption` module can be used to encrypt and authenticate values
+//! before they are stored.
+//!
 //! ## Versions
 //!
 //! TicKV stores the version when adding objects to the flash storage.
@@ -209,6 +221,7 @@
 
 pub mod async_ops;
 mod crc32;
+pub mod encryption;
 pub mod error_codes;
 pub mod flash_controller;
 pub mod success_codes;
@@ -224,6 +237,7 @@ pub use crate::flash_controller::FlashController;
 #[doc(inline)]
 pub use crate::tickv::TicKV;
 pub use crate::tickv::MAIN_KEY;
+pub use crate::tickv::{RegionStats, StoredKey};
 
 // This is used to run the tests on a host
 #[cfg(test)]
@@ -1,10 +1,14 @@
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
-use crate::tickv::{TicKV, HASH_OFFSET, LEN_OFFSET, MAIN_KEY, VERSION, VERSION_OFFSET};
+use crate::tickv::{
+    RegionStats, TicKV, CHECK_SUM_LEN, HASH_OFFSET, HEADER_LENGTH, LEN_OFFSET, MAIN_KEY, VERSION,
+    VERSION_OFFSET,
+};
 use core::hash::{Hash, Hasher};
 use std::cell::Cell;
 use std::cell::RefCell;
 use std::collections::hash_map::DefaultHasher;
+use std::vec::Vec;
 
 fn check_region_main(buf: &[u8]) {
     // Check the version
@@ -558,3 +562,803 @@ mod no_check_store_flast_ctrl {
         );
     }
 }
+
+/// Tests using a flash controller that can lose power before a write
+mod power_loss_flash_ctrl {
+    use super::*;
+
+    struct FlashCtrl {
+        buf: RefCell<[[u8; 1024]; 64]>,
+        // The number of writes left before power is lost
+        writes_left: Cell<Option<usize>>,
+    }
+
+    impl FlashCtrl {
+        fn new() -> Self {
+            Self {
+                buf: RefCell::new([[0xFF; 1024]; 64]),
+                writes_left: Cell::new(None),
+            }
+        }
+
+        // Restart with the same flash contents
+        fn restart(&self) -> Self {
+            Self {
+                buf: RefCell::new(*self.buf.borrow()),
+                writes_left: Cell::new(None),
+            }
+        }
+    }
+
+    impl FlashController<1024> for FlashCtrl {
+        fn read_region(
+            &self,
+            region_number: usize,
+            offset: usize,
+            buf: &mut [u8; 1024],
+        ) -> Result<(), ErrorCode> {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = self.buf.borrow()[region_number][offset + i]
+            }
+
+            Ok(())
+        }
+
+        fn write(&self, address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
+            match self.writes_left.get() {
+                Some(0) => {
+                    println!("Lost power before writing to address: {:#x}", address);
+                    return Err(ErrorCode::WriteFail);
+                }
+                Some(left) => self.writes_left.set(Some(left - 1)),
+                None => {}
+            }
+
+            for (i, d) in buf.iter().enumerate() {
+                self.buf.borrow_mut()[address / 1024][(address % 1024) + i] = *d;
+            }
+
+            Ok(())
+        }
+
+        fn erase_region(&self, region_number: usize) -> Result<(), ErrorCode> {
+            for d in self.buf.borrow_mut()[region_number].iter_mut() {
+                *d = 0xFF;
+            }
+
+            Ok(())
+        }
+    }
+
+    fn main_key_hash() -> u64 {
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        hash_function.finish()
+    }
+
+    #[test]
+    fn test_update() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initalise(main_key_hash()).unwrap();
+
+        let mut buf: [u8; 32] = [0; 32];
+
+        println!("Update non-existant key ONE");
+        assert_eq!(
+            tickv.update_key(get_hashed_key(b"ONE"), &[0x23; 32]),
+            Err(ErrorCode::KeyNotFound)
+        );
+
+        println!("Add key ONE");
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &[0x23; 32])
+            .unwrap();
+
+        println!("Update key ONE");
+        tickv
+            .update_key(get_hashed_key(b"ONE"), &[0x42; 32])
+            .unwrap();
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, [0x42; 32]);
+
+        println!("Update key ONE to a shorter value");
+        tickv
+            .update_key(get_hashed_key(b"ONE"), &[0x55; 16])
+            .unwrap();
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"ONE"), &mut buf[..8]),
+            Err(ErrorCode::BufferTooSmall(16))
+        );
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf[..16], [0x55; 16]);
+
+        println!("Delete key ONE");
+        tickv.invalidate_key(get_hashed_key(b"ONE")).unwrap();
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"ONE"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+
+        println!("Garbage collect the updated key");
+        assert_eq!(tickv.garbage_collect(), Ok(1024));
+    }
+
+    #[test]
+    fn test_compare_and_swap() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initalise(main_key_hash()).unwrap();
+
+        let mut buf: [u8; 4] = [0; 4];
+
+        println!("Add key COUNTER");
+        tickv
+            .append_key(get_hashed_key(b"COUNTER"), &1u32.to_le_bytes())
+            .unwrap();
+
+        println!("Swap with the wrong value");
+        assert_eq!(
+            tickv.compare_and_swap_key(
+                get_hashed_key(b"COUNTER"),
+                &2u32.to_le_bytes(),
+                &3u32.to_le_bytes()
+            ),
+            Err(ErrorCode::ValueMismatch)
+        );
+        assert_eq!(
+            tickv.compare_and_swap_key(get_hashed_key(b"COUNTER"), &[1], &3u32.to_le_bytes()),
+            Err(ErrorCode::ValueMismatch)
+        );
+        tickv.get_key(get_hashed_key(b"COUNTER"), &mut buf).unwrap();
+        assert_eq!(u32::from_le_bytes(buf), 1);
+
+        println!("Swap with the right value");
+        tickv
+            .compare_and_swap_key(
+                get_hashed_key(b"COUNTER"),
+                &1u32.to_le_bytes(),
+                &2u32.to_le_bytes(),
+            )
+            .unwrap();
+        tickv.get_key(get_hashed_key(b"COUNTER"), &mut buf).unwrap();
+        assert_eq!(u32::from_le_bytes(buf), 2);
+
+        println!("Swap the old value again");
+        assert_eq!(
+            tickv.compare_and_swap_key(
+                get_hashed_key(b"COUNTER"),
+                &1u32.to_le_bytes(),
+                &3u32.to_le_bytes()
+            ),
+            Err(ErrorCode::ValueMismatch)
+        );
+
+        println!("Swap a non-existant key");
+        assert_eq!(
+            tickv.compare_and_swap_key(
+                get_hashed_key(b"OTHER"),
+                &1u32.to_le_bytes(),
+                &3u32.to_le_bytes()
+            ),
+            Err(ErrorCode::KeyNotFound)
+        );
+    }
+
+    #[test]
+    fn test_power_loss_during_update() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initalise(main_key_hash()).unwrap();
+
+        let mut buf: [u8; 32] = [0; 32];
+
+        println!("Add key ONE");
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &[0x23; 32])
+            .unwrap();
+
+        println!("Lose power before the new value is written");
+        tickv.controller.writes_left.set(Some(0));
+        assert_eq!(
+            tickv.update_key(get_hashed_key(b"ONE"), &[0x42; 32]),
+            Err(ErrorCode::WriteFail)
+        );
+
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv =
+            TicKV::<FlashCtrl, 1024>::new(tickv.controller.restart(), &mut read_buf, 0x10000);
+        tickv.initalise(main_key_hash()).unwrap();
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, [0x23; 32]);
+
+        println!("Lose power before the old value is invalidated");
+        tickv.controller.writes_left.set(Some(1));
+        assert_eq!(
+            tickv.update_key(get_hashed_key(b"ONE"), &[0x42; 32]),
+            Err(ErrorCode::WriteFail)
+        );
+
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv =
+            TicKV::<FlashCtrl, 1024>::new(tickv.controller.restart(), &mut read_buf, 0x10000);
+        tickv.initalise(main_key_hash()).unwrap();
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, [0x42; 32]);
+
+        println!("Update key ONE again");
+        tickv
+            .compare_and_swap_key(get_hashed_key(b"ONE"), &[0x42; 32], &[0x55; 32])
+            .unwrap();
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, [0x55; 32]);
+
+        println!("Delete key ONE, including the old copy");
+        tickv.invalidate_key(get_hashed_key(b"ONE")).unwrap();
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"ONE"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+        assert_eq!(
+            tickv.invalidate_key(get_hashed_key(b"ONE")),
+            Err(ErrorCode::KeyNotFound)
+        );
+    }
+
+    #[test]
+    fn test_keys_and_region_stats() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initalise(main_key_hash()).unwrap();
+
+        println!("Add keys ONE, TWO and THREE");
+        tickv.append_key(get_hashed_key(b"ONE"), &[1; 32]).unwrap();
+        tickv.append_key(get_hashed_key(b"TWO"), &[2; 16]).unwrap();
+        tickv.append_key(get_hashed_key(b"THREE"), &[3; 8]).unwrap();
+
+        println!("List the keys");
+        let mut keys: Vec<(u64, usize)> = tickv
+            .keys()
+            .map(|key| {
+                let key = key.unwrap();
+                (key.hash, key.value_length)
+            })
+            .collect();
+        keys.sort_unstable();
+        let mut expected = vec![
+            (main_key_hash(), 0),
+            (get_hashed_key(b"ONE"), 32),
+            (get_hashed_key(b"TWO"), 16),
+            (get_hashed_key(b"THREE"), 8),
+        ];
+        expected.sort_unstable();
+        assert_eq!(keys, expected);
+
+        println!("Delete key TWO");
+        tickv.invalidate_key(get_hashed_key(b"TWO")).unwrap();
+        assert_eq!(
+            tickv
+                .keys()
+                .filter(|key| key.unwrap().hash == get_hashed_key(b"TWO"))
+                .count(),
+            0
+        );
+        assert_eq!(tickv.keys().count(), 3);
+
+        println!("Check the region stats");
+        let mut total = RegionStats::default();
+        for region in 0..(0x10000 / 1024) {
+            let stats = tickv.region_stats(region).unwrap();
+            assert_eq!(stats.live + stats.invalidated + stats.free, 1024);
+            total.live += stats.live;
+            total.invalidated += stats.invalidated;
+            total.free += stats.free;
+        }
+        assert_eq!(total.live, 15 + 47 + 23);
+        assert_eq!(total.invalidated, 31);
+        assert_eq!(total.free, 0x10000 - 15 - 47 - 23 - 31);
+    }
+}
+
+/// Tests using a flash controller that can lose power at any byte written
+/// or region erased, checking that no acknowledged key is ever lost.
+mod fault_injection_flash_ctrl {
+    use super::*;
+    use crate::success_codes::SuccessCode;
+
+    const REGION_SIZE: usize = 256;
+    const NUM_REGIONS: usize = 8;
+
+    type Flash = [[u8; REGION_SIZE]; NUM_REGIONS];
+    type Store<'a> = TicKV<'a, FlashCtrl, REGION_SIZE>;
+
+    struct FlashCtrl {
+        buf: RefCell<Flash>,
+        // The number of bytes written and regions erased so far
+        steps: Cell<usize>,
+        // The step that power is lost before
+        power_loss: Cell<Option<usize>>,
+    }
+
+    impl FlashCtrl {
+        fn new(buf: Flash) -> Self {
+            Self {
+                buf: RefCell::new(buf),
+                steps: Cell::new(0),
+                power_loss: Cell::new(None),
+            }
+        }
+
+        // Start the next step, returning false if power has been lost
+        fn step(&self) -> bool {
+            if let Some(power_loss) = self.power_loss.get() {
+                if self.steps.get() >= power_loss {
+                    return false;
+                }
+            }
+            self.steps.set(self.steps.get() + 1);
+            true
+        }
+    }
+
+    impl FlashController<REGION_SIZE> for FlashCtrl {
+        fn read_region(
+            &self,
+            region_number: usize,
+            offset: usize,
+            buf: &mut [u8; REGION_SIZE],
+        ) -> Result<(), ErrorCode> {
+            if let Some(power_loss) = self.power_loss.get() {
+                if self.steps.get() >= power_loss {
+                    return Err(ErrorCode::ReadFail);
+                }
+            }
+
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = self.buf.borrow()[region_number][offset + i]
+            }
+
+            Ok(())
+        }
+
+        fn write(&self, address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
+            // Write a byte at a time, like flash does, so that power can be
+            // lost part way through. Writing can only clear bits.
+            for (i, d) in buf.iter().enumerate() {
+                if !self.step() {
+                    return Err(ErrorCode::WriteFail);
+                }
+                self.buf.borrow_mut()[(address + i) / REGION_SIZE][(address + i) % REGION_SIZE] &=
+                    *d;
+            }
+
+            Ok(())
+        }
+
+        fn erase_region(&self, region_number: usize) -> Result<(), ErrorCode> {
+            if !self.step() {
+                return Err(ErrorCode::EraseFail);
+            }
+
+            for d in self.buf.borrow_mut()[region_number].iter_mut() {
+                *d = 0xFF;
+            }
+
+            Ok(())
+        }
+    }
+
+    fn main_key_hash() -> u64 {
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        hash_function.finish()
+    }
+
+    fn check_value(tickv: &Store, key: &[u8], value: &[u8]) {
+        let mut buf = vec![0; value.len()];
+        tickv.get_key(get_hashed_key(key), &mut buf).unwrap();
+        assert_eq!(buf, value);
+    }
+
+    fn get_value(tickv: &Store, key: &[u8]) -> Option<[u8; 32]> {
+        let mut buf = [0; 32];
+        match tickv.get_key(get_hashed_key(key), &mut buf) {
+            Ok(_) => Some(buf),
+            Err(ErrorCode::KeyNotFound) => None,
+            Err(e) => panic!("Failed to get key: {:?}", e),
+        }
+    }
+
+    /// Start TicKV on `flash`, losing power at `power_loss`.
+    fn start(
+        flash: Flash,
+        read_buf: &mut [u8; REGION_SIZE],
+        power_loss: Option<usize>,
+    ) -> (Store, Result<SuccessCode, ErrorCode>) {
+        let controller = FlashCtrl::new(flash);
+        controller.power_loss.set(power_loss);
+        let tickv = Store::new(controller, read_buf, REGION_SIZE * NUM_REGIONS);
+        let ret = tickv.initalise(main_key_hash());
+        (tickv, ret)
+    }
+
+    /// Restart TicKV after power was lost and check the store.
+    ///
+    /// Power is also lost at every step of the recovery.
+    fn check_restart(flash: Flash, check: &impl Fn(&Store)) {
+        for power_loss in 0.. {
+            let mut read_buf = [0; REGION_SIZE];
+            let (tickv, ret) = start(flash, &mut read_buf, Some(power_loss));
+            if ret.is_ok() {
+                break;
+            }
+
+            let mut read_buf = [0; REGION_SIZE];
+            let (tickv, ret) = start(*tickv.controller.buf.borrow(), &mut read_buf, None);
+            ret.unwrap();
+            check(&tickv);
+        }
+
+        let mut read_buf = [0; REGION_SIZE];
+        let (tickv, ret) = start(flash, &mut read_buf, None);
+        ret.unwrap();
+        check(&tickv);
+    }
+
+    /// Lose power at every step of `operation` on the store that `setup`
+    /// creates, checking the store with `check` after restarting.
+    fn power_loss_matrix(
+        setup: impl Fn(&Store),
+        operation: impl Fn(&Store) -> Result<SuccessCode, ErrorCode>,
+        check: impl Fn(&Store),
+    ) {
+        let mut read_buf = [0; REGION_SIZE];
+        let (tickv, ret) = start([[0xFF; REGION_SIZE]; NUM_REGIONS], &mut read_buf, None);
+        ret.unwrap();
+        setup(&tickv);
+        let flash = *tickv.controller.buf.borrow();
+
+        println!("Run without losing power");
+        let mut read_buf = [0; REGION_SIZE];
+        let (tickv, ret) = start(flash, &mut read_buf, None);
+        ret.unwrap();
+        tickv.controller.steps.set(0);
+        operation(&tickv).unwrap();
+        let steps = tickv.controller.steps.get();
+        check_restart(*tickv.controller.buf.borrow(), &check);
+
+        for power_loss in 0..steps {
+            println!("Lose power at step {} of {}", power_loss, steps);
+            let mut read_buf = [0; REGION_SIZE];
+            let (tickv, ret) = start(flash, &mut read_buf, None);
+            ret.unwrap();
+            tickv.controller.steps.set(0);
+            tickv.controller.power_loss.set(Some(power_loss));
+            assert!(operation(&tickv).is_err());
+            check_restart(*tickv.controller.buf.borrow(), &check);
+        }
+    }
+
+    fn setup_keys(tickv: &Store) {
+        tickv.append_key(get_hashed_key(b"ONE"), &[1; 32]).unwrap();
+        tickv.append_key(get_hashed_key(b"TWO"), &[2; 32]).unwrap();
+        tickv
+            .append_key(get_hashed_key(b"THREE"), &[3; 32])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_power_loss_initalise() {
+        let mut read_buf = [0; REGION_SIZE];
+        let (tickv, ret) = start([[0xFF; REGION_SIZE]; NUM_REGIONS], &mut read_buf, None);
+        ret.unwrap();
+        let steps = tickv.controller.steps.get();
+
+        for power_loss in 0..steps {
+            println!("Lose power at step {} of {}", power_loss, steps);
+            let mut read_buf = [0; REGION_SIZE];
+            let (tickv, ret) = start(
+                [[0xFF; REGION_SIZE]; NUM_REGIONS],
+                &mut read_buf,
+                Some(power_loss),
+            );
+            assert!(ret.is_err());
+
+            check_restart(*tickv.controller.buf.borrow(), &|tickv| {
+                tickv.append_key(get_hashed_key(b"ONE"), &[1; 32]).unwrap();
+                check_value(tickv, b"ONE", &[1; 32]);
+            });
+        }
+    }
+
+    #[test]
+    fn test_power_loss_append() {
+        power_loss_matrix(
+            |tickv| {
+                tickv.append_key(get_hashed_key(b"ONE"), &[1; 32]).unwrap();
+                tickv.append_key(get_hashed_key(b"TWO"), &[2; 32]).unwrap();
+            },
+            |tickv| tickv.append_key(get_hashed_key(b"THREE"), &[3; 32]),
+            |tickv| {
+                check_value(tickv, b"ONE", &[1; 32]);
+                check_value(tickv, b"TWO", &[2; 32]);
+                match get_value(tickv, b"THREE") {
+                    Some(value) => assert_eq!(value, [3; 32]),
+                    None => {
+                        tickv
+                            .append_key(get_hashed_key(b"THREE"), &[3; 32])
+                            .unwrap();
+                        check_value(tickv, b"THREE", &[3; 32]);
+                    }
+                }
+            },
+        );
+    }
+
+    #[test]
+    fn test_power_loss_update() {
+        power_loss_matrix(
+            setup_keys,
+            |tickv| tickv.update_key(get_hashed_key(b"TWO"), &[0x42; 32]),
+            |tickv| {
+                check_value(tickv, b"ONE", &[1; 32]);
+                check_value(tickv, b"THREE", &[3; 32]);
+                let value = get_value(tickv, b"TWO").unwrap();
+                assert!(value == [2; 32] || value == [0x42; 32]);
+
+                tickv
+                    .update_key(get_hashed_key(b"TWO"), &[0x55; 32])
+                    .unwrap();
+                check_value(tickv, b"TWO", &[0x55; 32]);
+                tickv.invalidate_key(get_hashed_key(b"TWO")).unwrap();
+                assert_eq!(get_value(tickv, b"TWO"), None);
+            },
+        );
+    }
+
+    #[test]
+    fn test_power_loss_compare_and_swap() {
+        power_loss_matrix(
+            setup_keys,
+            |tickv| tickv.compare_and_swap_key(get_hashed_key(b"TWO"), &[2; 32], &[0x42; 32]),
+            |tickv| {
+                check_value(tickv, b"ONE", &[1; 32]);
+                check_value(tickv, b"THREE", &[3; 32]);
+                let value = get_value(tickv, b"TWO").unwrap();
+                assert!(value == [2; 32] || value == [0x42; 32]);
+
+                tickv
+                    .compare_and_swap_key(get_hashed_key(b"TWO"), &value, &[0x55; 32])
+                    .unwrap();
+                check_value(tickv, b"TWO", &[0x55; 32]);
+            },
+        );
+    }
+
+    #[test]
+    fn test_power_loss_invalidate() {
+        power_loss_matrix(
+            |tickv| {
+                setup_keys(tickv);
+                tickv
+                    .update_key(get_hashed_key(b"TWO"), &[0x42; 32])
+                    .unwrap();
+            },
+            |tickv| tickv.invalidate_key(get_hashed_key(b"TWO")),
+            |tickv| {
+                check_value(tickv, b"ONE", &[1; 32]);
+                check_value(tickv, b"THREE", &[3; 32]);
+                if let Some(value) = get_value(tickv, b"TWO") {
+                    assert_eq!(value, [0x42; 32]);
+                    tickv.invalidate_key(get_hashed_key(b"TWO")).unwrap();
+                }
+                assert_eq!(get_value(tickv, b"TWO"), None);
+
+                tickv.append_key(get_hashed_key(b"TWO"), &[2; 32]).unwrap();
+                check_value(tickv, b"TWO", &[2; 32]);
+            },
+        );
+    }
+
+    #[test]
+    fn test_power_loss_garbage_collect() {
+        power_loss_matrix(
+            |tickv| {
+                setup_keys(tickv);
+                tickv.invalidate_key(get_hashed_key(b"TWO")).unwrap();
+                tickv.invalidate_key(get_hashed_key(b"THREE")).unwrap();
+            },
+            |tickv| tickv.garbage_collect().map(|_| SuccessCode::Complete),
+            |tickv| {
+                check_value(tickv, b"ONE", &[1; 32]);
+                assert_eq!(get_value(tickv, b"TWO"), None);
+                assert_eq!(get_value(tickv, b"THREE"), None);
+
+                tickv.append_key(get_hashed_key(b"TWO"), &[2; 32]).unwrap();
+                check_value(tickv, b"TWO", &[2; 32]);
+            },
+        );
+    }
+
+    /// Find the region and offset of the first byte that differs between
+    /// `before` and `after`.
+    fn first_change(before: &Flash, after: &Flash) -> (usize, usize) {
+        (0..REGION_SIZE * NUM_REGIONS)
+            .map(|i| (i / REGION_SIZE, i % REGION_SIZE))
+            .find(|&(region, offset)| before[region][offset] != after[region][offset])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_recover_object_with_only_its_length_written() {
+        let mut read_buf = [0; REGION_SIZE];
+        let (tickv, ret) = start([[0xFF; REGION_SIZE]; NUM_REGIONS], &mut read_buf, None);
+        ret.unwrap();
+        tickv.append_key(get_hashed_key(b"ONE"), &[1; 32]).unwrap();
+        let before = *tickv.controller.buf.borrow();
+
+        println!("Lose power once the version and length are written");
+        tickv.controller.steps.set(0);
+        tickv.controller.power_loss.set(Some(LEN_OFFSET + 2));
+        assert!(tickv.append_key(get_hashed_key(b"TWO"), &[2; 32]).is_err());
+        let flash = *tickv.controller.buf.borrow();
+        let (region, offset) = first_change(&before, &flash);
+
+        let mut read_buf = [0; REGION_SIZE];
+        let (tickv, ret) = start(flash, &mut read_buf, None);
+        ret.unwrap();
+
+        // The object is marked invalid, but still covers a header and check
+        // sum
+        let flash = *tickv.controller.buf.borrow();
+        let length = ((flash[region][offset + LEN_OFFSET] & 0x0F) as usize) << 8
+            | flash[region][offset + LEN_OFFSET + 1] as usize;
+        assert_eq!(flash[region][offset + LEN_OFFSET] & 0x80, 0);
+        assert_eq!(length, HEADER_LENGTH + CHECK_SUM_LEN);
+
+        check_value(&tickv, b"ONE", &[1; 32]);
+        assert_eq!(get_value(&tickv, b"TWO"), None);
+        tickv.append_key(get_hashed_key(b"TWO"), &[2; 32]).unwrap();
+        check_value(&tickv, b"TWO", &[2; 32]);
+    }
+
+    #[test]
+    fn test_recover_object_before_zero_length() {
+        let mut read_buf = [0; REGION_SIZE];
+        let (tickv, ret) = start([[0xFF; REGION_SIZE]; NUM_REGIONS], &mut read_buf, None);
+        ret.unwrap();
+        tickv.append_key(get_hashed_key(b"ONE"), &[1; 32]).unwrap();
+        let before = *tickv.controller.buf.borrow();
+        tickv.append_key(get_hashed_key(b"TWO"), &[2; 32]).unwrap();
+        let mut flash = *tickv.controller.buf.borrow();
+        let (region, offset) = first_change(&before, &flash);
+
+        println!("Corrupt the value of TWO and follow it with a zero length");
+        flash[region][offset + HEADER_LENGTH] = 0;
+        let end = offset + HEADER_LENGTH + 32 + CHECK_SUM_LEN;
+        flash[region][end + VERSION_OFFSET] = VERSION;
+        flash[region][end + LEN_OFFSET] = 0;
+        flash[region][end + LEN_OFFSET + 1] = 0;
+
+        // TWO is still the last object, so it is rolled back
+        let mut read_buf = [0; REGION_SIZE];
+        let (tickv, ret) = start(flash, &mut read_buf, None);
+        ret.unwrap();
+        check_value(&tickv, b"ONE", &[1; 32]);
+        assert_eq!(get_value(&tickv, b"TWO"), None);
+    }
+}
+
+/// Tests of encrypting values
+mod encryption {
+    use super::*;
+    use crate::encryption::{self, Aes128Ccm};
+
+    #[test]
+    fn test_ccm_vector() {
+        // Packet Vector #1 from RFC 3610
+        let cipher = Aes128Ccm::new(&[
+            0xC0, 0xC1, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xCB, 0xCC, 0xCD,
+            0xCE, 0xCF,
+        ]);
+        let nonce = [
+            0x00, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00, 0xA0, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5,
+        ];
+        let aad = [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07];
+        let mut data = [0; 23];
+        for (i, d) in data.iter_mut().enumerate() {
+            *d = 0x08 + i as u8;
+        }
+        let plaintext = data;
+        let mut tag = [0; 8];
+
+        cipher.encrypt(&nonce, &aad, &mut data, &mut tag);
+        assert_eq!(
+            data,
+            [
+                0x58, 0x8C, 0x97, 0x9A, 0x61, 0xC6, 0x63, 0xD2, 0xF0, 0x66, 0xD0, 0xC2, 0xC0, 0xF9,
+                0x89, 0x80, 0x6D, 0x5F, 0x6B, 0x61, 0xDA, 0xC3, 0x84
+            ]
+        );
+        assert_eq!(tag, [0x17, 0xE8, 0xD1, 0x2C, 0xFD, 0xF9, 0x26, 0xE0]);
+
+        cipher.decrypt(&nonce, &aad, &mut data, &tag).unwrap();
+        assert_eq!(data, plaintext);
+    }
+
+    #[test]
+    fn test_encrypted_values() {
+        let cipher = Aes128Ccm::new(&[0x42; 16]);
+        let mut buf = [0; 32 + encryption::OVERHEAD];
+
+        println!("Encrypt a value for key ONE");
+        buf[..32].copy_from_slice(&[0x23; 32]);
+        let length = encryption::encrypt_value(
+            &cipher,
+            get_hashed_key(b"ONE"),
+            &[1; encryption::NONCE_LEN],
+            &mut buf,
+            32,
+        )
+        .unwrap();
+        assert_eq!(length, 32 + encryption::OVERHEAD);
+        assert!(buf.windows(32).all(|window| window != [0x23; 32].as_ref()));
+
+        println!("Encrypting needs space for the header and tag");
+        let mut small_buf = [0; 32];
+        assert_eq!(
+            encryption::encrypt_value(
+                &cipher,
+                get_hashed_key(b"ONE"),
+                &[1; encryption::NONCE_LEN],
+                &mut small_buf,
+                32
+            ),
+            Err(ErrorCode::BufferTooSmall(32 + encryption::OVERHEAD))
+        );
+
+        println!("Decrypt it, with space to spare");
+        let mut stored = [0xFF; 64];
+        stored[..length].copy_from_slice(&buf);
+        let mut buf = stored;
+        assert_eq!(
+            encryption::decrypt_value(&cipher, get_hashed_key(b"ONE"), &mut buf),
+            Ok(32)
+        );
+        assert_eq!(buf[..32], [0x23; 32]);
+
+        println!("Read it with the wrong device key");
+        let mut buf = stored;
+        assert_eq!(
+            encryption::decrypt_value(
+                &Aes128Ccm::new(&[0x24; 16]),
+                get_hashed_key(b"ONE"),
+                &mut buf
+            ),
+            Err(ErrorCode::AuthenticationFailed)
+        );
+        assert_eq!(buf[encryption::HEADER_LEN..][..32], [0; 32]);
+
+        println!("Read it as if it was stored under key TWO");
+        let mut buf = stored;
+        assert_eq!(
+            encryption::decrypt_value(&cipher, get_hashed_key(b"TWO"), &mut buf),
+            Err(ErrorCode::AuthenticationFailed)
+        );
+
+        println!("Change a stored byte");
+        for i in 0..(32 + encryption::OVERHEAD) {
+            let mut buf = stored;
+            buf[i] ^= 0x01;
+            assert!(encryption::decrypt_value(&cipher, get_hashed_key(b"ONE"), &mut buf).is_err());
+        }
+
+        println!("Read it into a buffer that is too small");
+        let mut buf = [0; 32];
+        buf.copy_from_slice(&stored[..32]);
+        assert_eq!(
+            encryption::decrypt_value(&cipher, get_hashed_key(b"ONE"), &mut buf),
+            Err(ErrorCode::BufferTooSmall(32 + encryption::OVERHEAD))
+        );
+    }
+}
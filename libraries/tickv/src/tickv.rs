@@ -19,6 +19,10 @@ pub(crate) enum InitState {
     EraseComplete,
     /// Trying to read a region while appending a key
     AppendKeyReadRegion(usize),
+    /// Trying to read a region while recovering from a power loss
+    RecoverReadRegion(usize),
+    /// Recovering from a power loss, starting at this region
+    Recover(usize),
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -27,6 +31,31 @@ pub(crate) enum KeyState {
     ReadRegion(usize),
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum InvalidateState {
+    /// Trying to read the key from a region
+    ReadRegion(usize),
+    /// An object written by `update_key()` has been invalidated, so older
+    /// copies of the key still need to be searched for.
+    Cleanup,
+    /// Trying to read a region while searching for older copies of the key
+    CleanupReadRegion(usize),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum UpdateState {
+    /// Trying to read the key from a region
+    FindKeyReadRegion(usize),
+    /// Trying to read a region to add the new object to. Also includes the
+    /// region and offset of the object being replaced.
+    AppendKeyReadRegion(usize, usize, usize),
+    /// The new object has been written. The object being replaced, at this
+    /// region and offset, still needs to be invalidated.
+    InvalidateKey(usize, usize),
+    /// Trying to read the region of the object being replaced
+    InvalidateKeyReadRegion(usize, usize),
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub(crate) enum RubbishState {
     ReadRegion(usize),
@@ -46,9 +75,15 @@ pub(crate) enum State {
     /// Getting a key
     GetKey(KeyState),
     /// Invalidating a key
-    InvalidateKey(KeyState),
+    InvalidateKey(InvalidateState),
+    /// Updating a key
+    UpdateKey(UpdateState),
     /// Running garbage collection
     GarbageCollect(RubbishState),
+    /// Trying to read a region while finding the next key
+    NextKey(usize),
+    /// Trying to read a region while collecting its statistics
+    RegionStats(usize),
 }
 
 /// The struct storing all of the TicKV information.
@@ -71,6 +106,10 @@ struct ObjectHeader {
 }
 
 pub(crate) const FLAGS_VALID: u8 = 8;
+/// Set on objects written by `update_key()`, which replace an older object
+/// with the same key. If power was lost during the update the older object
+/// might still be valid.
+pub(crate) const FLAGS_REPLACES: u8 = 4;
 
 impl ObjectHeader {
     fn new(hashed_key: u64, len: u16) -> Self {
@@ -95,6 +134,31 @@ pub(crate) const CHECK_SUM_LEN: usize = 4;
 /// `initalise()`.
 pub const MAIN_KEY: &[u8; 15] = b"tickv-super-key";
 
+/// A valid key stored in flash, as returned by `next_key()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StoredKey {
+    /// The hashed key
+    pub hash: u64,
+    /// The length of the value stored under the key
+    pub value_length: usize,
+    region: usize,
+    offset: usize,
+}
+
+/// How the space in a region is used, as returned by `region_stats()`.
+///
+/// All of the values are in bytes and add up to the size of the region.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RegionStats {
+    /// Used by valid objects, including their headers and check sums
+    pub live: usize,
+    /// Used by invalidated objects. This is freed by garbage collection
+    /// once every object in the region has been invalidated.
+    pub invalidated: usize,
+    /// Not written to since the region was erased
+    pub free: usize,
+}
+
 /// This is the main TicKV struct.
 impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     /// Create a new struct
@@ -113,7 +177,8 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     }
 
     /// This function setups the flash region to be used as a key-value store.
-    /// If the region is already initalised this won't make any changes.
+    /// If the region is already initalised this won't make any changes,
+    /// apart from rolling back objects left partially written by a power loss.
     ///
     /// `hashed_main_key`: The u64 hash of the const string `MAIN_KEY`.
     ///
@@ -129,13 +194,14 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
             State::None => self.get_key(hashed_main_key, &mut buf),
             State::Init(state) => match state {
                 InitState::GetKeyReadRegion(_) => self.get_key(hashed_main_key, &mut buf),
+                InitState::RecoverReadRegion(_) | InitState::Recover(_) => return self.recover(),
                 _ => Err(ErrorCode::EraseNotReady(0)),
             },
             _ => unreachable!(),
         };
 
         match key_ret {
-            Ok(ret) => Ok(ret),
+            Ok(_) => self.recover(),
             Err(e) => {
                 match e {
                     ErrorCode::ReadNotReady(reg) => {
@@ -243,6 +309,10 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
     /// Find a key in some loaded region data.
     ///
+    /// If power was lost during `update_key()` the region can contain more
+    /// than one valid object for the key. Objects are appended in order, so
+    /// the last one is the newest and is the one returned.
+    ///
     /// On success return the offset in the region_data where the key is and the
     /// total length of the key.
     /// On failure return a bool indicating if the caller should keep looking in
@@ -259,11 +329,12 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
         let mut offset: usize = 0;
         let mut empty: bool = true;
+        let mut found: Option<(usize, u16)> = None;
 
         loop {
             if offset + HEADER_LENGTH >= S {
                 // We have reached the end of the region
-                return Err((false, ErrorCode::KeyNotFound));
+                return found.ok_or((false, ErrorCode::KeyNotFound));
             }
 
             // Check to see if we have data
@@ -273,7 +344,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
                 // We found a version, check that we support it
                 if region_data[offset + VERSION_OFFSET] != VERSION {
-                    return Err((false, ErrorCode::UnsupportedVersion));
+                    return found.ok_or((false, ErrorCode::UnsupportedVersion));
                 }
 
                 // Find this entries length
@@ -283,7 +354,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 // Check to see if all fields are just 0
                 if total_length == 0 {
                     // We found something invalid here
-                    return Err((false, ErrorCode::KeyNotFound));
+                    return found.ok_or((false, ErrorCode::KeyNotFound));
                 }
 
                 // Check to see if the entry has been deleted
@@ -308,15 +379,146 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     continue;
                 }
 
-                // If we get here we have found out value (assuming no collisions)
-                return Ok((offset, total_length));
+                // If we get here we have found out value (assuming no collisions).
+                // Keep looking in case there is a newer copy.
+                found = Some((offset, total_length));
+                offset += total_length as usize;
             } else {
                 // We hit the end.
-                return Err((!empty, ErrorCode::KeyNotFound));
+                return found.ok_or((!empty, ErrorCode::KeyNotFound));
             }
         }
     }
 
+    /// Find space for an object of `package_length` bytes (not including the
+    /// check sum) in some loaded region data.
+    ///
+    /// On success return the offset of the free space, or `None` if the
+    /// region is full.
+    fn find_free_offset(
+        &self,
+        region_data: &[u8],
+        package_length: usize,
+    ) -> Result<Option<usize>, ErrorCode> {
+        let mut offset: usize = 0;
+
+        loop {
+            if offset + package_length >= S {
+                // We have reached the end of the region
+                return Ok(None);
+            }
+
+            // Check to see if we have data
+            if region_data[offset + VERSION_OFFSET] != 0xFF {
+                // We found a version, check that we support it
+                if region_data[offset + VERSION_OFFSET] != VERSION {
+                    return Err(ErrorCode::UnsupportedVersion);
+                }
+
+                // Find this entries length
+                let total_length = ((region_data[offset + LEN_OFFSET] as u16) & !0xF0) << 8
+                    | region_data[offset + LEN_OFFSET + 1] as u16;
+
+                // Increment our offset by the length and repeat the loop
+                offset += total_length as usize;
+                continue;
+            }
+
+            // If we get here we have found an empty spot
+            // Double check that there is no valid hash
+
+            // Check to see if the entire header is 0xFFFF_FFFF_FFFF_FFFF
+            if region_data[offset + HASH_OFFSET..offset + HASH_OFFSET + 8]
+                .iter()
+                .any(|b| *b != 0xFF)
+            {
+                return Err(ErrorCode::CorruptData);
+            }
+
+            return Ok(Some(offset));
+        }
+    }
+
+    /// Write an object with `header` and `value` to the free space at
+    /// `offset` in `region`.
+    ///
+    /// `region_data` must contain the data of `region`.
+    fn write_object(
+        &self,
+        region: usize,
+        offset: usize,
+        region_data: &mut [u8; S],
+        header: &ObjectHeader,
+        value: &[u8],
+    ) -> Result<(), ErrorCode> {
+        let crc = crc32::Crc::new();
+        let mut check_sum = crc.digest();
+
+        // Length not including check sum
+        let package_length = HEADER_LENGTH + value.len();
+
+        // Copy in new header
+        // This is a little painful, but avoids any unsafe Rust
+        region_data[offset + VERSION_OFFSET] = header.version;
+        region_data[offset + LEN_OFFSET] =
+            (header.len >> 8) as u8 & 0x0F | (header.flags << 4) & 0xF0;
+        region_data[offset + LEN_OFFSET + 1] = (header.len & 0xFF) as u8;
+        region_data[offset + HASH_OFFSET] = (header.hashed_key >> 56) as u8;
+        region_data[offset + HASH_OFFSET + 1] = (header.hashed_key >> 48) as u8;
+        region_data[offset + HASH_OFFSET + 2] = (header.hashed_key >> 40) as u8;
+        region_data[offset + HASH_OFFSET + 3] = (header.hashed_key >> 32) as u8;
+        region_data[offset + HASH_OFFSET + 4] = (header.hashed_key >> 24) as u8;
+        region_data[offset + HASH_OFFSET + 5] = (header.hashed_key >> 16) as u8;
+        region_data[offset + HASH_OFFSET + 6] = (header.hashed_key >> 8) as u8;
+        region_data[offset + HASH_OFFSET + 7] = (header.hashed_key) as u8;
+
+        // Hash the new header data
+        check_sum.update(&region_data[offset + VERSION_OFFSET..=offset + HASH_OFFSET + 7]);
+
+        // Copy the value
+        let slice = &mut region_data[(offset + HEADER_LENGTH)..(offset + package_length)];
+        slice.copy_from_slice(value);
+
+        // Include the value in the hash
+        check_sum.update(value);
+
+        // Append a Check Hash
+        let check_sum = check_sum.finalise();
+        let slice =
+            &mut region_data[(offset + package_length)..(offset + package_length + CHECK_SUM_LEN)];
+        slice.copy_from_slice(&check_sum.to_ne_bytes());
+
+        // Write the data back to the region
+        self.controller.write(
+            S * region + offset,
+            &region_data[offset..(offset + package_length + CHECK_SUM_LEN)],
+        )
+    }
+
+    /// Read `region` into the read buffer and return it.
+    ///
+    /// If `loaded` is set the region has already been read by an async
+    /// read and is not read again. If the read is not ready the state is
+    /// set to `not_ready`.
+    fn load_region(
+        &self,
+        region: usize,
+        loaded: bool,
+        not_ready: State,
+    ) -> Result<&'a mut [u8; S], ErrorCode> {
+        let region_data = self.read_buffer.take().unwrap();
+        if !loaded {
+            if let Err(e) = self.controller.read_region(region, 0, region_data) {
+                self.read_buffer.replace(Some(region_data));
+                if let ErrorCode::ReadNotReady(_) = e {
+                    self.state.set(not_ready);
+                }
+                return Err(e);
+            }
+        }
+        Ok(region_data)
+    }
+
     /// Appends the key/value pair to flash storage.
     ///
     /// `hash`: A hashed key. This key will be used in future to retrieve
@@ -327,8 +529,6 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     /// On error a `ErrorCode` will be returned.
     pub fn append_key(&self, hash: u64, value: &[u8]) -> Result<SuccessCode, ErrorCode> {
         let region = self.get_region(hash);
-        let crc = crc32::Crc::new();
-        let mut check_sum = crc.digest();
 
         // Length not including check sum
         let package_length = HEADER_LENGTH + value.len();
@@ -388,10 +588,19 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 return Err(ErrorCode::KeyAlreadyExists);
             }
 
-            let mut offset: usize = 0;
+            match self.find_free_offset(region_data, package_length) {
+                Ok(Some(offset)) => {
+                    let ret =
+                        self.write_object(new_region as usize, offset, region_data, &header, value);
+                    self.read_buffer.replace(Some(region_data));
 
-            loop {
-                if offset + package_length >= S {
+                    return match ret {
+                        Ok(()) => Ok(SuccessCode::Written),
+                        Err(ErrorCode::WriteNotReady(_)) => Ok(SuccessCode::Queued),
+                        Err(e) => Err(e),
+                    };
+                }
+                Ok(None) => {
                     // We have reached the end of the region
                     // We will need to try the next region
 
@@ -406,111 +615,11 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                             return Err(ErrorCode::FlashFull);
                         }
                     }
-                    break;
-                }
-
-                // Check to see if we have data
-                if region_data[offset + VERSION_OFFSET] != 0xFF {
-                    // We found a version, check that we support it
-                    if region_data[offset + VERSION_OFFSET] != VERSION {
-                        self.read_buffer.replace(Some(region_data));
-                        return Err(ErrorCode::UnsupportedVersion);
-                    }
-
-                    // Find this entries length
-                    let total_length = ((region_data[offset + LEN_OFFSET] as u16) & !0xF0) << 8
-                        | region_data[offset + LEN_OFFSET + 1] as u16;
-
-                    // Increment our offset by the length and repeat the loop
-                    offset += total_length as usize;
-                    continue;
-                }
-
-                // If we get here we have found an empty spot
-                // Double check that there is no valid hash
-
-                // Check to see if the entire header is 0xFFFF_FFFF_FFFF_FFFF
-                // To avoid operating on 64-bit values check every 8 bytes at a time
-                if region_data[offset + HASH_OFFSET] != 0xFF {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if region_data[offset + HASH_OFFSET + 1] != 0xFF {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if region_data[offset + HASH_OFFSET + 2] != 0xFF {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if region_data[offset + HASH_OFFSET + 3] != 0xFF {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
                 }
-                if region_data[offset + HASH_OFFSET + 4] != 0xFF {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if region_data[offset + HASH_OFFSET + 5] != 0xFF {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if region_data[offset + HASH_OFFSET + 6] != 0xFF {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if region_data[offset + HASH_OFFSET + 7] != 0xFF {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-
-                // If we get here we have found an empty spot
-
-                // Copy in new header
-                // This is a little painful, but avoids any unsafe Rust
-                region_data[offset + VERSION_OFFSET] = header.version;
-                region_data[offset + LEN_OFFSET] =
-                    (header.len >> 8) as u8 & 0x0F | (header.flags << 4) & 0xF0;
-                region_data[offset + LEN_OFFSET + 1] = (header.len & 0xFF) as u8;
-                region_data[offset + HASH_OFFSET] = (header.hashed_key >> 56) as u8;
-                region_data[offset + HASH_OFFSET + 1] = (header.hashed_key >> 48) as u8;
-                region_data[offset + HASH_OFFSET + 2] = (header.hashed_key >> 40) as u8;
-                region_data[offset + HASH_OFFSET + 3] = (header.hashed_key >> 32) as u8;
-                region_data[offset + HASH_OFFSET + 4] = (header.hashed_key >> 24) as u8;
-                region_data[offset + HASH_OFFSET + 5] = (header.hashed_key >> 16) as u8;
-                region_data[offset + HASH_OFFSET + 6] = (header.hashed_key >> 8) as u8;
-                region_data[offset + HASH_OFFSET + 7] = (header.hashed_key) as u8;
-
-                // Hash the new header data
-                check_sum.update(&region_data[offset + VERSION_OFFSET..=offset + HASH_OFFSET + 7]);
-
-                // Copy the value
-                let slice = &mut region_data[(offset + HEADER_LENGTH)..(offset + package_length)];
-                slice.copy_from_slice(value);
-
-                // Include the value in the hash
-                check_sum.update(value);
-
-                // Append a Check Hash
-                let check_sum = check_sum.finalise();
-                let slice = &mut region_data
-                    [(offset + package_length)..(offset + package_length + CHECK_SUM_LEN)];
-                slice.copy_from_slice(&check_sum.to_ne_bytes());
-
-                // Write the data back to the region
-                if let Err(e) = self.controller.write(
-                    S * new_region as usize + offset,
-                    &region_data[offset..(offset + package_length + CHECK_SUM_LEN)],
-                ) {
+                Err(e) => {
                     self.read_buffer.replace(Some(region_data));
-                    match e {
-                        ErrorCode::WriteNotReady(_) => return Ok(SuccessCode::Queued),
-                        _ => return Err(e),
-                    }
+                    return Err(e);
                 }
-
-                self.read_buffer.replace(Some(region_data));
-                return Ok(SuccessCode::Written);
             }
         }
     }
@@ -632,6 +741,12 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     /// On success nothing will be returned.
     /// On error a `ErrorCode` will be returned.
     ///
+    /// If the key was written by `update_key()` any older copies left by a
+    /// power loss during the update are invalidated as well. If the flash
+    /// controller is async this can need more than one write, in which case
+    /// `WriteNotReady` is returned and the operation should be continued
+    /// once the write has completed.
+    ///
     /// If a power loss occurs before success is returned the data is
     /// assumed to be lost.
     pub fn invalidate_key(&self, hash: u64) -> Result<SuccessCode, ErrorCode> {
@@ -639,19 +754,32 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
         let mut region_offset: isize = 0;
 
+        // Set once an object written by `update_key()` has been invalidated
+        let mut cleanup = matches!(
+            self.state.get(),
+            State::InvalidateKey(InvalidateState::Cleanup)
+                | State::InvalidateKey(InvalidateState::CleanupReadRegion(_))
+        );
+
         loop {
             // Get the data from that region
             let new_region = match self.state.get() {
-                State::None => region as isize + region_offset,
-                State::InvalidateKey(key_state) => match key_state {
-                    KeyState::ReadRegion(reg) => reg as isize,
-                },
+                State::None | State::InvalidateKey(InvalidateState::Cleanup) => {
+                    region as isize + region_offset
+                }
+                State::InvalidateKey(InvalidateState::ReadRegion(reg))
+                | State::InvalidateKey(InvalidateState::CleanupReadRegion(reg)) => reg as isize,
                 _ => unreachable!(),
             };
+            let read_state = if cleanup {
+                InvalidateState::CleanupReadRegion(new_region as usize)
+            } else {
+                InvalidateState::ReadRegion(new_region as usize)
+            };
 
             // Get the data from that region
             let mut region_data = self.read_buffer.take().unwrap();
-            if self.state.get() != State::InvalidateKey(KeyState::ReadRegion(new_region as usize)) {
+            if self.state.get() != State::InvalidateKey(read_state) {
                 match self
                     .controller
                     .read_region(new_region as usize, 0, &mut region_data)
@@ -659,9 +787,8 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     Ok(()) => {}
                     Err(e) => {
                         self.read_buffer.replace(Some(region_data));
-                        if let ErrorCode::ReadNotReady(reg) = e {
-                            self.state
-                                .set(State::InvalidateKey(KeyState::ReadRegion(reg)));
+                        if let ErrorCode::ReadNotReady(_) = e {
+                            self.state.set(State::InvalidateKey(read_state));
                         }
                         return Err(e);
                     }
@@ -670,43 +797,441 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
             match self.find_key_offset(hash, region_data) {
                 Ok((offset, _data_len)) => {
+                    let replaces = region_data[offset + LEN_OFFSET] & (FLAGS_REPLACES << 4) != 0;
+
                     // We found a key, let's delete it
                     region_data[offset + LEN_OFFSET] &= !0x80;
 
-                    if let Err(e) = self.controller.write(
+                    let ret = self.controller.write(
                         S * new_region as usize + offset + LEN_OFFSET,
                         &region_data[offset + LEN_OFFSET..offset + LEN_OFFSET + 1],
-                    ) {
-                        self.read_buffer.replace(Some(region_data));
-                        match e {
-                            ErrorCode::WriteNotReady(_) => return Ok(SuccessCode::Queued),
-                            _ => return Err(e),
+                    );
+                    self.read_buffer.replace(Some(region_data));
+
+                    if replaces {
+                        // This object replaced an older one which might
+                        // still be valid, so search for the key again.
+                        self.state
+                            .set(State::InvalidateKey(InvalidateState::Cleanup));
+                        match ret {
+                            Ok(()) => {}
+                            Err(ErrorCode::WriteNotReady(address)) => {
+                                return Err(ErrorCode::WriteNotReady(address));
+                            }
+                            Err(e) => {
+                                self.state.set(State::None);
+                                return Err(e);
+                            }
                         }
+                        cleanup = true;
+                        region_offset = 0;
+                        continue;
                     }
 
-                    self.read_buffer.replace(Some(region_data));
-                    return Ok(SuccessCode::Written);
+                    if cleanup {
+                        self.state.set(State::None);
+                    }
+                    return match ret {
+                        Ok(()) => Ok(SuccessCode::Written),
+                        Err(ErrorCode::WriteNotReady(_)) => Ok(SuccessCode::Queued),
+                        Err(e) => Err(e),
+                    };
                 }
                 Err((cont, e)) => {
                     self.read_buffer.replace(Some(region_data));
 
                     if cont {
-                        match self.increment_region_offset(new_region) {
-                            Some(o) => {
-                                region_offset = o;
-                            }
-                            None => {
-                                return Err(e);
+                        if let Some(o) = self.increment_region_offset(new_region) {
+                            region_offset = o;
+                            if cleanup {
+                                self.state
+                                    .set(State::InvalidateKey(InvalidateState::Cleanup));
                             }
+                            continue;
                         }
-                    } else {
+                    }
+
+                    if cleanup {
+                        // All older copies have been invalidated
+                        self.state.set(State::None);
+                        return Ok(SuccessCode::Written);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Replaces the value of a key in flash storage.
+    ///
+    /// `hash`: A hashed key.
+    /// `value`: A buffer containing the new value to be stored to flash.
+    ///
+    /// The new value is written before the old one is invalidated. If power
+    /// is lost during the update the old value is kept or, if the new value
+    /// had already been written, both are kept and the newest is used.
+    ///
+    /// If the flash controller is async the update needs more than one
+    /// write. `WriteNotReady` is returned while there are still writes left
+    /// to do and the operation should be continued once the write has
+    /// completed.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn update_key(&self, hash: u64, value: &[u8]) -> Result<SuccessCode, ErrorCode> {
+        self.replace_key(hash, None, value)
+    }
+
+    /// Replaces the value of a key in flash storage, if the value currently
+    /// stored is `expected`.
+    ///
+    /// `hash`: A hashed key.
+    /// `expected`: The value that must currently be stored.
+    /// `value`: A buffer containing the new value to be stored to flash.
+    ///
+    /// If the stored value doesn't match `expected` nothing is written and
+    /// `ValueMismatch` is returned. Otherwise this behaves like
+    /// `update_key()`.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn compare_and_swap_key(
+        &self,
+        hash: u64,
+        expected: &[u8],
+        value: &[u8],
+    ) -> Result<SuccessCode, ErrorCode> {
+        self.replace_key(hash, Some(expected), value)
+    }
+
+    fn replace_key(
+        &self,
+        hash: u64,
+        expected: Option<&[u8]>,
+        value: &[u8],
+    ) -> Result<SuccessCode, ErrorCode> {
+        if HEADER_LENGTH + value.len() + CHECK_SUM_LEN > 0xFFF {
+            return Err(ErrorCode::ObjectTooLarge);
+        }
+
+        let (old_region, old_offset) = match self.state.get() {
+            State::None | State::UpdateKey(UpdateState::FindKeyReadRegion(_)) => {
+                self.find_newest_key(hash, expected)?
+            }
+            State::UpdateKey(UpdateState::AppendKeyReadRegion(_, reg, offset)) => (reg, offset),
+            State::UpdateKey(UpdateState::InvalidateKey(reg, offset))
+            | State::UpdateKey(UpdateState::InvalidateKeyReadRegion(reg, offset)) => {
+                return self.invalidate_replaced_object(reg, offset);
+            }
+            _ => unreachable!(),
+        };
+
+        match self.append_replacement(hash, value, old_region, old_offset) {
+            Ok(()) => {}
+            Err(ErrorCode::WriteNotReady(address)) => {
+                self.state.set(State::UpdateKey(UpdateState::InvalidateKey(
+                    old_region, old_offset,
+                )));
+                return Err(ErrorCode::WriteNotReady(address));
+            }
+            Err(e) => return Err(e),
+        }
+
+        self.invalidate_replaced_object(old_region, old_offset)
+    }
+
+    /// Find the region and offset of the newest object for the key, checking
+    /// that its value is `expected` if set.
+    fn find_newest_key(
+        &self,
+        hash: u64,
+        expected: Option<&[u8]>,
+    ) -> Result<(usize, usize), ErrorCode> {
+        let region = self.get_region(hash);
+        let mut region_offset: isize = 0;
+        let mut resume = self.state.get();
+
+        loop {
+            let new_region = match resume {
+                State::UpdateKey(UpdateState::FindKeyReadRegion(reg)) => reg,
+                _ => (region as isize + region_offset) as usize,
+            };
+            let read_state = State::UpdateKey(UpdateState::FindKeyReadRegion(new_region));
+            let region_data = self.load_region(new_region, resume == read_state, read_state)?;
+            resume = State::None;
+
+            match self.find_key_offset(hash, region_data) {
+                Ok((offset, total_length)) => {
+                    let ret = match expected {
+                        Some(expected) => {
+                            self.check_value(region_data, offset, total_length as usize, expected)
+                        }
+                        None => Ok(()),
+                    };
+                    self.read_buffer.replace(Some(region_data));
+                    return ret.map(|()| (new_region, offset));
+                }
+                Err((cont, e)) => {
+                    self.read_buffer.replace(Some(region_data));
+
+                    if !cont {
                         return Err(e);
                     }
+                    match self.increment_region_offset(new_region as isize) {
+                        Some(o) => {
+                            region_offset = o;
+                        }
+                        None => {
+                            return Err(e);
+                        }
+                    }
                 }
             }
         }
     }
 
+    /// Check that the check sum of the object at `offset` matches its
+    /// contents.
+    fn check_sum_valid(&self, region_data: &[u8], offset: usize, total_length: usize) -> bool {
+        let crc = crc32::Crc::new();
+        let mut check_sum = crc.digest();
+        let package_end = offset + total_length - CHECK_SUM_LEN;
+
+        check_sum.update(&region_data[offset..package_end]);
+        region_data[package_end..(package_end + CHECK_SUM_LEN)]
+            == check_sum.finalise().to_ne_bytes()
+    }
+
+    /// Find the last object in the region, if there is one.
+    fn find_last_object(&self, region_data: &[u8]) -> Option<usize> {
+        let mut offset: usize = 0;
+        let mut last = None;
+
+        loop {
+            if offset + HEADER_LENGTH >= S || region_data[offset + VERSION_OFFSET] != VERSION {
+                // We reached the end of the objects, or data we don't understand
+                return last;
+            }
+
+            let total_length = ((region_data[offset + LEN_OFFSET] as u16) & !0xF0) << 8
+                | region_data[offset + LEN_OFFSET + 1] as u16;
+            if total_length == 0 {
+                // Nothing valid can follow an object without a length
+                return last;
+            }
+
+            last = Some(offset);
+            offset += total_length as usize;
+        }
+    }
+
+    /// Roll back objects that were only partially written when power was
+    /// lost.
+    ///
+    /// A partially written object is marked as invalid, so that any older
+    /// copy of the key is used instead. If the length of the object wasn't
+    /// written, it is set to cover the bytes that were written so that the
+    /// rest of the region can still be used.
+    ///
+    /// This is called by `initalise()` on every region.
+    fn recover(&self) -> Result<SuccessCode, ErrorCode> {
+        let num_regions = self.flash_size / S;
+        let (start, loaded) = match self.state.get() {
+            State::Init(InitState::RecoverReadRegion(reg)) => (reg, true),
+            State::Init(InitState::Recover(reg)) => (reg, false),
+            _ => (0, false),
+        };
+        let mut written = false;
+
+        for region in start..num_regions {
+            let region_data = self.load_region(
+                region,
+                loaded && region == start,
+                State::Init(InitState::RecoverReadRegion(region)),
+            )?;
+
+            let offset = match self.find_last_object(region_data) {
+                Some(offset) => offset,
+                None => {
+                    self.read_buffer.replace(Some(region_data));
+                    continue;
+                }
+            };
+
+            let length = ((region_data[offset + LEN_OFFSET] as u16) & !0xF0) << 8
+                | region_data[offset + LEN_OFFSET + 1] as u16;
+            let length = length as usize;
+            let valid = region_data[offset + LEN_OFFSET] & 0x80 == 0x80;
+
+            // Objects are only ever appended to the end of a region, so only
+            // the last object can be partially written. It is if its length
+            // runs past the end of the region, or if it is still valid but
+            // its check sum doesn't match.
+            let partial = offset + length > S
+                || (valid
+                    && (length < HEADER_LENGTH + CHECK_SUM_LEN
+                        || !self.check_sum_valid(region_data, offset, length)));
+
+            // Nothing after the last object has been written, apart from
+            // the length we are about to write. Objects are never shorter
+            // than a header and check sum, as the other scanners expect.
+            let written_length = (region_data.iter().rposition(|b| *b != 0xFF).unwrap() + 1
+                - offset)
+                .max(HEADER_LENGTH + CHECK_SUM_LEN);
+
+            // Shrink invalid objects to cover what was written, so that the
+            // rest of the region can still be used. We can only clear bits,
+            // so keep the length if the new one needs bits that are cleared.
+            let new_length = if (partial || !valid)
+                && written_length < length
+                && written_length & !length == 0
+            {
+                written_length
+            } else {
+                length
+            };
+
+            if !partial && new_length == length {
+                self.read_buffer.replace(Some(region_data));
+                continue;
+            }
+
+            region_data[offset + LEN_OFFSET] =
+                (region_data[offset + LEN_OFFSET] & 0x70) | (new_length >> 8) as u8;
+            region_data[offset + LEN_OFFSET + 1] = new_length as u8;
+
+            let ret = self.controller.write(
+                S * region + offset + LEN_OFFSET,
+                &region_data[(offset + LEN_OFFSET)..(offset + LEN_OFFSET + 2)],
+            );
+            self.read_buffer.replace(Some(region_data));
+
+            match ret {
+                Ok(()) => written = true,
+                Err(ErrorCode::WriteNotReady(address)) => {
+                    self.state.set(State::Init(InitState::Recover(region + 1)));
+                    return Err(ErrorCode::WriteNotReady(address));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if written {
+            Ok(SuccessCode::Written)
+        } else {
+            Ok(SuccessCode::Complete)
+        }
+    }
+
+    /// Check that the object at `offset` is intact and has the value
+    /// `expected`.
+    fn check_value(
+        &self,
+        region_data: &[u8],
+        offset: usize,
+        total_length: usize,
+        expected: &[u8],
+    ) -> Result<(), ErrorCode> {
+        if !self.check_sum_valid(region_data, offset, total_length) {
+            return Err(ErrorCode::InvalidCheckSum);
+        }
+
+        let package_end = offset + total_length - CHECK_SUM_LEN;
+        if &region_data[(offset + HEADER_LENGTH)..package_end] != expected {
+            return Err(ErrorCode::ValueMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// Write the object replacing the one at `old_offset` in `old_region`.
+    ///
+    /// Lookups stop at the first region containing the key, so the new
+    /// object is only written to regions that are searched before or are
+    /// the same as `old_region`. In `old_region` it is written after the old
+    /// object, so it is the newest.
+    fn append_replacement(
+        &self,
+        hash: u64,
+        value: &[u8],
+        old_region: usize,
+        old_offset: usize,
+    ) -> Result<(), ErrorCode> {
+        let region = self.get_region(hash);
+        let package_length = HEADER_LENGTH + value.len();
+        let object_length = HEADER_LENGTH + value.len() + CHECK_SUM_LEN;
+
+        let mut header = ObjectHeader::new(hash, object_length as u16);
+        header.flags |= FLAGS_REPLACES;
+
+        let mut region_offset: isize = 0;
+        let mut resume = self.state.get();
+
+        loop {
+            let new_region = match resume {
+                State::UpdateKey(UpdateState::AppendKeyReadRegion(reg, _, _)) => reg,
+                _ => (region as isize + region_offset) as usize,
+            };
+            let read_state = State::UpdateKey(UpdateState::AppendKeyReadRegion(
+                new_region, old_region, old_offset,
+            ));
+            let region_data = self.load_region(new_region, resume == read_state, read_state)?;
+            resume = State::None;
+
+            match self.find_free_offset(region_data, package_length) {
+                Ok(Some(offset)) => {
+                    let ret = self.write_object(new_region, offset, region_data, &header, value);
+                    self.read_buffer.replace(Some(region_data));
+                    return ret;
+                }
+                Ok(None) => {
+                    self.read_buffer.replace(Some(region_data));
+
+                    if new_region == old_region {
+                        return Err(ErrorCode::RegionFull);
+                    }
+                    match self.increment_region_offset(new_region as isize) {
+                        Some(o) => {
+                            region_offset = o;
+                        }
+                        None => {
+                            return Err(ErrorCode::RegionFull);
+                        }
+                    }
+                }
+                Err(e) => {
+                    self.read_buffer.replace(Some(region_data));
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Invalidate the object at `offset` in `region` once it has been
+    /// replaced.
+    fn invalidate_replaced_object(
+        &self,
+        region: usize,
+        offset: usize,
+    ) -> Result<SuccessCode, ErrorCode> {
+        let read_state = State::UpdateKey(UpdateState::InvalidateKeyReadRegion(region, offset));
+        let region_data = self.load_region(region, self.state.get() == read_state, read_state)?;
+
+        region_data[offset + LEN_OFFSET] &= !0x80;
+
+        let ret = self.controller.write(
+            S * region + offset + LEN_OFFSET,
+            &region_data[offset + LEN_OFFSET..offset + LEN_OFFSET + 1],
+        );
+        self.read_buffer.replace(Some(region_data));
+
+        match ret {
+            Ok(()) => Ok(SuccessCode::Written),
+            Err(ErrorCode::WriteNotReady(_)) => Ok(SuccessCode::Queued),
+            Err(e) => Err(e),
+        }
+    }
+
     fn garbage_collect_region(&self, region: usize) -> Result<usize, ErrorCode> {
         // Get the data from that region
         let mut region_data = self.read_buffer.take().unwrap();
@@ -817,4 +1342,170 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
         Ok(flash_freed)
     }
+
+    /// Find the next valid key after `after`, or the first valid key if
+    /// `after` is `None`.
+    ///
+    /// Keys are returned in the order they are stored in flash, including
+    /// the main key. If power was lost while a key was being updated, the
+    /// key can be returned twice until it is next updated or invalidated.
+    /// `get_key()` always returns the newest value.
+    ///
+    /// On success the key is returned, or `None` if there are no more keys.
+    /// On error a `ErrorCode` will be returned.
+    pub fn next_key(&self, after: Option<StoredKey>) -> Result<Option<StoredKey>, ErrorCode> {
+        let num_region = self.flash_size / S;
+        let (start_region, start_offset) = match after {
+            Some(key) => (
+                key.region,
+                key.offset + HEADER_LENGTH + key.value_length + CHECK_SUM_LEN,
+            ),
+            None => (0, 0),
+        };
+        let resume_region = match self.state.get() {
+            State::NextKey(reg) => reg,
+            _ => start_region,
+        };
+
+        for region in resume_region..num_region {
+            let read_state = State::NextKey(region);
+            let region_data =
+                self.load_region(region, self.state.get() == read_state, read_state)?;
+            let mut offset = if region == start_region {
+                start_offset
+            } else {
+                0
+            };
+
+            loop {
+                if offset + HEADER_LENGTH >= S || region_data[offset + VERSION_OFFSET] != VERSION {
+                    // We reached the end of the objects, or data we don't
+                    // understand
+                    break;
+                }
+
+                let total_length = ((region_data[offset + LEN_OFFSET] as u16) & !0xF0) << 8
+                    | region_data[offset + LEN_OFFSET + 1] as u16;
+                let total_length = total_length as usize;
+                if total_length == 0 {
+                    break;
+                }
+
+                if region_data[offset + LEN_OFFSET] & 0x80 == 0x80
+                    && total_length >= HEADER_LENGTH + CHECK_SUM_LEN
+                {
+                    let mut hash = [0; 8];
+                    hash.copy_from_slice(
+                        &region_data[(offset + HASH_OFFSET)..(offset + HASH_OFFSET + 8)],
+                    );
+                    self.read_buffer.replace(Some(region_data));
+
+                    return Ok(Some(StoredKey {
+                        hash: u64::from_be_bytes(hash),
+                        value_length: total_length - HEADER_LENGTH - CHECK_SUM_LEN,
+                        region,
+                        offset,
+                    }));
+                }
+
+                offset += total_length;
+            }
+
+            self.read_buffer.replace(Some(region_data));
+        }
+
+        Ok(None)
+    }
+
+    /// Iterate over the valid keys, see `next_key()`.
+    ///
+    /// This can only be used with a synchronous `FlashController`. If an
+    /// error occurs it is returned and the iteration stops.
+    pub fn keys(&self) -> Keys<'_, 'a, C, S> {
+        Keys {
+            tickv: self,
+            last: None,
+            done: false,
+        }
+    }
+
+    /// Find out how the space in `region` is used.
+    ///
+    /// On success the `RegionStats` of the region will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn region_stats(&self, region: usize) -> Result<RegionStats, ErrorCode> {
+        let read_state = State::RegionStats(region);
+        let region_data = self.load_region(region, self.state.get() == read_state, read_state)?;
+        let mut stats = RegionStats::default();
+        let mut offset: usize = 0;
+
+        loop {
+            if offset >= S {
+                break;
+            }
+
+            if region_data[offset + VERSION_OFFSET] == 0xFF {
+                // The rest of the region is unused
+                stats.free = S - offset;
+                break;
+            }
+
+            let total_length =
+                if offset + HEADER_LENGTH < S && region_data[offset + VERSION_OFFSET] == VERSION {
+                    let total_length = ((region_data[offset + LEN_OFFSET] as u16) & !0xF0) << 8
+                        | region_data[offset + LEN_OFFSET + 1] as u16;
+                    total_length as usize
+                } else {
+                    0
+                };
+
+            if total_length == 0 || offset + total_length > S {
+                // We can't use the rest of the region until it is erased
+                stats.invalidated += S - offset;
+                break;
+            }
+
+            if region_data[offset + LEN_OFFSET] & 0x80 == 0x80 {
+                stats.live += total_length;
+            } else {
+                stats.invalidated += total_length;
+            }
+            offset += total_length;
+        }
+
+        self.read_buffer.replace(Some(region_data));
+        Ok(stats)
+    }
+}
+
+/// An iterator over the valid keys in TicKV, created by `TicKV::keys()`.
+pub struct Keys<'t, 'a, C: FlashController<S>, const S: usize> {
+    tickv: &'t TicKV<'a, C, S>,
+    last: Option<StoredKey>,
+    done: bool,
+}
+
+impl<'t, 'a, C: FlashController<S>, const S: usize> Iterator for Keys<'t, 'a, C, S> {
+    type Item = Result<StoredKey, ErrorCode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.tickv.next_key(self.last) {
+            Ok(Some(key)) => {
+                self.last = Some(key);
+                Some(Ok(key))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
 }
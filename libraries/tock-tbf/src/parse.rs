@@ -126,10 +126,19 @@ pub fn parse_tbf_header(
                 // Places to save fields that we parse out of the header
                 // options.
                 let mut main_pointer: Option<types::TbfHeaderV2Main> = None;
+                let mut program_pointer: Option<types::TbfHeaderV2Program> = None;
                 let mut wfr_pointer: [Option<types::TbfHeaderV2WriteableFlashRegion>; 4] =
                     Default::default();
                 let mut app_name_str = "";
                 let mut fixed_address_pointer: Option<types::TbfHeaderV2FixedAddresses> = None;
+                let mut permissions_pointer: Option<types::TbfHeaderV2Permissions> = None;
+                let mut quotas_pointer: Option<types::TbfHeaderV2Quotas> = None;
+                let mut scheduling_pointer: Option<types::TbfHeaderV2Scheduling> = None;
+                let mut persistent_ram_pointer: Option<types::TbfHeaderV2PersistentRam> = None;
+                let mut ipc_services_pointer: Option<types::TbfHeaderV2IpcServices> = None;
+                let mut heartbeat_pointer: Option<types::TbfHeaderV2Heartbeat> = None;
+                let mut storage_permissions_pointer: Option<types::TbfHeaderV2StoragePermissions> =
+                    None;
 
                 // Iterate the remainder of the header looking for TLV entries.
                 while remaining.len() > 0 {
@@ -157,6 +166,29 @@ pub fn parse_tbf_header(
                             }
                         }
 
+                        types::TbfHeaderTypes::TbfHeaderProgram => {
+                            let entry_len = 20;
+
+                            // The program TLV has a fixed size, and the binary
+                            // it describes must end within the app.
+                            if tlv_header.length as usize == entry_len {
+                                let program: types::TbfHeaderV2Program = remaining.try_into()?;
+                                let binary_end = program.binary_end_offset();
+                                if binary_end < u32::from(tbf_header_base.header_size)
+                                    || binary_end > tbf_header_base.total_size
+                                {
+                                    return Err(types::TbfParseError::BadTlvEntry(
+                                        tlv_header.tipe as usize,
+                                    ));
+                                }
+                                program_pointer = Some(program);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
                         types::TbfHeaderTypes::TbfHeaderWriteableFlashRegions => {
                             // Length must be a multiple of the size of a region definition.
                             if tlv_header.length as usize
@@ -219,6 +251,74 @@ pub fn parse_tbf_header(
                             }
                         }
 
+                        types::TbfHeaderTypes::TbfHeaderPermissions => {
+                            let permissions_slice = remaining
+                                .get(0..tlv_header.length as usize)
+                                .ok_or(types::TbfParseError::NotEnoughFlash)?;
+                            permissions_pointer = Some(permissions_slice.try_into()?);
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderQuotas => {
+                            let entry_len = tlv_header.length as usize;
+                            if entry_len == 12 || entry_len == 16 {
+                                let quotas_slice = remaining
+                                    .get(0..entry_len)
+                                    .ok_or(types::TbfParseError::NotEnoughFlash)?;
+                                quotas_pointer = Some(quotas_slice.try_into()?);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderScheduling => {
+                            let entry_len = 12;
+                            if tlv_header.length as usize == entry_len {
+                                scheduling_pointer = Some(remaining.try_into()?);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderPersistentRam => {
+                            let entry_len = 8;
+                            if tlv_header.length as usize == entry_len {
+                                persistent_ram_pointer = Some(remaining.try_into()?);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderIpcServices => {
+                            let services_slice = remaining
+                                .get(0..tlv_header.length as usize)
+                                .ok_or(types::TbfParseError::NotEnoughFlash)?;
+                            ipc_services_pointer = Some(services_slice.try_into()?);
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderHeartbeat => {
+                            let entry_len = 4;
+                            if tlv_header.length as usize == entry_len {
+                                heartbeat_pointer = Some(remaining.try_into()?);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderStoragePermissions => {
+                            let permissions_slice = remaining
+                                .get(0..tlv_header.length as usize)
+                                .ok_or(types::TbfParseError::NotEnoughFlash)?;
+                            storage_permissions_pointer = Some(permissions_slice.try_into()?);
+                        }
+
                         _ => {}
                     }
 
@@ -233,14 +333,69 @@ pub fn parse_tbf_header(
                 let tbf_header = types::TbfHeaderV2 {
                     base: tbf_header_base,
                     main: main_pointer,
+                    program: program_pointer,
                     package_name: Some(app_name_str),
                     writeable_regions: Some(wfr_pointer),
                     fixed_addresses: fixed_address_pointer,
+                    permissions: permissions_pointer,
+                    quotas: quotas_pointer,
+                    scheduling: scheduling_pointer,
+                    persistent_ram: persistent_ram_pointer,
+                    ipc_services: ipc_services_pointer,
+                    heartbeat: heartbeat_pointer,
+                    storage_permissions: storage_permissions_pointer,
                 };
+                let tbf_header = types::TbfHeader::TbfHeaderV2(tbf_header);
 
-                Ok(types::TbfHeader::TbfHeaderV2(tbf_header))
+                // An app cannot keep more state across restarts than it has
+                // RAM.
+                if let Some(persistent_ram) = persistent_ram_pointer {
+                    if persistent_ram.size() > tbf_header.get_minimum_app_ram_size() {
+                        return Err(types::TbfParseError::BadTlvEntry(
+                            types::TbfHeaderTypes::TbfHeaderPersistentRam as usize,
+                        ));
+                    }
+                }
+
+                Ok(tbf_header)
             }
         }
         _ => Err(types::TbfParseError::UnsupportedVersion(version)),
     }
 }
+
+/// Parse one footer of a TBF binary stored in flash.
+///
+/// `footers` must start at a footer and end at the end of the app, that is
+/// it covers the region from `TbfHeader::get_binary_end()` (for the first
+/// footer) to `TbfHeader::get_total_size()`. Footers are TLVs laid out like
+/// the TLVs in the header. The only footer currently defined holds
+/// credentials.
+///
+/// ## Return
+///
+/// The credentials and the number of bytes the footer occupies, including
+/// its padding. Advance `footers` by that many bytes to parse the next
+/// footer.
+pub fn parse_tbf_footer(
+    footers: &'static [u8],
+) -> Result<(types::TbfFooterV2Credentials, u32), types::TbfParseError> {
+    let tlv_header: types::TbfHeaderTlv = footers
+        .get(0..4)
+        .ok_or(types::TbfParseError::NotEnoughFlash)?
+        .try_into()?;
+    match tlv_header.tipe {
+        types::TbfHeaderTypes::TbfFooterCredentials => {
+            // Credentials start with a 4 byte format.
+            if (tlv_header.length as usize) < 4 {
+                return Err(types::TbfParseError::BadTlvEntry(tlv_header.tipe as usize));
+            }
+            let credentials_slice = footers
+                .get(4..4 + tlv_header.length as usize)
+                .ok_or(types::TbfParseError::NotEnoughFlash)?;
+            let credentials = credentials_slice.try_into()?;
+            Ok((credentials, 4 + align4!(tlv_header.length as u32)))
+        }
+        _ => Err(types::TbfParseError::BadTlvEntry(tlv_header.tipe as usize)),
+    }
+}
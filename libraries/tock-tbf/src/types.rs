@@ -2,6 +2,7 @@
 
 use core::convert::TryInto;
 use core::fmt;
+use core::str;
 
 /// Error when parsing just the beginning of the TBF header. This is only used
 /// when establishing the linked list structure of apps installed in flash.
@@ -105,6 +106,18 @@ pub enum TbfHeaderTypes {
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
     TbfHeaderFixedAddresses = 5,
+    TbfHeaderPermissions = 6,
+    TbfHeaderProgram = 9,
+    TbfHeaderQuotas = 10,
+    TbfHeaderScheduling = 11,
+    TbfHeaderPersistentRam = 12,
+    TbfHeaderIpcServices = 13,
+    TbfHeaderHeartbeat = 14,
+    TbfHeaderStoragePermissions = 15,
+
+    /// Credentials footer. This is only valid in the footers that follow the
+    /// app binary, not in the header.
+    TbfFooterCredentials = 128,
 
     /// Some field in the header that we do not understand. Since the TLV format
     /// specifies the length of each section, if we get a field we do not
@@ -130,6 +143,22 @@ pub struct TbfHeaderV2Main {
     minimum_ram_size: u32,
 }
 
+/// The v2 program section for apps.
+///
+/// This is a superset of the main section that also records where the app
+/// binary ends. Everything from the end of the binary to the end of the app
+/// (`total_size`) holds footers, such as credentials. An app has either a
+/// main or a program section; if both are present the program section is
+/// used.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2Program {
+    init_fn_offset: u32,
+    protected_size: u32,
+    minimum_ram_size: u32,
+    binary_end_offset: u32,
+    version: u32,
+}
+
 /// Writeable flash regions only need an offset and size.
 ///
 /// There can be multiple (or zero) flash regions defined, so this is its own
@@ -164,6 +193,243 @@ pub struct TbfHeaderV2FixedAddresses {
     start_process_flash: u32,
 }
 
+/// Resource limits the app runs under.
+///
+/// A field that is zero is not specified by the app, and the board's default
+/// for that resource applies.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TbfHeaderV2Quotas {
+    cpu_budget_us: u32,
+    cpu_period_us: u32,
+    grant_memory_limit: u32,
+    upcall_queue_depth: u32,
+}
+
+/// Real-time parameters of the app. The app does some work, a job, at most
+/// once every `period_us`, and each job needs at most `budget_us` of CPU time
+/// and must finish within `deadline_us`.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2Scheduling {
+    period_us: u32,
+    deadline_us: u32,
+    budget_us: u32,
+}
+
+/// State the app keeps across restarts. The kernel sets aside `size` bytes in
+/// which the app can save a region of its RAM, and the app gets the saved
+/// copy back after it restarts. If `flash_slot` is not zero, the app can also
+/// save the copy to slot `flash_slot - 1` of the board's checkpoint storage so
+/// that it survives a reboot.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2PersistentRam {
+    size: u32,
+    flash_slot: u32,
+}
+
+/// How often the app checks in with the kernel to show that it has not hung.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2Heartbeat {
+    interval_ms: u32,
+}
+
+/// Which values in the kernel's key-value store the app may access. Values
+/// are tagged with the `write_id` of the app that stored them.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2StoragePermissions {
+    write_id: u32,
+    read_ids: &'static [u8],
+    modify_ids: &'static [u8],
+}
+
+/// One entry of the permissions section: the app may use the driver with
+/// `driver_number`, and may call its commands `first_command` through
+/// `last_command` (inclusive).
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderDriverPermission {
+    pub driver_number: u32,
+    pub first_command: u32,
+    pub last_command: u32,
+}
+
+/// The system calls an app is allowed to make.
+///
+/// An app may only subscribe, allow buffers to, and call commands on the
+/// drivers listed here, and may only call the listed commands. The entries
+/// are kept in flash and read when they are checked, so there is no limit on
+/// their number.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2Permissions {
+    entries: &'static [u8],
+}
+
+impl TbfHeaderV2Permissions {
+    const ENTRY_SIZE: usize = 12;
+
+    /// Iterate over the permission entries.
+    pub fn iter(&self) -> impl Iterator<Item = TbfHeaderDriverPermission> {
+        self.entries
+            .chunks_exact(Self::ENTRY_SIZE)
+            .filter_map(|entry| entry.try_into().ok())
+    }
+
+    /// Whether the app may use the driver at all.
+    pub fn allows_driver(&self, driver_number: u32) -> bool {
+        self.iter()
+            .any(|permission| permission.driver_number == driver_number)
+    }
+
+    /// Whether the app may call command `command_number` of the driver.
+    pub fn allows_command(&self, driver_number: u32, command_number: u32) -> bool {
+        self.iter().any(|permission| {
+            permission.driver_number == driver_number
+                && permission.first_command <= command_number
+                && command_number <= permission.last_command
+        })
+    }
+}
+
+/// One IPC service an app provides: its name and the package names of the
+/// apps that may use it.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderIpcService {
+    name: &'static str,
+    num_clients: usize,
+    clients: &'static [u8],
+}
+
+impl TbfHeaderIpcService {
+    /// Parse the service at the start of `b`, and return it with the bytes
+    /// that follow it.
+    fn parse(b: &'static [u8]) -> Result<(TbfHeaderIpcService, &'static [u8]), TbfParseError> {
+        let bad = || TbfParseError::BadTlvEntry(TbfHeaderTypes::TbfHeaderIpcServices as usize);
+        let name_len = *b.get(0).ok_or_else(bad)? as usize;
+        let num_clients = *b.get(1).ok_or_else(bad)? as usize;
+        let name = b.get(2..2 + name_len).ok_or_else(bad)?;
+        if name.is_empty() {
+            return Err(bad());
+        }
+        let name = str::from_utf8(name).map_err(|_| bad())?;
+        let clients_start = 2 + name_len;
+        let mut clients_end = clients_start;
+        for _ in 0..num_clients {
+            let client_len = *b.get(clients_end).ok_or_else(bad)? as usize;
+            let client = b
+                .get(clients_end + 1..clients_end + 1 + client_len)
+                .ok_or_else(bad)?;
+            str::from_utf8(client).map_err(|_| bad())?;
+            clients_end += 1 + client_len;
+        }
+        Ok((
+            TbfHeaderIpcService {
+                name: name,
+                num_clients: num_clients,
+                clients: &b[clients_start..clients_end],
+            },
+            &b[clients_end..],
+        ))
+    }
+
+    /// The name clients find the service by.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The package names of the apps that may use the service. If there are
+    /// none, any app may use it.
+    pub fn clients(&self) -> impl Iterator<Item = &'static str> {
+        let mut remaining = self.clients;
+        core::iter::from_fn(move || {
+            let (len, rest) = remaining.split_first()?;
+            let client = rest.get(..*len as usize)?;
+            remaining = &rest[*len as usize..];
+            str::from_utf8(client).ok()
+        })
+    }
+
+    /// Whether the app with package name `client` may use the service.
+    pub fn allows(&self, client: &str) -> bool {
+        self.num_clients == 0 || self.clients().any(|name| name == client)
+    }
+}
+
+/// The IPC services an app provides, and which apps may use each of them.
+///
+/// An app without this section provides one service, named after its
+/// package, that any app may use.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2IpcServices {
+    entries: &'static [u8],
+}
+
+impl TbfHeaderV2IpcServices {
+    /// Iterate over the services, in the order they are listed in the
+    /// header.
+    pub fn iter(&self) -> impl Iterator<Item = TbfHeaderIpcService> {
+        let mut remaining = self.entries;
+        core::iter::from_fn(move || {
+            if remaining.is_empty() {
+                return None;
+            }
+            let (service, rest) = TbfHeaderIpcService::parse(remaining).ok()?;
+            remaining = rest;
+            Some(service)
+        })
+    }
+
+    /// Find a service by name. Returns its index and the service.
+    pub fn find(&self, name: &[u8]) -> Option<(usize, TbfHeaderIpcService)> {
+        self.iter()
+            .enumerate()
+            .find(|(_, service)| service.name.as_bytes() == name)
+    }
+
+    /// Whether the app with package name `client` may use any of the
+    /// services.
+    pub fn allows(&self, client: &str) -> bool {
+        self.iter().any(|service| service.allows(client))
+    }
+}
+
+/// Formats of credentials that can be stored in a credentials footer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TbfFooterV2CredentialsType {
+    /// Space reserved for credentials that have not been filled in yet. The
+    /// data is meaningless.
+    Reserved = 0,
+    /// SHA-256 hash of the app, 32 bytes.
+    Sha256 = 1,
+    /// HMAC-SHA256 of the app, 32 bytes.
+    HmacSha256 = 2,
+
+    /// A credentials format that this library does not understand. A policy
+    /// cannot check these credentials, but other credentials of the same app
+    /// may still be checked.
+    Unknown,
+}
+
+/// A credentials footer.
+///
+/// Credentials cover the app from the start of the TBF header to the end of
+/// the binary (`binary_end_offset` in the program header). The footers
+/// themselves are not covered, so an app can carry several credentials.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfFooterV2Credentials {
+    format: TbfFooterV2CredentialsType,
+    data: &'static [u8],
+}
+
+impl TbfFooterV2Credentials {
+    /// The format of the credentials.
+    pub fn format(&self) -> TbfFooterV2CredentialsType {
+        self.format
+    }
+
+    /// The credentials themselves, such as a hash or a signature.
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+}
+
 // Conversion functions from slices to the various TBF fields.
 
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Base {
@@ -209,6 +475,15 @@ impl core::convert::TryFrom<u16> for TbfHeaderTypes {
             2 => Ok(TbfHeaderTypes::TbfHeaderWriteableFlashRegions),
             3 => Ok(TbfHeaderTypes::TbfHeaderPackageName),
             5 => Ok(TbfHeaderTypes::TbfHeaderFixedAddresses),
+            6 => Ok(TbfHeaderTypes::TbfHeaderPermissions),
+            9 => Ok(TbfHeaderTypes::TbfHeaderProgram),
+            10 => Ok(TbfHeaderTypes::TbfHeaderQuotas),
+            11 => Ok(TbfHeaderTypes::TbfHeaderScheduling),
+            12 => Ok(TbfHeaderTypes::TbfHeaderPersistentRam),
+            13 => Ok(TbfHeaderTypes::TbfHeaderIpcServices),
+            14 => Ok(TbfHeaderTypes::TbfHeaderHeartbeat),
+            15 => Ok(TbfHeaderTypes::TbfHeaderStoragePermissions),
+            128 => Ok(TbfHeaderTypes::TbfFooterCredentials),
             _ => Ok(TbfHeaderTypes::Unknown),
         }
     }
@@ -258,6 +533,335 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Main {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Program {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Program, Self::Error> {
+        Ok(TbfHeaderV2Program {
+            init_fn_offset: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            protected_size: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            minimum_ram_size: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            binary_end_offset: u32::from_le_bytes(
+                b.get(12..16)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            version: u32::from_le_bytes(
+                b.get(16..20)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl TbfHeaderV2Program {
+    pub(crate) fn binary_end_offset(&self) -> u32 {
+        self.binary_end_offset
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Quotas {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Quotas, Self::Error> {
+        Ok(TbfHeaderV2Quotas {
+            cpu_budget_us: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            cpu_period_us: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            grant_memory_limit: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            // Older headers end before the upcall queue depth.
+            upcall_queue_depth: match b.get(12..16) {
+                Some(depth) => u32::from_le_bytes(depth.try_into()?),
+                None => 0,
+            },
+        })
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Scheduling {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Scheduling, Self::Error> {
+        let scheduling = TbfHeaderV2Scheduling {
+            period_us: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            deadline_us: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            budget_us: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        };
+        // A job must fit in its deadline, and the deadline in the period.
+        if scheduling.period_us == 0
+            || scheduling.deadline_us() > scheduling.period_us
+            || scheduling.budget_us() > scheduling.deadline_us()
+        {
+            return Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfHeaderScheduling as usize,
+            ));
+        }
+        Ok(scheduling)
+    }
+}
+
+impl TbfHeaderV2Scheduling {
+    /// Minimum time, in microseconds, between the starts of two jobs.
+    pub fn period_us(&self) -> u32 {
+        self.period_us
+    }
+
+    /// Time, in microseconds, from the start of a job by which it must be
+    /// done. If the app does not specify a deadline, it is the period.
+    pub fn deadline_us(&self) -> u32 {
+        match self.deadline_us {
+            0 => self.period_us,
+            deadline => deadline,
+        }
+    }
+
+    /// CPU time, in microseconds, a job may use. If the app does not specify
+    /// a budget, it is the deadline.
+    pub fn budget_us(&self) -> u32 {
+        match self.budget_us {
+            0 => self.deadline_us(),
+            budget => budget,
+        }
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Heartbeat {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Heartbeat, Self::Error> {
+        let heartbeat = TbfHeaderV2Heartbeat {
+            interval_ms: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        };
+        if heartbeat.interval_ms == 0 {
+            return Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfHeaderHeartbeat as usize,
+            ));
+        }
+        Ok(heartbeat)
+    }
+}
+
+impl TbfHeaderV2StoragePermissions {
+    /// The ID that values the app stores are tagged with, or `None` if the
+    /// app may not store values.
+    pub fn write_id(&self) -> Option<u32> {
+        match self.write_id {
+            0 => None,
+            id => Some(id),
+        }
+    }
+
+    /// The IDs of the apps whose values this app may read.
+    pub fn read_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.read_ids
+            .chunks_exact(4)
+            .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+    }
+
+    /// The IDs of the apps whose values this app may overwrite or delete.
+    pub fn modify_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.modify_ids
+            .chunks_exact(4)
+            .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+    }
+}
+
+impl core::convert::TryFrom<&'static [u8]> for TbfHeaderV2StoragePermissions {
+    type Error = TbfParseError;
+
+    fn try_from(b: &'static [u8]) -> Result<TbfHeaderV2StoragePermissions, Self::Error> {
+        let bad =
+            || TbfParseError::BadTlvEntry(TbfHeaderTypes::TbfHeaderStoragePermissions as usize);
+        let write_id = u32::from_le_bytes(b.get(0..4).ok_or_else(bad)?.try_into()?);
+        let read_count = u16::from_le_bytes(b.get(4..6).ok_or_else(bad)?.try_into()?) as usize;
+        let modify_count = u16::from_le_bytes(b.get(6..8).ok_or_else(bad)?.try_into()?) as usize;
+        let read_end = 8 + read_count * 4;
+        let modify_end = read_end + modify_count * 4;
+        if b.len() != modify_end {
+            return Err(bad());
+        }
+        Ok(TbfHeaderV2StoragePermissions {
+            write_id,
+            read_ids: &b[8..read_end],
+            modify_ids: &b[read_end..modify_end],
+        })
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2PersistentRam {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2PersistentRam, Self::Error> {
+        let persistent_ram = TbfHeaderV2PersistentRam {
+            size: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            flash_slot: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        };
+        if persistent_ram.size == 0 {
+            return Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfHeaderPersistentRam as usize,
+            ));
+        }
+        Ok(persistent_ram)
+    }
+}
+
+impl TbfHeaderV2PersistentRam {
+    /// Number of bytes of RAM the app keeps across restarts.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The slot of the board's checkpoint storage the app saves its state to,
+    /// or `None` if the app's state is not saved to flash.
+    pub fn flash_slot(&self) -> Option<usize> {
+        match self.flash_slot {
+            0 => None,
+            slot => Some(slot as usize - 1),
+        }
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderDriverPermission {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderDriverPermission, Self::Error> {
+        Ok(TbfHeaderDriverPermission {
+            driver_number: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            first_command: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            last_command: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl core::convert::TryFrom<&'static [u8]> for TbfHeaderV2Permissions {
+    type Error = TbfParseError;
+
+    fn try_from(b: &'static [u8]) -> Result<TbfHeaderV2Permissions, Self::Error> {
+        // Every entry must be complete and describe a valid range.
+        if b.len() % Self::ENTRY_SIZE != 0 {
+            return Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfHeaderPermissions as usize,
+            ));
+        }
+        for entry in b.chunks_exact(Self::ENTRY_SIZE) {
+            let permission: TbfHeaderDriverPermission = entry.try_into()?;
+            if permission.first_command > permission.last_command {
+                return Err(TbfParseError::BadTlvEntry(
+                    TbfHeaderTypes::TbfHeaderPermissions as usize,
+                ));
+            }
+        }
+        Ok(TbfHeaderV2Permissions { entries: b })
+    }
+}
+
+impl core::convert::TryFrom<&'static [u8]> for TbfHeaderV2IpcServices {
+    type Error = TbfParseError;
+
+    fn try_from(b: &'static [u8]) -> Result<TbfHeaderV2IpcServices, Self::Error> {
+        // Every service must be complete, and there must be at least one.
+        if b.is_empty() {
+            return Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfHeaderIpcServices as usize,
+            ));
+        }
+        let mut remaining = b;
+        while !remaining.is_empty() {
+            remaining = TbfHeaderIpcService::parse(remaining)?.1;
+        }
+        Ok(TbfHeaderV2IpcServices { entries: b })
+    }
+}
+
+impl core::convert::TryFrom<u32> for TbfFooterV2CredentialsType {
+    type Error = TbfParseError;
+
+    fn try_from(format: u32) -> Result<TbfFooterV2CredentialsType, Self::Error> {
+        match format {
+            0 => Ok(TbfFooterV2CredentialsType::Reserved),
+            1 => Ok(TbfFooterV2CredentialsType::Sha256),
+            2 => Ok(TbfFooterV2CredentialsType::HmacSha256),
+            _ => Ok(TbfFooterV2CredentialsType::Unknown),
+        }
+    }
+}
+
+impl core::convert::TryFrom<&'static [u8]> for TbfFooterV2Credentials {
+    type Error = TbfParseError;
+
+    fn try_from(b: &'static [u8]) -> Result<TbfFooterV2Credentials, Self::Error> {
+        let format: u32 = u32::from_le_bytes(
+            b.get(0..4)
+                .ok_or(TbfParseError::InternalError)?
+                .try_into()?,
+        );
+        Ok(TbfFooterV2Credentials {
+            format: format.try_into()?,
+            data: b.get(4..).ok_or(TbfParseError::InternalError)?,
+        })
+    }
+}
+
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2WriteableFlashRegion {
     type Error = TbfParseError;
 
@@ -305,9 +909,17 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2FixedAddresses {
 pub struct TbfHeaderV2 {
     pub(crate) base: TbfHeaderV2Base,
     pub(crate) main: Option<TbfHeaderV2Main>,
+    pub(crate) program: Option<TbfHeaderV2Program>,
     pub(crate) package_name: Option<&'static str>,
     pub(crate) writeable_regions: Option<[Option<TbfHeaderV2WriteableFlashRegion>; 4]>,
     pub(crate) fixed_addresses: Option<TbfHeaderV2FixedAddresses>,
+    pub(crate) permissions: Option<TbfHeaderV2Permissions>,
+    pub(crate) quotas: Option<TbfHeaderV2Quotas>,
+    pub(crate) scheduling: Option<TbfHeaderV2Scheduling>,
+    pub(crate) persistent_ram: Option<TbfHeaderV2PersistentRam>,
+    pub(crate) ipc_services: Option<TbfHeaderV2IpcServices>,
+    pub(crate) heartbeat: Option<TbfHeaderV2Heartbeat>,
+    pub(crate) storage_permissions: Option<TbfHeaderV2StoragePermissions>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -348,7 +960,10 @@ impl TbfHeader {
     /// needed for this app.
     pub fn get_minimum_app_ram_size(&self) -> u32 {
         match *self {
-            TbfHeader::TbfHeaderV2(hd) => hd.main.map_or(0, |m| m.minimum_ram_size),
+            TbfHeader::TbfHeaderV2(hd) => match hd.program {
+                Some(p) => p.minimum_ram_size,
+                None => hd.main.map_or(0, |m| m.minimum_ram_size),
+            },
             _ => 0,
         }
     }
@@ -358,7 +973,11 @@ impl TbfHeader {
     pub fn get_protected_size(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.protected_size) + (hd.base.header_size as u32)
+                let protected_size = match hd.program {
+                    Some(p) => p.protected_size,
+                    None => hd.main.map_or(0, |m| m.protected_size),
+                };
+                protected_size + (hd.base.header_size as u32)
             }
             _ => 0,
         }
@@ -369,12 +988,46 @@ impl TbfHeader {
     pub fn get_init_function_offset(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.init_fn_offset) + (hd.base.header_size as u32)
+                let init_fn_offset = match hd.program {
+                    Some(p) => p.init_fn_offset,
+                    None => hd.main.map_or(0, |m| m.init_fn_offset),
+                };
+                init_fn_offset + (hd.base.header_size as u32)
             }
             _ => 0,
         }
     }
 
+    /// Get the total size of the app in flash, including the header, the
+    /// binary, any footers and padding.
+    pub fn get_total_size(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.base.total_size,
+            TbfHeader::Padding(base) => base.total_size,
+        }
+    }
+
+    /// Get the offset from the beginning of the app's flash region where the
+    /// app binary ends and the footers begin. Apps without a program header
+    /// have no footers, so this is the total size of the app.
+    pub fn get_binary_end(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .program
+                .map_or(hd.base.total_size, |p| p.binary_end_offset),
+            TbfHeader::Padding(base) => base.total_size,
+        }
+    }
+
+    /// Get the version of the app from the program header, or 0 if the app
+    /// does not have a program header.
+    pub fn get_app_version(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.program.map_or(0, |p| p.version),
+            _ => 0,
+        }
+    }
+
     /// Get the name of the app.
     pub fn get_package_name(&self) -> Option<&'static str> {
         match *self {
@@ -434,4 +1087,103 @@ impl TbfHeader {
             start => Some(start),
         }
     }
+
+    /// Get the system calls the app is allowed to make. If the app does not
+    /// specify any permissions, return `None`, meaning that the app is not
+    /// restricted.
+    pub fn get_permissions(&self) -> Option<TbfHeaderV2Permissions> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.permissions,
+            _ => None,
+        }
+    }
+
+    /// Get the CPU time, in microseconds, the app may use in each period,
+    /// and the length of the period in microseconds. If the app does not
+    /// specify a CPU budget, return `None`.
+    pub fn get_cpu_budget(&self) -> Option<(u32, u32)> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.quotas.and_then(|q| {
+                if q.cpu_budget_us == 0 || q.cpu_period_us == 0 {
+                    None
+                } else {
+                    Some((q.cpu_budget_us, q.cpu_period_us))
+                }
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get the maximum number of bytes of grant memory the kernel may
+    /// allocate for the app. If the app does not specify a limit, return
+    /// `None`.
+    pub fn get_grant_memory_limit(&self) -> Option<u32> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.quotas.and_then(|q| match q.grant_memory_limit {
+                0 => None,
+                limit => Some(limit),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get the number of upcalls that may wait for the app at once. If the
+    /// app does not specify a queue depth, return `None`.
+    pub fn get_upcall_queue_depth(&self) -> Option<u32> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.quotas.and_then(|q| match q.upcall_queue_depth {
+                0 => None,
+                depth => Some(depth),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get the real-time parameters of the app. If the app does not specify
+    /// any, return `None`.
+    pub fn get_scheduling_parameters(&self) -> Option<TbfHeaderV2Scheduling> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.scheduling,
+            _ => None,
+        }
+    }
+
+    /// Get the persistent state parameters of the app. If the app does not
+    /// keep any state across restarts, return `None`.
+    pub fn get_persistent_ram(&self) -> Option<TbfHeaderV2PersistentRam> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.persistent_ram,
+            _ => None,
+        }
+    }
+
+    /// Get the IPC services the app provides. If the app does not list any,
+    /// return `None`, meaning that the app provides one service, named after
+    /// its package, that any app may use.
+    pub fn get_ipc_services(&self) -> Option<TbfHeaderV2IpcServices> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.ipc_services,
+            _ => None,
+        }
+    }
+
+    /// Get the longest time, in milliseconds, the app may go without checking
+    /// in with the kernel. If the app does not have to check in, return
+    /// `None`.
+    pub fn get_heartbeat_interval_ms(&self) -> Option<u32> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.heartbeat.map(|h| h.interval_ms),
+            _ => None,
+        }
+    }
+
+    /// Get which values in the kernel's key-value store the app may access.
+    /// If the app does not list any, return `None`, and the app may not
+    /// access the store.
+    pub fn get_storage_permissions(&self) -> Option<TbfHeaderV2StoragePermissions> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.storage_permissions,
+            _ => None,
+        }
+    }
 }
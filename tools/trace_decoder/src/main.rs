@@ -0,0 +1,269 @@
+//! Decodes the records of the kernel trace into readable text.
+//!
+//! The record format is described in `kernel/src/trace.rs`. Records are read
+//! from standard input either as the hex lines printed by the `trace` command
+//! of the process console, or with `--binary` as the raw bytes sent by
+//! `capsules::trace_drain`.
+
+use std::io::{self, BufRead, Read};
+
+/// Length of a trace record in bytes.
+const RECORD_LEN: usize = 24;
+
+/// Value of the process field of records that are not about a process.
+const KERNEL: u16 = 0xffff;
+
+/// Prefix of the lines of the process console that hold a record.
+const CONSOLE_PREFIX: &str = "trace ";
+
+/// Prints an error message and usage string. Used to report command line
+/// argument errors.
+fn usage_error(message: &str) {
+    println!(
+        "{}
+
+Usage: trace_decoder [--binary] < INPUT
+Print the kernel trace records in INPUT as text.
+
+By default INPUT is the output of the process console `trace` command. Lines
+that do not start with `trace ` are ignored. With --binary, INPUT is the raw
+records sent by the trace_drain capsule.
+
+Examples:
+  trace_decoder < console.log          Decodes a process console log
+  trace_decoder --binary < rtt.bin     Decodes records received over RTT",
+        message
+    );
+}
+
+struct Record {
+    time_us: u32,
+    kind: u8,
+    detail: u8,
+    process: u16,
+    args: [u32; 4],
+}
+
+impl Record {
+    fn parse(bytes: &[u8]) -> Record {
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        Record {
+            time_us: word(0),
+            kind: bytes[4],
+            detail: bytes[5],
+            process: u16::from_le_bytes([bytes[6], bytes[7]]),
+            args: [word(8), word(12), word(16), word(20)],
+        }
+    }
+}
+
+fn error_code(code: u32) -> String {
+    let name = match code {
+        1 => "FAIL",
+        2 => "BUSY",
+        3 => "ALREADY",
+        4 => "OFF",
+        5 => "RESERVE",
+        6 => "INVAL",
+        7 => "SIZE",
+        8 => "CANCEL",
+        9 => "NOMEM",
+        10 => "NOSUPPORT",
+        11 => "NODEVICE",
+        12 => "UNINSTALLED",
+        13 => "NOACK",
+        _ => return format!("error {}", code),
+    };
+    name.to_string()
+}
+
+fn syscall_class(class: u8) -> &'static str {
+    match class {
+        0 => "yield",
+        1 => "subscribe",
+        2 => "command",
+        3 => "read-write allow",
+        4 => "read-only allow",
+        5 => "memop",
+        6 => "exit",
+        _ => "unknown syscall",
+    }
+}
+
+/// Decodes the registers a system call returns, as in TRD104.
+fn syscall_return(args: [u32; 4]) -> String {
+    let [variant, r1, r2, r3] = args;
+    let u64_value = |low: u32, high: u32| (high as u64) << 32 | low as u64;
+    match variant {
+        0 => format!("failure {}", error_code(r1)),
+        1 => format!("failure {} {:#x}", error_code(r1), r2),
+        2 => format!("failure {} {:#x} {:#x}", error_code(r1), r2, r3),
+        3 => format!("failure {} {:#x}", error_code(r1), u64_value(r2, r3)),
+        128 => "success".to_string(),
+        129 => format!("success {:#x}", r1),
+        130 => format!("success {:#x} {:#x}", r1, r2),
+        131 => format!("success {:#x}", u64_value(r1, r2)),
+        132 => format!("success {:#x} {:#x} {:#x}", r1, r2, r3),
+        133 => format!("success {:#x} {:#x}", u64_value(r1, r2), r3),
+        _ => format!(
+            "unknown variant {} [{:#x}, {:#x}, {:#x}]",
+            variant, r1, r2, r3
+        ),
+    }
+}
+
+fn describe(record: &Record) -> String {
+    let [a0, a1, a2, a3] = record.args;
+    match record.kind {
+        1 => match record.detail {
+            0 => format!("yield(which: {}, {:#x})", a0, a1),
+            1 => format!("subscribe({:#x}, {}, @{:#x}, {:#x})", a0, a1, a2, a3),
+            2 => format!("command({:#x}, {}, {:#x}, {:#x})", a0, a1, a2, a3),
+            3 | 4 => format!(
+                "{}({:#x}, {}, @{:#x}, {:#x})",
+                syscall_class(record.detail),
+                a0,
+                a1,
+                a2,
+                a3
+            ),
+            5 => format!("memop({}, {:#x})", a0, a1),
+            6 => format!("exit(which: {}, {})", a0, a1),
+            class => format!(
+                "syscall class {} [{:#x}, {:#x}, {:#x}, {:#x}]",
+                class, a0, a1, a2, a3
+            ),
+        },
+        2 => format!("return {}", syscall_return(record.args)),
+        3 => format!(
+            "filtered {} = {}",
+            syscall_class(record.detail),
+            error_code(a0)
+        ),
+        4 => format!(
+            "schedule upcall [{:#x}:{}]({:#x}, {:#x}){}",
+            a0,
+            a1,
+            a2,
+            a3,
+            if record.detail == 0 { " dropped" } else { "" }
+        ),
+        5 => format!("removed {} pending upcall(s) [{:#x}:{}]", a2, a0, a1),
+        6 => format!("function call @{:#x}({:#x}, {:#x}, {:#x})", a0, a1, a2, a3),
+        7 => {
+            if a0 == u32::MAX {
+                "switch to process".to_string()
+            } else {
+                format!("switch to process, {} us left", a0)
+            }
+        }
+        8 => {
+            let reason = match record.detail {
+                0 => "fault",
+                1 => "syscall",
+                2 => "interrupted",
+                3 => "failed to run",
+                _ => "unknown reason",
+            };
+            format!("switch from process: {}", reason)
+        }
+        9 => "timeslice expired".to_string(),
+        10 => format!(
+            "kernel work for {} us{}{}",
+            a2,
+            if a0 != 0 { ", interrupts" } else { "" },
+            if a1 != 0 { ", deferred calls" } else { "" }
+        ),
+        kind => format!(
+            "unknown kind {} detail {} [{:#x}, {:#x}, {:#x}, {:#x}]",
+            kind, record.detail, a0, a1, a2, a3
+        ),
+    }
+}
+
+/// Prints records, keeping track of the time of the previous one.
+struct Printer {
+    last_time_us: Option<u32>,
+}
+
+impl Printer {
+    fn print(&mut self, bytes: &[u8]) {
+        let record = Record::parse(bytes);
+        let delta = self
+            .last_time_us
+            .map_or(0, |last| record.time_us.wrapping_sub(last));
+        self.last_time_us = Some(record.time_us);
+        let process = if record.process == KERNEL {
+            "kernel".to_string()
+        } else {
+            format!("pid {}", record.process)
+        };
+        println!(
+            "{:>11} us (+{:>7})  {:<9} {}",
+            record.time_us,
+            delta,
+            process,
+            describe(&record)
+        );
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() != RECORD_LEN * 2 {
+        return None;
+    }
+    (0..RECORD_LEN)
+        .map(|i| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect()
+}
+
+fn main() {
+    let binary = match std::env::args().nth(1).as_deref() {
+        None => false,
+        Some("--binary") => true,
+        Some(_) => {
+            usage_error("Unknown argument");
+            return;
+        }
+    };
+
+    let mut printer = Printer { last_time_us: None };
+    if binary {
+        let mut input = Vec::new();
+        if let Err(e) = io::stdin().read_to_end(&mut input) {
+            eprintln!("Unable to read input: {}", e);
+            return;
+        }
+        for record in input.chunks_exact(RECORD_LEN) {
+            printer.print(record);
+        }
+        if input.len() % RECORD_LEN != 0 {
+            eprintln!("Ignored {} trailing bytes", input.len() % RECORD_LEN);
+        }
+    } else {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("Unable to read input: {}", e);
+                    return;
+                }
+            };
+            let hex = match line.trim().strip_prefix(CONSOLE_PREFIX) {
+                Some(hex) => hex.trim(),
+                None => continue,
+            };
+            match parse_hex(hex) {
+                Some(record) => printer.print(&record),
+                None => eprintln!("Ignored malformed record: {}", line),
+            }
+        }
+    }
+}